@@ -1,5 +1,9 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 use bevy::prelude::*;
-use crate::{GameMap, Player, Monster, AttackEvent, TileType, GameState};
+use rand::Rng;
+use crate::{GameMap, GameMode, GameRng, Occupancy, OpenDoorEvent, PendingTurns, Player, Monster, MonsterType, TileType, AttackEvent, GameplaySet, spawn_heal_flash, TILE_SIZE};
+use crate::fighting::Actor;
 
 #[derive(Component, PartialEq, Debug, Clone, Copy)]
 pub enum MonsterAIState {
@@ -9,6 +13,142 @@ pub enum MonsterAIState {
     Fading
 }
 
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum AlertSymbol {
+    /// spotted the player
+    Exclamation,
+    /// lost sight of the player
+    Question
+}
+
+/// Short lived symbol shown above a monster when its aggro changes
+#[derive(Component)]
+pub struct MonsterAlert {
+    pub symbol: AlertSymbol,
+    pub timer: Timer
+}
+
+impl MonsterAlert {
+    fn new(symbol: AlertSymbol) -> Self {
+        Self {
+            symbol,
+            timer: Timer::from_seconds(ALERT_TIME, TimerMode::Once)
+        }
+    }
+}
+
+const ALERT_TIME: f32 = 1.0;
+
+/// Detects a pursuing monster that makes no progress and lets it sidestep
+#[derive(Component)]
+pub struct StuckDetection {
+    last_position: Vec3,
+    check_timer: Timer,
+    unstick: Option<(Vec3, Timer)>
+}
+
+impl StuckDetection {
+    pub fn new(position: Vec3) -> Self {
+        Self {
+            last_position: position,
+            check_timer: Timer::from_seconds(STUCK_CHECK_TIME, TimerMode::Repeating),
+            unstick: None
+        }
+    }
+}
+
+/// Idle monsters amble to a random neighbouring floor cell now and then
+#[derive(Component)]
+pub struct WanderTimer {
+    timer: Timer,
+    target: Option<Vec3>
+}
+
+impl WanderTimer {
+    pub fn new() -> Self {
+        Self {
+            timer: Timer::from_seconds(WANDER_INTERVAL, TimerMode::Repeating),
+            target: None
+        }
+    }
+}
+
+const WANDER_INTERVAL: f32 = 3.0;
+/// Share of the speed an idle monster ambles with
+const WANDER_SPEED_FACTOR: f32 = 0.4;
+/// Chance of an idle monster to step in a turn of the turn based mode
+const WANDER_TURN_CHANCE: f64 = 0.25;
+
+/// Temporary effect on a monster, removed when its time is up
+#[derive(Component)]
+pub enum StatusEffect {
+    /// walks in a random direction and doesn't attack
+    Confused { timer: Timer, direction: Vec3 }
+}
+
+impl StatusEffect {
+    pub fn confused() -> Self {
+        StatusEffect::Confused {
+            timer: Timer::from_seconds(CONFUSION_TIME, TimerMode::Once),
+            direction: Vec3::ZERO
+        }
+    }
+
+    fn timer_mut(&mut self) -> &mut Timer {
+        match self {
+            StatusEffect::Confused { timer, .. } => timer
+        }
+    }
+}
+
+const CONFUSION_TIME: f32 = 5.0;
+/// Time a status effect or heal cooldown loses in a turn of the turn based mode
+const STATUS_EFFECT_TURN_TIME: f32 = 1.0;
+
+/// Time between two heals of a healing monster
+#[derive(Component)]
+pub struct HealCooldown(Timer);
+
+impl HealCooldown {
+    pub fn new() -> Self {
+        Self(Timer::from_seconds(HEAL_INTERVAL, TimerMode::Repeating))
+    }
+}
+
+const HEAL_INTERVAL: f32 = 2.0;
+
+/// Time between two attacks of a monster in the real time mode
+#[derive(Component)]
+pub struct MonsterAttackCooldown(Timer);
+
+impl MonsterAttackCooldown {
+    /// Ready, the first blow comes without delay
+    pub fn new() -> Self {
+        let mut timer = Timer::from_seconds(MONSTER_ATTACK_INTERVAL, TimerMode::Once);
+        timer.tick(timer.duration());
+        Self(timer)
+    }
+}
+
+const MONSTER_ATTACK_INTERVAL: f32 = 1.0;
+const HEAL_RANGE: f32 = 6.0;
+
+/// Path of a pursuing monster to the player, grid cells without the start
+#[derive(Component, Default)]
+pub struct PathTo {
+    pub steps: Vec<(usize, usize)>,
+    pub index: usize,
+    /// player cell the path leads to
+    goal: Option<(usize, usize)>
+}
+
+/// Distance to a path cell center at which the monster heads for the next one
+const PATH_CELL_REACHED: f32 = 0.5;
+
+const STUCK_CHECK_TIME: f32 = 0.5;
+const STUCK_DISTANCE: f32 = 0.05;
+const UNSTICK_TIME: f32 = 1.0;
+
 pub struct MonsterAIPlugin;
 
 impl Plugin for MonsterAIPlugin {
@@ -16,32 +156,110 @@ impl Plugin for MonsterAIPlugin {
         app
             .add_systems(Update, (
                 update_monster_ai,
-                monster_movement
-            ).chain().run_if(in_state(GameState::InGame)));
+                monster_movement,
+                tick_status_effects,
+                heal_allies,
+                end_monster_turn
+            ).chain().run_if(monsters_may_act).in_set(GameplaySet::AI));
     }
 }
 
-const MONSTER_SPEED: f32 = 1.0;
-const ATTACK_RANGE: f32 = 2.0;
-const VISION_RANGE: f32 = 10.0;
+/// Always in real time, turn based only after a turn of the player
+fn monsters_may_act(game_mode: Res<GameMode>, pending_turns: Res<PendingTurns>) -> bool {
+    *game_mode == GameMode::RealTime || pending_turns.0 > 0
+}
+
+/// Status effects wear off in real time or turn by turn
+fn tick_status_effects(
+    mut commands: Commands,
+    mut status_query: Query<(Entity, &mut StatusEffect)>,
+    game_mode: Res<GameMode>,
+    time: Res<Time>
+) {
+    let delta = match *game_mode {
+        GameMode::RealTime => time.delta(),
+        GameMode::TurnBased => std::time::Duration::from_secs_f32(STATUS_EFFECT_TURN_TIME)
+    };
+    for (entity, mut status_effect) in status_query.iter_mut() {
+        if status_effect.timer_mut().tick(delta).finished() {
+            commands.entity(entity).remove::<StatusEffect>();
+        }
+    }
+}
+
+fn end_monster_turn(game_mode: Res<GameMode>, mut pending_turns: ResMut<PendingTurns>) {
+    if *game_mode == GameMode::TurnBased {
+        pending_turns.0 = pending_turns.0.saturating_sub(1);
+    }
+}
+
+/// Movement and perception values of a monster type
+#[derive(Debug, Clone, Copy)]
+pub struct MonsterBehaviour {
+    pub speed: f32,
+    pub attack_range: f32,
+    pub vision_range: f32,
+    /// hit points healed on a wounded ally, 0 for monsters that attack
+    pub heal_power: usize
+}
+
+impl MonsterType {
+    pub fn behaviour(&self) -> MonsterBehaviour {
+        match self {
+            // fast with short reach
+            MonsterType::Orc => MonsterBehaviour {
+                speed: 1.5,
+                attack_range: 1.6,
+                vision_range: 10.0,
+                heal_power: 0
+            },
+            // slow with long reach
+            MonsterType::Troll => MonsterBehaviour {
+                speed: 0.7,
+                attack_range: 2.0,
+                vision_range: 8.0,
+                heal_power: 0
+            },
+            // keeps its distance and heals
+            MonsterType::Shaman => MonsterBehaviour {
+                speed: 1.0,
+                attack_range: 5.0,
+                vision_range: 10.0,
+                heal_power: 4
+            }
+        }
+    }
+}
 
 fn update_monster_ai(
-    commands: Commands,
+    mut commands: Commands,
     player_query: Query<&Transform, (With<Player>,Without<Monster>)>,
-    mut monster_query: Query<(Entity, &Transform, &mut MonsterAIState), (With<Monster>,Without<Player>)>,
-    game_map: Res<GameMap>
+    mut monster_query: Query<(Entity, &Monster, &Transform, &mut MonsterAIState), Without<Player>>,
+    game_map: Res<GameMap>,
+    game_mode: Res<GameMode>
 ) {
     let player_transform = player_query.single();
+    let player_cell = game_map.world_to_grid(player_transform.translation);
 
-    for (monster_entity, monster_transform, mut ai_state) in monster_query.iter_mut() {
+    for (monster_entity, monster, monster_transform, mut ai_state) in monster_query.iter_mut() {
         if *ai_state != MonsterAIState::Fading {
+            let behaviour = monster.monster_type.behaviour();
+            let previous_state = *ai_state;
             let distance = monster_transform.translation.distance(player_transform.translation);
 
             // First, check if player is within vision range
-            if distance <= VISION_RANGE {
+            if distance <= behaviour.vision_range {
                 // Perform line of sight check
                 if has_line_of_sight(&game_map, monster_transform.translation, player_transform.translation) {
-                    *ai_state = if distance <= ATTACK_RANGE {
+                    // turn based the actors stand on cell centres, the reach is counted in cells
+                    let in_reach = if *game_mode == GameMode::TurnBased {
+                        let (x, y) = game_map.world_to_grid(monster_transform.translation);
+                        let cells = x.abs_diff(player_cell.0).max(y.abs_diff(player_cell.1));
+                        cells <= ((behaviour.attack_range / TILE_SIZE).ceil() as usize).max(1)
+                    } else {
+                        distance <= behaviour.attack_range
+                    };
+                    *ai_state = if in_reach {
                         MonsterAIState::Attacking
                     } else {
                         MonsterAIState::Pursuing
@@ -52,12 +270,23 @@ fn update_monster_ai(
             } else {
                 *ai_state = MonsterAIState::Idle;
             }
+
+            match (previous_state, *ai_state) {
+                (MonsterAIState::Idle, MonsterAIState::Pursuing) |
+                (MonsterAIState::Idle, MonsterAIState::Attacking) => {
+                    commands.entity(monster_entity).insert(MonsterAlert::new(AlertSymbol::Exclamation));
+                },
+                (MonsterAIState::Pursuing, MonsterAIState::Idle) => {
+                    commands.entity(monster_entity).insert(MonsterAlert::new(AlertSymbol::Question));
+                },
+                _ => {}
+            }
         }
     }
 }
 
 /// Checks if there is a clear line of sight between two points in the game map
-fn has_line_of_sight(game_map: &GameMap, start: Vec3, end: Vec3) -> bool {
+pub fn has_line_of_sight(game_map: &GameMap, start: Vec3, end: Vec3) -> bool {
     const STEP_SIZE: f32 = 0.5; // Smaller step size for more precise checking
     let direction = (end - start).normalize();
     let total_distance = start.distance(end);
@@ -68,7 +297,7 @@ fn has_line_of_sight(game_map: &GameMap, start: Vec3, end: Vec3) -> bool {
         let grid_pos = game_map.world_to_grid(current_pos);
 
         // Check if the current grid position is a wall
-        if game_map.grid[grid_pos].tile_type == TileType::Wall {
+        if game_map.grid[grid_pos].tile_type.is_blocking() {
             return false;
         }
 
@@ -80,70 +309,690 @@ fn has_line_of_sight(game_map: &GameMap, start: Vec3, end: Vec3) -> bool {
 }
 
 fn monster_movement(
-    mut monster_query: Query<(Entity, &mut Transform, &MonsterAIState), With<Monster>>,
-    player_query: Query<&Transform, (With<Player>,Without<Monster>)>,
+    mut monster_query: Query<(Entity, &Monster, &mut Transform, &MonsterAIState, &mut StuckDetection, &mut PathTo, &mut WanderTimer, &mut MonsterAttackCooldown, Option<&mut StatusEffect>)>,
+    player_query: Query<(Entity, &Transform), (With<Player>,Without<Monster>)>,
     mut attack_events: EventWriter<AttackEvent>,
+    mut open_door_events: EventWriter<OpenDoorEvent>,
     game_map: Res<GameMap>,
+    mut occupancy: ResMut<Occupancy>,
+    mut game_rng: ResMut<GameRng>,
+    game_mode: Res<GameMode>,
     time: Res<Time>
 ) {
-    let player_transform = player_query.single();
+    let (player_entity, player_transform) = player_query.single();
+
+    let player_cell = game_map.world_to_grid(player_transform.translation);
+
+    for (monster_entity, monster, mut monster_transform, ai_state, mut stuck, mut path, mut wander, mut attack_cooldown, mut status_effect) in monster_query.iter_mut() {
+        attack_cooldown.0.tick(time.delta());
+
+        // a confused monster staggers around instead of hunting the player
+        if let Some(StatusEffect::Confused { direction, .. }) = status_effect.as_deref_mut() {
+            if *ai_state == MonsterAIState::Fading {
+                continue;
+            }
+            let old_position = monster_transform.translation;
+            let new_position = if *game_mode == GameMode::TurnBased {
+                match wander_cell(&game_map, &occupancy, &mut game_rng, monster_entity, old_position) {
+                    Some((x, y)) if (x, y) != player_cell => {
+                        let cell_center = game_map.grid_to_world(x, y);
+                        Vec3::new(cell_center.x, old_position.y, cell_center.z)
+                    },
+                    _ => old_position
+                }
+            } else {
+                if *direction == Vec3::ZERO {
+                    let angle = game_rng.0.gen_range(0.0..std::f32::consts::TAU);
+                    *direction = Vec3::new(angle.cos(), 0.0, angle.sin());
+                }
+                let movement = *direction * monster.monster_type.behaviour().speed * time.delta_secs();
+                let new_position = monster_without_colliding(&game_map, &occupancy, monster_entity, old_position, movement);
+                // blocked, another direction next time
+                if new_position == old_position {
+                    *direction = Vec3::ZERO;
+                }
+                new_position
+            };
+            if new_position != old_position {
+                monster_transform.translation = new_position;
+                occupancy.update(&game_map, monster_entity, old_position, new_position);
+                monster_transform.look_to(new_position - old_position, Vec3::Y);
+            }
+            continue;
+        }
 
-    for (monster_entity, mut monster_transform, ai_state) in monster_query.iter_mut() {
         match ai_state {
             MonsterAIState::Attacking => {
-                // Send attack event if close enough
+                // healers only keep their distance
+                if monster.monster_type.behaviour().heal_power > 0 {
+                    monster_transform.look_at(player_transform.translation, Vec3::Y);
+                    continue;
+                }
+
+                // one attack per turn, in real time once per cooldown
+                if *game_mode == GameMode::RealTime && !attack_cooldown.0.finished() {
+                    continue;
+                }
+                attack_cooldown.0.reset();
+
+                // Send attack event if close enough, turn based the player a cell away is hit directly
                 attack_events.send(AttackEvent {
                     attacker: monster_entity,
-                    direction: (player_transform.translation - monster_transform.translation).normalize()
+                    direction: (player_transform.translation - monster_transform.translation).normalize(),
+                    target: (*game_mode == GameMode::TurnBased).then_some(player_entity)
                 });
             },
+            MonsterAIState::Pursuing if *game_mode == GameMode::TurnBased => {
+                // one cell along the path, the player's cell is taken
+                let monster_cell = game_map.world_to_grid(monster_transform.translation);
+                let Some((x, y)) = a_star(&game_map, monster_cell, player_cell)
+                    .and_then(|steps| steps.first().copied()) else { continue };
+                // opening a door takes the turn
+                if game_map.grid[(x, y)].tile_type == TileType::DoorClosed {
+                    open_door_events.send(OpenDoorEvent { cell: (x, y) });
+                    continue;
+                }
+                let cell_center = game_map.grid_to_world(x, y);
+                let old_position = monster_transform.translation;
+                let new_position = Vec3::new(cell_center.x, old_position.y, cell_center.z);
+
+                if (x, y) != player_cell && !game_map.is_occupied(&occupancy, new_position, monster_entity) {
+                    monster_transform.translation = new_position;
+                    occupancy.update(&game_map, monster_entity, old_position, new_position);
+                }
+                monster_transform.look_at(player_transform.translation, Vec3::Y);
+            },
             MonsterAIState::Pursuing => {
-                // Move towards player
-                let direction = (player_transform.translation - monster_transform.translation).normalize();
-                let movement = direction * MONSTER_SPEED * time.delta_secs();
+                let speed = monster.monster_type.behaviour().speed;
+
+                // Sidestep while unsticking
+                if let Some((direction, timer)) = stuck.unstick.as_mut() {
+                    timer.tick(time.delta());
+                    let movement = *direction * speed * time.delta_secs();
+                    let old_position = monster_transform.translation;
+                    monster_transform.translation = monster_without_colliding(
+                        &game_map,
+                        &occupancy,
+                        monster_entity,
+                        old_position,
+                        movement
+                    );
+                    occupancy.update(&game_map, monster_entity, old_position, monster_transform.translation);
+                    if timer.finished() {
+                        stuck.unstick = None;
+                        stuck.last_position = monster_transform.translation;
+                    }
+                    continue;
+                }
+
+                // Move along the path to the player, straight when close
+                let monster_cell = game_map.world_to_grid(monster_transform.translation);
+                if path.goal != Some(player_cell) {
+                    *path = PathTo {
+                        steps: a_star(&game_map, monster_cell, player_cell).unwrap_or_default(),
+                        index: 0,
+                        goal: Some(player_cell)
+                    };
+                }
+                let mut target = player_transform.translation;
+                while let Some(&(x, y)) = path.steps.get(path.index) {
+                    if (x, y) == player_cell {
+                        break;
+                    }
+                    let cell_center = game_map.grid_to_world(x, y);
+                    let to_cell = Vec3::new(cell_center.x - monster_transform.translation.x, 0.0,
+                                            cell_center.z - monster_transform.translation.z);
+                    if to_cell.length() < PATH_CELL_REACHED {
+                        path.index += 1;
+                    } else {
+                        // a closed door in the way opens once the monster stands in front of it
+                        if game_map.grid[(x, y)].tile_type == TileType::DoorClosed && to_cell.length() <= TILE_SIZE {
+                            open_door_events.send(OpenDoorEvent { cell: (x, y) });
+                        }
+                        target = Vec3::new(cell_center.x, monster_transform.translation.y, cell_center.z);
+                        break;
+                    }
+                }
+                let direction = (target - monster_transform.translation).normalize_or_zero();
+                let movement = direction * speed * time.delta_secs();
 
+                let old_position = monster_transform.translation;
                 let new_position = monster_without_colliding(
                     &game_map,
-                    monster_transform.translation,
+                    &occupancy,
+                    monster_entity,
+                    old_position,
                     movement
                 );
 
                 monster_transform.translation = new_position;
+                occupancy.update(&game_map, monster_entity, old_position, new_position);
                 monster_transform.look_at(player_transform.translation, Vec3::Y);
+
+                // No progress since the last check -> sidestep
+                stuck.check_timer.tick(time.delta());
+                if stuck.check_timer.just_finished() {
+                    if stuck.last_position.distance(monster_transform.translation) < STUCK_DISTANCE {
+                        stuck.unstick = Some((
+                            unstick_direction(&game_map, &occupancy, &mut game_rng, monster_entity, monster_transform.translation, direction),
+                            Timer::from_seconds(UNSTICK_TIME, TimerMode::Once)
+                        ));
+                    }
+                    stuck.last_position = monster_transform.translation;
+                }
+            },
+            MonsterAIState::Idle if *game_mode == GameMode::TurnBased => {
+                if !game_rng.0.gen_bool(WANDER_TURN_CHANCE) {
+                    continue;
+                }
+                let old_position = monster_transform.translation;
+                let Some((x, y)) = wander_cell(&game_map, &occupancy, &mut game_rng, monster_entity, old_position) else { continue };
+                if (x, y) == player_cell {
+                    continue;
+                }
+                let cell_center = game_map.grid_to_world(x, y);
+                let new_position = Vec3::new(cell_center.x, old_position.y, cell_center.z);
+                monster_transform.translation = new_position;
+                occupancy.update(&game_map, monster_entity, old_position, new_position);
+                monster_transform.look_to(new_position - old_position, Vec3::Y);
             },
             MonsterAIState::Idle => {
-                // Optionally add idle wandering behavior
+                // a target left from before the last pursuit is dropped
+                if wander.target.is_some_and(|target| target.distance(monster_transform.translation) > 1.5 * TILE_SIZE) {
+                    wander.target = None;
+                }
+
+                wander.timer.tick(time.delta());
+                if wander.timer.just_finished() {
+                    wander.target = wander_cell(&game_map, &occupancy, &mut game_rng, monster_entity, monster_transform.translation)
+                        .map(|(x, y)| {
+                            let cell_center = game_map.grid_to_world(x, y);
+                            Vec3::new(cell_center.x, monster_transform.translation.y, cell_center.z)
+                        });
+                }
+
+                let Some(target) = wander.target else { continue };
+                let to_target = target - monster_transform.translation;
+                if to_target.length() < PATH_CELL_REACHED {
+                    wander.target = None;
+                    continue;
+                }
+                let speed = monster.monster_type.behaviour().speed * WANDER_SPEED_FACTOR;
+                let movement = to_target.normalize() * speed * time.delta_secs();
+
+                let old_position = monster_transform.translation;
+                let new_position = monster_without_colliding(
+                    &game_map,
+                    &occupancy,
+                    monster_entity,
+                    old_position,
+                    movement
+                );
+                // blocked, a new cell is chosen next time
+                if new_position == old_position {
+                    wander.target = None;
+                    continue;
+                }
+                monster_transform.translation = new_position;
+                occupancy.update(&game_map, monster_entity, old_position, new_position);
+                monster_transform.look_to(to_target, Vec3::Y);
             },
             MonsterAIState::Fading => {}
         }
     }
 }
 
+/// Healers restore hit points of the most wounded monster in range,
+/// the cooldown runs in real time or turn by turn
+fn heal_allies(
+    mut commands: Commands,
+    game_mode: Res<GameMode>,
+    time: Res<Time>,
+    mut healer_query: Query<(Entity, &Monster, &Transform, &MonsterAIState, &mut HealCooldown)>,
+    mut ally_query: Query<(Entity, &Transform, &mut Actor, &MonsterAIState), With<Monster>>,
+) {
+    let delta = match *game_mode {
+        GameMode::RealTime => time.delta(),
+        GameMode::TurnBased => std::time::Duration::from_secs_f32(STATUS_EFFECT_TURN_TIME)
+    };
+    for (healer_entity, healer, healer_transform, healer_state, mut cooldown) in healer_query.iter_mut() {
+        if *healer_state == MonsterAIState::Fading {
+            continue;
+        }
+        cooldown.0.tick(delta);
+        if !cooldown.0.just_finished() {
+            continue;
+        }
+
+        let most_wounded = ally_query.iter_mut()
+            .filter(|(entity, transform, actor, state)| {
+                *entity != healer_entity
+                    && **state != MonsterAIState::Fading
+                    && actor.hit_points < actor.max_hit_points
+                    && transform.translation.distance(healer_transform.translation) <= HEAL_RANGE
+            })
+            .min_by(|(_, _, a, _), (_, _, b, _)| {
+                let a = a.hit_points as f32 / a.max_hit_points as f32;
+                let b = b.hit_points as f32 / b.max_hit_points as f32;
+                a.total_cmp(&b)
+            });
+
+        if let Some((ally_entity, _, mut actor, _)) = most_wounded {
+            actor.hit_points = actor.max_hit_points.min(actor.hit_points + healer.monster_type.behaviour().heal_power);
+            spawn_heal_flash(&mut commands, ally_entity);
+        }
+    }
+}
+
+/// Shortest path over the grid, walls and empty cells can't be passed,
+/// closed doors can as monsters open them.
+/// The path leads from the cell after start up to goal.
+pub fn a_star(game_map: &GameMap, start: (usize, usize), goal: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+    let grid = &game_map.grid;
+    let passable = |cell: (usize, usize)| {
+        grid.is_valid_position(cell.0, cell.1)
+            && !grid[cell].tile_type.is_wall()
+            && grid[cell].tile_type != TileType::Empty
+    };
+    if !passable(goal) {
+        return None;
+    }
+    let heuristic = |cell: (usize, usize)| cell.0.abs_diff(goal.0) + cell.1.abs_diff(goal.1);
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+    let mut costs: HashMap<(usize, usize), usize> = HashMap::new();
+    open.push(Reverse((heuristic(start), start)));
+    costs.insert(start, 0);
+
+    while let Some(Reverse((_, cell))) = open.pop() {
+        if cell == goal {
+            let mut path = vec![cell];
+            let mut current = cell;
+            while let Some(&previous) = came_from.get(&current) {
+                if previous == start {
+                    break;
+                }
+                path.push(previous);
+                current = previous;
+            }
+            path.reverse();
+            if goal == start {
+                path.clear();
+            }
+            return Some(path);
+        }
+
+        let cost = costs[&cell] + 1;
+        let (x, y) = cell;
+        for next in [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)] {
+            if !passable(next) || costs.get(&next).is_some_and(|known| *known <= cost) {
+                continue;
+            }
+            costs.insert(next, cost);
+            came_from.insert(next, cell);
+            open.push(Reverse((cost + heuristic(next), next)));
+        }
+    }
+
+    None
+}
+
+/// Random free floor cell next to the monster
+fn wander_cell(
+    game_map: &GameMap,
+    occupancy: &Occupancy,
+    game_rng: &mut GameRng,
+    monster: Entity,
+    position: Vec3
+) -> Option<(usize, usize)> {
+    let (x, y) = game_map.world_to_grid(position);
+    let cells: Vec<(usize, usize)> = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)]
+        .into_iter()
+        .filter(|&(x, y)| game_map.grid.is_valid_position(x, y)
+            && game_map.grid[(x, y)].tile_type == TileType::Floor
+            && !game_map.is_occupied(occupancy, game_map.grid_to_world(x, y), monster))
+        .collect();
+    if cells.is_empty() {
+        return None;
+    }
+    Some(cells[game_rng.0.gen_range(0..cells.len())])
+}
+
+/// Picks a free direction sideways to the blocked one
+fn unstick_direction(
+    game_map: &GameMap,
+    occupancy: &Occupancy,
+    game_rng: &mut GameRng,
+    monster: Entity,
+    position: Vec3,
+    blocked: Vec3
+) -> Vec3 {
+    const PROBE_DISTANCE: f32 = 0.2;
+    let rng = &mut game_rng.0;
+
+    let blocked = Vec3::new(blocked.x, 0.0, blocked.z).normalize_or_zero();
+    let side = Vec3::new(-blocked.z, 0.0, blocked.x);
+    let mut candidates = [side, -side, -blocked];
+    if rng.gen_bool(0.5) {
+        candidates.swap(0, 1);
+    }
+
+    for candidate in candidates {
+        if monster_without_colliding(game_map, occupancy, monster, position, candidate * PROBE_DISTANCE) != position {
+            return candidate;
+        }
+    }
+
+    let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+    Vec3::new(angle.cos(), 0.0, angle.sin())
+}
+
 fn monster_without_colliding(
     game_map: &GameMap,
+    occupancy: &Occupancy,
+    monster: Entity,
     position: Vec3,
     move_vector: Vec3
 ) -> Vec3 {
-    const MONSTER_DISTANCE: f32 = 0.5;
     let new_position = position + move_vector;
 
-    // Check collision points around the monster
-    let check_points = [
-        Vec3::new(0.0, 0.0, -MONSTER_DISTANCE),
-        Vec3::new(0.0, 0.0, MONSTER_DISTANCE),
-        Vec3::new(-MONSTER_DISTANCE, 0.0, 0.0),
-        Vec3::new(MONSTER_DISTANCE, 0.0, 0.0)
-    ];
+    // walls, player and other monsters
+    if game_map.is_occupied(occupancy, new_position, monster) {
+        return position;
+    }
+
+    new_position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::Duration;
+
+    /// World for the monster systems with the player on its start cell
+    fn monster_world(map: &str) -> World {
+        let mut world = World::new();
+        let game_map = GameMap::from_string(map).unwrap();
+        world.spawn((Transform::from_translation(game_map.grid_to_world(game_map.player_position.0, game_map.player_position.1)), Player));
+        world.insert_resource(game_map);
+        world.insert_resource(GameMode::RealTime);
+        world.insert_resource(GameRng::new(Some(1)));
+        world.init_resource::<Occupancy>();
+        world.init_resource::<Time>();
+        world.init_resource::<Events<AttackEvent>>();
+        world.init_resource::<Events<OpenDoorEvent>>();
+        world
+    }
+
+    fn spawn_monster(world: &mut World, monster_type: MonsterType, position: Vec3, ai_state: MonsterAIState) -> Entity {
+        world.spawn((
+            Monster { monster_type },
+            Transform::from_translation(position),
+            ai_state,
+            StuckDetection::new(position),
+            PathTo::default(),
+            WanderTimer::new(),
+            MonsterAttackCooldown::new()
+        )).id()
+    }
+
+    #[test]
+    fn faster_monster_type_covers_more_ground() {
+        let mut world = monster_world("##########\n#@.......#\n#........#\n##########");
+        let (orc_start, troll_start) = {
+            let game_map = world.resource::<GameMap>();
+            (game_map.grid_to_world(7, 1), game_map.grid_to_world(7, 2))
+        };
+        let orc = spawn_monster(&mut world, MonsterType::Orc, orc_start, MonsterAIState::Pursuing);
+        let troll = spawn_monster(&mut world, MonsterType::Troll, troll_start, MonsterAIState::Pursuing);
+
+        world.resource_mut::<Time>().advance_by(Duration::from_millis(500));
+        world.run_system_once(monster_movement).unwrap();
+
+        let orc_moved = world.get::<Transform>(orc).unwrap().translation.distance(orc_start);
+        let troll_moved = world.get::<Transform>(troll).unwrap().translation.distance(troll_start);
+        assert!(troll_moved > 0.0);
+        assert!(orc_moved > troll_moved);
+        assert!((orc_moved / troll_moved - MonsterType::Orc.behaviour().speed / MonsterType::Troll.behaviour().speed).abs() < 0.01);
+    }
+
+    #[test]
+    fn idle_monster_spotting_the_player_gets_an_alert() {
+        let mut world = monster_world("########\n#@.....#\n########");
+        let position = world.resource::<GameMap>().grid_to_world(3, 1);
+        let monster = spawn_monster(&mut world, MonsterType::Orc, position, MonsterAIState::Idle);
 
-    for point in check_points.iter() {
-        let check_pos = *point + new_position;
-        let map_pos = game_map.world_to_grid(check_pos);
+        world.run_system_once(update_monster_ai).unwrap();
+
+        assert_eq!(world.get::<MonsterAIState>(monster), Some(&MonsterAIState::Pursuing));
+        assert_eq!(world.get::<MonsterAlert>(monster).map(|alert| alert.symbol), Some(AlertSymbol::Exclamation));
+    }
 
-        // Check tile type and reject movement if it's a wall
-        if game_map.grid[map_pos].tile_type == crate::TileType::Wall {
-            return position;
+    #[test]
+    fn healer_next_to_a_wounded_orc_heals_it_instead_of_attacking() {
+        let mut world = monster_world("######\n#@..>#\n#....#\n######");
+        let (healer_position, orc_position) = {
+            let game_map = world.resource::<GameMap>();
+            (game_map.grid_to_world(2, 1), game_map.grid_to_world(2, 2))
+        };
+        let healer = spawn_monster(&mut world, MonsterType::Shaman, healer_position, MonsterAIState::Attacking);
+        world.entity_mut(healer).insert((HealCooldown::new(), Actor::new(10, 10, 0, 2, 0)));
+        let orc = spawn_monster(&mut world, MonsterType::Orc, orc_position, MonsterAIState::Idle);
+        world.entity_mut(orc).insert(Actor::new(20, 5, 0, 3, 0));
+        let heal_allies = world.register_system(heal_allies);
+        let monster_movement = world.register_system(monster_movement);
+
+        for _ in 0..5 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(HEAL_INTERVAL / 2.0));
+            world.run_system(heal_allies).unwrap();
+            world.run_system(monster_movement).unwrap();
         }
+
+        let heal_power = MonsterType::Shaman.behaviour().heal_power;
+        assert_eq!(world.get::<Actor>(orc).unwrap().hit_points, 5 + 2 * heal_power);
+        assert!(world.resource::<Events<AttackEvent>>().is_empty());
     }
 
-    new_position
-}
\ No newline at end of file
+    #[test]
+    fn confused_monster_next_to_the_player_does_not_attack() {
+        let mut world = monster_world("######\n#@..>#\n#....#\n######");
+        let orc_position = world.resource::<GameMap>().grid_to_world(2, 1);
+        let orc = spawn_monster(&mut world, MonsterType::Orc, orc_position, MonsterAIState::Attacking);
+        world.entity_mut(orc).insert(StatusEffect::confused());
+        let monster_movement = world.register_system(monster_movement);
+
+        for _ in 0..3 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs(1));
+            world.run_system(monster_movement).unwrap();
+        }
+        assert!(world.resource::<Events<AttackEvent>>().is_empty());
+
+        // clear headed again it attacks
+        world.entity_mut(orc).remove::<StatusEffect>();
+        world.get_mut::<Transform>(orc).unwrap().translation = orc_position;
+        world.resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        world.run_system(monster_movement).unwrap();
+        assert_eq!(world.resource::<Events<AttackEvent>>().len(), 1);
+    }
+
+    #[test]
+    fn monster_stuck_in_a_corner_gets_moving_again() {
+        let mut world = monster_world("########\n#..#...#\n#..#.@.#\n#..#..>#\n########");
+        // pressed into the north-east corner, the player beyond the wall can't be reached
+        let corner = world.resource::<GameMap>().grid_to_world(2, 1) + Vec3::new(1.45, 0.0, -1.45);
+        let monster = spawn_monster(&mut world, MonsterType::Orc, corner, MonsterAIState::Pursuing);
+        let monster_movement = world.register_system(monster_movement);
+
+        world.resource_mut::<Time>().advance_by(Duration::from_millis(100));
+        world.run_system(monster_movement).unwrap();
+        assert_eq!(world.get::<Transform>(monster).unwrap().translation, corner, "the straight way is blocked");
+
+        for _ in 0..20 {
+            world.resource_mut::<Time>().advance_by(Duration::from_millis(100));
+            world.run_system(monster_movement).unwrap();
+        }
+        assert!(world.get::<Transform>(monster).unwrap().translation.distance(corner) > 0.1);
+    }
+
+    #[test]
+    fn attacking_monster_strikes_once_per_cooldown() {
+        let mut world = World::new();
+        let game_map = GameMap::from_string("######\n#@..>#\n######").unwrap();
+        let player_position = game_map.grid_to_world(1, 1);
+        let monster_position = game_map.grid_to_world(2, 1);
+        world.insert_resource(game_map);
+        world.insert_resource(GameMode::RealTime);
+        world.insert_resource(GameRng::new(Some(1)));
+        world.init_resource::<Occupancy>();
+        world.init_resource::<Time>();
+        world.init_resource::<Events<AttackEvent>>();
+        world.init_resource::<Events<OpenDoorEvent>>();
+        world.spawn((Transform::from_translation(player_position), Player));
+        world.spawn((
+            Monster { monster_type: MonsterType::Orc },
+            Transform::from_translation(monster_position),
+            MonsterAIState::Attacking,
+            StuckDetection::new(monster_position),
+            PathTo::default(),
+            WanderTimer::new(),
+            MonsterAttackCooldown::new()
+        ));
+        let monster_movement = world.register_system(monster_movement);
+
+        // ten frames within the first second
+        for _ in 0..10 {
+            world.resource_mut::<Time>().advance_by(Duration::from_millis(50));
+            world.run_system(monster_movement).unwrap();
+        }
+        assert_eq!(world.resource::<Events<AttackEvent>>().len(), 1);
+
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(MONSTER_ATTACK_INTERVAL));
+        world.run_system(monster_movement).unwrap();
+        assert_eq!(world.resource::<Events<AttackEvent>>().len(), 2);
+    }
+
+    #[test]
+    fn a_star_follows_an_l_shaped_corridor() {
+        let game_map = GameMap::from_string("#######\n#@....#\n#####.#\n#####.#\n#####>#\n#######").unwrap();
+
+        let path = a_star(&game_map, (1, 1), (5, 4)).expect("a path along the corridor");
+
+        assert_eq!(path.len(), 7, "four cells east and three south");
+        assert_eq!(path.last(), Some(&(5, 4)));
+        assert!(path.iter().all(|cell| !game_map.grid[*cell].tile_type.is_wall()));
+        // every step goes to a neighbouring cell
+        let mut previous = (1, 1);
+        for cell in path {
+            assert_eq!(cell.0.abs_diff(previous.0) + cell.1.abs_diff(previous.1), 1);
+            previous = cell;
+        }
+        assert_eq!(a_star(&game_map, (1, 1), (3, 3)), None, "no path into a wall");
+    }
+
+    #[test]
+    fn idle_monster_wanders_to_a_neighbour_cell_until_it_sees_the_player() {
+        let mut world = World::new();
+        let game_map = GameMap::from_string("############\n#@.........#\n#..........#\n############").unwrap();
+        let monster_position = game_map.grid_to_world(9, 1);
+        let player = world.spawn((Transform::from_translation(game_map.grid_to_world(1, 1)), Player)).id();
+        world.insert_resource(game_map);
+        world.insert_resource(GameMode::RealTime);
+        world.insert_resource(GameRng::new(Some(1)));
+        world.init_resource::<Occupancy>();
+        world.init_resource::<Time>();
+        world.init_resource::<Events<AttackEvent>>();
+        world.init_resource::<Events<OpenDoorEvent>>();
+        let monster = world.spawn((
+            Monster { monster_type: MonsterType::Orc },
+            Transform::from_translation(monster_position),
+            MonsterAIState::Idle,
+            StuckDetection::new(monster_position),
+            PathTo::default(),
+            WanderTimer::new(),
+            MonsterAttackCooldown::new()
+        )).id();
+        let update_monster_ai = world.register_system(update_monster_ai);
+        let monster_movement = world.register_system(monster_movement);
+
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(WANDER_INTERVAL));
+        world.run_system(monster_movement).unwrap();
+        let target = world.get::<WanderTimer>(monster).unwrap().target.expect("a cell to wander to");
+        let target_cell = world.resource::<GameMap>().world_to_grid(target);
+        assert_eq!(target_cell.0.abs_diff(9) + target_cell.1.abs_diff(1), 1);
+        assert_eq!(world.resource::<GameMap>().grid[target_cell].tile_type, TileType::Floor);
+
+        // ambles on until the cell is reached, before the next wander interval is over
+        for _ in 0..11 {
+            world.resource_mut::<Time>().advance_by(Duration::from_millis(250));
+            world.run_system(monster_movement).unwrap();
+        }
+        let position = world.get::<Transform>(monster).unwrap().translation;
+        assert_eq!(world.resource::<GameMap>().world_to_grid(position), target_cell);
+
+        let in_view = world.resource::<GameMap>().grid_to_world(target_cell.0 - 2, target_cell.1);
+        world.get_mut::<Transform>(player).unwrap().translation = in_view;
+        world.run_system(update_monster_ai).unwrap();
+        assert_eq!(world.get::<MonsterAIState>(monster), Some(&MonsterAIState::Pursuing));
+    }
+
+    #[test]
+    fn one_key_press_moves_player_and_monster_one_tile() {
+        let mut world = World::new();
+        let game_map = GameMap::from_string("########\n#@.....#\n########").unwrap();
+        let player_position = game_map.grid_to_world(1, 1);
+        let monster_position = game_map.grid_to_world(4, 1);
+        world.insert_resource(game_map);
+        world.insert_resource(GameMode::TurnBased);
+        world.insert_resource(GameRng::new(Some(1)));
+        world.insert_resource(crate::CurrentFloor(0));
+        world.init_resource::<Occupancy>();
+        world.init_resource::<PendingTurns>();
+        world.init_resource::<Time>();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        world.init_resource::<NextState<crate::TransitionState>>();
+        world.init_resource::<crate::CardinalAim>();
+        world.init_resource::<crate::KeyBindings>();
+        world.init_resource::<Events<AttackEvent>>();
+        world.init_resource::<Events<OpenDoorEvent>>();
+        let player = world.spawn((Transform::from_translation(player_position), Player)).id();
+        world.spawn((
+            crate::ThirdPersonCamera::default(),
+            Transform::from_xyz(player_position.x, 10.0, player_position.z + 10.0).looking_at(player_position, Vec3::Y)
+        ));
+        let monster = world.spawn((
+            Monster { monster_type: MonsterType::Orc },
+            Transform::from_translation(monster_position),
+            MonsterAIState::Idle,
+            StuckDetection::new(monster_position),
+            PathTo::default(),
+            WanderTimer::new(),
+            MonsterAttackCooldown::new()
+        )).id();
+        let update_occupancy = world.register_system(crate::update_occupancy);
+        let move_player = world.register_system(crate::move_player);
+        let update_monster_ai = world.register_system(update_monster_ai);
+        let monster_movement = world.register_system(monster_movement);
+
+        world.run_system(update_occupancy).unwrap();
+        world.resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::ArrowRight);
+        world.run_system(move_player).unwrap();
+        assert_eq!(world.resource::<PendingTurns>().0, 1);
+        world.run_system(update_monster_ai).unwrap();
+        world.run_system(monster_movement).unwrap();
+
+        let moved = world.get::<Transform>(player).unwrap().translation - player_position;
+        assert_eq!(moved, Vec3::new(TILE_SIZE, 0.0, 0.0));
+        assert_eq!(world.get::<Transform>(monster).unwrap().translation, monster_position - Vec3::new(TILE_SIZE, 0.0, 0.0));
+        assert_eq!(world.get::<MonsterAIState>(monster), Some(&MonsterAIState::Pursuing));
+
+        // next to the player the monster strikes it on its turn
+        world.run_system(update_monster_ai).unwrap();
+        world.run_system(monster_movement).unwrap();
+        assert_eq!(world.get::<MonsterAIState>(monster), Some(&MonsterAIState::Attacking));
+        let attack_events = world.resource::<Events<AttackEvent>>();
+        let targets: Vec<Option<Entity>> = attack_events.get_cursor().read(attack_events).map(|event| event.target).collect();
+        assert_eq!(targets, vec![Some(player)]);
+    }
+}
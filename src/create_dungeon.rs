@@ -1,8 +1,12 @@
 use bevy::math::Vec3;
+use bevy::log::{debug, trace, warn};
 use petgraph::graph::{Graph, NodeIndex};
-use rand::distributions::WeightedIndex;
-use rand::distributions::Distribution;
 use rand::Rng;
+use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::{HashSet, VecDeque};
+use thiserror::Error;
 
 use crate::{GameMap, TileMapping, Tile, TileType, Grid, MonsterInMap, MonsterType, ItemInMap, ItemType, RenderHint, ItemAndMonsterParameterItem, Floor};
 
@@ -86,8 +90,8 @@ impl Iterator for BresenhamLine {
     }
 }
 
-#[derive(Clone)]
-struct Room{
+#[derive(Clone, Debug)]
+pub struct Room{
     id: String,
     x1: usize,
     y1: usize,
@@ -97,7 +101,7 @@ struct Room{
 }
 
 impl Room{
-    fn new(id: String, x: usize, y: usize, width: usize, height: usize) -> Self {
+    pub fn new(id: String, x: usize, y: usize, width: usize, height: usize) -> Self {
 
         let x1 = x;
         let y1 = y;
@@ -120,6 +124,12 @@ impl Room{
         (self.x1 + 1..self.x2, self.y1 + 1..self.y2)
     }
 
+    /// Cells inside the walls of the room
+    pub fn cells(&self) -> impl Iterator<Item = (usize, usize)> {
+        let (x_range, y_range) = self.inner();
+        x_range.flat_map(move |x| y_range.clone().map(move |y| (x, y)))
+    }
+
     fn width(&self) -> usize {
         self.x2.abs_diff(self.x1)
     }
@@ -138,7 +148,11 @@ impl Room{
         }
     }
 
-    fn create_tunnel(&self,grid: &mut Grid,other:&Room){
+    fn create_tunnel(&self,grid: &mut Grid,other:&Room, rng: &mut impl Rng){
+        self.carve_tunnel(grid, other, rng.gen());
+    }
+
+    fn carve_tunnel(&self, grid: &mut Grid, other: &Room, horizontal: bool){
         //create an L-shaped tunnel between these two rooms
         // Move vertically, then horizontally.
         let center1 = self.center;
@@ -146,7 +160,6 @@ impl Room{
         let mut corner_x = center1.0;
         let mut corner_y = center2.1;
 
-        let horizontal: bool = rand::thread_rng().gen();
         if horizontal {
             // Move horizontally, then vertically.
             corner_x = center2.0;
@@ -158,34 +171,54 @@ impl Room{
                                       corner_x as i32, corner_y as i32, true);
 
         for point in line {
-            grid[(point.0 as usize, point.1 as usize)].tile_type =  TileType::Floor;
+            Self::carve_corridor_cell(grid, (point.0 as usize, point.1 as usize));
         };
 
         let line = BresenhamLine::new(corner_x as i32, corner_y as i32,
                                       center2.0 as i32, center2.1 as i32, true);
 
         for point in line {
-            grid[(point.0 as usize, point.1 as usize)].tile_type =  TileType::Floor;
+            Self::carve_corridor_cell(grid, (point.0 as usize, point.1 as usize));
         };
 
     }
 
-    /*fn create_in_area(area: &Area) -> Room {
-        let mut rng = rand::thread_rng();
+    /// Floor of a tunnel, the part running through a room keeps the room floor
+    fn carve_corridor_cell(grid: &mut Grid, cell: (usize, usize)) {
+        grid[cell].tile_type = TileType::Floor;
+        if grid[cell].render_hint != RenderHint::RoomFloor {
+            grid[cell].render_hint = RenderHint::Corridor;
+        }
+    }
 
-        let width = rng.gen_range(ROOM_MIN_SIZE..=ROOM_MAX_SIZE.min(area.width - 2));
-        let height = rng.gen_range(ROOM_MIN_SIZE..=ROOM_MAX_SIZE.min(area.height - 2));
+    /// Random room inside a BSP area, the walls stay inside the area
+    fn create_in_area(id: String, area: &Area, room_min_size: usize, room_max_size: usize, rng: &mut impl Rng) -> Room {
+        let width = rng.gen_range(room_min_size.min(area.width - 2)..=room_max_size.min(area.width - 2));
+        let height = rng.gen_range(room_min_size.min(area.height - 2)..=room_max_size.min(area.height - 2));
 
-        let x_offset = rng.gen_range(0..=(area.width - width));
-        let y_offset = rng.gen_range(0..=(area.height - height));
+        let x_offset = rng.gen_range(0..(area.width - width));
+        let y_offset = rng.gen_range(0..(area.height - height));
 
         Room::new(
+            id,
             area.x + x_offset,
             area.y + y_offset,
             width,
             height
         )
-    }*/
+    }
+
+    /// Distance of the center to the nearest map edge
+    fn edge_distance(&self, width: usize, height: usize) -> usize {
+        let (x, y) = self.center;
+        x.min(y).min(width - 1 - x).min(height - 1 - y)
+    }
+
+    fn distance_squared(&self, other: &Room) -> usize {
+        let dx = self.center.0.abs_diff(other.center.0);
+        let dy = self.center.1.abs_diff(other.center.1);
+        dx * dx + dy * dy
+    }
 
     fn intersects(&self, other: &Room) -> bool{
         //Return True if this room overlaps with another RectangularRoom.
@@ -212,13 +245,13 @@ impl Area {
         graph: &mut Graph<(Area, Option<Room>), ()>,
         node: NodeIndex,
         min_width: usize,
-        min_height: usize
+        min_height: usize,
+        rng: &mut impl Rng
     ) {
         if self.width <= 20 || self.height <= 20 {
             return;
         }
 
-        let mut rng = rand::thread_rng();
         let split_vertical = rng.gen_bool(0.5);
 
         if split_vertical {
@@ -240,8 +273,8 @@ impl Area {
                 let right_node = graph.add_node((right.clone(), None));
                 graph.add_edge(node, left_node, ());
                 graph.add_edge(node, right_node, ());
-                left.split_area(graph, left_node, min_width, min_height);
-                right.split_area(graph, right_node, min_width, min_height);
+                left.split_area(graph, left_node, min_width, min_height, rng);
+                right.split_area(graph, right_node, min_width, min_height, rng);
             }
         } else {
             if self.height > min_height {
@@ -262,8 +295,8 @@ impl Area {
                 let bottom_node = graph.add_node((bottom.clone(), None));
                 graph.add_edge(node, top_node, ());
                 graph.add_edge(node, bottom_node, ());
-                top.split_area(graph, top_node, min_width, min_height);
-                bottom.split_area(graph, bottom_node, min_width, min_height);
+                top.split_area(graph, top_node, min_width, min_height, rng);
+                bottom.split_area(graph, bottom_node, min_width, min_height, rng);
             }
         }
     }
@@ -271,9 +304,9 @@ impl Area {
 
 fn get_random_leaf_room_bsp(
     graph: &Graph<(Area, Option<Room>), ()>,
-    node: NodeIndex
+    node: NodeIndex,
+    rng: &mut impl Rng
 ) -> Option<(NodeIndex, Room)> {
-    let mut rng = rand::thread_rng();
     let mut leaf_rooms = Vec::new();
 
     if graph.neighbors(node).count() == 0 {
@@ -282,7 +315,7 @@ fn get_random_leaf_room_bsp(
         }
     } else {
         for neighbor in graph.neighbors(node) {
-            if let Some((leaf_node, room)) = get_random_leaf_room_bsp(graph, neighbor) {
+            if let Some((leaf_node, room)) = get_random_leaf_room_bsp(graph, neighbor, rng) {
                 leaf_rooms.push((leaf_node, room));
             }
         }
@@ -308,10 +341,301 @@ fn get_first_room_bsp(graph: &Graph<(Area, Option<Room>), ()>, root: NodeIndex)
 
     None
 }
+
+/// Splits the map into areas with one room each, rooms of neighbouring
+/// areas are connected by tunnels. The first room is where the player starts.
+fn generate_bsp_rooms(
+    grid: &mut Grid,
+    width: usize,
+    height: usize,
+    room_min_size: usize,
+    room_max_size: usize,
+    id_prefix: &str,
+    rng: &mut impl Rng
+) -> Vec<Room> {
+    // Initialize a directed graph with nodes of type Area
+    let mut graph = Graph::<(Area, Option<Room>), ()>::new();
+
+    //start with the entire dungeon area // root node of the BSP tree
+    let root_area = Area {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    };
+
+    let root = graph.add_node((root_area.clone(), None));
+
+    // Start the recursive splitting process
+    root_area.split_area(&mut graph, root, 20, 20, rng);
+
+    // create a room within each leaf cell
+    let leaves: Vec<NodeIndex> = graph.node_indices()
+        .filter(|node| graph.neighbors(*node).count() == 0)
+        .collect();
+    for (i, node) in leaves.iter().enumerate() {
+        let room = Room::create_in_area(format!("{}_{}", id_prefix, i + 1), &graph[*node].0,
+                                        room_min_size, room_max_size, rng);
+        room.fill_grid(grid);
+        graph[*node].1 = Some(room);
+    }
+
+    //starting from the root, connect a room of each child with a room of its sibling
+    let mut nodes_to_process = vec![root];
+
+    while let Some(current_node) = nodes_to_process.pop() {
+        let children: Vec<_> = graph.neighbors(current_node).collect();
+
+        if children.len() == 2 {
+            if let (Some((_, room1)), Some((_, room2))) = (
+                get_random_leaf_room_bsp(&graph, children[0], rng),
+                get_random_leaf_room_bsp(&graph, children[1], rng)
+            ) {
+                room1.create_tunnel(grid, &room2, rng);
+            }
+            nodes_to_process.extend(children);
+        }
+    }
+
+    let mut rooms: Vec<Room> = leaves.iter()
+        .filter_map(|node| graph[*node].1.clone())
+        .collect();
+    if let Some(first_room) = get_first_room_bsp(&graph, root) {
+        if let Some(index) = rooms.iter().position(|room| room.id == first_room.id) {
+            rooms.swap(0, index);
+        }
+    }
+    rooms
+}
+
+/// Binary space partitioning: one room per area, without monsters and items
+pub struct MapGeneratorBsp {
+    pub width: usize,
+    pub height: usize,
+    pub room_min_size: usize,
+    pub room_max_size: usize,
+    pub seed: Option<u64>
+}
+
+impl MapGeneratorBsp {
+    /// Rooms carved into the grid, the first room of the BSP tree first
+    fn carve(&self, grid: &mut Grid, id_prefix: &str, rng: &mut impl Rng) -> Vec<Room> {
+        generate_bsp_rooms(grid, self.width, self.height,
+                           self.room_min_size, self.room_max_size, id_prefix, rng)
+    }
+}
+
+impl DungeonGeneratorStrategy for MapGeneratorBsp {
+    fn generate(&self) -> Result<GameMap, GenError> {
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy()
+        };
+
+        let mut grid = Grid::new(self.width, self.height, TileType::Wall);
+        let rooms = self.carve(&mut grid, "bsp", &mut rng);
+        let Some(first_room) = rooms.first() else {
+            return Err(GenError::NoRooms);
+        };
+
+        remove_walls(self.width, self.height, &mut grid);
+
+        let game_map = GameMap {
+            grid,
+            tile_mapping: TileMapping::new(),
+            player_position: first_room.center,
+            monsters: Vec::new(),
+            items: Vec::new(),
+            width: self.width,
+            height: self.height,
+            player_start_room: Some(first_room.clone())
+        };
+        warn_if_invalid(&game_map);
+        Ok(game_map)
+    }
+}
+
+/// Cells of a cave with their walking distance from the start
+type CaveCells = Vec<((usize, usize), usize)>;
+
+/// Cave of cellular automata: random rock smoothed into round shapes,
+/// only the largest open region is kept, without monsters and items
+pub struct MapGeneratorCaves {
+    pub width: usize,
+    pub height: usize,
+    /// share of the cells starting as rock
+    pub fill_ratio: f64,
+    pub smoothing_iterations: usize,
+    pub seed: Option<u64>
+}
+
+impl MapGeneratorCaves {
+    /// Rock neighbours of a cell, the border counts as rock
+    fn wall_neighbours(grid: &Grid, x: usize, y: usize) -> usize {
+        let mut count = 0;
+        for dy in -1i32..=1 {
+            for dx in -1i32..=1 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                if nx < 0 || ny < 0 || !grid.is_valid_position(nx as usize, ny as usize)
+                    || grid[(nx as usize, ny as usize)].tile_type == TileType::Wall {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    /// Open cells connected to the start with their walking distance
+    fn region(grid: &Grid, start: (usize, usize)) -> Vec<((usize, usize), usize)> {
+        let mut visited: HashSet<(usize, usize)> = HashSet::new();
+        let mut queue: VecDeque<((usize, usize), usize)> = VecDeque::new();
+        let mut region = Vec::new();
+        queue.push_back((start, 0));
+        visited.insert(start);
+
+        while let Some(((x, y), distance)) = queue.pop_front() {
+            region.push(((x, y), distance));
+            let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+            for next in neighbours {
+                if grid.is_valid_position(next.0, next.1)
+                    && grid[next].tile_type.is_open()
+                    && visited.insert(next) {
+                    queue.push_back((next, distance + 1));
+                }
+            }
+        }
+        region
+    }
+}
+
+impl MapGeneratorCaves {
+    /// Rock and the cave floor with the stairs down, before the walls are removed.
+    /// The cells of the cave come with their walking distance, the start first.
+    fn carve(&self, rng: &mut impl Rng) -> Result<(Grid, CaveCells), GenError> {
+        // the border stays rock
+        let mut grid = Grid::new(self.width, self.height, TileType::Wall);
+        for y in 1..self.height.saturating_sub(1) {
+            for x in 1..self.width.saturating_sub(1) {
+                if !rng.gen_bool(self.fill_ratio) {
+                    grid[(x, y)] = Tile::new(TileType::Floor);
+                }
+            }
+        }
+
+        for _ in 0..self.smoothing_iterations {
+            let mut smoothed = grid.clone();
+            for y in 1..self.height.saturating_sub(1) {
+                for x in 1..self.width.saturating_sub(1) {
+                    smoothed[(x, y)] = if Self::wall_neighbours(&grid, x, y) >= 5 {
+                        Tile::new(TileType::Wall)
+                    } else {
+                        Tile::new(TileType::Floor)
+                    };
+                }
+            }
+            grid = smoothed;
+        }
+
+        // the largest region becomes the cave, the others are filled with rock
+        let mut visited: HashSet<(usize, usize)> = HashSet::new();
+        let mut largest: Vec<((usize, usize), usize)> = Vec::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if grid[(x, y)].tile_type != TileType::Floor || visited.contains(&(x, y)) {
+                    continue;
+                }
+                let region = Self::region(&grid, (x, y));
+                visited.extend(region.iter().map(|(cell, _)| *cell));
+                if region.len() > largest.len() {
+                    largest = region;
+                }
+            }
+        }
+        if largest.is_empty() {
+            return Err(GenError::NoPlayerStart);
+        }
+        let cave: HashSet<(usize, usize)> = largest.iter().map(|(cell, _)| *cell).collect();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if grid[(x, y)].tile_type == TileType::Floor && !cave.contains(&(x, y)) {
+                    grid[(x, y)] = Tile::new(TileType::Wall);
+                }
+            }
+        }
+
+        // stairs at the far end of the cave
+        let Some((stairs, _)) = largest.iter().skip(1).max_by_key(|(_, distance)| *distance) else {
+            return Err(GenError::Unreachable);
+        };
+        grid[*stairs] = Tile::new(TileType::StaircaseDown);
+
+        Ok((grid, largest))
+    }
+}
+
+impl DungeonGeneratorStrategy for MapGeneratorCaves {
+    fn generate(&self) -> Result<GameMap, GenError> {
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy()
+        };
+
+        let (mut grid, cave) = self.carve(&mut rng)?;
+        let player_position = cave[0].0;
+        remove_walls(self.width, self.height, &mut grid);
+
+        let game_map = GameMap {
+            grid,
+            tile_mapping: TileMapping::new(),
+            player_position,
+            monsters: Vec::new(),
+            items: Vec::new(),
+            width: self.width,
+            height: self.height,
+            player_start_room: None
+        };
+        warn_if_invalid(&game_map);
+        Ok(game_map)
+    }
+}
+
+/// Why a generator could not build a map
+#[derive(Debug, Error, PartialEq)]
+pub enum GenError {
+    #[error("empty map")]
+    EmptyMap,
+    #[error("no rooms generated")]
+    NoRooms,
+    #[error("stairs can't be reached from the start")]
+    Unreachable,
+    /// pos is (row, col), counted from 0
+    #[error("unknown tile '{char}' at row {} col {}", .pos.0, .pos.1)]
+    ParseError { char: char, pos: (usize, usize) },
+    #[error("inconsistent width, line {line} has {width} tiles instead of {expected}")]
+    RaggedMap { line: usize, width: usize, expected: usize },
+    #[error("room {0} is outside the map")]
+    RoomOutsideMap(String),
+    #[error("no floor to place the player on")]
+    NoPlayerStart
+}
+
+/// Maps without stairs or read from a file are playable but may break invariants,
+/// the generated floors are checked with a debug assertion
+fn warn_if_invalid(game_map: &GameMap) {
+    if let Err(errors) = game_map.validate() {
+        warn!("Map breaks invariants: {}", errors.join(", "));
+    }
+}
+
 pub trait DungeonGeneratorStrategy {
-    fn generate(&self) -> Result<GameMap, String>;
+    fn generate(&self) -> Result<GameMap, GenError>;
 }
 
+/// Map of a text as written by DungeonWriter, the cells of the player,
+/// monsters and items become floor
 pub struct StringMapGenerator {
     pub map_string: String,
 }
@@ -325,37 +649,46 @@ impl StringMapGenerator {
 }
 
 impl DungeonGeneratorStrategy for StringMapGenerator {
-     fn generate(&self) -> Result<GameMap, String> {
+     fn generate(&self) -> Result<GameMap, GenError> {
         let tile_mapping = TileMapping::new();
         let lines: Vec<&str> = self.map_string.lines().collect();
 
         if lines.is_empty() {
-            return Err("Empty map".to_string());
+            return Err(GenError::EmptyMap);
         }
 
         let height = lines.len();
-        let width = lines[0].len();
-         println!("height: {} width: {}", height, width);
+        let width = lines[0].chars().count();
+        // all lines as wide as the first, a hand written map may be ragged
+        if let Some((line, line_width)) = lines.iter()
+            .map(|line| line.chars().count())
+            .enumerate()
+            .find(|(_, line_width)| *line_width != width) {
+            return Err(GenError::RaggedMap { line, width: line_width, expected: width });
+        }
+         debug!("height: {} width: {}", height, width);
 
         let mut grid = Grid::new(width,height,TileType::Wall);
-        let mut player_position: (usize, usize) = (0,0);
+        let mut player_position: Option<(usize, usize)> = None;
          let mut monsters:Vec<MonsterInMap>= Vec::new();
          let mut items:Vec<ItemInMap>= Vec::new();
 
         for (y, line) in lines.iter().enumerate() {
             for (x, ch) in line.chars().enumerate() {
 
-                println!("x: {} y: {}", x, y);
-                let tile_row = tile_mapping.get_tile_row(ch);
+                trace!("x: {} y: {}", x, y);
+                let tile_row = tile_mapping.get_tile_row(ch)
+                    .ok_or(GenError::ParseError { char: ch, pos: (y, x) })?;
                 let tile = Tile::new(tile_row.tile_type);
 
                 if tile_row.tile_type == TileType::Player {
-                    player_position = (x, y);
-                    grid[player_position] = Tile::new(TileType::Floor);
+                    player_position = Some((x, y));
+                    grid[(x, y)] = Tile::new(TileType::Floor);
                 } else if let Some(item_type) = tile_row.item_type {
                     items.push(ItemInMap {
                         item_type: item_type,
                         position: (x, y),
+                        gold: None
                     });
                     grid[(x, y)] = Tile::new(TileType::Floor);
                 } else if let Some(monster_type) = tile_row.monster_type {
@@ -370,252 +703,133 @@ impl DungeonGeneratorStrategy for StringMapGenerator {
             }
         }
 
-        let x_center = width / 2;
-        let y_center = height / 2;
-
-        let center = (x_center, y_center);
+        // maps without '@' start on the first free floor cell
+        let player_position = match player_position {
+            Some(position) => position,
+            None => {
+                let position = (0..height)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .find(|cell| grid[*cell].tile_type == TileType::Floor
+                        && !monsters.iter().any(|monster| monster.position == *cell))
+                    .ok_or(GenError::NoPlayerStart)?;
+                warn!("Map without player marker, the player starts at {:?}", position);
+                position
+            }
+        };
 
-        Ok(GameMap {
+        let game_map = GameMap {
             grid,
             tile_mapping,
             player_position,
             monsters,
             items,
-            center,
             width,
-            height
-        })
+            height,
+            player_start_room: None
+        };
+        warn_if_invalid(&game_map);
+        Ok(game_map)
     }
 }
 
-
-
-pub struct MapGeneratorFirst {
-    width: usize,
-    height: usize
+/// Map of the given rooms, each connected to the one before by a tunnel
+pub struct RoomListGenerator {
+    pub width: usize,
+    pub height: usize,
+    pub rooms: Vec<Room>
 }
 
-impl MapGeneratorFirst {
-    pub fn new(width:usize, height:usize) -> Self {
-        MapGeneratorFirst{
-            width,
-            height
+impl RoomListGenerator {
+    /// Floors of the rooms and their tunnels, the walls of a room must be inside the map
+    fn carve(&self, grid: &mut Grid) -> Result<(), GenError> {
+        if let Some(room) = self.rooms.iter().find(|room| room.x2 >= self.width || room.y2 >= self.height) {
+            return Err(GenError::RoomOutsideMap(room.id.clone()));
         }
-    }
-}
-
-impl DungeonGeneratorStrategy for MapGeneratorFirst {
-    fn generate(&self) -> Result<GameMap, String> {
-        let tile_mapping = TileMapping::new();
-
-        let grid = Grid::new(self.width,self.height,TileType::Floor);
-
-        let x_center = self.width / 2;
-        let y_center = self.height / 2;
-
-        let center = (x_center, y_center);
-
-        let player_position: (usize, usize) = (x_center,y_center);
-
-        Ok(GameMap {
-            grid,
-            tile_mapping,
-            player_position,
-            monsters: Vec::new(),
-            items: Vec::new(),
-            center,
-            width: self.width,
-            height: self.height
-        })
-    }
-}
 
-
-pub struct MapGeneratorSecond {
-    width: usize,
-    height: usize
-}
-
-impl MapGeneratorSecond {
-    pub fn new(width:usize, height:usize) -> Self {
-        MapGeneratorSecond {
-            width,
-            height
+        for (i, room) in self.rooms.iter().enumerate() {
+            room.fill_grid(grid);
+            if i > 0 {
+                room.carve_tunnel(grid, &self.rooms[i - 1], true);
+            }
         }
+        Ok(())
     }
 }
 
-impl DungeonGeneratorStrategy for MapGeneratorSecond {
-    fn generate(&self) -> Result<GameMap, String> {
-        let tile_mapping = TileMapping::new();
-
-        let mut grid = Grid::new(self.width,self.height,TileType::Wall);
-
-        let x_center = self.width / 2;
-        let y_center = self.width / 2;
-
-        let center = (x_center, y_center);
-
-        let room_1 = Room::new("1".to_string(), 20, 15, 10, 15);
-        let room_2 = Room::new("2".to_string(), 35, 15, 10, 15);
-
-        room_1.fill_grid(&mut grid);
-        room_2.fill_grid(&mut grid);
+impl DungeonGeneratorStrategy for RoomListGenerator {
+    fn generate(&self) -> Result<GameMap, GenError> {
+        let Some(start_room) = self.rooms.first() else {
+            return Err(GenError::NoRooms);
+        };
 
-        let player_position: (usize, usize) = room_1.center.clone();
+        let mut grid = Grid::new(self.width, self.height, TileType::Wall);
+        self.carve(&mut grid)?;
+        remove_walls(self.width, self.height, &mut grid);
 
-        Ok(GameMap {
+        let game_map = GameMap {
             grid,
-            tile_mapping,
-            player_position,
+            tile_mapping: TileMapping::new(),
+            player_position: start_room.center,
             monsters: Vec::new(),
             items: Vec::new(),
-
-            center,
             width: self.width,
-            height: self.height
-        })
+            height: self.height,
+            player_start_room: Some(start_room.clone())
+        };
+        warn_if_invalid(&game_map);
+        Ok(game_map)
     }
 }
 
-
-pub struct MapGeneratorThird {
-    width: usize,
-    height: usize
+/// Room layouts the parameterized generator can produce
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GeneratorKind {
+    /// random rooms connected by L-shaped tunnels
+    #[default]
+    Rooms,
+    /// a single open hall covering the whole map
+    Open,
+    /// two rooms side by side connected by a tunnel, sized by the map
+    TwoRooms,
+    /// binary space partitioning, one room per area
+    Bsp,
+    /// cellular automata cave without rooms
+    Cave
 }
 
-impl MapGeneratorThird {
-    pub fn new(width:usize, height:usize) -> Self {
-        MapGeneratorThird {
-            width,
-            height
+impl GeneratorKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rooms" => Some(GeneratorKind::Rooms),
+            "open" => Some(GeneratorKind::Open),
+            "two-rooms" => Some(GeneratorKind::TwoRooms),
+            "bsp" => Some(GeneratorKind::Bsp),
+            "cave" => Some(GeneratorKind::Cave),
+            _ => None
         }
     }
 }
 
-impl DungeonGeneratorStrategy for MapGeneratorThird {
-    fn generate(&self) -> Result<GameMap, String> {
-        let tile_mapping = TileMapping::new();
-
-        let mut grid = Grid::new(self.width,self.height,TileType::Wall);
-
-        let x_center = self.width / 2;
-        let y_center = self.height / 2;
-
-        let center = (x_center, y_center);
-
-        let room_1 = Room::new("1".to_string(), 20, 15, 10, 15);
-        let room_2 = Room::new("2".to_string(), 35, 15, 10, 15);
-
-        room_1.fill_grid(&mut grid);
-        room_2.fill_grid(&mut grid);
-
-        room_1.create_tunnel(&mut grid, &room_2);
-
-        let player_position: (usize, usize) = room_1.center.clone();
-
-        Ok(GameMap {
-            grid,
-            tile_mapping,
-            player_position,
-            monsters: Vec::new(),
-            items: Vec::new(),
-            center,
-            width: self.width,
-            height: self.height
-        })
-    }
-}
-
-pub struct MapGeneratorStart1 {
-    width: usize,
-    height: usize,
-    max_rooms: usize,
-    room_min_size: usize,
-    room_max_size: usize
+/// How the monsters of a room are placed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MonsterPlacement {
+    /// anywhere on the floor of the room
+    #[default]
+    Scatter,
+    /// large rooms get a group around a leader or guards in front of an exit
+    Formation
 }
 
-impl MapGeneratorStart1 {
-    pub fn new(width:usize, height:usize,
-               max_rooms: usize,
-               room_min_size: usize,
-               room_max_size: usize) -> Self {
-        MapGeneratorStart1 {
-            width,
-            height,
-            max_rooms,
-            room_min_size,
-            room_max_size
+impl MonsterPlacement {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "scatter" => Some(MonsterPlacement::Scatter),
+            "formation" => Some(MonsterPlacement::Formation),
+            _ => None
         }
     }
 }
 
-impl DungeonGeneratorStrategy for MapGeneratorStart1 {
-    fn generate(&self) -> Result<GameMap, String> {
-        let tile_mapping = TileMapping::new();
-
-        let mut grid = Grid::new(self.width,self.height,TileType::Wall);
-
-        let x_center = self.width / 2;
-        let y_center = self.height / 2;
-
-        let center = (x_center, y_center);
-
-        let mut player_position: (usize, usize) = (0, 0);
-
-        let mut rooms: Vec<Room> = Vec::new();
-
-        let mut rng = rand::thread_rng();
-
-        for _ in 0..self.max_rooms {
-            let room_width = rng.gen_range(self.room_min_size..=self.room_max_size);
-            let room_height = rng.gen_range(self.room_min_size..=self.room_max_size);
-
-            let x = rng.gen_range(0..(self.width - room_width - 1));
-            let y = rng.gen_range(0..(self.height - room_height - 1));
-
-            let new_room = Room::new(rooms.len().to_string(),  x, y, room_width, room_height);
-
-            // Run through the other rooms and see if they intersect with this one.
-            let mut intersection_found = false;
-            for room in rooms.iter() {
-                if room.intersects(&new_room) {
-                    intersection_found = true;
-                }
-            }
-
-            if !intersection_found {
-                new_room.fill_grid(&mut grid);
-
-                if rooms.len() == 0 {
-                    // The first room, where the player starts.
-                    player_position = new_room.center.clone();
-                } else {
-                    // Dig out a tunnel between this room and the previous one.
-                    new_room.create_tunnel(&mut grid, rooms.last().unwrap());
-                }
-
-                rooms.push(new_room);
-            }
-        }
-
-        //remove walls
-        remove_walls(self.width, self.height, &mut grid);
-
-        Ok(GameMap {
-            grid,
-            tile_mapping,
-            player_position,
-            monsters: Vec::new(),
-            items: Vec::new(),
-            center,
-            width: self.width,
-            height: self.height
-        })
-    }
-}
-
-
 pub struct MapGeneratorStart {
     width: usize,
     height: usize,
@@ -626,7 +840,11 @@ pub struct MapGeneratorStart {
     max_monsters_per_room: usize,
     max_items_per_room: usize,
     item_and_monster_params: ItemAndMonsterParameterItem,
-    player_start_position: Option<(usize, usize)>
+    player_start_position: Option<(usize, usize)>,
+    kind: GeneratorKind,
+    monster_placement: MonsterPlacement,
+    /// Same seed and floor give the same map, None for a random one
+    seed: Option<u64>
 }
 
 impl MapGeneratorStart {
@@ -638,7 +856,8 @@ impl MapGeneratorStart {
                max_monsters_per_room: usize,
                max_items_per_room: usize,
                item_and_monster_params: ItemAndMonsterParameterItem,
-               player_start_position: Option<(usize, usize)>) -> Self {
+               player_start_position: Option<(usize, usize)>,
+               seed: Option<u64>) -> Self {
         MapGeneratorStart {
             width,
             height,
@@ -649,19 +868,116 @@ impl MapGeneratorStart {
             max_monsters_per_room,
             max_items_per_room,
             item_and_monster_params,
-            player_start_position
+            player_start_position,
+            kind: GeneratorKind::default(),
+            monster_placement: MonsterPlacement::default(),
+            seed
         }
     }
 
+    pub fn with_kind(mut self, kind: GeneratorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_monster_placement(mut self, monster_placement: MonsterPlacement) -> Self {
+        self.monster_placement = monster_placement;
+        self
+    }
+
+    fn generate_open(&self, grid: &mut Grid) -> Vec<Room> {
+        let room = Room::new(format!("{}_1", self.floor), 0, 0, self.width - 1, self.height - 1);
+        room.fill_grid(grid);
+        vec![room]
+    }
+
+    fn generate_two_rooms(&self, grid: &mut Grid) -> Result<Vec<Room>, GenError> {
+        // each room in the middle of one half of the map
+        let half = self.width / 2;
+        let room_width = (half / 4).max(2);
+        let room_height = (self.height / 3).max(2);
+        let x = half.saturating_sub(room_width) / 2;
+        let y = self.height.saturating_sub(room_height) / 2;
+
+        let generator = RoomListGenerator {
+            width: self.width,
+            height: self.height,
+            rooms: vec![
+                Room::new(format!("{}_1", self.floor), x, y, room_width, room_height),
+                Room::new(format!("{}_2", self.floor), half + x, y, room_width, room_height)
+            ]
+        };
+        generator.carve(grid)?;
+        Ok(generator.rooms)
+    }
+
+    /// A cave populated area by area, the start is its first open cell
+    fn generate_cave(&self, rng: &mut impl Rng) -> Result<GameMap, GenError> {
+        let caves = MapGeneratorCaves {
+            width: self.width,
+            height: self.height,
+            fill_ratio: CAVE_FILL_RATIO,
+            smoothing_iterations: CAVE_SMOOTHING_ITERATIONS,
+            seed: self.seed
+        };
+        let (mut grid, cave) = caves.carve(rng)?;
+        let player_position = cave[0].0;
+
+        // stairs up and the first heal potion on the nearest free cells
+        let mut near_start = cave.iter()
+            .map(|(cell, _)| *cell)
+            .filter(|cell| *cell != player_position && grid[*cell].tile_type == TileType::Floor)
+            .take(2)
+            .collect::<Vec<_>>()
+            .into_iter();
+        if self.floor > 0 {
+            if let Some(cell) = near_start.next() {
+                grid[cell].tile_type = TileType::StaircaseUp;
+            }
+        }
+
+        let sectors = cave_sectors(&grid, player_position);
+        let monsters = add_monsters(&grid, &sectors,
+                                    self.max_monsters_per_room,
+                                    &self.item_and_monster_params.monsters,
+                                    &self.item_and_monster_params.allowed_monsters,
+                                    self.monster_placement,
+                                    rng);
+        let mut items = add_items(&grid, &sectors,
+                                  self.max_items_per_room,
+                                  &self.item_and_monster_params.items,
+                                  rng);
+        if self.floor == 0 && !items.iter().any(|item| item.item_type == ItemType::HealPotion) {
+            if let Some(position) = near_start.next() {
+                items.push(ItemInMap { item_type: ItemType::HealPotion, position, gold: None });
+            }
+        }
+
+        remove_walls(self.width, self.height, &mut grid);
+        add_mineral_veins(&mut grid, rng);
+
+        let game_map = GameMap {
+            grid,
+            tile_mapping: TileMapping::new(),
+            player_position,
+            monsters,
+            items,
+            width: self.width,
+            height: self.height,
+            player_start_room: None
+        };
+        debug_assert_eq!(game_map.validate(), Ok(()));
+        Ok(game_map)
+    }
+
     pub fn generate_floor(&self,
                           mut grid: &mut Grid,
                           floor: usize,
-                          start_position: Option<(usize,usize)>) -> Vec<Room> {
+                          start_position: Option<(usize,usize)>,
+                          rng: &mut impl Rng) -> Vec<Room> {
 
         let mut rooms: Vec<Room> = Vec::new();
 
-        let mut rng = rand::thread_rng();
-
         for _ in 0..self.max_rooms {
             let mut room_width = rng.gen_range(self.room_min_size..=self.room_max_size);
             let mut room_height = rng.gen_range(self.room_min_size..=self.room_max_size);
@@ -672,8 +988,8 @@ impl MapGeneratorStart {
             //place in first room in start position
             if let Some((start_x, start_y)) = start_position {
                 if rooms.len() == 0 {
-                    x = ((start_y - room_width / 2) as i32).max(0) as usize;
-                    y = ((start_y - room_height / 2) as i32).max(0) as usize;
+                    x = start_x.saturating_sub(room_width / 2);
+                    y = start_y.saturating_sub(room_height / 2);
                     if x + room_width > self.width - 1 {
                         room_width = self.width - x - 1;
                     }
@@ -698,7 +1014,7 @@ impl MapGeneratorStart {
 
                 if rooms.len() == 0 {} else {
                     // Dig out a tunnel between this room and the previous one.
-                    new_room.create_tunnel(&mut grid, rooms.last().unwrap());
+                    new_room.create_tunnel(&mut grid, rooms.last().unwrap(), rng);
                 }
 
                 rooms.push(new_room);
@@ -709,6 +1025,14 @@ impl MapGeneratorStart {
 }
 
 impl MapGeneratorStart {
+    /// Generator of a floor, the same seed and floor give the same map
+    fn floor_rng(&self, floor: usize) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(floor as u64)),
+            None => StdRng::from_entropy()
+        }
+    }
+
     fn generate_floors(&self, floor_count:usize)->Vec<Vec<Room>>{
 
         let mut floors:Vec<Vec<Room>> = vec![];
@@ -716,17 +1040,13 @@ impl MapGeneratorStart {
         for i in 0..self.floor {
             let mut grid = Grid::new(self.width, self.height, TileType::Wall);
 
-            let x_center = self.width / 2;
-            let y_center = self.height / 2;
-
-            let center = (x_center, y_center);
-
             let mut player_position: (usize, usize) = (0, 0);
 
             let rooms = self.generate_floor(
                 &mut grid,
                 i,
-                self.player_start_position.clone()
+                self.player_start_position.clone(),
+                &mut self.floor_rng(i)
             );
             floors.push(rooms)
         }
@@ -735,127 +1055,392 @@ impl MapGeneratorStart {
 }
 
 impl DungeonGeneratorStrategy for MapGeneratorStart {
-    fn generate(&self) -> Result<GameMap, String> {
+    fn generate(&self) -> Result<GameMap, GenError> {
         let tile_mapping = TileMapping::new();
 
         let mut grid = Grid::new(self.width,self.height,TileType::Wall);
 
-        let x_center = self.width / 2;
-        let y_center = self.height / 2;
+        let mut player_position: (usize, usize) = (0, 0);
 
-        let center = (x_center, y_center);
+        let mut rng = self.floor_rng(self.floor);
 
-        let mut player_position: (usize, usize) = (0, 0);
+        let rooms = match self.kind {
+            GeneratorKind::Rooms => self.generate_floor(
+                &mut grid,
+                self.floor,
+                self.player_start_position.clone(),
+                &mut rng
+            ),
+            GeneratorKind::Open => self.generate_open(&mut grid),
+            GeneratorKind::TwoRooms => self.generate_two_rooms(&mut grid)?,
+            GeneratorKind::Bsp => MapGeneratorBsp {
+                width: self.width,
+                height: self.height,
+                room_min_size: self.room_min_size,
+                room_max_size: self.room_max_size,
+                seed: self.seed
+            }.carve(&mut grid, &self.floor.to_string(), &mut rng),
+            GeneratorKind::Cave => return self.generate_cave(&mut rng)
+        };
 
-        let rooms = self.generate_floor(
-            &mut grid,
-            self.floor,
-            self.player_start_position.clone()
-        );
+        if rooms.is_empty() {
+            return Err(GenError::NoRooms);
+        }
 
-        // The first room, where the player starts
-        player_position = if let Some((player_x, player_y)) = self.player_start_position {
-            (player_x, player_y)
+        // The room where the player starts: the one built around a given start
+        // position, otherwise the one nearest to the map edge
+        let start_room = if self.player_start_position.is_some() {
+            rooms[0].clone()
         } else {
-            rooms[0].center.clone()
+            rooms.iter()
+                .min_by_key(|room| room.edge_distance(self.width, self.height))
+                .unwrap()
+                .clone()
         };
 
-        //add stairs to next floor
-        let stairs_position = rooms.last().unwrap().center.clone();
+        player_position = match self.player_start_position {
+            Some(position) if grid.is_valid_position(position.0, position.1)
+                && grid[position].tile_type == TileType::Floor => position,
+            _ => start_room.center
+        };
+
+        //add stairs to next floor in the room farthest away from the start
+        let stairs_room = rooms.iter()
+            .max_by_key(|room| room.distance_squared(&start_room))
+            .unwrap();
+        let mut stairs_position = stairs_room.center;
+        if stairs_position == player_position {
+            stairs_position = (stairs_room.x2 - 1, stairs_room.y2 - 1);
+        }
         grid[stairs_position].tile_type = TileType::StaircaseDown;
+        if !is_reachable(&grid, player_position, stairs_position) {
+            return Err(GenError::Unreachable);
+        }
+
+        //add stairs back up in the start room, the first floor has none
+        if self.floor > 0 {
+            let stairs_up_position = free_position_in_room(&grid, &start_room, player_position, &mut rng);
+            if stairs_up_position != player_position && grid[stairs_up_position].tile_type == TileType::Floor {
+                grid[stairs_up_position].tile_type = TileType::StaircaseUp;
+            }
+        }
+
+        //doors where tunnels enter the rooms
+        add_doors(&mut grid, &rooms, player_position);
 
         //add monsters
         let monsters = add_monsters(&grid, &rooms,
                                     self.max_monsters_per_room,
-                                    &self.item_and_monster_params.monsters);
+                                    &self.item_and_monster_params.monsters,
+                                    &self.item_and_monster_params.allowed_monsters,
+                                    self.monster_placement,
+                                    &mut rng);
 
         //add items
-        let items = add_items(&grid, &rooms,
+        let mut items = add_items(&grid, &rooms,
                                 self.max_items_per_room,
-                                &self.item_and_monster_params.items);
+                                &self.item_and_monster_params.items,
+                                &mut rng);
+
+        //no unlucky start without healing
+        if self.floor == 0 && !items.iter().any(|item| item.item_type == ItemType::HealPotion) {
+            items.push(ItemInMap {
+                item_type: ItemType::HealPotion,
+                position: free_position_in_room(&grid, &start_room, player_position, &mut rng),
+                gold: None
+            });
+        }
 
 
         //remove walls
         remove_walls(self.width, self.height, &mut grid);
 
-        Ok(GameMap {
+        //some walls can be mined
+        add_mineral_veins(&mut grid, &mut rng);
+
+        let game_map = GameMap {
             grid,
             tile_mapping,
             player_position,
             monsters,
             items,
-            center,
             width: self.width,
-            height: self.height
-        })
+            height: self.height,
+            player_start_room: Some(start_room)
+        };
+        debug_assert_eq!(game_map.validate(), Ok(()));
+        Ok(game_map)
+    }
+}
+
+/// Share of rock in a new cave of the cave floors
+const CAVE_FILL_RATIO: f64 = 0.45;
+const CAVE_SMOOTHING_ITERATIONS: usize = 4;
+/// Cells of a side of the areas a cave is populated by
+const CAVE_SECTOR_SIZE: usize = 10;
+/// Floor cells an area needs to get monsters and items
+const CAVE_SECTOR_MIN_FLOOR: usize = 8;
+
+/// Areas of a cave that stand in for rooms when monsters and items are placed,
+/// the area of the start stays empty
+fn cave_sectors(grid: &Grid, start: (usize, usize)) -> Vec<Room> {
+    let mut sectors = Vec::new();
+    for y in (0..grid.height().saturating_sub(2)).step_by(CAVE_SECTOR_SIZE) {
+        for x in (0..grid.width().saturating_sub(2)).step_by(CAVE_SECTOR_SIZE) {
+            let width = CAVE_SECTOR_SIZE.min(grid.width() - 1 - x);
+            let height = CAVE_SECTOR_SIZE.min(grid.height() - 1 - y);
+            let sector = Room::new(format!("cave_{}_{}", x, y), x, y, width, height);
+
+            let (x_range, y_range) = sector.inner();
+            if x_range.contains(&start.0) && y_range.contains(&start.1) {
+                continue;
+            }
+            let floor_cells = x_range
+                .flat_map(|x| y_range.clone().map(move |y| (x, y)))
+                .filter(|cell| grid[*cell].tile_type == TileType::Floor)
+                .count();
+            if floor_cells >= CAVE_SECTOR_MIN_FLOOR {
+                sectors.push(sector);
+            }
+        }
+    }
+    sectors
+}
+
+/// Entry chosen with a chance proportional to its weight (cumulative weights),
+/// none without a positive weight
+fn weighted_pick<T: Clone>(items: &[(T, f32)], rng: &mut impl Rng) -> Option<T> {
+    let total: f32 = items.iter().map(|(_, weight)| weight.max(0.0)).sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut roll = rng.gen_range(0.0..total);
+    for (item, weight) in items {
+        let weight = weight.max(0.0);
+        if roll < weight {
+            return Some(item.clone());
+        }
+        roll -= weight;
     }
+    // rounding can leave the roll just past the last weight
+    items.iter().rev().find(|(_, weight)| *weight > 0.0).map(|(item, _)| item.clone())
 }
 
 fn add_items(grid: &Grid,
              rooms: &Vec<Room>,
              items_per_room:usize,
-             items_and_weights:&Vec<(ItemType,f32)>) -> Vec<ItemInMap> {
+             items_and_weights:&Vec<(ItemType,f32)>,
+             rng: &mut impl Rng) -> Vec<ItemInMap> {
     let mut items:Vec<ItemInMap> = Vec::new();
 
-    let mut rng = rand::thread_rng();
-
-    let (item_types, weights): (Vec<_>, Vec<_>) = items_and_weights.to_vec().into_iter().unzip();
-    let dist = WeightedIndex::new(&weights).unwrap();
+    if !items_and_weights.iter().any(|(_, weight)| *weight > 0.0) {
+        return items;
+    }
 
     //For each room 0 and a maximum items
     for room in rooms {
         let items_per_room = rng.gen_range(0..=items_per_room);
         for _ in 0..items_per_room {
-            loop{
+            let position = loop{
                 let position = (rng.gen_range(room.x1+1..room.x2),
                                 rng.gen_range(room.y1+1..room.y2));
                 if grid[position].tile_type == TileType::Floor {
-                    break;
+                    break position;
                 }
             };
-            let position = (rng.gen_range(room.x1+1..room.x2),
-                            rng.gen_range(room.y1+1..room.y2));
 
-            let item_type = item_types[dist.sample(&mut rng)].clone();
+            let Some(item_type) = weighted_pick(items_and_weights, rng) else { continue };
 
             items.push(ItemInMap{
                 item_type,
-                position
+                position,
+                gold: None
             })
         }
     }
     items
 }
 
+/// Walks over floor and stairs from one cell to the other
+pub fn is_reachable(grid: &Grid, from: (usize, usize), to: (usize, usize)) -> bool {
+    let mut visited: HashSet<(usize, usize)> = HashSet::new();
+    let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+    queue.push_back(from);
+    visited.insert(from);
+
+    while let Some((x, y)) = queue.pop_front() {
+        if (x, y) == to {
+            return true;
+        }
+        let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+        for next in neighbours {
+            if grid.is_valid_position(next.0, next.1)
+                && grid[next].tile_type.is_open()
+                && visited.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    false
+}
+
+/// Random floor cell inside the room other than the excluded one
+fn free_position_in_room(grid: &Grid, room: &Room, excluded: (usize, usize), rng: &mut impl Rng) -> (usize, usize) {
+    let (x_range, y_range) = room.inner();
+    let cells: Vec<(usize, usize)> = x_range
+        .flat_map(|x| y_range.clone().map(move |y| (x, y)))
+        .filter(|cell| *cell != excluded && grid[*cell].tile_type == TileType::Floor)
+        .collect();
+
+    if cells.is_empty() {
+        room.center
+    } else {
+        cells[rng.gen_range(0..cells.len())]
+    }
+}
+
+/// Inner cells of a room, below this formations don't fit
+const FORMATION_MIN_ROOM_CELLS: usize = 49;
+/// Followers stay within this many cells of their leader
+const FORMATION_SPREAD: usize = 2;
+
+/// Free floor cells of the room in front of an exit, the nearest row first
+fn guard_line(grid: &Grid, room: &Room, taken: &[(usize, usize)], count: usize, rng: &mut impl Rng) -> Vec<(usize, usize)> {
+    let (x_range, y_range) = room.inner();
+
+    // openings in the room border lead to tunnels
+    let mut exits: Vec<((usize, usize), (i32, i32))> = Vec::new();
+    for x in x_range.clone() {
+        exits.push(((x, room.y1), (0, 1)));
+        exits.push(((x, room.y2), (0, -1)));
+    }
+    for y in y_range.clone() {
+        exits.push(((room.x1, y), (1, 0)));
+        exits.push(((room.x2, y), (-1, 0)));
+    }
+    exits.retain(|(cell, _)| grid[*cell].tile_type == TileType::Floor);
+    if exits.is_empty() {
+        return Vec::new();
+    }
+    let (exit, direction) = exits[rng.gen_range(0..exits.len())];
+
+    // guards stand side by side two cells inside the room
+    let center = (exit.0 as i32 + direction.0 * 2, exit.1 as i32 + direction.1 * 2);
+    let mut line = Vec::new();
+    for offset in 0..(x_range.len().max(y_range.len()) as i32) {
+        for side in [offset, -offset] {
+            let cell = (center.0 + direction.1 * side, center.1 + direction.0 * side);
+            if cell.0 < 0 || cell.1 < 0 {
+                continue;
+            }
+            let cell = (cell.0 as usize, cell.1 as usize);
+            if x_range.contains(&cell.0) && y_range.contains(&cell.1)
+                && grid[cell].tile_type == TileType::Floor
+                && !taken.contains(&cell) && !line.contains(&cell) {
+                line.push(cell);
+            }
+        }
+        if line.len() >= count {
+            break;
+        }
+    }
+    line.truncate(count);
+    line
+}
+
+/// A leader on a free floor cell and followers on free cells around it
+fn leader_cluster(grid: &Grid, room: &Room, taken: &[(usize, usize)], count: usize, rng: &mut impl Rng) -> Vec<(usize, usize)> {
+    let (x_range, y_range) = room.inner();
+    let free = |cell: &(usize, usize)| grid[*cell].tile_type == TileType::Floor && !taken.contains(cell);
+
+    let candidates: Vec<(usize, usize)> = x_range.clone()
+        .flat_map(|x| y_range.clone().map(move |y| (x, y)))
+        .filter(free)
+        .collect();
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+    let leader = candidates[rng.gen_range(0..candidates.len())];
+
+    let mut followers: Vec<(usize, usize)> = candidates.into_iter()
+        .filter(|cell| *cell != leader
+            && cell.0.abs_diff(leader.0) <= FORMATION_SPREAD
+            && cell.1.abs_diff(leader.1) <= FORMATION_SPREAD)
+        .collect();
+    followers.shuffle(rng);
+    followers.truncate(count.saturating_sub(1));
+
+    let mut cluster = vec![leader];
+    cluster.extend(followers);
+    cluster
+}
+
 fn add_monsters(grid: &Grid,
                 rooms: &Vec<Room>,
                 max_monsters_per_room:usize,
-                monsters_and_weights: &Vec<(MonsterType,f32)>
+                monsters_and_weights: &Vec<(MonsterType,f32)>,
+                allowed_monsters: &[MonsterType],
+                monster_placement: MonsterPlacement,
+                rng: &mut impl Rng
 ) -> Vec<MonsterInMap> {
     let mut monsters:Vec<MonsterInMap> = Vec::new();
 
-    let mut rng = rand::thread_rng();
+    for (monster_type, _) in monsters_and_weights.iter() {
+        if !allowed_monsters.contains(monster_type) {
+            warn!("Monster {:?} has a weight but is not allowed on this floor", monster_type);
+        }
+    }
 
-    let (monster_types, weights): (Vec<_>, Vec<_>) = monsters_and_weights.to_vec().into_iter().unzip();
-    let dist = WeightedIndex::new(&weights).unwrap();
+    let allowed_weights: Vec<(MonsterType, f32)> = monsters_and_weights.iter()
+        .filter(|(monster_type, _)| allowed_monsters.contains(monster_type))
+        .cloned()
+        .collect();
+    if !allowed_weights.iter().any(|(_, weight)| *weight > 0.0) {
+        return monsters;
+    }
 
     //For each room 0 and a maximum monsters
     for room in rooms {
         let monsters_per_room = rng.gen_range(0..=max_monsters_per_room);
+
+        let (x_range, y_range) = room.inner();
+        if monster_placement == MonsterPlacement::Formation
+            && monsters_per_room > 1
+            && x_range.len() * y_range.len() >= FORMATION_MIN_ROOM_CELLS {
+            let taken: Vec<(usize, usize)> = monsters.iter().map(|monster| monster.position).collect();
+            let mut positions = if rng.gen_bool(0.5) {
+                guard_line(grid, room, &taken, monsters_per_room, rng)
+            } else {
+                Vec::new()
+            };
+            // rooms without an exit get a group around a leader
+            if positions.is_empty() {
+                positions = leader_cluster(grid, room, &taken, monsters_per_room, rng);
+            }
+            for position in positions {
+                let Some(monster_type) = weighted_pick(&allowed_weights, rng) else { continue };
+                monsters.push(MonsterInMap{
+                    monster_type,
+                    position
+                });
+            }
+            continue;
+        }
+
         for _ in 0..monsters_per_room {
 
-            let monster_type = monster_types[dist.sample(&mut rng)].clone();
+            let Some(monster_type) = weighted_pick(&allowed_weights, rng) else { continue };
 
-            loop{
+            let position = loop{
                 let position = (rng.gen_range(room.x1+1..room.x2),
                                 rng.gen_range(room.y1+1..room.y2));
                 if grid[position].tile_type == TileType::Floor {
-                    break;
+                    break position;
                 }
             };
 
-            let position = (rng.gen_range(room.x1+1..room.x2),
-                                         rng.gen_range(room.y1+1..room.y2));
             monsters.push(MonsterInMap{
                 monster_type,
                 position
@@ -865,26 +1450,76 @@ fn add_monsters(grid: &Grid,
     monsters
 }
 
+/// Turns the floor cells in the border of a room into closed doors, where a tunnel
+/// passes between two walls into the room
+fn add_doors(grid: &mut Grid, rooms: &[Room], player_position: (usize, usize)) {
+    for room in rooms {
+        let (x_range, y_range) = room.inner();
+        let horizontal = x_range.flat_map(|x| [((x, room.y1), (1, 0), (0, -1)), ((x, room.y2), (1, 0), (0, 1))]);
+        let vertical = y_range.flat_map(|y| [((room.x1, y), (0, 1), (-1, 0)), ((room.x2, y), (0, 1), (1, 0))]);
+
+        for ((x, y), along, outward) in horizontal.chain(vertical) {
+            if (x, y) == player_position || grid[(x, y)].tile_type != TileType::Floor {
+                continue;
+            }
+            let cell_at = |(dx, dy): (i32, i32)| {
+                let (next_x, next_y) = (x as i32 + dx, y as i32 + dy);
+                (next_x >= 0 && next_y >= 0 && grid.is_valid_position(next_x as usize, next_y as usize))
+                    .then(|| grid[(next_x as usize, next_y as usize)].tile_type)
+            };
+            let between_walls = cell_at(along) == Some(TileType::Wall)
+                && cell_at((-along.0, -along.1)) == Some(TileType::Wall);
+            if between_walls && cell_at(outward) == Some(TileType::Floor) {
+                grid[(x, y)].tile_type = TileType::DoorClosed;
+            }
+        }
+    }
+}
+
+const VEIN_CHANCE: f64 = 0.03;
+const TREASURE_CHANCE: f64 = 0.3;
+
+/// Turns some walls next to a floor into mineral veins, never on the map border
+fn add_mineral_veins(grid: &mut Grid, rng: &mut impl Rng) {
+    for x in 1..grid.width() - 1 {
+        for y in 1..grid.height() - 1 {
+            if grid[(x, y)].tile_type != TileType::Wall {
+                continue;
+            }
+            let next_to_floor = [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter()
+                .any(|neighbour| grid[*neighbour].tile_type == TileType::Floor);
+            if next_to_floor && rng.gen_bool(VEIN_CHANCE) {
+                grid[(x, y)].tile_type = if rng.gen_bool(TREASURE_CHANCE) {
+                    TileType::MineralVeinWithTreasure
+                } else {
+                    TileType::MineralVein
+                };
+            }
+        }
+    }
+}
+
+/// Empties every cell without an open neighbour, the walls nobody can see
 fn remove_walls(width:usize, height:usize, grid: &mut Grid){
     for x in 0..width {
         for y in 0..height {
             if y != 0 { //up
-                if grid[(x, y - 1)].tile_type == TileType::Floor {
+                if grid[(x, y - 1)].tile_type.is_open() {
                     continue;
                 }
             }
             if y != height - 1 {  //down
-                if grid[(x, y + 1)].tile_type == TileType::Floor {
+                if grid[(x, y + 1)].tile_type.is_open() {
                     continue;
                 }
             }
             if x != 0 { //left
-                if grid[(x - 1, y)].tile_type == TileType::Floor {
+                if grid[(x - 1, y)].tile_type.is_open() {
                     continue;
                 }
             }
             if x != width - 1 {  //right
-                if grid[(x + 1, y)].tile_type == TileType::Floor {
+                if grid[(x + 1, y)].tile_type.is_open() {
                     continue;
                 }
             }
@@ -893,120 +1528,6 @@ fn remove_walls(width:usize, height:usize, grid: &mut Grid){
     }
 }
 
-/*
-
-    fn create_dungeon_bsp(width:usize,
-                      height:usize,
-                      max_rooms: usize,
-                      room_min_size: usize,
-                      room_max_size: usize
-    ) -> Result<Self, String> {
-        let tile_mapping = TileMapping::new();
-
-        let mut grid: Vec<Vec<Tile>> = vec![vec![Tile::new(TileType::Wall); width]; height];
-
-        let x_center = width / 2;
-        let y_center = height / 2;
-
-        let center = (x_center, y_center);
-
-        // Initialize a directed graph with nodes of type Area
-        let mut graph = Graph::<(Area, Option<Room>), ()>::new();
-
-        //start with the entire dungeon area // root node of the BSP tree
-        let root_area = Area {
-            x: 0,
-            y: 0,
-            width: width,
-            height: height,
-        };
-
-        let root = graph.add_node((root_area.clone(), None));
-
-        // Start the recursive splitting process
-        root_area.split_area(&mut graph, root,ROOM_MIN_SIZE, ROOM_MAX_SIZE);
-
-        // create a room within the cell by randomly choosing two points ("top leftand bottom right ") within its boundaries
-        for node in graph.node_indices() {
-            let area = &graph[node].0;
-            let room = Room::create_in_area(area);
-            room.fill_grid(&mut grid);
-            graph[node].1 = Some(room);
-        }
-
-        //starting from the lowest layers , draw corridors to connect rooms in the nodes of the BSP tree with children of the same parent
-        //until the children of the root node are connected
-        let mut nodes_to_process = vec![root];
-
-        while let Some(current_node) = nodes_to_process.pop() {
-            let children: Vec<_> = graph.neighbors(current_node).collect();
-
-            if children.len() == 2 {
-                if graph.neighbors(children[0]).count() == 0 && graph.neighbors(children[1]).count() == 0 {
-                    // both are leafs
-                    if let (Some(room1), Some(room2)) = (&graph[children[0]].1, &graph[children[1]].1) {
-                        room1.create_tunnel(&mut grid, room2);
-                    }
-                } else {
-                    // there are more nodes
-                    if let (Some((_, room1)), Some((_, room2))) = (
-                        get_random_leaf_room_bsp(&graph, children[0]),
-                        get_random_leaf_room_bsp(&graph, children[1])
-                    ) {
-                        room1.create_tunnel(&mut grid, &room2);
-                    }
-                }
-                nodes_to_process.extend(children);
-            }
-        }
-
-        let mut player_position: (usize, usize) =(0,0);
-
-        if let Some(first_room) = get_first_room_bsp(&graph, root) {
-            player_position  = first_room.center;
-        }
-
-        //remove walls
-        for x in 0..width {
-            for y in 0..height  {
-                if y != 0 { //up
-                    if grid[y-1][x].tile_type == TileType::Floor {
-                        continue;
-                    }
-                }
-                if y!=height {  //down
-                    if grid[y+1][x].tile_type == TileType::Floor {
-                        continue;
-                    }
-                }
-                if x!=0 { //left
-                    if grid[y][x-1].tile_type == TileType::Floor {
-                        continue;
-                    }
-                }
-                if x!=width{  //right
-                    if grid[y][x+1].tile_type == TileType::Floor {
-                        continue;
-                    }
-                }
-                grid[y][x].tile_type = TileType::Empty
-
-            }
-        }
-
-        Ok(GameMap {
-            grid,
-            tile_mapping,
-            player_position,
-            center,
-            width,
-            height
-        })
-    }
-
-
-*/
-
 pub struct DungeonWriter{}
 
 impl Default for DungeonWriter {
@@ -1055,4 +1576,316 @@ impl DungeonWriter {
             .collect::<Vec<String>>()
             .join("\n")
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(width: usize, height: usize, floor: usize, kind: GeneratorKind, seed: u64) -> MapGeneratorStart {
+        let params = ItemAndMonsterParameterItem {
+            items: vec![(ItemType::HealPotion, 0.5), (ItemType::Lightning, 0.5)],
+            monsters: vec![(MonsterType::Orc, 1.0)],
+            allowed_monsters: vec![MonsterType::Orc]
+        };
+        MapGeneratorStart::new(width, height, floor, 30, 6, 10, 2, 2, params, None, Some(seed))
+            .with_kind(kind)
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_map() {
+        let tiles = |game_map: &GameMap| -> Vec<TileType> {
+            (0..game_map.height)
+                .flat_map(|y| (0..game_map.width).map(move |x| (x, y)))
+                .map(|cell| game_map.grid[cell].tile_type)
+                .collect()
+        };
+        let first = generator(80, 45, 1, GeneratorKind::Rooms, 42).generate().unwrap();
+        let second = generator(80, 45, 1, GeneratorKind::Rooms, 42).generate().unwrap();
+        let other = generator(80, 45, 1, GeneratorKind::Rooms, 43).generate().unwrap();
+
+        assert_eq!(tiles(&first), tiles(&second));
+        assert_eq!(first.player_position, second.player_position);
+        assert_eq!(format!("{:?}", first.monsters), format!("{:?}", second.monsters));
+        assert_eq!(first.items, second.items);
+        assert_ne!(tiles(&first), tiles(&other));
+    }
+
+    #[test]
+    fn all_lightning_table_places_only_lightning() {
+        let params = ItemAndMonsterParameterItem {
+            items: vec![(ItemType::HealPotion, 0.0), (ItemType::Lightning, 1.0)],
+            monsters: vec![(MonsterType::Orc, 1.0)],
+            allowed_monsters: vec![MonsterType::Orc]
+        };
+        // the first floor always gets a heal potion, the second one doesn't
+        let game_map = MapGeneratorStart::new(80, 45, 1, 30, 6, 10, 2, 4, params, None, Some(7))
+            .generate()
+            .unwrap();
+
+        assert!(!game_map.items.is_empty());
+        assert!(game_map.items.iter().all(|item| item.item_type == ItemType::Lightning));
+    }
+
+    #[test]
+    fn first_floor_always_has_a_heal_potion() {
+        let params = ItemAndMonsterParameterItem {
+            items: vec![(ItemType::HealPotion, 0.0), (ItemType::Lightning, 1.0)],
+            monsters: vec![(MonsterType::Orc, 1.0)],
+            allowed_monsters: vec![MonsterType::Orc]
+        };
+        for kind in [GeneratorKind::Rooms, GeneratorKind::Cave] {
+            for seed in 0..20 {
+                let game_map = MapGeneratorStart::new(80, 45, 0, 30, 6, 10, 2, 4, params.clone(), None, Some(seed))
+                    .with_kind(kind)
+                    .generate()
+                    .unwrap();
+                assert!(game_map.items.iter().any(|item| item.item_type == ItemType::HealPotion), "{:?} seed {}", kind, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn forbidden_trolls_never_spawn() {
+        let params = ItemAndMonsterParameterItem {
+            items: vec![(ItemType::Lightning, 1.0)],
+            monsters: vec![(MonsterType::Orc, 1.0), (MonsterType::Troll, 10.0)],
+            allowed_monsters: vec![MonsterType::Orc]
+        };
+        for seed in 0..20 {
+            let game_map = MapGeneratorStart::new(80, 45, 1, 30, 6, 10, 3, 4, params.clone(), None, Some(seed))
+                .generate()
+                .unwrap();
+            assert!(!game_map.monsters.is_empty(), "seed {}", seed);
+            assert!(game_map.monsters.iter().all(|monster| monster.monster_type == MonsterType::Orc), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn formation_keeps_the_monsters_of_a_large_room_together() {
+        let room = Room::new(String::from("hall"), 1, 1, 20, 20);
+        let mut grid = Grid::new(24, 24, TileType::Wall);
+        for cell in room.cells() {
+            grid[cell].tile_type = TileType::Floor;
+        }
+        let weights = vec![(MonsterType::Orc, 1.0)];
+        // largest distance between two monsters of the room, in cells along x or y
+        let spread = |monsters: &[MonsterInMap]| monsters.iter()
+            .flat_map(|a| monsters.iter().map(move |b| a.position.0.abs_diff(b.position.0).max(a.position.1.abs_diff(b.position.1))))
+            .max()
+            .unwrap_or(0);
+
+        let mut scattered_spread = 0;
+        for seed in 0..20 {
+            let formation = add_monsters(&grid, &vec![room.clone()], 6, &weights, &[MonsterType::Orc],
+                                         MonsterPlacement::Formation, &mut StdRng::seed_from_u64(seed));
+            assert!(spread(&formation) <= 2 * FORMATION_SPREAD, "seed {}", seed);
+
+            let scattered = add_monsters(&grid, &vec![room.clone()], 6, &weights, &[MonsterType::Orc],
+                                         MonsterPlacement::Scatter, &mut StdRng::seed_from_u64(seed));
+            scattered_spread = scattered_spread.max(spread(&scattered));
+        }
+        assert!(scattered_spread > 2 * FORMATION_SPREAD);
+    }
+
+    #[test]
+    fn weighted_pick_follows_the_weights() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let weights = [(MonsterType::Orc, 3.0), (MonsterType::Troll, 1.0), (MonsterType::Shaman, 0.0)];
+        let samples = 10000;
+        let mut counts = [0; 3];
+        for _ in 0..samples {
+            let monster_type = weighted_pick(&weights, &mut rng).unwrap();
+            counts[weights.iter().position(|(entry, _)| *entry == monster_type).unwrap()] += 1;
+        }
+
+        let shares = counts.map(|count| count as f32 / samples as f32);
+        assert!((shares[0] - 0.75).abs() < 0.03);
+        assert!((shares[1] - 0.25).abs() < 0.03);
+        assert_eq!(shares[2], 0.0);
+        assert_eq!(weighted_pick(&[(MonsterType::Orc, 0.0)], &mut rng), None);
+    }
+
+    #[test]
+    fn every_generator_kind_builds_a_valid_map() {
+        for kind in [GeneratorKind::Rooms, GeneratorKind::Open, GeneratorKind::TwoRooms, GeneratorKind::Bsp, GeneratorKind::Cave] {
+            for seed in 0..5 {
+                let game_map = generator(80, 45, 1, kind, seed).generate()
+                    .unwrap_or_else(|error| panic!("{:?} seed {}: {}", kind, seed, error));
+                assert_eq!(game_map.validate(), Ok(()), "{:?} seed {}", kind, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn bsp_rooms_are_inside_the_map_and_connected() {
+        for seed in 0..10 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut grid = Grid::new(80, 45, TileType::Wall);
+            let rooms = generate_bsp_rooms(&mut grid, 80, 45, 6, 10, "bsp", &mut rng);
+
+            assert!(rooms.len() > 1, "seed {}", seed);
+            for room in rooms.iter() {
+                assert!(room.x2 < 80 && room.y2 < 45, "room {} of seed {} is outside", room.id, seed);
+                assert!(is_reachable(&grid, rooms[0].center, room.center), "room {} of seed {} is cut off", room.id, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn cave_floor_is_connected() {
+        for seed in 0..5 {
+            let game_map = MapGeneratorCaves {
+                width: 60,
+                height: 40,
+                fill_ratio: 0.45,
+                smoothing_iterations: 4,
+                seed: Some(seed)
+            }.generate().unwrap();
+
+            let reached: HashSet<(usize, usize)> = MapGeneratorCaves::region(&game_map.grid, game_map.player_position)
+                .into_iter()
+                .map(|(cell, _)| cell)
+                .collect();
+            for y in 0..40 {
+                for x in 0..60 {
+                    if game_map.grid[(x, y)].tile_type.is_open() {
+                        assert!(reached.contains(&(x, y)), "seed {}: {:?} is cut off", seed, (x, y));
+                    }
+                }
+            }
+            assert_eq!(game_map.validate(), Ok(()), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn bsp_generator_builds_rooms_and_starts_on_floor() {
+        for seed in 0..5 {
+            let game_map = MapGeneratorBsp { width: 80, height: 45, room_min_size: 6, room_max_size: 10, seed: Some(seed) }
+                .generate()
+                .unwrap();
+
+            let floor_cells = (0..game_map.height)
+                .flat_map(|y| (0..game_map.width).map(move |x| (x, y)))
+                .filter(|cell| game_map.grid[*cell].tile_type == TileType::Floor)
+                .count();
+            // the smallest room has 5 x 5 floor cells inside its walls
+            assert!(floor_cells >= 2 * 5 * 5, "seed {} has only {} floor cells", seed, floor_cells);
+            assert_eq!(game_map.grid[game_map.player_position].tile_type, TileType::Floor, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn start_room_is_near_the_edge_and_apart_from_the_stairs() {
+        for seed in 0..10 {
+            let game_map = generator(80, 45, 1, GeneratorKind::Rooms, seed).generate().unwrap();
+            let start_room = game_map.player_start_room.clone().unwrap();
+            let stairs = (0..game_map.height)
+                .flat_map(|y| (0..game_map.width).map(move |x| (x, y)))
+                .find(|cell| game_map.grid[*cell].tile_type == TileType::StaircaseDown)
+                .unwrap();
+
+            assert!(start_room.edge_distance(80, 45) <= 10,
+                    "start room of seed {} is {} cells from the edge", seed, start_room.edge_distance(80, 45));
+            assert!(!start_room.cells().any(|cell| cell == stairs), "seed {} has the stairs in the start room", seed);
+        }
+    }
+
+    #[test]
+    fn filled_room_cells_are_room_floor() {
+        let mut grid = Grid::new(10, 8, TileType::Wall);
+        let room = Room::new(String::from("room"), 1, 1, 5, 4);
+        room.fill_grid(&mut grid);
+
+        for cell in room.cells() {
+            assert_eq!(grid[cell].tile_type, TileType::Floor, "cell {:?}", cell);
+            assert_eq!(grid[cell].render_hint, RenderHint::RoomFloor, "cell {:?}", cell);
+        }
+    }
+
+    #[test]
+    fn tunnel_cells_outside_rooms_are_corridor() {
+        let mut grid = Grid::new(16, 8, TileType::Wall);
+        let left = Room::new(String::from("left"), 1, 1, 4, 4);
+        let right = Room::new(String::from("right"), 10, 1, 4, 4);
+        left.fill_grid(&mut grid);
+        right.fill_grid(&mut grid);
+        left.carve_tunnel(&mut grid, &right, true);
+
+        // the tunnel runs along the row of both centers
+        for x in 5..=10 {
+            assert_eq!(grid[(x, 3)].tile_type, TileType::Floor, "cell {:?}", (x, 3));
+            assert_eq!(grid[(x, 3)].render_hint, RenderHint::Corridor, "cell {:?}", (x, 3));
+        }
+        assert_eq!(grid[left.center].render_hint, RenderHint::RoomFloor);
+        assert_eq!(grid[right.center].render_hint, RenderHint::RoomFloor);
+    }
+
+    #[test]
+    fn two_rooms_fit_a_small_map() {
+        let game_map = generator(20, 10, 0, GeneratorKind::TwoRooms, 1).generate().unwrap();
+        assert_eq!(game_map.validate(), Ok(()));
+
+        let error = generator(4, 10, 0, GeneratorKind::TwoRooms, 1).generate().unwrap_err();
+        assert!(matches!(error, GenError::RoomOutsideMap(_)));
+    }
+
+    #[test]
+    fn empty_map_string_is_an_error() {
+        assert_eq!(StringMapGenerator::new("").generate().unwrap_err(), GenError::EmptyMap);
+    }
+
+    #[test]
+    fn room_outside_the_map_is_an_error() {
+        let error = RoomListGenerator {
+            width: 10,
+            height: 10,
+            rooms: vec![Room::new(String::from("wide"), 2, 2, 8, 4)]
+        }.generate().unwrap_err();
+        assert_eq!(error, GenError::RoomOutsideMap(String::from("wide")));
+    }
+
+    #[test]
+    fn room_list_builds_two_connected_rooms() {
+        let game_map = RoomListGenerator {
+            width: 30,
+            height: 12,
+            rooms: vec![
+                Room::new(String::from("1"), 1, 1, 6, 6),
+                Room::new(String::from("2"), 18, 3, 6, 6)
+            ]
+        }.generate().unwrap();
+
+        for (x, y) in [(2, 2), (6, 6), (19, 4), (23, 8)] {
+            assert_eq!(game_map.grid[(x, y)].tile_type, TileType::Floor);
+        }
+        assert_eq!(game_map.player_position, (4, 4));
+        assert!(is_reachable(&game_map.grid, game_map.player_position, (21, 6)));
+    }
+
+    #[test]
+    fn stray_character_is_a_parse_error_at_its_row_and_col() {
+        // '*' is a mineral vein with treasure, 'X' is no tile
+        let error = StringMapGenerator::new("#####\n#@.>#\n##X##").generate().unwrap_err();
+
+        assert_eq!(error, GenError::ParseError { char: 'X', pos: (2, 2) });
+        assert_eq!(error.to_string(), "unknown tile 'X' at row 2 col 2");
+        assert_eq!(StringMapGenerator::new("####\n#@>X").generate().unwrap_err(), GenError::ParseError { char: 'X', pos: (1, 3) });
+    }
+
+    #[test]
+    fn map_without_player_starts_on_the_first_free_floor_cell() {
+        let game_map = StringMapGenerator::new("######\n#o..>#\n######").generate().unwrap();
+        assert_eq!(game_map.player_position, (2, 1));
+        assert_eq!(game_map.grid[game_map.player_position].tile_type, TileType::Floor);
+
+        assert_eq!(StringMapGenerator::new("####\n#o>#\n####").generate().unwrap_err(), GenError::NoPlayerStart);
+    }
+
+    #[test]
+    fn ragged_map_is_an_error() {
+        let error = StringMapGenerator::new("#####\n#@.>#\n###").generate().unwrap_err();
+
+        assert_eq!(error, GenError::RaggedMap { line: 2, width: 3, expected: 5 });
+        assert!(error.to_string().contains("inconsistent width"));
+    }
+}
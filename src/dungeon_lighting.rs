@@ -1,21 +1,25 @@
 use bevy::prelude::*;
 use std::f32::consts::PI;
-use crate::{GameMap, TileType, Player, GameState, setup};
+use rand::Rng;
+use crate::{CurrentFloor, Floor, GameMap, GameRng, TileType, Player, GameState, setup};
 
 // Lighting configuration constants
 const AMBIENT_INTENSITY: f32 = 0.15;
 const AMBIENT_COLOR: Color = Color::rgba(0.6, 0.7, 1.0, AMBIENT_INTENSITY);
 
-const TORCH_BASE_INTENSITY: f32 = 0.5;
+// PointLight and SpotLight intensities are in lumens
+const TORCH_BASE_INTENSITY: f32 = 100_000.0;
 const TORCH_COLOR: Color = Color::rgba(1.0, 0.6, 0.2, 1.0);
 const TORCH_RANGE: f32 = 8.0;
+/// Default chance for a torch on a floor tile next to a wall
+const TORCH_DENSITY: f32 = 0.3;
 
-const PLAYER_LIGHT_BASE_INTENSITY: f32 = 0.3;
-const PLAYER_LIGHT_MAX_INTENSITY: f32 = 0.7;
+const PLAYER_LIGHT_BASE_INTENSITY: f32 = 240_000.0;
 const PLAYER_LIGHT_COLOR: Color = Color::rgba(1.0, 1.0, 0.9, 1.0);
 
 // Torch flickering parameters
 const TORCH_FLICKER_SPEED: f32 = 5.0;
+/// Fraction of the base intensity the torches flicker by
 const TORCH_FLICKER_INTENSITY: f32 = 0.1;
 
 /// Represents different types of lights in the dungeon
@@ -26,24 +30,61 @@ enum DungeonLightType {
     SpotLight,
 }
 
+impl DungeonLightType {
+    /// Intensity of the light right next to the player
+    fn base_intensity(&self) -> f32 {
+        match self {
+            DungeonLightType::Torch => TORCH_BASE_INTENSITY,
+            DungeonLightType::Ambient | DungeonLightType::SpotLight => PLAYER_LIGHT_BASE_INTENSITY
+        }
+    }
+}
+
 #[derive(Component)]
 struct PlayerLight;
 
+/// Global directional light for readability
+#[derive(Component)]
+struct FillLight;
+
+/// Lighting options that can be changed at runtime
+#[derive(Resource)]
+pub struct LightingSettings {
+    /// directional fill light, off for a darker dungeon
+    pub fill_light: bool,
+    /// chance for a torch on a floor tile next to a wall
+    pub torch_density: f32,
+    /// most torches on one floor
+    pub max_torches: usize,
+}
+
+impl Default for LightingSettings {
+    fn default() -> Self {
+        Self {
+            fill_light: false,
+            torch_density: TORCH_DENSITY,
+            max_torches: 32,
+        }
+    }
+}
+
 /// Plugin for managing dynamic dungeon lighting
 pub struct DungeonLightingPlugin;
 
 impl Plugin for DungeonLightingPlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<LightingSettings>()
             .add_systems(OnEnter(GameState::InGame),
                          setup_ambient_lighting)
             .add_systems(OnEnter(GameState::InGame),
-                         setup_player_light
+                         (setup_player_light,
+                          place_torch_lights)
                              .after(setup))
-            //.add_systems(PostStartup, place_torch_lights)
             .add_systems(Update, (
-                torch_flickering,
-                dynamic_light_intensity).run_if(in_state(GameState::InGame)));
+                (torch_flickering,
+                 dynamic_light_intensity).chain(),
+                update_fill_light).run_if(in_state(GameState::InGame)));
     }
 }
 
@@ -54,17 +95,33 @@ fn setup_ambient_lighting(mut commands: Commands) {
         color: AMBIENT_COLOR,
         brightness: AMBIENT_INTENSITY,
     });
+}
 
-    // Add a subtle global directional light for depth
-    commands.spawn((
-        DirectionalLight {
-            illuminance: 500.0,
-            color: Color::srgba(0.8, 0.8, 1.0, 0.2),
-            shadows_enabled: true,
-            ..default()
-        },
-        Transform::from_xyz(-2.0, 10.0, -2.0).looking_at(Vec3::ZERO, Vec3::Y)
-    ));
+/// Spawns or despawns the directional fill light to match the settings
+fn update_fill_light(
+    mut commands: Commands,
+    lighting_settings: Res<LightingSettings>,
+    fill_light_query: Query<Entity, With<FillLight>>,
+) {
+    let fill_lights = fill_light_query.iter().count();
+
+    if lighting_settings.fill_light && fill_lights == 0 {
+        // Add a subtle global directional light for depth
+        commands.spawn((
+            DirectionalLight {
+                illuminance: 500.0,
+                color: Color::srgba(0.8, 0.8, 1.0, 0.2),
+                shadows_enabled: true,
+                ..default()
+            },
+            Transform::from_xyz(-2.0, 10.0, -2.0).looking_at(Vec3::ZERO, Vec3::Y),
+            FillLight
+        )).insert(Name::new("FillLight"));
+    } else if !lighting_settings.fill_light && fill_lights > 0 {
+        for entity in fill_light_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
 }
 
 fn setup_player_light(
@@ -75,8 +132,8 @@ fn setup_player_light(
         commands.entity(player_entity).with_children(|parent| {
             parent.spawn((
                              SpotLight {
-                                     intensity: 240_000.0, // lumens
-                                     color: Color::WHITE,
+                                     intensity: PLAYER_LIGHT_BASE_INTENSITY,
+                                     color: PLAYER_LIGHT_COLOR,
                                      shadows_enabled: false,
                                      radius: 20.0,
                                      range: 200.0,
@@ -92,66 +149,121 @@ fn setup_player_light(
     }
 }
 
-/// Strategically place torch lights throughout the dungeon
-pub fn place_torch_lights(
+/// Torches of the floor the game starts or is loaded on
+fn place_torch_lights(
     mut commands: Commands,
     game_map: Res<GameMap>,
+    current_floor: Res<CurrentFloor>,
+    lighting_settings: Res<LightingSettings>,
+    mut game_rng: ResMut<GameRng>,
+) {
+    spawn_torch_lights(&mut commands, &game_map, current_floor.0, &lighting_settings, &mut game_rng.0);
+}
+
+/// Strategically place torch lights throughout the dungeon, at most one per floor tile.
+/// They belong to the floor and are despawned with it.
+pub fn spawn_torch_lights(
+    commands: &mut Commands,
+    game_map: &GameMap,
+    floor: usize,
+    lighting_settings: &LightingSettings,
+    rng: &mut impl Rng,
 ) {
-    // Iterate through the game map to place torches near walls
+    // Candidates: floor tiles next to a wall, with some randomness
+    let mut candidates: Vec<(usize, usize)> = Vec::new();
     for y in 0..game_map.height {
         for x in 0..game_map.width {
-            // Place torches near wall edges with some randomness
-            if game_map.grid[(x,y)].tile_type == TileType::Wall {
-                // Check adjacent tiles to ensure we're near a floor tile
-                let adjacent_floor = [
-                    (x as i32 - 1, y as i32),
-                    (x as i32 + 1, y as i32),
-                    (x as i32, y as i32 - 1),
-                    (x as i32, y as i32 + 1),
-                ];
-
-                for (adj_x, adj_y) in adjacent_floor.iter() {
-                    if *adj_x >= 0 && *adj_x < game_map.width as i32 &&
-                        *adj_y >= 0 && *adj_y < game_map.height as i32 {
-                        let adj_tile = &game_map.grid[(*adj_x as usize, *adj_y as usize)].tile_type;
-                        if *adj_tile == TileType::Floor && rand::random::<f32>() < 0.3 {
-                            let position = game_map.grid_to_world(*adj_x as usize, *adj_y as usize);
-
-                            commands.spawn((
-                                 PointLight {
-                                        intensity: TORCH_BASE_INTENSITY,
-                                        range: TORCH_RANGE,
-                                        color: TORCH_COLOR,
-                                        shadows_enabled: true,
-                                        ..default()
-                                    },
-                                 Transform::from_translation(position + Vec3::Y * 2.0),
-                                DungeonLightType::Torch,
-                            ));
-                        }
-                    }
-                }
+            if game_map.grid[(x,y)].tile_type != TileType::Floor {
+                continue;
+            }
+            let adjacent = [
+                (x as i32 - 1, y as i32),
+                (x as i32 + 1, y as i32),
+                (x as i32, y as i32 - 1),
+                (x as i32, y as i32 + 1),
+            ];
+            let near_wall = adjacent.iter().any(|(adj_x, adj_y)| {
+                *adj_x >= 0 && *adj_x < game_map.width as i32 &&
+                    *adj_y >= 0 && *adj_y < game_map.height as i32 &&
+                    game_map.grid[(*adj_x as usize, *adj_y as usize)].tile_type.is_wall()
+            });
+            if near_wall && rng.gen::<f32>() < lighting_settings.torch_density {
+                candidates.push((x, y));
             }
         }
     }
+
+    for (x, y) in spread_torches(candidates, lighting_settings.max_torches) {
+        let position = game_map.grid_to_world(x, y);
+
+        commands.spawn((
+             PointLight {
+                    intensity: TORCH_BASE_INTENSITY,
+                    range: TORCH_RANGE,
+                    color: TORCH_COLOR,
+                    shadows_enabled: true,
+                    ..default()
+                },
+             Transform::from_translation(position + Vec3::Y * 2.0),
+            DungeonLightType::Torch,
+            Floor(floor)
+        ));
+    }
+}
+
+/// Picks at most max_torches candidates, each one as far as possible from the ones already picked
+fn spread_torches(mut candidates: Vec<(usize, usize)>, max_torches: usize) -> Vec<(usize, usize)> {
+    if candidates.len() <= max_torches {
+        return candidates;
+    }
+
+    let distance = |a: (usize, usize), b: (usize, usize)| {
+        let dx = a.0.abs_diff(b.0);
+        let dy = a.1.abs_diff(b.1);
+        dx * dx + dy * dy
+    };
+
+    let mut torches: Vec<(usize, usize)> = Vec::with_capacity(max_torches);
+    if max_torches == 0 {
+        return torches;
+    }
+    torches.push(candidates.swap_remove(0));
+
+    // distance of every candidate to its nearest torch
+    let mut nearest: Vec<usize> = candidates.iter().map(|candidate| distance(*candidate, torches[0])).collect();
+
+    while torches.len() < max_torches {
+        let Some((index, _)) = nearest.iter().enumerate().max_by_key(|(_, distance)| **distance) else { break };
+        let torch = candidates.swap_remove(index);
+        nearest.swap_remove(index);
+        for (candidate, nearest_distance) in candidates.iter().zip(nearest.iter_mut()) {
+            *nearest_distance = (*nearest_distance).min(distance(*candidate, torch));
+        }
+        torches.push(torch);
+    }
+
+    torches
 }
 
 
-/// Create a realistic torch flickering effect
+/// Create a realistic torch flickering effect, resets every light to its base intensity
 fn torch_flickering(
     time: Res<Time>,
-    mut torch_lights: Query<&mut PointLight, With<DungeonLightType>>,
+    mut lights: Query<(&mut PointLight, &DungeonLightType)>,
 ) {
-    for mut light in torch_lights.iter_mut() {
+    for (mut light, light_type) in lights.iter_mut() {
         // Sine wave to create natural flickering
-        let flicker = (time.elapsed_secs() * TORCH_FLICKER_SPEED).sin() * TORCH_FLICKER_INTENSITY;
-        light.intensity = TORCH_BASE_INTENSITY + flicker;
+        let flicker = match light_type {
+            DungeonLightType::Torch => (time.elapsed_secs() * TORCH_FLICKER_SPEED).sin() * TORCH_FLICKER_INTENSITY,
+            _ => 0.0
+        };
+        light.intensity = light_type.base_intensity() * (1.0 + flicker);
     }
 }
 
-/// Adjust light intensity based on player movement and proximity
+/// Adjust light intensity based on player movement and proximity, runs after
+/// torch_flickering set the intensity of this frame
 fn dynamic_light_intensity(
-    time: Res<Time>,
     player_query: Query<&Transform, With<Player>>,
     mut lights: Query<(&mut PointLight, &Transform), With<DungeonLightType>>,
 ) {
@@ -160,16 +272,66 @@ fn dynamic_light_intensity(
             // Calculate distance between light and player
             let distance = player_transform.translation.distance(light_transform.translation);
 
-            // Adjust light intensity based on distance
-            let base_intensity = match light.color {
-                _ if light.color == TORCH_COLOR => TORCH_BASE_INTENSITY,
-                _ if light.color == PLAYER_LIGHT_COLOR => PLAYER_LIGHT_BASE_INTENSITY,
-                _ => 0.2
-            };
-
             // Soft distance-based intensity falloff
             let intensity_factor = 1.0 - (distance / 20.0).min(1.0);
-            light.intensity = base_intensity * intensity_factor;
+            light.intensity *= intensity_factor;
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn large_floor_never_gets_more_torches_than_the_cap() {
+        // a hall with pillars, lots of floor next to a wall
+        let (width, height) = (120, 80);
+        let rows: Vec<String> = (0..height).map(|y| (0..width).map(|x| {
+            if (x, y) == (1, 1) {
+                '@'
+            } else if (x, y) == (2, 1) {
+                '>'
+            } else if x == 0 || y == 0 || x == width - 1 || y == height - 1 || (x % 4 == 0 && y % 4 == 0) {
+                '#'
+            } else {
+                '.'
+            }
+        }).collect()).collect();
+        let game_map = GameMap::from_string(&rows.join("\n")).unwrap();
+        let lighting_settings = LightingSettings { torch_density: 1.0, max_torches: 12, ..default() };
+
+        let mut world = World::new();
+        world.run_system_once(move |mut commands: Commands| {
+            spawn_torch_lights(&mut commands, &game_map, 0, &lighting_settings, &mut StdRng::seed_from_u64(3));
+        }).unwrap();
+
+        let torches = world.query::<&DungeonLightType>().iter(&world)
+            .filter(|light_type| matches!(light_type, DungeonLightType::Torch))
+            .count();
+        assert_eq!(torches, 12);
+    }
+
+    #[test]
+    fn toggling_the_fill_light_keeps_at_most_one() {
+        let mut world = World::new();
+        world.insert_resource(LightingSettings { fill_light: true, ..default() });
+        let update_fill_light = world.register_system(update_fill_light);
+        let fill_lights = |world: &mut World| world.query_filtered::<(), (With<FillLight>, With<DirectionalLight>)>().iter(world).count();
+
+        world.run_system(update_fill_light).unwrap();
+        world.run_system(update_fill_light).unwrap();
+        assert_eq!(fill_lights(&mut world), 1);
+
+        world.resource_mut::<LightingSettings>().fill_light = false;
+        world.run_system(update_fill_light).unwrap();
+        assert_eq!(fill_lights(&mut world), 0);
+
+        world.resource_mut::<LightingSettings>().fill_light = true;
+        world.run_system(update_fill_light).unwrap();
+        world.run_system(update_fill_light).unwrap();
+        assert_eq!(fill_lights(&mut world), 1);
+    }
+}
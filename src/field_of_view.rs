@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+
+use crate::create_dungeon::BresenhamLine;
+use crate::save_slot::SaveSlot;
+use crate::{load_explored_cells, save_explored_cells, EXPLORED_JSON_FILE};
+use crate::{CellMesh, CurrentFloor, Floor, GameMap, GameState, Player, WallChunk, TILE_SIZE};
+
+/// Cells the player sees around the own cell
+pub const VISION_RADIUS: usize = 8;
+
+/// Cells the player sees now and has seen on this floor
+#[derive(Resource, Debug)]
+pub struct FieldOfView {
+    pub radius: usize,
+    pub visible: HashSet<(usize, usize)>,
+    pub explored: HashSet<(usize, usize)>,
+    player_cell: Option<(usize, usize)>,
+    /// floor the explored cells belong to
+    floor: Option<usize>
+}
+
+impl Default for FieldOfView {
+    fn default() -> Self {
+        Self {
+            radius: VISION_RADIUS,
+            visible: HashSet::new(),
+            explored: HashSet::new(),
+            player_cell: None,
+            floor: None
+        }
+    }
+}
+
+impl FieldOfView {
+    pub fn save(&self, save_slot: &SaveSlot) {
+        save_explored_cells(save_slot.path(EXPLORED_JSON_FILE), &self.explored);
+    }
+
+    /// Explored cells of the saved floor, a save without them starts unexplored
+    pub fn load(save_slot: &SaveSlot, floor: usize) -> Self {
+        Self {
+            explored: load_explored_cells(save_slot.path(EXPLORED_JSON_FILE)),
+            floor: Some(floor),
+            ..default()
+        }
+    }
+
+    /// Recomputed on the next update although the player stays on the cell,
+    /// e.g. after a wall was mined or a door opened
+    pub fn invalidate(&mut self) {
+        self.player_cell = None;
+    }
+
+    /// Cells within the radius whose line to the player is not blocked by a wall,
+    /// the wall at the end of a line is seen
+    fn compute(&mut self, game_map: &GameMap, player_cell: (usize, usize)) {
+        self.visible.clear();
+        let radius = self.radius as i32;
+        let (player_x, player_y) = (player_cell.0 as i32, player_cell.1 as i32);
+
+        for y in (player_y - radius).max(0)..=player_y + radius {
+            for x in (player_x - radius).max(0)..=player_x + radius {
+                if !game_map.grid.is_valid_position(x as usize, y as usize)
+                    || (x - player_x).pow(2) + (y - player_y).pow(2) > radius * radius {
+                    continue;
+                }
+
+                let blocked = BresenhamLine::new(player_x, player_y, x, y, false)
+                    .filter(|point| *point != (x, y))
+                    .any(|point| game_map.grid[(point.0 as usize, point.1 as usize)].tile_type.is_blocking());
+                if !blocked {
+                    self.visible.insert((x as usize, y as usize));
+                }
+            }
+        }
+
+        self.explored.extend(self.visible.iter().copied());
+        self.player_cell = Some(player_cell);
+    }
+}
+
+/// Darkens an explored cell the player doesn't see right now
+#[derive(Component)]
+struct FogShade;
+
+pub struct FieldOfViewPlugin;
+
+impl Plugin for FieldOfViewPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FieldOfView>()
+            .add_systems(Update, (
+                update_field_of_view,
+                apply_field_of_view
+            ).chain().run_if(in_state(GameState::InGame)));
+    }
+}
+
+fn update_field_of_view(
+    mut field_of_view: ResMut<FieldOfView>,
+    game_map: Res<GameMap>,
+    current_floor: Res<CurrentFloor>,
+    player_query: Query<&Transform, With<Player>>
+) {
+    let Ok(player_transform) = player_query.get_single() else { return };
+
+    // a new floor starts unexplored
+    if field_of_view.floor != Some(current_floor.0) {
+        field_of_view.explored.clear();
+        field_of_view.player_cell = None;
+        field_of_view.floor = Some(current_floor.0);
+    }
+
+    let player_cell = game_map.world_to_grid(player_transform.translation);
+    if field_of_view.player_cell != Some(player_cell) {
+        field_of_view.compute(&game_map, player_cell);
+    }
+}
+
+/// Hides unexplored cells and shades the explored ones out of sight
+fn apply_field_of_view(
+    mut commands: Commands,
+    field_of_view: Res<FieldOfView>,
+    game_map: Res<GameMap>,
+    current_floor: Res<CurrentFloor>,
+    mut cell_query: Query<(&CellMesh, &mut Visibility)>,
+    mut chunk_query: Query<(&mut WallChunk, &mut Visibility), Without<CellMesh>>,
+    new_cells: Query<(), Or<(Added<CellMesh>, Added<WallChunk>)>>,
+    shade_query: Query<Entity, With<FogShade>>,
+    mut shades: Local<HashMap<(usize, usize), Entity>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut shade_handles: Local<Option<(Handle<Mesh>, Handle<StandardMaterial>)>>
+) {
+    if !field_of_view.is_changed() && new_cells.is_empty() {
+        return;
+    }
+
+    for (cell_mesh, mut visibility) in cell_query.iter_mut() {
+        *visibility = if field_of_view.explored.contains(&cell_mesh.0) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+
+    // batched walls only show the faces of explored cells, the mesh is rebuilt when the set grows
+    for (mut wall_chunk, mut visibility) in chunk_query.iter_mut() {
+        let shown = wall_chunk.explored_cells(&field_of_view.explored);
+        *visibility = if shown.is_empty() {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+        if shown != wall_chunk.shown {
+            wall_chunk.shown = shown;
+        }
+    }
+
+    // shades of the last floor are despawned with it
+    shades.retain(|_, entity| shade_query.contains(*entity));
+
+    let (mesh, material) = shade_handles.get_or_insert_with(|| (
+        meshes.add(Mesh::from(Cuboid::new(TILE_SIZE, TILE_SIZE * 1.2, TILE_SIZE))),
+        materials.add(StandardMaterial {
+            base_color: Color::srgba(0.0, 0.0, 0.0, 0.6),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        })
+    )).clone();
+
+    shades.retain(|cell, entity| {
+        let keep = !field_of_view.visible.contains(cell);
+        if !keep {
+            commands.entity(*entity).despawn_recursive();
+        }
+        keep
+    });
+    for cell in field_of_view.explored.difference(&field_of_view.visible) {
+        if shades.contains_key(cell) {
+            continue;
+        }
+        let position = game_map.grid_to_world(cell.0, cell.1);
+        let entity = commands.spawn((
+            Mesh3d(mesh.clone()),
+            MeshMaterial3d(material.clone()),
+            Transform::from_xyz(position.x, TILE_SIZE * 0.6, position.z),
+            FogShade,
+            Floor(current_floor.0)
+        )).id();
+        shades.insert(*cell, entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use crate::save_slot::test_slot;
+
+    #[test]
+    fn explored_cells_survive_save_and_load() {
+        let save_slot = test_slot("explored");
+        let mut field_of_view = FieldOfView::default();
+        field_of_view.explored.extend([(1, 1), (2, 1), (30, 12)]);
+        field_of_view.save(&save_slot);
+
+        let loaded = FieldOfView::load(&save_slot, 2);
+        save_slot.delete();
+        assert_eq!(loaded.explored, field_of_view.explored);
+
+        // the loaded floor is not taken for a new one
+        let mut world = World::new();
+        world.insert_resource(loaded);
+        world.insert_resource(CurrentFloor(2));
+        world.insert_resource(GameMap::from_string("#####\n#@.>#\n#####").unwrap());
+        world.spawn((Transform::default(), Player));
+        world.run_system_once(update_field_of_view).unwrap();
+
+        assert!(world.resource::<FieldOfView>().explored.contains(&(30, 12)));
+    }
+
+    #[test]
+    fn mining_a_wall_reveals_the_cells_behind_it() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Mesh>()
+            .init_asset::<StandardMaterial>()
+            .init_asset::<Scene>()
+            .init_asset::<Image>()
+            .add_event::<crate::AttackEvent>()
+            .add_systems(Startup, crate::load_cell_handles);
+        app.finish();
+        app.update();
+
+        let game_map = GameMap::from_string("#######\n#@%..>#\n#######").unwrap();
+        // at the east side of the start cell, the vein within reach
+        let player_position = game_map.grid_to_world(1, 1) + Vec3::new(1.5, 0.0, 0.0);
+        let world = app.world_mut();
+        world.insert_resource(game_map);
+        world.insert_resource(CurrentFloor(0));
+        world.insert_resource(FieldOfView::default());
+        world.insert_resource(crate::GameRng::new(Some(1)));
+        world.insert_resource(crate::WallRendering::PerFace);
+        let player = world.spawn((
+            Transform::from_translation(player_position),
+            Player,
+            crate::fighting::Actor::new(10, 10, 0, crate::VEIN_HIT_POINTS, 0)
+        )).id();
+        world.run_system_once(update_field_of_view).unwrap();
+        assert!(!world.resource::<FieldOfView>().visible.contains(&(3, 1)));
+
+        world.send_event(crate::AttackEvent { attacker: player, direction: Vec3::X, target: None });
+        world.run_system_once(crate::mine_walls).unwrap();
+        world.run_system_once(update_field_of_view).unwrap();
+
+        let field_of_view = world.resource::<FieldOfView>();
+        assert!(field_of_view.visible.contains(&(3, 1)) && field_of_view.visible.contains(&(4, 1)));
+    }
+}
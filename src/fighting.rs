@@ -5,9 +5,11 @@ use std::time::Duration;
 use std::io::Write;
 use bevy::color::palettes::css::{GRAY, LIGHT_GRAY, LIGHT_GREEN, RED};
 use serde::{Deserialize, Serialize};
-use crate::{AttackTimer, Player, Monster, RightArm, GameState, INVENTORY_JSON_FILE,ACTOR_JSON_FILE};
-use crate::chracter_controller::MonsterAIState;
-use crate::third_person_camera::ThirdPersonCamera;
+use crate::{AttackTimer, Player, Monster, RightArm, GameState, GameplaySet, TransitionState, CurrentFloor, ACTOR_JSON_FILE};
+use crate::save_slot::SaveSlot;
+use crate::chracter_controller::{MonsterAIState, MonsterAlert, AlertSymbol};
+use crate::ui::MessageLog;
+use crate::sound_effects::{PlaySoundEvent, SoundEffect};
 
 #[derive(Component, Debug, Clone,Serialize, Deserialize)]
 pub struct Actor {
@@ -31,7 +33,7 @@ impl Actor {
             defense,
             power,
             current_level: 1,
-            current_xp: 200,
+            current_xp: 0,
             level_up_base: 0,
             level_up_factor: 150,
             xp_given
@@ -47,21 +49,25 @@ impl Actor {
         self.current_xp > self.experience_to_next_level()
     }
 
-    fn add_xp(&mut self, xp: usize) {
+    /// Levels gained with the xp, a lot of xp at once can be worth several levels
+    fn add_xp(&mut self, xp: usize) -> Vec<LevelUpEvent> {
 
         self.current_xp += xp;
 
-        if self.requires_level_up() {
-           self.increase_level();
+        let mut level_ups = Vec::new();
+        while self.requires_level_up() {
+           level_ups.push(self.increase_level());
         }
+        level_ups
     }
 
-    fn increase_level(&mut self) {
+    fn increase_level(&mut self) -> LevelUpEvent {
         self.current_xp -= self.experience_to_next_level();
         self.current_level += 1;
         self.increase_max_hp(20);
         self.increase_power(1);
         self.increase_defense(1);
+        LevelUpEvent { level: self.current_level }
     }
 
     fn increase_max_hp(&mut self, amount: usize) {
@@ -77,18 +83,85 @@ impl Actor {
         self.defense += amount;
     }
 
-    pub fn save(&self) {
-        let mut file = File::create(ACTOR_JSON_FILE).expect("Unable to create file");
+    pub fn save(&self, save_slot: &SaveSlot) {
+        let mut file = File::create(save_slot.path(ACTOR_JSON_FILE)).expect("Unable to create file");
         let inventory = serde_json::to_string(self).expect("Unable to serialize inventory");
         file.write_all(inventory.as_bytes()).expect("Unable to write data");
     }
-    pub fn load() -> Self {
-        let file = File::open(ACTOR_JSON_FILE).expect("Unable to open file");
+    pub fn load(save_slot: &SaveSlot) -> Self {
+        let file = File::open(save_slot.path(ACTOR_JSON_FILE)).expect("Unable to open file");
         let actor: Actor = serde_json::from_reader(file).expect("Unable to read file");
         actor
     }
 }
 
+/// How a target's defense reduces incoming damage
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MitigationModel {
+    /// power - defense, a defense >= power blocks everything
+    Flat,
+    /// every defense point removes a fixed percentage, capped
+    #[default]
+    Percentage,
+    /// power * k / (k + defense), never reaches zero
+    Diminishing
+}
+
+#[derive(Resource, Debug)]
+pub struct DamageSettings {
+    pub mitigation: MitigationModel,
+    pub min_damage: usize
+}
+
+impl Default for DamageSettings {
+    fn default() -> Self {
+        Self {
+            mitigation: MitigationModel::default(),
+            min_damage: 1
+        }
+    }
+}
+
+const DEFENSE_PERCENT_PER_POINT: usize = 10;
+const MAX_MITIGATION_PERCENT: usize = 80;
+const DIMINISHING_FACTOR: usize = 10;
+
+impl MitigationModel {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "flat" => Some(MitigationModel::Flat),
+            "percentage" => Some(MitigationModel::Percentage),
+            "diminishing" => Some(MitigationModel::Diminishing),
+            _ => None
+        }
+    }
+}
+
+impl DamageSettings {
+    pub fn with_mitigation(mitigation: MitigationModel) -> Self {
+        Self {
+            mitigation,
+            ..default()
+        }
+    }
+
+    pub fn mitigate(&self, power: usize, defense: usize) -> usize {
+        if power == 0 {
+            return 0;
+        }
+        match self.mitigation {
+            MitigationModel::Flat => power.saturating_sub(defense),
+            MitigationModel::Percentage => {
+                let reduction = (defense * DEFENSE_PERCENT_PER_POINT).min(MAX_MITIGATION_PERCENT);
+                (power * (100 - reduction) / 100).max(self.min_damage)
+            },
+            MitigationModel::Diminishing => {
+                (power * DIMINISHING_FACTOR / (DIMINISHING_FACTOR + defense)).max(self.min_damage)
+            }
+        }
+    }
+}
+
 #[derive(Component)]
 struct Fading {
     fade_duration: Timer,
@@ -110,11 +183,17 @@ const HEALTHBAR_WIDTH: f32 = 1.0;     // Breite des Healthbars
 #[derive(Component)]
 pub struct MonsterHealthbar;
 
+/// Debug switch to show the healthbars of all monsters regardless of distance
+#[derive(Resource, Default)]
+pub struct AlwaysShowHealthbars(pub bool);
+
 // Events
 #[derive(Event)]
 pub struct AttackEvent {
     pub attacker: Entity,
     pub direction: Vec3,
+    /// Actor on the attacked cell in turn-based mode, otherwise the first actor in reach is hit
+    pub target: Option<Entity>,
 }
 
 #[derive(Event)]
@@ -124,6 +203,21 @@ pub struct DamageEvent {
     pub fixed_damage: usize
 }
 
+#[derive(Event)]
+pub struct PlayerDiedEvent;
+
+/// The player reached a new level
+#[derive(Event, Debug, Clone, Copy)]
+pub struct LevelUpEvent {
+    pub level: usize
+}
+
+/// The last monster of the floor is gone
+#[derive(Event)]
+pub struct FloorCleared {
+    pub floor: usize
+}
+
 #[derive(Default, Reflect, GizmoConfigGroup)]
 struct MyGizmos {}
 
@@ -134,16 +228,28 @@ impl Plugin for FightingPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<AttackEvent>()
             .add_event::<DamageEvent>()
+            .add_event::<PlayerDiedEvent>()
+            .add_event::<LevelUpEvent>()
+            .add_event::<FloorCleared>()
+            .init_resource::<DamageSettings>()
+            .init_resource::<AlwaysShowHealthbars>()
             .init_gizmo_group::<MyGizmos>()
             .add_systems(Update, (
                 handle_attacks,
                 process_damage,
                 sword_rotation,
                 fade_out_monsters,
-            ).chain().run_if(in_state(GameState::InGame)))
+            ).chain().in_set(GameplaySet::Combat))
+            .add_systems(Update, (
+                detect_floor_cleared,
+                announce_floor_cleared
+            ).chain()
+                .in_set(GameplaySet::Cleanup)
+                .run_if(in_state(TransitionState::Running)))
             .add_systems(Update, (
                 update_healthbar_visibility,
                 render_healthbars,
+                render_monster_alerts,
                 update_config_gizmo
             ).chain().run_if(in_state(GameState::InGame)));
     }
@@ -151,7 +257,7 @@ impl Plugin for FightingPlugin {
 
 const ATTACK_TIME:f32=0.5;
 const ATTACK_DISTANCE:f32=2.0;
-fn handle_attacks(
+pub fn handle_attacks(
     mut commands: Commands,
     mut attack_events: EventReader<AttackEvent>,
     mut damage_events: EventWriter<DamageEvent>,
@@ -166,12 +272,21 @@ fn handle_attacks(
         for &child in player_children.iter() {
             let Ok(arm_entity) = arm_query.get(child) else {continue};
             //add animation
+            commands.send_event(PlaySoundEvent(SoundEffect::SwordSwing));
             commands.entity(arm_entity).insert(AttackTimer(Timer::new(
                 Duration::from_secs_f32(ATTACK_TIME),
                 TimerMode::Once
             )));
 
             //add damage
+            if let Some(target) = event.target {
+                damage_events.send(DamageEvent {
+                    attacker,
+                    target,
+                    fixed_damage: 0
+                });
+                continue;
+            }
             for (actor, actor_transform) in all_actors.iter() {
                 if actor != attacker {
                     if actor_transform.translation.distance(attacker_transform.translation) <= ATTACK_DISTANCE {
@@ -218,17 +333,23 @@ fn sword_rotation(
 
 fn process_damage(
     mut damage_events: EventReader<DamageEvent>,
+    mut player_died_events: EventWriter<PlayerDiedEvent>,
     mut commands: Commands,
     player_query: Query<Entity, With<Player>>,
+    damage_settings: Res<DamageSettings>,
+    mut message_log: ResMut<MessageLog>,
     mut actors: Query<(Entity, &mut Actor, &Name, Option<&mut MonsterAIState>)>
 ) {
+    let Ok(player) = player_query.get_single() else { return };
+
     for event in damage_events.read() {
 
-        let attacker_power = if event.fixed_damage == 0 {
+        // fixed damage comes from the player's thrown ball
+        let (attacker_power, attacker_name) = if event.fixed_damage == 0 {
             let Ok((_attacker_entity, attacker, attacker_name, _)) = actors.get(event.attacker) else { continue };
-            attacker.power
+            (attacker.power, attacker_name.to_string())
         } else {
-            0
+            (0, String::new())
         };
 
         let Ok((target_entity,
@@ -236,7 +357,7 @@ fn process_damage(
                    target_name,
                    mut monster_ai_state)) = actors.get_mut(event.target) else { continue };
 
-        println!("target {}", target_name);
+        debug!("target {}", target_name);
 
         let mut fight = true;
 
@@ -250,26 +371,29 @@ fn process_damage(
             let damage = if event.fixed_damage > 0 {
                 event.fixed_damage
             } else {
-                if target.defense < attacker_power {
-                    attacker_power - target.defense
-                } else {
-                    0
-                }
+                damage_settings.mitigate(attacker_power, target.defense)
             };
 
             let new_hit_points:i32 = target.hit_points as i32 - damage as i32;
 
-            if new_hit_points <= 0 {
-                let player = player_query.single();
+            if target_entity == player {
+                message_log.push_message(format!("The {} hits you for {}", attacker_name, damage));
+            } else if event.fixed_damage > 0 || event.attacker == player {
+                message_log.push_message(format!("You hit the {} for {}", target_name, damage));
+            } else {
+                message_log.push_message(format!("The {} hits the {} for {}", attacker_name, target_name, damage));
+            }
 
+            if new_hit_points <= 0 {
                 if player == target_entity {
-
+                    target.hit_points = 0;
+                    player_died_events.send(PlayerDiedEvent);
                 } else {
-                    println!("added fading");
+                    debug!("{} starts fading", target_name);
+                    message_log.push_message(format!("{} died", capitalize(target_name)));
                     commands.entity(target_entity).insert(Fading::new());
                     if let Some(ref mut ai_state) = monster_ai_state {
                         **ai_state = MonsterAIState::Fading;
-                        //player_actor.add_xp(target.xp_given);
                     } ;
                 }
             } else {
@@ -279,64 +403,126 @@ fn process_damage(
     }
 }
 
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new()
+    }
+}
+
+/// Scale of a monster at the end of fading
+const FADE_MIN_SCALE: f32 = 0.2;
+
 fn fade_out_monsters(
     mut commands: Commands,
     time: Res<Time>,
-    mut query: Query<(Entity, &mut Fading, &mut MeshMaterial3d<StandardMaterial>), With<Monster>>,
+    mut query: Query<(Entity, &mut Fading, &mut Transform, &Actor), With<Monster>>,
     mut query_player: Query<&mut Actor, (With<Player>,Without<Monster>)>,
-    query_monster: Query<(Entity, &Actor), With<Monster>>,
+    mut level_up_events: EventWriter<LevelUpEvent>,
+    children_query: Query<&Children>,
+    material_query: Query<&MeshMaterial3d<StandardMaterial>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    for (entity, mut fading, material_handle) in query.iter_mut() {
+    for (entity, mut fading, mut transform, monster_actor) in query.iter_mut() {
         // Timer aktualisieren
         fading.fade_duration.tick(time.delta());
 
-        // Aktuelle Materialien abrufen
-        if let Some(material) = materials.get_mut(material_handle.id()) {
-            // Alpha-Wert linear reduzieren
-            let alpha = 1.0 - fading.fade_duration.fraction();
+        // Alpha-Wert linear reduzieren
+        let alpha = 1.0 - fading.fade_duration.fraction();
 
-            // Neuen Farbwert mit reduziertem Alpha erstellen
-            material.base_color.set_alpha(alpha);
+        // Monster und alle Körperteile ausblenden
+        for part in std::iter::once(entity).chain(children_query.iter_descendants(entity)) {
+            let Ok(material_handle) = material_query.get(part) else { continue };
+            if let Some(material) = materials.get_mut(material_handle.id()) {
+                material.base_color.set_alpha(alpha);
+            }
+        }
 
-            // Monster entfernen, wenn vollständig ausgeblendet
-            if fading.fade_duration.finished() {
-                commands.entity(entity).despawn_recursive();
-                for mut player_actor in query_player.iter_mut() {
-                    let Ok((_,monster_actor)) = query_monster.get(entity) else { continue };
-                    player_actor.add_xp(monster_actor.xp_given);
-                }
+        // Monster schrumpfen
+        transform.scale = Vec3::splat(FADE_MIN_SCALE + (1.0 - FADE_MIN_SCALE) * alpha);
+
+        // Monster entfernen, wenn vollständig ausgeblendet, vorher gibt es die XP
+        if fading.fade_duration.finished() {
+            for mut player_actor in query_player.iter_mut() {
+                level_up_events.send_batch(player_actor.add_xp(monster_actor.xp_given));
             }
+            commands.send_event(PlaySoundEvent(SoundEffect::MonsterDeath));
+            commands.entity(entity).despawn_recursive();
         }
     }
 }
 
+/// Monsters still fighting, dying ones don't count
+pub fn alive_monsters(monster_query: &Query<&MonsterAIState, With<Monster>>) -> usize {
+    monster_query.iter()
+        .filter(|ai_state| **ai_state != MonsterAIState::Fading)
+        .count()
+}
+
+fn detect_floor_cleared(
+    mut removed_monsters: RemovedComponents<Monster>,
+    monster_query: Query<&MonsterAIState, With<Monster>>,
+    current_floor: Res<CurrentFloor>,
+    mut floor_cleared_events: EventWriter<FloorCleared>,
+    mut cleared: Local<bool>
+) {
+    // a dying monster is already gone, so its despawn must not clear the floor a second time,
+    // floor changes are not running, their despawns don't count
+    if alive_monsters(&monster_query) > 0 {
+        *cleared = false;
+    } else if removed_monsters.read().count() > 0 && !*cleared {
+        floor_cleared_events.send(FloorCleared { floor: current_floor.0 });
+        *cleared = true;
+    }
+}
+
+/// Tells the player that the floor is cleared
+fn announce_floor_cleared(
+    mut floor_cleared_events: EventReader<FloorCleared>,
+    mut message_log: ResMut<MessageLog>
+) {
+    for floor_cleared in floor_cleared_events.read() {
+        message_log.push_message(format!("Floor {} is cleared", floor_cleared.floor));
+    }
+}
+
 fn update_healthbar_visibility(
     player_query: Query<&Transform, (With<Player>,Without<Monster>)>,
     mut monsters_query: Query<(Entity, &Transform, &Actor, Option<&MonsterHealthbar>), With<Monster>>,
+    always_show_healthbars: Res<AlwaysShowHealthbars>,
     mut commands: Commands,
 ) {
     let player_transform = player_query.single();
 
     for (monster_entity, monster_transform, actor, healthbar) in monsters_query.iter_mut() {
         let distance = player_transform.translation.distance(monster_transform.translation);
+        let visible = always_show_healthbars.0 || distance <= HEALTHBAR_DISTANCE;
 
-        if distance <= HEALTHBAR_DISTANCE && healthbar.is_none() {
+        if visible && healthbar.is_none() {
             commands.entity(monster_entity)
                 .insert(MonsterHealthbar);
-        } else if distance > HEALTHBAR_DISTANCE && healthbar.is_some() {
+        } else if !visible && healthbar.is_some() {
             commands.entity(monster_entity)
                 .remove::<MonsterHealthbar>();
         }
     }
 }
 
+/// Transform of the active camera rendering first, none while cameras are switched
+fn active_camera_transform(camera_query: &Query<(&GlobalTransform, &Camera), With<Camera3d>>) -> Option<Transform> {
+    camera_query.iter()
+        .filter(|(_, camera)| camera.is_active)
+        .min_by_key(|(_, camera)| camera.order)
+        .map(|(global_transform, _)| global_transform.compute_transform())
+}
+
 fn render_healthbars(
     mut my_gizmos: Gizmos<MyGizmos>,
-    camera_query: Query<&Transform, With<ThirdPersonCamera>>,
+    camera_query: Query<(&GlobalTransform, &Camera), With<Camera3d>>,
     monsters_query: Query<(&Transform, &Actor), (With<Monster>, With<MonsterHealthbar>)>,
 ) {
-    let camera_transform = camera_query.single();
+    let Some(camera_transform) = active_camera_transform(&camera_query) else { return };
 
     for (monster_transform, actor) in monsters_query.iter() {
 
@@ -367,6 +553,50 @@ fn render_healthbars(
     }
 }
 
+const ALERT_HEIGHT: f32 = 1.8;      // Höhe des Symbols über dem Monster
+
+fn render_monster_alerts(
+    mut commands: Commands,
+    mut my_gizmos: Gizmos<MyGizmos>,
+    time: Res<Time>,
+    camera_query: Query<(&GlobalTransform, &Camera), With<Camera3d>>,
+    mut monsters_query: Query<(Entity, &Transform, &mut MonsterAlert), With<Monster>>,
+) {
+    let Some(camera_transform) = active_camera_transform(&camera_query) else { return };
+
+    for (monster_entity, monster_transform, mut alert) in monsters_query.iter_mut() {
+        alert.timer.tick(time.delta());
+        if alert.timer.finished() {
+            commands.entity(monster_entity).remove::<MonsterAlert>();
+            continue;
+        }
+
+        // Symbol in der Ebene der Kamera zeichnen
+        let base = monster_transform.translation + Vec3::Y * ALERT_HEIGHT;
+        let right = camera_transform.right().as_vec3();
+        let point = |x: f32, y: f32| base + right * x + Vec3::Y * y;
+
+        match alert.symbol {
+            AlertSymbol::Exclamation => {
+                my_gizmos.line(point(0.0, 0.25), point(0.0, 0.7), Color::Srgba(RED));
+                my_gizmos.line(point(0.0, 0.0), point(0.0, 0.08), Color::Srgba(RED));
+            },
+            AlertSymbol::Question => {
+                my_gizmos.linestrip([
+                    point(-0.15, 0.55),
+                    point(-0.1, 0.68),
+                    point(0.05, 0.7),
+                    point(0.15, 0.6),
+                    point(0.1, 0.48),
+                    point(0.0, 0.4),
+                    point(0.0, 0.25)
+                ], Color::Srgba(LIGHT_GRAY));
+                my_gizmos.line(point(0.0, 0.0), point(0.0, 0.08), Color::Srgba(LIGHT_GRAY));
+            }
+        }
+    }
+}
+
 fn update_config_gizmo(
     mut config_store: ResMut<GizmoConfigStore>,
 ) {
@@ -374,4 +604,258 @@ fn update_config_gizmo(
     let (my_config, _) = config_store.config_mut::<MyGizmos>();
     my_config.line_width = 20.0;
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn flat_mitigation_subtracts_defense() {
+        let settings = DamageSettings::with_mitigation(MitigationModel::Flat);
+        assert_eq!(settings.mitigate(10, 3), 7);
+        assert_eq!(settings.mitigate(3, 5), 0);
+    }
+
+    #[test]
+    fn percentage_mitigation_keeps_chip_damage() {
+        let settings = DamageSettings::with_mitigation(MitigationModel::Percentage);
+        assert_eq!(settings.mitigate(10, 3), 7);
+        // capped at 80 percent
+        assert_eq!(settings.mitigate(10, 20), 2);
+        assert_eq!(settings.mitigate(3, 5), settings.min_damage);
+    }
+
+    #[test]
+    fn diminishing_mitigation_never_reaches_zero() {
+        let settings = DamageSettings::with_mitigation(MitigationModel::Diminishing);
+        assert_eq!(settings.mitigate(10, 0), 10);
+        assert_eq!(settings.mitigate(10, 10), 5);
+        assert_eq!(settings.mitigate(3, 100), settings.min_damage);
+    }
+
+    #[test]
+    fn killing_the_last_monster_clears_the_floor_once() {
+        let mut world = World::new();
+        world.insert_resource(CurrentFloor(2));
+        world.init_resource::<MessageLog>();
+        world.init_resource::<Events<FloorCleared>>();
+        let detect = world.register_system(detect_floor_cleared);
+        let announce = world.register_system(announce_floor_cleared);
+        let first = world.spawn((Monster { monster_type: crate::MonsterType::Orc }, MonsterAIState::Idle)).id();
+        let dying = world.spawn((Monster { monster_type: crate::MonsterType::Orc }, MonsterAIState::Idle)).id();
+        let last = world.spawn((Monster { monster_type: crate::MonsterType::Orc }, MonsterAIState::Idle)).id();
+
+        world.despawn(first);
+        world.run_system(detect).unwrap();
+        assert!(world.resource::<Events<FloorCleared>>().is_empty());
+
+        *world.get_mut::<MonsterAIState>(dying).unwrap() = MonsterAIState::Fading;
+        world.despawn(last);
+        world.run_system(detect).unwrap();
+        world.despawn(dying);
+        world.run_system(detect).unwrap();
+        world.run_system(announce).unwrap();
+        assert_eq!(world.resource::<Events<FloorCleared>>().len(), 1);
+        assert_eq!(world.resource::<MessageLog>().last_messages(1).next().unwrap(), "Floor 2 is cleared");
+    }
+
+    /// Log output of a tracing subscriber, shared with the test
+    #[derive(Clone, Default)]
+    struct LogBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Log lines written while one damage event is processed, up to the level
+    fn damage_log(level: bevy::log::Level) -> String {
+        let mut world = World::new();
+        world.init_resource::<DamageSettings>();
+        world.init_resource::<MessageLog>();
+        world.init_resource::<Events<DamageEvent>>();
+        world.init_resource::<Events<PlayerDiedEvent>>();
+        let player = world.spawn((Player, Actor::new(30, 30, 2, 5, 0), Name::new("player"))).id();
+        let orc = world.spawn((Actor::new(10, 10, 0, 3, 0), Name::new("orc"))).id();
+        world.send_event(DamageEvent { attacker: player, target: orc, fixed_damage: 0 });
+
+        let buffer = LogBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = bevy::log::tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        bevy::utils::tracing::subscriber::with_default(subscriber, || {
+            world.run_system_once(process_damage).unwrap();
+        });
+        let log = buffer.0.lock().unwrap().clone();
+        String::from_utf8(log).unwrap()
+    }
+
+    #[test]
+    fn damage_is_logged_at_debug_level_only() {
+        let log = damage_log(bevy::log::Level::DEBUG);
+        assert!(log.lines().any(|line| line.contains("DEBUG") && line.contains("target orc")), "{}", log);
+
+        // the message log still shows at info level
+        let log = damage_log(bevy::log::Level::INFO);
+        assert!(!log.contains("target orc"), "{}", log);
+        assert!(log.contains("You hit the orc for 5"), "{}", log);
+    }
+
+    #[test]
+    fn always_shown_healthbars_ignore_the_distance() {
+        let mut world = World::new();
+        world.init_resource::<AlwaysShowHealthbars>();
+        world.spawn((Player, Transform::default()));
+        let far = world.spawn((
+            Monster { monster_type: crate::MonsterType::Orc },
+            Actor::new(10, 10, 0, 3, 0),
+            Transform::from_xyz(HEALTHBAR_DISTANCE * 3.0, 0.0, 0.0)
+        )).id();
+        let update_healthbar_visibility = world.register_system(update_healthbar_visibility);
+
+        world.run_system(update_healthbar_visibility).unwrap();
+        assert!(world.get::<MonsterHealthbar>(far).is_none());
+
+        world.insert_resource(AlwaysShowHealthbars(true));
+        world.run_system(update_healthbar_visibility).unwrap();
+        assert!(world.get::<MonsterHealthbar>(far).is_some());
+    }
+
+    #[test]
+    fn all_parts_of_a_fading_monster_turn_transparent() {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        world.init_resource::<Events<LevelUpEvent>>();
+        let materials: Vec<Handle<StandardMaterial>> = (0..3)
+            .map(|_| world.resource_mut::<Assets<StandardMaterial>>().add(StandardMaterial::default()))
+            .collect();
+        let (body, arm, club) = (materials[0].clone(), materials[1].clone(), materials[2].clone());
+        let monster = world.spawn((
+            Monster { monster_type: crate::MonsterType::Troll },
+            Actor::new(10, 0, 0, 3, 0),
+            Fading::new(),
+            Transform::default(),
+            MeshMaterial3d(body)
+        )).with_children(|parent| {
+            parent.spawn(MeshMaterial3d(arm)).with_children(|arm| {
+                arm.spawn(MeshMaterial3d(club));
+            });
+        }).id();
+
+        world.resource_mut::<Time>().advance_by(Duration::from_secs(1));
+        world.run_system_once(fade_out_monsters).unwrap();
+
+        let assets = world.resource::<Assets<StandardMaterial>>();
+        for material in materials.iter() {
+            let alpha = assets.get(material).unwrap().base_color.alpha();
+            assert!(alpha > 0.0 && alpha < 1.0, "alpha {}", alpha);
+        }
+        assert!(world.get::<Transform>(monster).unwrap().scale.x < 1.0);
+    }
+
+    #[test]
+    fn new_actor_starts_without_xp() {
+        let actor = Actor::new(30, 30, 1, 2, 0);
+
+        assert_eq!(actor.current_xp, 0);
+        assert!(!actor.requires_level_up());
+    }
+
+    #[test]
+    fn killing_a_monster_gives_its_xp_to_the_player() {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        world.init_resource::<Events<LevelUpEvent>>();
+        world.init_resource::<Events<PlaySoundEvent>>();
+        let player = world.spawn((Player, Actor::new(30, 30, 1, 2, 0))).id();
+        let experience_to_next_level = world.get::<Actor>(player).unwrap().experience_to_next_level();
+        world.spawn((
+            Monster { monster_type: crate::MonsterType::Troll },
+            Actor::new(16, 0, 1, 4, 200),
+            Fading::new(),
+            Transform::default()
+        ));
+
+        world.resource_mut::<Time>().advance_by(Duration::from_secs(3));
+        world.run_system_once(fade_out_monsters).unwrap();
+
+        let player_actor = world.get::<Actor>(player).unwrap();
+        assert_eq!(player_actor.current_level, 2);
+        assert_eq!(player_actor.current_xp, 200 - experience_to_next_level);
+        let level_ups: Vec<usize> = world.resource_mut::<Events<LevelUpEvent>>().drain().map(|event| event.level).collect();
+        assert_eq!(level_ups, vec![2]);
+    }
+
+    /// Spawns the monster on the floor, kills it and returns the player's xp gain
+    fn xp_for_killing(monster_type: crate::MonsterType, floor: usize) -> usize {
+        let mut world = World::new();
+        world.insert_resource(CurrentFloor(floor));
+        world.init_resource::<Time>();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        world.init_resource::<DamageSettings>();
+        world.init_resource::<MessageLog>();
+        world.init_resource::<Events<DamageEvent>>();
+        world.init_resource::<Events<PlayerDiedEvent>>();
+        world.init_resource::<Events<LevelUpEvent>>();
+        world.init_resource::<Events<PlaySoundEvent>>();
+        let player = world.spawn((Player, Actor::new(30, 30, 1, 2, 0), Name::new("Player"))).id();
+        let mut game_map = crate::GameMap::from_string("#####\n#@.>#\n#####").unwrap();
+        game_map.monsters = vec![crate::MonsterInMap { position: (2, 1), monster_type }];
+        world.run_system_once(move |mut commands: Commands, current_floor: ResMut<CurrentFloor>,
+                                    mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<StandardMaterial>>| {
+            crate::setup_monster(&mut commands, &current_floor, &crate::Difficulty::Normal.settings(), &mut meshes, &mut materials, &mut game_map);
+        }).unwrap();
+        let monster = world.query_filtered::<Entity, With<Monster>>().single(&world);
+
+        world.send_event(DamageEvent { attacker: player, target: monster, fixed_damage: 1000 });
+        world.run_system_once(process_damage).unwrap();
+        world.resource_mut::<Time>().advance_by(Duration::from_secs(3));
+        world.run_system_once(fade_out_monsters).unwrap();
+
+        world.get::<Actor>(player).unwrap().current_xp
+    }
+
+    #[test]
+    fn killing_a_troll_gives_more_xp_than_an_orc() {
+        let troll_xp = xp_for_killing(crate::MonsterType::Troll, 1);
+        let orc_xp = xp_for_killing(crate::MonsterType::Orc, 1);
+
+        assert!(orc_xp > 0);
+        assert!(troll_xp > orc_xp);
+    }
+
+    #[test]
+    fn healthbars_without_an_active_camera_are_skipped() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .init_gizmo_group::<MyGizmos>();
+        let world = app.world_mut();
+        world.spawn((Monster { monster_type: crate::MonsterType::Orc }, MonsterHealthbar, Actor::new(10, 5, 0, 3, 0), Transform::default()));
+
+        world.run_system_once(render_healthbars).unwrap();
+
+        // the third person camera switched off while the orbit camera takes over
+        world.spawn((Camera3d::default(), Camera { is_active: false, ..default() }, GlobalTransform::default()));
+        world.run_system_once(render_healthbars).unwrap();
+    }
+
+    #[test]
+    fn mitigation_models_by_name() {
+        assert_eq!(MitigationModel::from_name("flat"), Some(MitigationModel::Flat));
+        assert_eq!(MitigationModel::from_name("percentage"), Some(MitigationModel::Percentage));
+        assert_eq!(MitigationModel::from_name("diminishing"), Some(MitigationModel::Diminishing));
+        assert_eq!(MitigationModel::from_name("none"), None);
+    }
+}
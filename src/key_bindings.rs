@@ -0,0 +1,139 @@
+use std::fs;
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+
+pub const KEY_BINDINGS_FILE: &str = "keybindings.json";
+
+/// Keys of the game, missing values in the file are defaults
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub move_up: KeyCode,
+    pub move_down: KeyCode,
+    pub move_left: KeyCode,
+    pub move_right: KeyCode,
+    pub attack: KeyCode,
+    pub throw: KeyCode,
+    pub switch_item: KeyCode,
+    pub use_potion: KeyCode,
+    pub toggle_inventory: KeyCode,
+    pub toggle_fps: KeyCode,
+    pub toggle_minimap: KeyCode,
+    pub quit: KeyCode,
+    pub pause: KeyCode,
+    pub look: KeyCode,
+    pub camera_left: KeyCode,
+    pub camera_right: KeyCode,
+    pub camera_up: KeyCode,
+    pub camera_down: KeyCode,
+    pub camera_mode: KeyCode,
+    pub pick_up: KeyCode,
+    pub drop: KeyCode,
+    pub read_scroll: KeyCode,
+    pub aim: KeyCode,
+    pub confirm: KeyCode,
+    pub cancel: KeyCode,
+    pub toggle_camera: KeyCode,
+    pub next_msaa: KeyCode,
+    pub narrower_fov: KeyCode,
+    pub wider_fov: KeyCode,
+    pub toggle_healthbars: KeyCode,
+    pub toggle_fill_light: KeyCode,
+    pub toggle_auto_pickup: KeyCode,
+    /// Stairs down from anywhere, only with the debug feature
+    pub debug_descend: KeyCode
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            move_up: KeyCode::ArrowUp,
+            move_down: KeyCode::ArrowDown,
+            move_left: KeyCode::ArrowLeft,
+            move_right: KeyCode::ArrowRight,
+            attack: KeyCode::Space,
+            throw: KeyCode::KeyX,
+            switch_item: KeyCode::Tab,
+            use_potion: KeyCode::KeyP,
+            toggle_inventory: KeyCode::KeyI,
+            toggle_fps: KeyCode::KeyF,
+            toggle_minimap: KeyCode::KeyM,
+            quit: KeyCode::KeyQ,
+            pause: KeyCode::Escape,
+            look: KeyCode::KeyL,
+            camera_left: KeyCode::KeyA,
+            camera_right: KeyCode::KeyD,
+            camera_up: KeyCode::KeyW,
+            camera_down: KeyCode::KeyS,
+            camera_mode: KeyCode::KeyC,
+            pick_up: KeyCode::KeyE,
+            drop: KeyCode::KeyG,
+            read_scroll: KeyCode::KeyR,
+            aim: KeyCode::KeyT,
+            confirm: KeyCode::Enter,
+            cancel: KeyCode::Escape,
+            toggle_camera: KeyCode::KeyO,
+            next_msaa: KeyCode::KeyV,
+            narrower_fov: KeyCode::BracketLeft,
+            wider_fov: KeyCode::BracketRight,
+            toggle_healthbars: KeyCode::KeyH,
+            toggle_fill_light: KeyCode::KeyB,
+            toggle_auto_pickup: KeyCode::KeyU,
+            debug_descend: KeyCode::KeyN
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Defaults when there is no file or it can't be read
+    pub fn load(file_name: &str) -> Self {
+        let Ok(input) = fs::read_to_string(file_name) else {
+            return KeyBindings::default();
+        };
+        serde_json::from_str(&input).unwrap_or_else(|error| {
+            warn!("Ignoring {}: {}", file_name, error);
+            KeyBindings::default()
+        })
+    }
+}
+
+pub struct KeyBindingsPlugin;
+
+impl Plugin for KeyBindingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(KeyBindings::load(KEY_BINDINGS_FILE));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_keys_keep_their_defaults() {
+        let key_bindings: KeyBindings = serde_json::from_str(r#"{ "pick_up": "KeyF", "aim": "KeyY" }"#).unwrap();
+
+        assert_eq!(key_bindings.pick_up, KeyCode::KeyF);
+        assert_eq!(key_bindings.aim, KeyCode::KeyY);
+        assert_eq!(KeyBindings { pick_up: KeyCode::KeyE, aim: KeyCode::KeyT, ..key_bindings }, KeyBindings::default());
+    }
+
+    #[test]
+    fn remapped_attack_key_is_loaded_from_the_file() {
+        let file_name = std::env::temp_dir().join("rust-bevy-rogue-test-keybindings.json");
+        fs::write(&file_name, r#"{ "attack": "KeyK" }"#).unwrap();
+
+        let key_bindings = KeyBindings::load(file_name.to_str().unwrap());
+        fs::remove_file(&file_name).unwrap();
+
+        assert_eq!(key_bindings.attack, KeyCode::KeyK);
+        assert_eq!(KeyBindings { attack: KeyCode::Space, ..key_bindings }, KeyBindings::default());
+    }
+
+    #[test]
+    fn defaults_survive_serialization() {
+        let json = serde_json::to_string(&KeyBindings::default()).unwrap();
+
+        assert_eq!(serde_json::from_str::<KeyBindings>(&json).unwrap(), KeyBindings::default());
+    }
+}
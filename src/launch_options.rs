@@ -0,0 +1,115 @@
+use crate::create_dungeon::{GeneratorKind, MonsterPlacement};
+use crate::WallRendering;
+use crate::fighting::MitigationModel;
+
+pub const USAGE: &str = "Usage: rust-bevy-rogue [OPTIONS]
+
+Options:
+  --monsters-per-room <N>   maximum monsters per room on every floor
+  --seed <N>                seed for the dungeon generator
+  --start-floor <N>         floor of a new game
+  --generator <NAME>        rooms, open, two-rooms, bsp or cave on every floor
+  --placement <NAME>        scatter or formation of the monsters in large rooms
+  --walls <NAME>            per-face or batched wall meshes
+  --audio <on|off>          sound effects, off by default because the sound files are not shipped
+  --mitigation <NAME>       flat, percentage or diminishing damage reduction by defense
+  --lives <N>               lives of a new game instead of permadeath
+  --help                    print this help";
+
+/// Overrides of the default floor parameters from the command line
+#[derive(Debug, Default, PartialEq)]
+pub struct LaunchOptions {
+    pub monsters_per_room: Option<usize>,
+    pub seed: Option<u64>,
+    pub start_floor: Option<usize>,
+    pub generator: Option<GeneratorKind>,
+    pub monster_placement: Option<MonsterPlacement>,
+    pub walls: Option<WallRendering>,
+    pub audio: Option<bool>,
+    pub mitigation: Option<MitigationModel>,
+    pub lives: Option<usize>,
+    pub help: bool
+}
+
+impl LaunchOptions {
+    /// Parses the arguments without the program name
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = LaunchOptions::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--help" {
+                options.help = true;
+                continue;
+            }
+
+            let value = args.next().ok_or(format!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--monsters-per-room" => options.monsters_per_room = Some(parse_number(arg, value)?),
+                "--seed" => options.seed = Some(parse_number(arg, value)?),
+                "--start-floor" => options.start_floor = Some(parse_number(arg, value)?),
+                "--lives" => options.lives = Some(parse_number(arg, value)?),
+                "--generator" => {
+                    options.generator = Some(GeneratorKind::from_name(value)
+                        .ok_or(format!("Unknown generator {}", value))?);
+                }
+                "--placement" => {
+                    options.monster_placement = Some(MonsterPlacement::from_name(value)
+                        .ok_or(format!("Unknown placement {}", value))?);
+                }
+                "--walls" => {
+                    options.walls = Some(WallRendering::from_name(value)
+                        .ok_or(format!("Unknown wall rendering {}", value))?);
+                }
+                "--audio" => {
+                    options.audio = Some(match value.as_str() {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(format!("Unknown audio setting {}", value))
+                    });
+                }
+                "--mitigation" => {
+                    options.mitigation = Some(MitigationModel::from_name(value)
+                        .ok_or(format!("Unknown mitigation {}", value))?);
+                }
+                _ => return Err(format!("Unknown argument {}", arg))
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value {} for {}", value, arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn seed_floor_and_monsters_are_parsed() {
+        let options = LaunchOptions::parse(&args("--seed 42 --start-floor 3 --monsters-per-room 5")).unwrap();
+
+        assert_eq!(options, LaunchOptions {
+            seed: Some(42),
+            start_floor: Some(3),
+            monsters_per_room: Some(5),
+            ..LaunchOptions::default()
+        });
+        assert_eq!(LaunchOptions::parse(&[]).unwrap(), LaunchOptions::default());
+    }
+
+    #[test]
+    fn bad_arguments_give_an_error() {
+        assert_eq!(LaunchOptions::parse(&args("--seed forty-two")), Err("Invalid value forty-two for --seed".to_string()));
+        assert_eq!(LaunchOptions::parse(&args("--start-floor")), Err("Missing value for --start-floor".to_string()));
+        assert_eq!(LaunchOptions::parse(&args("--floor 3")), Err("Unknown argument --floor".to_string()));
+        assert_eq!(LaunchOptions::parse(&args("--audio maybe")), Err("Unknown audio setting maybe".to_string()));
+    }
+}
@@ -1,11 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use bevy::color::palettes::css::{LIGHT_GRAY};
 use bevy::prelude::*;
+use bevy::ecs::system::SystemParam;
 use bevy::window::WindowResolution;
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin};
-use bevy_egui::EguiPlugin;
+use bevy_egui::{egui, EguiPlugin};
 use rand::Rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::f32::consts::PI;
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
@@ -13,14 +17,22 @@ use std::time::Duration;
 use serde::{Serialize, Deserialize};
 
 use orbitcamera::{OrbitCameraPlugin,OrbitCamera};
-use third_person_camera::ThirdPersonCameraPlugin;
-use dungeon_lighting::{DungeonLightingPlugin,place_torch_lights};
+use third_person_camera::{ThirdPersonCameraPlugin, camera_intro_finished};
+use dungeon_lighting::{DungeonLightingPlugin,LightingSettings,spawn_torch_lights};
 use crate::third_person_camera::ThirdPersonCamera;
 use crate::create_dungeon::{StringMapGenerator, DungeonGeneratorStrategy,
-                            MapGeneratorStart, BresenhamLine, DungeonWriter};
-use crate::fighting::{FightingPlugin, Actor, AttackEvent, DamageEvent};
-use crate::chracter_controller::{MonsterAIPlugin,MonsterAIState};
-use crate::ui::{HeadUpDisplay, UiPlugin};
+                            MapGeneratorStart, GeneratorKind, MonsterPlacement, GenError, BresenhamLine, DungeonWriter, Room,
+                            is_reachable};
+use crate::fighting::{FightingPlugin, Actor, AttackEvent, DamageEvent, PlayerDiedEvent, AlwaysShowHealthbars, DamageSettings};
+use crate::chracter_controller::{MonsterAIPlugin,MonsterAIState,StuckDetection,HealCooldown,MonsterAttackCooldown,PathTo,WanderTimer,StatusEffect,has_line_of_sight};
+use crate::ui::{HeadUpDisplay, MessageLog, Minimap, UiPlugin};
+use crate::render_settings::{RenderSettingsPlugin, RenderSettings};
+use crate::launch_options::{LaunchOptions, USAGE};
+use crate::field_of_view::{FieldOfViewPlugin, FieldOfView};
+use crate::user_config::{UserConfigPlugin, UserConfig, USER_CONFIG_FILE};
+use crate::save_slot::SaveSlot;
+use crate::key_bindings::{KeyBindingsPlugin, KeyBindings};
+use crate::sound_effects::{AudioEnabled, PlaySoundEvent, SoundEffect, SoundEffectsPlugin};
 
 mod orbitcamera;
 mod third_person_camera;
@@ -29,12 +41,51 @@ mod create_dungeon;
 mod fighting;
 mod chracter_controller;
 mod ui;
+mod render_settings;
+mod launch_options;
+mod field_of_view;
+mod user_config;
+mod save_slot;
+mod key_bindings;
+mod sound_effects;
 
 #[derive(Debug, Clone, Default, Copy, Eq, PartialEq, Hash, States)]
 enum GameState {
     #[default]
     MainMenu,
-    InGame
+    InGame,
+    GameOver
+}
+
+/// Order of the gameplay systems within a frame, every set sees the results of the sets before
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemSet)]
+enum GameplaySet {
+    /// Keys turned into actions
+    Input,
+    /// Player and projectiles change their position
+    Movement,
+    /// Reactions to the new positions: items, doors, mined walls
+    Collision,
+    /// Attacks, damage and deaths
+    Combat,
+    /// Monsters decide and move
+    AI,
+    /// Fading effects and finished floors
+    Cleanup
+}
+
+impl GameplaySet {
+    /// The sets one after another
+    fn chain() -> impl IntoSystemSetConfigs {
+        (
+            GameplaySet::Input,
+            GameplaySet::Movement,
+            GameplaySet::Collision,
+            GameplaySet::Combat,
+            GameplaySet::AI,
+            GameplaySet::Cleanup
+        ).chain()
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, SubStates)]
@@ -47,19 +98,84 @@ enum TransitionState {
     #[default]
     Running,
     StairsDown,
+    StairsUp,
+}
+
+/// Escape freezes the game and shows the pause menu
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, SubStates)]
+#[source(GameState = GameState::InGame)]
+enum PauseState {
+    #[default]
+    Running,
+    Paused
+}
+
+/// What happens once the game is saved
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AfterSave {
+    Continue,
+    MainMenu,
+    Exit
 }
 
+/// Saves the map, inventory, player and floor of the save slot
+#[derive(Event, Debug)]
+struct SaveGameEvent(AfterSave);
+
 #[derive(Debug, PartialEq, Clone, Eq, Hash,Copy)]
 enum TileType {
     Empty,
     Wall,
     Floor,
     StaircaseDown,
+    StaircaseUp,
     Player,
     Potion,
     Lightning,
+    Scroll,
+    ConfusionScroll,
+    Gold,
     Orc,
-    Troll
+    Troll,
+    Shaman,
+    MineralVein,
+    MineralVeinWithTreasure,
+    DoorClosed,
+    DoorOpen
+}
+
+impl TileType {
+    /// Solid rock, rendered with wall faces
+    fn is_wall(&self) -> bool {
+        matches!(self, TileType::Wall | TileType::MineralVein | TileType::MineralVeinWithTreasure)
+    }
+
+    /// Blocks movement and line of sight: walls and closed doors
+    fn is_blocking(&self) -> bool {
+        self.is_wall() || *self == TileType::DoorClosed
+    }
+
+    /// Ground the player can see from, walls facing it get a mesh.
+    /// A closed door counts, it is opened by walking into it.
+    fn is_open(&self) -> bool {
+        matches!(self, TileType::Floor | TileType::StaircaseDown | TileType::StaircaseUp
+            | TileType::DoorClosed | TileType::DoorOpen)
+    }
+
+    /// Name in the look messages, items and monsters are told by their entities
+    fn description(&self) -> &'static str {
+        match self {
+            TileType::Empty => "solid rock",
+            TileType::Wall => "a wall",
+            TileType::StaircaseDown => "stairs down",
+            TileType::StaircaseUp => "stairs up",
+            TileType::MineralVein => "a mineral vein",
+            TileType::MineralVeinWithTreasure => "a golden mineral vein",
+            TileType::DoorClosed => "a closed door",
+            TileType::DoorOpen => "an open door",
+            _ => "floor"
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -82,28 +198,30 @@ impl TileMapping {
         rows.push(TileRow{character: '#', tile_type: TileType::Wall, item_type: None, monster_type: None});
         rows.push(TileRow{character: '.', tile_type: TileType::Floor, item_type: None, monster_type: None});
         rows.push(TileRow{character: '>', tile_type: TileType::StaircaseDown, item_type: None, monster_type: None});
+        rows.push(TileRow{character: '<', tile_type: TileType::StaircaseUp, item_type: None, monster_type: None});
         rows.push(TileRow{character: '@', tile_type: TileType::Player, item_type: None, monster_type: None});
         rows.push(TileRow{character: '!', tile_type: TileType::Potion, item_type: Some(ItemType::HealPotion), monster_type: None});
         rows.push(TileRow{character: '?', tile_type: TileType::Lightning, item_type: Some(ItemType::Lightning), monster_type: None});
+        rows.push(TileRow{character: '-', tile_type: TileType::Scroll, item_type: Some(ItemType::LightningScroll), monster_type: None});
+        rows.push(TileRow{character: '~', tile_type: TileType::ConfusionScroll, item_type: Some(ItemType::ConfusionScroll), monster_type: None});
+        rows.push(TileRow{character: '$', tile_type: TileType::Gold, item_type: Some(ItemType::Gold), monster_type: None});
         rows.push(TileRow{character: 'o', tile_type: TileType::Orc, item_type: None, monster_type: Some(MonsterType::Orc)});
         rows.push(TileRow{character: 'T', tile_type: TileType::Troll, item_type: None, monster_type: Some(MonsterType::Troll)});
+        rows.push(TileRow{character: 's', tile_type: TileType::Shaman, item_type: None, monster_type: Some(MonsterType::Shaman)});
+        rows.push(TileRow{character: '%', tile_type: TileType::MineralVein, item_type: None, monster_type: None});
+        rows.push(TileRow{character: '*', tile_type: TileType::MineralVeinWithTreasure, item_type: None, monster_type: None});
+        rows.push(TileRow{character: '+', tile_type: TileType::DoorClosed, item_type: None, monster_type: None});
+        rows.push(TileRow{character: '\'', tile_type: TileType::DoorOpen, item_type: None, monster_type: None});
         rows.push(TileRow{character: ' ', tile_type: TileType::Empty, item_type: None, monster_type: None});
 
         /*
         ^   A trap (known)
         ;   A glyph of warding
-        '   An open door
-        <   A staircase up
-        +   A closed door
-        %   A mineral vein
-        *   A mineral vein with treasure
         :   A pile of rubble
         ,   A mushroom (or food)
-        -   A wand or rod
         _   A staff
         =   A ring
         "   An amulet
-        $   Gold or gems
         ~   Lights, Tools, Chests, etc
         &   Multiple items
         /   A pole-arm
@@ -125,8 +243,8 @@ impl TileMapping {
         self.rows.iter().find(|&row| row.tile_type == *tile_type).unwrap().character
     }
 
-    fn get_tile_row(&self, character: char) -> TileRow {
-        self.rows.iter().find(|&row| row.character == character).unwrap().clone()
+    fn get_tile_row(&self, character: char) -> Option<TileRow> {
+        self.rows.iter().find(|&row| row.character == character).cloned()
     }
 
     fn get_tile_type(&self, character: char) -> TileType {
@@ -137,19 +255,33 @@ impl TileMapping {
 #[derive(Debug, PartialEq, Clone)]
 pub struct Tile {
     tile_type: TileType,
-    render_hint: RenderHint
+    render_hint: RenderHint,
+    /// damage taken by a mineral vein
+    damage: usize
 }
 
 impl Tile {
     fn new(tile_type: TileType) -> Self {
         Tile{
             tile_type,
-            render_hint: RenderHint::Empty
+            render_hint: RenderHint::Empty,
+            damage: 0
         }
     }
 
 }
 
+/// Damage a mineral vein takes before it crumbles to floor
+const VEIN_HIT_POINTS: usize = 10;
+
+/// Outcome of GameMap::apply_damage_to_tile
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TileDamage {
+    Indestructible,
+    Damaged,
+    Destroyed { treasure: bool }
+}
+
 const TILE_SIZE: f32 = 4.0;
 
 const ROOM_MAX_SIZE:usize = 10;
@@ -159,35 +291,68 @@ const MAX_ROOMS:usize = 30;
 #[derive(Debug)]
 struct FloorParameterItem{
     max_monsters_per_room: usize,
-    max_items_per_room: usize
+    max_items_per_room: usize,
+    generator: GeneratorKind
 }
 
 #[derive(Debug, Resource)]
 struct FloorParameters{
-    items:Vec<FloorParameterItem>
+    items:Vec<FloorParameterItem>,
+    monster_placement: MonsterPlacement,
+    /// floor of a new game
+    start_floor: usize,
+    seed: Option<u64>
 }
 
 impl FloorParameters {
     fn new() -> Self {
         let mut items: Vec<FloorParameterItem> = Vec::new();
-        items.push(FloorParameterItem { max_monsters_per_room: 2, max_items_per_room: 10 });
-        items.push(FloorParameterItem { max_monsters_per_room: 2, max_items_per_room: 1 });
-        items.push(FloorParameterItem { max_monsters_per_room: 2, max_items_per_room: 1 });
-        items.push(FloorParameterItem { max_monsters_per_room: 3, max_items_per_room: 2 });
-        items.push(FloorParameterItem { max_monsters_per_room: 3, max_items_per_room: 2 });
-        items.push(FloorParameterItem { max_monsters_per_room: 5, max_items_per_room: 2 });
-        items.push(FloorParameterItem { max_monsters_per_room: 5, max_items_per_room: 2 });
-        items.push(FloorParameterItem { max_monsters_per_room: 5, max_items_per_room: 2 });
+        items.push(FloorParameterItem { max_monsters_per_room: 2, max_items_per_room: 10, generator: GeneratorKind::Rooms });
+        items.push(FloorParameterItem { max_monsters_per_room: 2, max_items_per_room: 1, generator: GeneratorKind::Rooms });
+        items.push(FloorParameterItem { max_monsters_per_room: 2, max_items_per_room: 1, generator: GeneratorKind::Rooms });
+        items.push(FloorParameterItem { max_monsters_per_room: 3, max_items_per_room: 2, generator: GeneratorKind::Rooms });
+        items.push(FloorParameterItem { max_monsters_per_room: 3, max_items_per_room: 2, generator: GeneratorKind::Rooms });
+        items.push(FloorParameterItem { max_monsters_per_room: 5, max_items_per_room: 2, generator: GeneratorKind::Rooms });
+        items.push(FloorParameterItem { max_monsters_per_room: 5, max_items_per_room: 2, generator: GeneratorKind::Rooms });
+        items.push(FloorParameterItem { max_monsters_per_room: 5, max_items_per_room: 2, generator: GeneratorKind::Rooms });
 
         Self {
-            items
+            items,
+            monster_placement: MonsterPlacement::default(),
+            start_floor: 0,
+            seed: None
+        }
+    }
+
+    /// Applies the overrides given on the command line
+    fn with_launch_options(mut self, launch_options: &LaunchOptions) -> Self {
+        for item in self.items.iter_mut() {
+            if let Some(monsters_per_room) = launch_options.monsters_per_room {
+                item.max_monsters_per_room = monsters_per_room;
+            }
+            if let Some(generator) = launch_options.generator {
+                item.generator = generator;
+            }
         }
+        if let Some(monster_placement) = launch_options.monster_placement {
+            self.monster_placement = monster_placement;
+        }
+        self.start_floor = launch_options.start_floor.unwrap_or(0);
+        self.seed = launch_options.seed;
+        self
+    }
+
+    /// Parameters of the floor, deeper floors use the last ones
+    fn get(&self, floor: usize) -> &FloorParameterItem {
+        &self.items[floor.min(self.items.len() - 1)]
     }
 }
 #[derive(Clone, Debug)]
 struct ItemAndMonsterParameterItem {
     items: Vec<(ItemType,f32)>,
-    monsters: Vec<(MonsterType,f32)>
+    monsters: Vec<(MonsterType,f32)>,
+    /// only these monster types can appear on the floor, whatever the weights say
+    allowed_monsters: Vec<MonsterType>
 }
 
 #[derive(Resource)]
@@ -200,35 +365,43 @@ impl ItemAndMonsterParameters {
         let mut parameters:Vec<ItemAndMonsterParameterItem> = Vec::new();
         parameters.push(ItemAndMonsterParameterItem{
             items: vec![(ItemType::Lightning,1.0)],
-            monsters: vec![(MonsterType::Orc,1.0)]
+            monsters: vec![(MonsterType::Orc,1.0)],
+            allowed_monsters: vec![MonsterType::Orc]
         });
         parameters.push(ItemAndMonsterParameterItem{
-            items: vec![(ItemType::HealPotion,0.8),(ItemType::Lightning,0.2)],
-            monsters: vec![(MonsterType::Orc,0.8),(MonsterType::Troll,0.2)]
+            items: vec![(ItemType::HealPotion,0.8),(ItemType::Lightning,0.2),(ItemType::Gold,0.2)],
+            monsters: vec![(MonsterType::Orc,0.8),(MonsterType::Troll,0.2)],
+            allowed_monsters: vec![MonsterType::Orc, MonsterType::Troll]
         });
         parameters.push(ItemAndMonsterParameterItem{
-            items: vec![(ItemType::HealPotion,0.8),(ItemType::Lightning,0.2)],
-            monsters: vec![(MonsterType::Orc,0.8),(MonsterType::Troll,0.2)]
+            items: vec![(ItemType::HealPotion,0.8),(ItemType::Lightning,0.2),(ItemType::Gold,0.2)],
+            monsters: vec![(MonsterType::Orc,0.8),(MonsterType::Troll,0.2)],
+            allowed_monsters: vec![MonsterType::Orc, MonsterType::Troll]
         });
         parameters.push(ItemAndMonsterParameterItem{
-            items: vec![(ItemType::HealPotion,0.5),(ItemType::Lightning,0.5)],
-            monsters: vec![(MonsterType::Orc,0.5),(MonsterType::Troll,0.5)]
+            items: vec![(ItemType::HealPotion,0.5),(ItemType::Lightning,0.5),(ItemType::Gold,0.2)],
+            monsters: vec![(MonsterType::Orc,0.4),(MonsterType::Troll,0.4),(MonsterType::Shaman,0.2)],
+            allowed_monsters: vec![MonsterType::Orc, MonsterType::Troll, MonsterType::Shaman]
         });
         parameters.push(ItemAndMonsterParameterItem{
-            items: vec![(ItemType::HealPotion,0.5),(ItemType::Lightning,0.5)],
-            monsters: vec![(MonsterType::Orc,0.5),(MonsterType::Troll,0.5)]
+            items: vec![(ItemType::HealPotion,0.4),(ItemType::Lightning,0.4),(ItemType::LightningScroll,0.2),(ItemType::ConfusionScroll,0.1),(ItemType::Gold,0.2)],
+            monsters: vec![(MonsterType::Orc,0.4),(MonsterType::Troll,0.4),(MonsterType::Shaman,0.2)],
+            allowed_monsters: vec![MonsterType::Orc, MonsterType::Troll, MonsterType::Shaman]
         });
         parameters.push(ItemAndMonsterParameterItem{
-            items: vec![(ItemType::HealPotion,0.5),(ItemType::Lightning,0.5)],
-            monsters: vec![(MonsterType::Orc,0.5),(MonsterType::Troll,0.5)]
+            items: vec![(ItemType::HealPotion,0.4),(ItemType::Lightning,0.4),(ItemType::LightningScroll,0.2),(ItemType::ConfusionScroll,0.1),(ItemType::Gold,0.2)],
+            monsters: vec![(MonsterType::Orc,0.4),(MonsterType::Troll,0.4),(MonsterType::Shaman,0.2)],
+            allowed_monsters: vec![MonsterType::Orc, MonsterType::Troll, MonsterType::Shaman]
         });
         parameters.push(ItemAndMonsterParameterItem{
-            items: vec![(ItemType::HealPotion,0.5),(ItemType::Lightning,0.5)],
-            monsters: vec![(MonsterType::Orc,0.5),(MonsterType::Troll,0.5)]
+            items: vec![(ItemType::HealPotion,0.4),(ItemType::Lightning,0.4),(ItemType::LightningScroll,0.2),(ItemType::ConfusionScroll,0.1),(ItemType::Gold,0.2)],
+            monsters: vec![(MonsterType::Orc,0.4),(MonsterType::Troll,0.4),(MonsterType::Shaman,0.2)],
+            allowed_monsters: vec![MonsterType::Orc, MonsterType::Troll, MonsterType::Shaman]
         });
         parameters.push(ItemAndMonsterParameterItem{
-            items: vec![(ItemType::HealPotion,0.5),(ItemType::Lightning,0.5)],
-            monsters: vec![(MonsterType::Orc,0.5),(MonsterType::Troll,0.5)]
+            items: vec![(ItemType::HealPotion,0.5),(ItemType::Lightning,0.5),(ItemType::Gold,0.2)],
+            monsters: vec![(MonsterType::Orc,0.4),(MonsterType::Troll,0.4),(MonsterType::Shaman,0.2)],
+            allowed_monsters: vec![MonsterType::Orc, MonsterType::Troll, MonsterType::Shaman]
         });
 
         Self {
@@ -236,12 +409,18 @@ impl ItemAndMonsterParameters {
         }
 
     }
+
+    /// Parameters of the floor, deeper floors use the last ones
+    fn get(&self, floor: usize) -> &ItemAndMonsterParameterItem {
+        &self.parameters[floor.min(self.parameters.len() - 1)]
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum RenderHint {
     Empty,
-    RoomFloor
+    RoomFloor,
+    Corridor
 }
 
 #[derive(Clone, Debug)]
@@ -284,7 +463,7 @@ impl Grid {
                                         pos_1.0 as i32,
                                         pos_1.1 as i32,false);
         for i in line {
-            if self[(i.0 as usize, i.1 as usize)].tile_type == TileType::Wall {
+            if self[(i.0 as usize, i.1 as usize)].tile_type.is_blocking() {
                 return true;
             }
         }
@@ -308,12 +487,59 @@ impl std::ops::IndexMut<(usize, usize)> for Grid {
 #[derive(Resource)]
 struct LoadMapAndItems(bool);
 
+/// Real time movement or one tile per key press with a monster turn after it
+#[derive(Debug, Resource, Default, PartialEq)]
+pub enum GameMode {
+    #[default]
+    RealTime,
+    TurnBased
+}
+
+impl GameMode {
+    fn next(&self) -> Self {
+        match self {
+            GameMode::RealTime => GameMode::TurnBased,
+            GameMode::TurnBased => GameMode::RealTime
+        }
+    }
+}
+
+impl fmt::Display for GameMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            GameMode::RealTime => "Real time",
+            GameMode::TurnBased => "Turn based"
+        })
+    }
+}
+
+/// Player turns the monsters still have to answer, only used turn based
+#[derive(Debug, Resource, Default)]
+pub struct PendingTurns(pub usize);
+
+/// Picking up items by walking over them or with the interact key
+#[derive(Debug, Resource)]
+struct Pickup {
+    auto: bool,
+    /// item the player stands on while auto-pickup is off
+    item_in_reach: Option<(Entity, ItemType)>
+}
+
+impl Default for Pickup {
+    fn default() -> Self {
+        Pickup {
+            auto: true,
+            item_in_reach: None
+        }
+    }
+}
+
 #[derive(Resource,Copy, Clone)]
 struct CurrentFloor(usize);
 
 impl CurrentFloor {
-    fn load(file_name: &str) -> Self {
-        let input = fs::read_to_string(file_name).expect("Unable to read file");
+    fn load(save_slot: &SaveSlot) -> Self {
+        let input = fs::read_to_string(save_slot.path(FLOOR_JSON_FILE)).expect("Unable to read file");
         let current_floor:usize = input.parse().expect("Unable to parse floor");
         CurrentFloor(current_floor)
     }
@@ -322,8 +548,13 @@ impl CurrentFloor {
         self.0 += 1;
     }
 
-    fn save(&self) {
-        let mut file = File::create(FLOOR_JSON_FILE).expect("Unable to create file");
+    /// Depth shown in the HUD
+    fn depth_label(&self) -> String {
+        format!("Depth: {}", self.0)
+    }
+
+    fn save(&self, save_slot: &SaveSlot) {
+        let mut file = File::create(save_slot.path(FLOOR_JSON_FILE)).expect("Unable to create file");
         file.write_all(self.0.to_string().as_bytes()).expect("Unable to write data");
     }
 }
@@ -331,46 +562,163 @@ impl CurrentFloor {
 #[derive(Debug,PartialEq,Eq, Hash, Copy, Clone,Serialize, Deserialize)]
 enum ItemType {
     HealPotion,
-    Lightning
+    Lightning,
+    LightningScroll,
+    /// confuses the monster it hits when thrown
+    ConfusionScroll,
+    /// a pile of coins, added to the gold total instead of the items
+    Gold
 }
 
 impl ItemType {
     fn to_string(&self) -> String {
         match self {
             ItemType::HealPotion => String::from("HealPotion"),
-            ItemType::Lightning => String::from("Lightning")
+            ItemType::Lightning => String::from("Lightning"),
+            ItemType::LightningScroll => String::from("LightningScroll"),
+            ItemType::ConfusionScroll => String::from("ConfusionScroll"),
+            ItemType::Gold => String::from("Gold")
         }
     }
 
     fn to_tile_type(&self) -> TileType {
         match self {
             ItemType::HealPotion => TileType::Potion,
-            ItemType::Lightning => TileType::Lightning
+            ItemType::Lightning => TileType::Lightning,
+            ItemType::LightningScroll => TileType::Scroll,
+            ItemType::ConfusionScroll => TileType::ConfusionScroll,
+            ItemType::Gold => TileType::Gold
         }
     }
+
+    /// Can be the active item thrown with <X> or <T>, potions and scrolls have their own keys
+    fn is_throwable(&self) -> bool {
+        matches!(self, ItemType::Lightning | ItemType::ConfusionScroll)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct ItemInMap{
     position: (usize, usize),
-    item_type: ItemType
+    item_type: ItemType,
+    /// coins of a saved gold pile, new floors roll them in setup_item
+    #[serde(default)]
+    gold: Option<usize>
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 enum MonsterType {
     Orc,
-    Troll
+    Troll,
+    /// heals wounded monsters nearby instead of attacking
+    Shaman
 }
 
 impl MonsterType {
     fn to_tile_type(&self) -> TileType {
         match self {
             MonsterType::Orc => TileType::Orc,
-            MonsterType::Troll => TileType::Troll
+            MonsterType::Troll => TileType::Troll,
+            MonsterType::Shaman => TileType::Shaman
+        }
+    }
+
+    /// Table of the monsters, a new monster type is a new entry
+    const fn stats(&self) -> MonsterStats {
+        match self {
+            // green and brutish with a battle axe
+            MonsterType::Orc => MonsterStats {
+                name: "orc",
+                max_hit_points: 10,
+                defense: 0,
+                power: 3,
+                xp_given: 35,
+                body_radius: 0.6,
+                body_length: 1.2,
+                base_color: Color::srgba(0.4, 0.6, 0.3, 1.0),
+                front_size: 0.3,
+                front_position: Vec3::new(0.0, 0.6, -0.6),
+                held: MonsterHeld::Weapon {
+                    arm_size: Vec3::new(0.4, 1.0, 0.4),
+                    arm_offset: 0.8,
+                    arm_tilt: 0.2,
+                    weapon_size: Vec3::new(0.2, 1.2, 0.3),
+                    weapon_position: Vec3::new(0.0, -0.7, -0.3),
+                    weapon_color: Color::srgba(0.5, 0.4, 0.3, 1.0)
+                }
+            },
+            // large and bulky, stone-like with a giant club
+            MonsterType::Troll => MonsterStats {
+                name: "troll",
+                max_hit_points: 16,
+                defense: 1,
+                power: 4,
+                xp_given: 100,
+                body_radius: 0.8,
+                body_length: 1.5,
+                base_color: Color::srgba(0.5, 0.4, 0.3, 1.0),
+                front_size: 0.4,
+                front_position: Vec3::new(0.0, 0.7, -0.7),
+                held: MonsterHeld::Weapon {
+                    arm_size: Vec3::new(0.5, 1.2, 0.5),
+                    arm_offset: 1.0,
+                    arm_tilt: 0.3,
+                    weapon_size: Vec3::new(0.3, 1.5, 0.3),
+                    weapon_position: Vec3::new(0.0, -1.0, -0.3),
+                    weapon_color: Color::srgba(0.4, 0.3, 0.2, 1.0)
+                }
+            },
+            // thin in a purple robe
+            MonsterType::Shaman => MonsterStats {
+                name: "shaman",
+                max_hit_points: 8,
+                defense: 0,
+                power: 0,
+                xp_given: 50,
+                body_radius: 0.5,
+                body_length: 1.4,
+                base_color: Color::srgba(0.45, 0.25, 0.55, 1.0),
+                front_size: 0.25,
+                front_position: Vec3::new(0.0, 0.6, -0.5),
+                held: MonsterHeld::Staff
+            }
         }
     }
 }
 
+/// Values and looks of a monster type
+#[derive(Debug, Clone, Copy)]
+struct MonsterStats {
+    name: &'static str,
+    max_hit_points: usize,
+    defense: usize,
+    power: usize,
+    /// experience on the first floor
+    xp_given: usize,
+    body_radius: f32,
+    body_length: f32,
+    base_color: Color,
+    front_size: f32,
+    front_position: Vec3,
+    held: MonsterHeld
+}
+
+/// What a monster carries
+#[derive(Debug, Clone, Copy)]
+enum MonsterHeld {
+    /// two arms, the right one swings the weapon
+    Weapon {
+        arm_size: Vec3,
+        arm_offset: f32,
+        arm_tilt: f32,
+        weapon_size: Vec3,
+        weapon_position: Vec3,
+        weapon_color: Color
+    },
+    /// a staff with a glowing tip
+    Staff
+}
+
 #[derive(Debug)]
 struct MonsterInMap{
     position: (usize, usize),
@@ -380,80 +728,324 @@ struct MonsterInMap{
 #[derive(Debug, Resource)]
 struct ShowFps(bool);
 
+/// Permadeath or a number of remaining lives
+#[derive(Debug, Resource, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum LivesMode {
+    Permadeath,
+    Lives(usize)
+}
+
+impl LivesMode {
+    fn next(&self) -> Self {
+        match self {
+            LivesMode::Permadeath => LivesMode::Lives(1),
+            LivesMode::Lives(lives) if *lives < 3 => LivesMode::Lives(3),
+            LivesMode::Lives(_) => LivesMode::Permadeath
+        }
+    }
+
+    /// Lives left in the saved game, older saves have none
+    fn load(save_slot: &SaveSlot) -> Option<Self> {
+        let input = fs::read_to_string(save_slot.path(LIVES_JSON_FILE)).ok()?;
+        serde_json::from_str(&input).ok()
+    }
+
+    fn save(&self, save_slot: &SaveSlot) {
+        let mut file = File::create(save_slot.path(LIVES_JSON_FILE)).expect("Unable to create file");
+        let lives_mode = serde_json::to_string(self).expect("Unable to serialize lives");
+        file.write_all(lives_mode.as_bytes()).expect("Unable to write data");
+    }
+}
+
+impl fmt::Display for LivesMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LivesMode::Permadeath => f.write_str("Permadeath"),
+            LivesMode::Lives(lives) => write!(f, "{}", lives)
+        }
+    }
+}
+
+/// Lives of a new game, chosen in the main menu or with --lives
+#[derive(Debug, Resource, Clone, Copy, PartialEq)]
+struct StartingLives(LivesMode);
+
+/// Character values and the inventory screen, the arrows choose an item while it is open
 #[derive(Debug, Resource)]
 struct ShowPlayerValuesAndInventar(bool);
 
+/// Presets for the starting loadout, chosen in the main menu, the resource is the one of the running game
+#[derive(Debug, Resource, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Difficulty {
+    Easy,
+    Normal,
+    Hard
+}
+
+impl Difficulty {
+    fn next(&self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy
+        }
+    }
+
+    /// Difficulty of the saved game, older saves have none
+    fn load(save_slot: &SaveSlot) -> Option<Self> {
+        let input = fs::read_to_string(save_slot.path(DIFFICULTY_JSON_FILE)).ok()?;
+        serde_json::from_str(&input).ok()
+    }
+
+    fn save(&self, save_slot: &SaveSlot) {
+        let mut file = File::create(save_slot.path(DIFFICULTY_JSON_FILE)).expect("Unable to create file");
+        let difficulty = serde_json::to_string(self).expect("Unable to serialize difficulty");
+        file.write_all(difficulty.as_bytes()).expect("Unable to write data");
+    }
+
+    fn settings(&self) -> DifficultySettings {
+        match self {
+            Difficulty::Easy => DifficultySettings {
+                monsters_per_room: 0.75,
+                monster_stats: 0.8,
+                heal_potion_weight: 1.25
+            },
+            Difficulty::Normal => DifficultySettings {
+                monsters_per_room: 1.0,
+                monster_stats: 1.0,
+                heal_potion_weight: 1.0
+            },
+            Difficulty::Hard => DifficultySettings {
+                monsters_per_room: 1.5,
+                monster_stats: 1.25,
+                heal_potion_weight: 0.5
+            }
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard"
+        })
+    }
+}
+
+/// Multipliers of the monsters and potions of a difficulty
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DifficultySettings {
+    monsters_per_room: f32,
+    monster_stats: f32,
+    heal_potion_weight: f32
+}
+
+impl DifficultySettings {
+    fn max_monsters_per_room(&self, max_monsters_per_room: usize) -> usize {
+        (max_monsters_per_room as f32 * self.monsters_per_room).round() as usize
+    }
+
+    /// Monster with scaled hit points, defense and power, it keeps at least one hit point
+    fn monster_actor(&self, max_hit_points: usize, defense: usize, power: usize, xp_given: usize) -> Actor {
+        let scale = |value: usize| (value as f32 * self.monster_stats).round() as usize;
+        let max_hit_points = scale(max_hit_points).max(1);
+        Actor::new(max_hit_points, max_hit_points, scale(defense), scale(power), xp_given)
+    }
+
+    fn item_and_monster_parameters(&self, parameters: &ItemAndMonsterParameterItem) -> ItemAndMonsterParameterItem {
+        let mut parameters = parameters.clone();
+        for (item_type, weight) in parameters.items.iter_mut() {
+            if *item_type == ItemType::HealPotion {
+                *weight *= self.heal_potion_weight;
+            }
+        }
+        parameters
+    }
+}
+
+/// Growth of the monster stats per floor below the first one
+const DEPTH_SCALE_PER_FLOOR: f32 = 0.15;
+
+/// Monster hit points, defense and power grow with the floor, the experience
+/// for the kill in the same measure
+fn depth_scaled_stats(base: Actor, floor: usize) -> Actor {
+    let factor = 1.0 + DEPTH_SCALE_PER_FLOOR * floor as f32;
+    let scale = |value: usize| (value as f32 * factor).round() as usize;
+    let max_hit_points = scale(base.max_hit_points);
+    Actor {
+        max_hit_points,
+        hit_points: max_hit_points,
+        defense: scale(base.defense),
+        power: scale(base.power),
+        xp_given: scale(base.xp_given),
+        ..base
+    }
+}
+
+/// Stats and items of the player in a new game
+#[derive(Debug, Resource, Clone)]
+struct StartingLoadout {
+    difficulty: Difficulty,
+    hit_points: usize,
+    defense: usize,
+    power: usize,
+    items: Vec<ItemType>
+}
+
+impl StartingLoadout {
+    fn new(difficulty: Difficulty) -> Self {
+        match difficulty {
+            Difficulty::Easy => StartingLoadout {
+                difficulty,
+                hit_points: 40,
+                defense: 3,
+                power: 6,
+                items: vec![ItemType::HealPotion]
+            },
+            Difficulty::Normal => StartingLoadout {
+                difficulty,
+                hit_points: 30,
+                defense: 2,
+                power: 5,
+                items: Vec::new()
+            },
+            Difficulty::Hard => StartingLoadout {
+                difficulty,
+                hit_points: 20,
+                defense: 1,
+                power: 4,
+                items: Vec::new()
+            }
+        }
+    }
+
+    fn inventory(&self) -> Inventory {
+        let mut inventory = Inventory::new();
+        for item_type in self.items.iter() {
+            inventory.add_item(*item_type);
+        }
+        inventory
+    }
+}
+
 #[derive(Debug, Resource, Serialize, Deserialize)]
+#[serde(from = "InventoryFile")]
 struct Inventory{
-    heal_potion: usize,
     items:HashMap<ItemType, usize>,
-    activ_item:Option<ItemType>
+    activ_item:Option<ItemType>,
+    /// coins picked up, older saves have none
+    gold: usize
+}
+
+/// Inventory as saved, older saves count the heal potions apart from the items
+#[derive(Deserialize)]
+struct InventoryFile {
+    items: HashMap<ItemType, usize>,
+    activ_item: Option<ItemType>,
+    #[serde(default)]
+    gold: usize,
+    #[serde(default)]
+    heal_potion: usize
+}
+
+impl From<InventoryFile> for Inventory {
+    fn from(file: InventoryFile) -> Self {
+        let mut items = file.items;
+        if file.heal_potion > 0 {
+            *items.entry(ItemType::HealPotion).or_insert(0) += file.heal_potion;
+        }
+        Inventory {
+            items,
+            activ_item: file.activ_item,
+            gold: file.gold
+        }
+    }
 }
 
 impl Inventory {
     fn new() -> Self {
         Inventory{
-            heal_potion: 0,
             items:HashMap::new(),
-            activ_item: None
+            activ_item: None,
+            gold: 0
         }
     }
 
+    fn count(&self, item_type: ItemType) -> usize {
+        self.items.get(&item_type).copied().unwrap_or(0)
+    }
+
     fn add_item(&mut self, item_type: ItemType) {
-        println!("Item added: {:?}", item_type);
-        if item_type == ItemType::HealPotion {
-            self.heal_potion += 1;
-        } else {
-            *self.items.entry(item_type).or_insert(0) += 1;
-            if self.activ_item == None {
-                self.activ_item = Some(item_type);
-            }
+        info!("Item added: {:?}", item_type);
+        *self.items.entry(item_type).or_insert(0) += 1;
+        // potions are drunk with <P> and scrolls read with <R>, not activated
+        if self.activ_item == None && item_type.is_throwable() {
+            self.activ_item = Some(item_type);
         }
     }
 
     fn remove_item(&mut self, item_type: ItemType) {
-        if self.heal_potion > 0 {
-            self.heal_potion -=1;
-        }
-        else {
-            if let Some(value) = self.items.get_mut(&item_type) {
-                if *value > 1 {
-                    *value -= 1;
-                } else {
-                    self.items.remove(&item_type);
-                    if let Some(active_type) = self.activ_item {
-                        if active_type == item_type {
-                            if self.items.len() == 0 {
-                                self.activ_item = None;
-                            } else {
-                                if let Some((item_type,_)) = self.items.iter().next() {
-                                    self.activ_item = Some(*item_type);
-                                }
-                            }
-                        }
-                    }
+        if let Some(value) = self.items.get_mut(&item_type) {
+            if *value > 1 {
+                *value -= 1;
+            } else {
+                self.items.remove(&item_type);
+                if self.activ_item == Some(item_type) {
+                    self.activ_item = self.items.keys()
+                        .find(|item_type| item_type.is_throwable())
+                        .copied();
                 }
             }
         }
     }
+    /// Next throwable item of the inventory becomes the active one
+    fn switch_active_item(&mut self) {
+        let mut throwables: Vec<ItemType> = self.items.keys()
+            .filter(|item_type| item_type.is_throwable())
+            .copied()
+            .collect();
+        throwables.sort_by_key(|item_type| item_type.to_string());
+        let next = match self.activ_item.and_then(|active| throwables.iter().position(|item_type| *item_type == active)) {
+            Some(index) => (index + 1) % throwables.len(),
+            None => 0
+        };
+        self.activ_item = throwables.get(next).copied();
+    }
+
+    /// The ball in the hand shows while there is something left to throw
+    fn held_ball_visibility(&self) -> Visibility {
+        if self.activ_item.is_some_and(|item_type| self.count(item_type) > 0) {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        }
+    }
+
+    /// Label of the heal potions in the head up display
+    fn potion_label(&self) -> String {
+        format!("<P>ortion {}", self.count(ItemType::HealPotion))
+    }
+
     fn get_active_item_name(&self) -> String {
         match self.activ_item {
             Some(item_type) => {
-                let sum =  self.items[&item_type];
+                let sum = self.count(item_type);
                 format!("<X> {} {}", item_type.to_string(), sum)
             },
             None => "nothing active".to_string()
         }
     }
 
-    fn save(&self) {
-        let mut file = File::create(INVENTORY_JSON_FILE).expect("Unable to create file");
+    fn save(&self, save_slot: &SaveSlot) {
+        let mut file = File::create(save_slot.path(INVENTORY_JSON_FILE)).expect("Unable to create file");
         let inventory = serde_json::to_string(self).expect("Unable to serialize inventory");
         file.write_all(inventory.as_bytes()).expect("Unable to write data");
     }
 
-    fn load(file_name: &str) -> Self {
-        let input = fs::read_to_string(file_name).expect("Unable to read file");
+    fn load(save_slot: &SaveSlot) -> Self {
+        let input = fs::read_to_string(save_slot.path(INVENTORY_JSON_FILE)).expect("Unable to read file");
         let inventory:Inventory = serde_json::from_str(&input).expect("Unable to parse inventory");
         inventory
     }
@@ -466,24 +1058,88 @@ struct GameMap {
     player_position: (usize, usize),
     monsters: Vec<MonsterInMap>,
     items: Vec<ItemInMap>,
-    center: (usize, usize),
     width: usize,
-    height: usize
+    height: usize,
+    player_start_room: Option<Room>
+}
+
+/// Positions of player and monsters per grid cell, rebuilt every frame
+#[derive(Resource, Default)]
+struct Occupancy {
+    cells: HashMap<(usize, usize), Vec<(Entity, Vec3)>>
+}
+
+impl Occupancy {
+    fn insert(&mut self, cell: (usize, usize), entity: Entity, position: Vec3) {
+        self.cells.entry(cell).or_default().push((entity, position));
+    }
+
+    fn remove(&mut self, cell: (usize, usize), entity: Entity) {
+        if let Some(actors) = self.cells.get_mut(&cell) {
+            actors.retain(|(actor, _)| *actor != entity);
+        }
+    }
+
+    /// Keeps the index valid for actors that move later in the same frame
+    fn update(&mut self, game_map: &GameMap, entity: Entity, old_position: Vec3, new_position: Vec3) {
+        self.remove(game_map.world_to_grid(old_position), entity);
+        self.insert(game_map.world_to_grid(new_position), entity, new_position);
+    }
+}
+
+/// Outcome of a grid move, see GameMap::grid_step
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GridStep {
+    Move((usize, usize)),
+    Attack(Entity),
+    Blocked
 }
 
+/// Collision radius of player and monsters
+const ACTOR_DISTANCE: f32 = 0.5;
+
 const MAP_TEXT_FILE: &'static str = "dungeon.map";
 const INVENTORY_JSON_FILE: &'static str = "inventory.json";
 const ACTOR_JSON_FILE: &'static str = "actor.json";
 const FLOOR_JSON_FILE: &'static str = "floor.json";
+const ITEMS_JSON_FILE: &str = "items.json";
+const LIVES_JSON_FILE: &str = "lives.json";
+const DIFFICULTY_JSON_FILE: &str = "difficulty.json";
+
+const EXPLORED_JSON_FILE: &str = "explored.json";
+
+/// Cells the player has seen on the saved floor
+fn save_explored_cells(path: impl AsRef<std::path::Path>, explored: &HashSet<(usize, usize)>) {
+    let explored: Vec<&(usize, usize)> = explored.iter().collect();
+    let explored = serde_json::to_string(&explored).expect("Unable to serialize explored cells");
+    if let Err(error) = fs::write(path, explored) {
+        warn!("Unable to save the explored cells: {}", error);
+    }
+}
+
+/// Explored cells of the saved floor, a save without them starts unexplored
+fn load_explored_cells(path: impl AsRef<std::path::Path>) -> HashSet<(usize, usize)> {
+    let explored: Vec<(usize, usize)> = fs::read_to_string(path)
+        .ok()
+        .and_then(|input| serde_json::from_str(&input).ok())
+        .unwrap_or_default();
+    explored.into_iter().collect()
+}
 
 impl GameMap {
-    fn from_string(map_string: &str) -> Result<Self, String> {
+    fn from_string(map_string: &str) -> Result<Self, GenError> {
         StringMapGenerator{map_string: map_string.to_string()}.generate()
     }
 
-    fn load(file_name: &str) -> Self{
-        let input = fs::read_to_string(file_name).expect("Unable to read file");
-        let game_map = GameMap::from_string(&input).expect("Failed to parse level");
+    fn load(save_slot: &SaveSlot) -> Self{
+        let input = fs::read_to_string(save_slot.path(MAP_TEXT_FILE)).expect("Unable to read file");
+        let mut game_map = GameMap::from_string(&input).expect("Failed to parse level");
+        // the map text has one character per cell, an item under a monster is only in the item file,
+        // older saves have no item file
+        if let Some(items) = fs::read_to_string(save_slot.path(ITEMS_JSON_FILE)).ok()
+            .and_then(|input| serde_json::from_str(&input).ok()) {
+            game_map.items = items;
+        }
         game_map
     }
 
@@ -503,34 +1159,80 @@ No runtime overhead
 Compiler optimizations possible
      */
 
-    fn create_dungeon(generator: impl DungeonGeneratorStrategy) -> Result<Self, String> {
+    fn create_dungeon(generator: impl DungeonGeneratorStrategy) -> Result<Self, GenError> {
         generator.generate()
     }
 
+    /// Invariants every playable map keeps, one message per violation
+    fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
 
-    fn print(
-        &self,
-        player: Vec3,
-        items: Vec<(Vec3, ItemType)>,
-        monsters:Vec<(Vec3,MonsterType)>
-    ) {
-        let writer = DungeonWriter::default();
-        println!("{}", writer.write(self,player,items,monsters));
+        if self.grid.height() != self.height || self.grid.inner.iter().any(|row| row.len() != self.width) {
+            errors.push(format!("grid is not a rectangle of {}x{}", self.width, self.height));
+            return Err(errors);
+        }
+
+        let walkable = |cell: (usize, usize)| {
+            let tile_type = self.grid[cell].tile_type;
+            tile_type != TileType::Empty && !tile_type.is_blocking()
+        };
+
+        let player = self.player_position;
+        if !self.grid.is_valid_position(player.0, player.1) {
+            errors.push(format!("player at {:?} is outside the map", player));
+        } else if !walkable(player) {
+            errors.push(format!("player at {:?} is on {:?}", player, self.grid[player].tile_type));
+        } else {
+            let stairs: Vec<(usize, usize)> = (0..self.height)
+                .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+                .filter(|cell| self.grid[*cell].tile_type == TileType::StaircaseDown)
+                .collect();
+            let reachable = stairs.iter().filter(|cell| is_reachable(&self.grid, player, **cell)).count();
+            if stairs.len() != 1 || reachable != 1 {
+                errors.push(format!("{} stairs down, {} of them reachable, instead of one", stairs.len(), reachable));
+            }
+        }
+
+        let entities = self.monsters.iter().map(|monster| (format!("{:?}", monster.monster_type), monster.position))
+            .chain(self.items.iter().map(|item| (format!("{:?}", item.item_type), item.position)));
+        for (name, position) in entities {
+            if !self.grid.is_valid_position(position.0, position.1) {
+                errors.push(format!("{} at {:?} is outside the map", name, position));
+            } else if !walkable(position) {
+                errors.push(format!("{} at {:?} is on {:?}", name, position, self.grid[position].tile_type));
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
     }
 
     fn save(
         &self,
+        save_slot: &SaveSlot,
         player: Vec3,
-        items: Vec<(Vec3, ItemType)>,
+        items: Vec<(Vec3, ItemType, Option<usize>)>,
         monsters:Vec<(Vec3,MonsterType)>
     ) {
         let writer = DungeonWriter::default();
 
+        let saved_items: Vec<ItemInMap> = items.iter()
+            .map(|(position, item_type, gold)| ItemInMap {
+                position: self.world_to_grid(*position),
+                item_type: *item_type,
+                gold: *gold
+            })
+            .collect();
+
+        let items = items.into_iter().map(|(position, item_type, _)| (position, item_type)).collect();
         let map_text = writer.write(self, player, items, monsters);
 
-        let mut file = File::create(MAP_TEXT_FILE).expect("Unable to create file");
+        let mut file = File::create(save_slot.path(MAP_TEXT_FILE)).expect("Unable to create file");
 
         file.write_all(map_text.as_bytes()).expect("Unable to write data");
+
+        let mut file = File::create(save_slot.path(ITEMS_JSON_FILE)).expect("Unable to create file");
+        let saved_items = serde_json::to_string(&saved_items).expect("Unable to serialize items");
+        file.write_all(saved_items.as_bytes()).expect("Unable to write data");
     }
 
     fn to_string(
@@ -561,156 +1263,831 @@ Compiler optimizations possible
         parts.into_iter().collect()
     }
 
+    /// Rasterizes the grid into an image with one pixel per cell, the player is marked
+    /// Cells outside the explored ones stay transparent, without a set the whole map is shown
+    fn to_minimap_texture(&self, player_position: (usize, usize), explored: Option<&HashSet<(usize, usize)>>) -> egui::ColorImage {
+        const MINIMAP_ALPHA: u8 = 180;
+        let mut pixels: Vec<u8> = Vec::with_capacity(self.width * self.height * 4);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let color = if player_position == (x, y) {
+                    [57, 255, 20, 255]
+                } else if explored.is_some_and(|explored| !explored.contains(&(x, y))) {
+                    [0, 0, 0, 0]
+                } else {
+                    match self.grid[(x, y)].tile_type {
+                        TileType::Empty => [0, 0, 0, 0],
+                        TileType::Wall => [128, 128, 128, MINIMAP_ALPHA],
+                        TileType::MineralVein => [140, 90, 50, MINIMAP_ALPHA],
+                        TileType::MineralVeinWithTreasure => [220, 170, 40, MINIMAP_ALPHA],
+                        TileType::StaircaseDown => [255, 200, 0, 255],
+                        TileType::StaircaseUp => [0, 200, 255, 255],
+                        TileType::DoorClosed => [150, 90, 40, MINIMAP_ALPHA],
+                        _ => [40, 40, 40, MINIMAP_ALPHA]
+                    }
+                };
+                pixels.extend_from_slice(&color);
+            }
+        }
+
+        egui::ColorImage::from_rgba_unmultiplied([self.width, self.height], &pixels)
+    }
+
+    /// Grid cell at the world origin, always taken from the real grid size
+    fn center(&self) -> (usize, usize) {
+        (self.grid.width() / 2, self.grid.height() / 2)
+    }
+
     fn grid_to_world(&self, x:usize, y:usize) -> Vec3 {
-        Vec3::new((x as f32 - self.center.0 as f32) * TILE_SIZE,
+        let center = self.center();
+        Vec3::new((x as f32 - center.0 as f32) * TILE_SIZE,
                   0.0,
-                  (y as f32 - self.center.1 as f32) * TILE_SIZE)
+                  (y as f32 - center.1 as f32) * TILE_SIZE)
 
     }
 
+    /// Cell of the world position, positions outside the map give the nearest border cell
     fn world_to_grid(&self, position: Vec3) -> (usize, usize) {
-        let x = ((position.x+0.5*TILE_SIZE) / TILE_SIZE + self.center.0 as f32) as usize;
-        let y = ((position.z+0.5*TILE_SIZE) / TILE_SIZE + self.center.1 as f32) as usize;
+        let center = self.center();
+        let x = ((position.x+0.5*TILE_SIZE) / TILE_SIZE + center.0 as f32)
+            .clamp(0.0, (self.grid.width() - 1) as f32) as usize;
+        let y = ((position.z+0.5*TILE_SIZE) / TILE_SIZE + center.1 as f32)
+            .clamp(0.0, (self.grid.height() - 1) as f32) as usize;
         (x, y)
     }
 
-    fn collide_with_wall(&self, position:Vec3, distance:f32)->bool{
-        let directions = vec![
-            Vec3::new(0.0,0.0,-distance),
-            Vec3::new(0.0,0.0,distance),
-            Vec3::new(distance,0.0,0.0),
-            Vec3::new(-distance,0.0,0.0),
-        ];
+    /// Nearest floor cell (breadth first) not occupied by a monster
+    fn nearest_free_cell(&self, start: (usize, usize)) -> (usize, usize) {
+        let mut visited: HashSet<(usize, usize)> = HashSet::new();
+        let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+        queue.push_back(start);
+        visited.insert(start);
+
+        while let Some(cell) = queue.pop_front() {
+            let tile_type = self.grid[cell].tile_type;
+            if tile_type == TileType::Floor &&
+                !self.monsters.iter().any(|monster| monster.position == cell) {
+                return cell;
+            }
+            if cell != start && !tile_type.is_open() {
+                continue;
+            }
 
-        for i in directions {
-            let new_position = position + i;
-            let map_up =  self.world_to_grid(new_position);
-            if self.grid[map_up].tile_type == TileType::Wall {
-                return true
+            let (x, y) = (cell.0 as i32, cell.1 as i32);
+            for (next_x, next_y) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                if next_x < 0 || next_y < 0 {
+                    continue;
+                }
+                let next = (next_x as usize, next_y as usize);
+                if self.grid.is_valid_position(next.0, next.1) && visited.insert(next) {
+                    queue.push_back(next);
+                }
             }
         }
-
-        false
+        start
     }
 
-    fn generate(
-        &mut self,
-        commands: &mut Commands,
-        current_floor: &mut ResMut<CurrentFloor>,
-        asset_server: &Res<AssetServer>,
-        meshes: &mut ResMut<Assets<Mesh>>,
-        materials: &mut ResMut<Assets<StandardMaterial>>,
-    ) {
-        // By default AssetServer will load assets from inside the "assets" folder.
-        // For example, the next line will load GltfAssetLabel::Primitive{mesh:0,primitive:0}.from_asset("ROOT/assets/models/cube/cube.gltf"),
-        // where "ROOT" is the directory of the Application.
-        //
-        // This can be overridden by setting [`AssetPlugin.file_path`].
-        let abstract_mesh = false;
-        let wall_size:f32 = 1.0;
-        let mut rng = rand::thread_rng();
+    /// Moves the player arriving on a new floor off a monster or a wall to the nearest free cell
+    fn move_player_to_free_cell(&mut self, player: &mut Transform) {
+        let free_cell = self.nearest_free_cell(self.player_position);
+        self.player_position = free_cell;
+        if free_cell != self.world_to_grid(player.translation) {
+            let position = self.grid_to_world(free_cell.0, free_cell.1);
+            player.translation.x = position.x;
+            player.translation.z = position.z;
+        }
+    }
 
-        let wall_handle:Handle<Scene> = asset_server.load("models/wall.gltf#Scene0");
+    /// Free floor cell of the start room nearest to the start of the floor, maps without
+    /// a start room (caves, loaded floors) respawn the player at the start itself
+    fn respawn_cell(&self, occupancy: &Occupancy, player: Entity) -> (usize, usize) {
+        let Some(start_room) = &self.player_start_room else { return self.player_position };
+        let start = self.player_position;
+        start_room.cells()
+            .filter(|cell| self.grid[*cell].tile_type == TileType::Floor)
+            .filter(|cell| occupancy.cells.get(cell)
+                .is_none_or(|actors| actors.iter().all(|(entity, _)| *entity == player)))
+            .min_by_key(|cell| cell.0.abs_diff(start.0) + cell.1.abs_diff(start.1))
+            .unwrap_or(start)
+    }
 
-        let floor_handle:Handle<Scene> = asset_server.load("models/floor_dirt_large.gltf#Scene0");
+    /// Wall around the position or another actor (player or monster) too close
+    fn is_occupied(&self, occupancy: &Occupancy, position: Vec3, mover: Entity) -> bool {
+        if self.collide_circle(position, ACTOR_DISTANCE) {
+            return true;
+        }
 
-        let floor_room_handle:Handle<Scene> = asset_server.load("models/floor_tile_large.gltf#Scene0");
+        let (x, y) = self.world_to_grid(position);
+        for cell_x in x.saturating_sub(1)..=x + 1 {
+            for cell_y in y.saturating_sub(1)..=y + 1 {
+                if let Some(actors) = occupancy.cells.get(&(cell_x, cell_y)) {
+                    if actors.iter().any(|(entity, actor_position)| {
+                        *entity != mover && actor_position.distance(position) <= ACTOR_DISTANCE * 2.0
+                    }) {
+                        return true;
+                    }
+                }
+            }
+        }
 
+        false
+    }
+
+    /// Living monster closest to the player and its distance, searched in growing
+    /// rings of cells around the player
+    fn nearest_monster(&self, occupancy: &Occupancy, player: Entity, position: Vec3) -> Option<(Entity, f32)> {
+        let (x, y) = self.world_to_grid(position);
+        let mut nearest: Option<(Entity, f32)> = None;
+
+        for ring in 0..self.width.max(self.height) {
+            // every cell of this ring and the ones after is farther away
+            if nearest.is_some_and(|(_, distance)| distance < (ring as f32 - 1.0) * TILE_SIZE) {
+                break;
+            }
+            for cell_x in x.saturating_sub(ring)..=x + ring {
+                for cell_y in y.saturating_sub(ring)..=y + ring {
+                    if cell_x.abs_diff(x).max(cell_y.abs_diff(y)) != ring {
+                        continue;
+                    }
+                    let Some(actors) = occupancy.cells.get(&(cell_x, cell_y)) else { continue };
+                    for (entity, actor_position) in actors {
+                        let distance = actor_position.distance(position);
+                        if *entity != player && nearest.is_none_or(|(_, nearest_distance)| distance < nearest_distance) {
+                            nearest = Some((*entity, distance));
+                        }
+                    }
+                }
+            }
+        }
+
+        nearest
+    }
+
+    /// Result of a one cell step on the grid: moving into a monster attacks it (bump attack)
+    fn grid_step(&self, occupancy: &Occupancy, mover: Entity, from: (usize, usize), direction: (i32, i32)) -> GridStep {
+        let (x, y) = (from.0 as i32 + direction.0, from.1 as i32 + direction.1);
+        if x < 0 || y < 0 || !self.grid.is_valid_position(x as usize, y as usize) {
+            return GridStep::Blocked;
+        }
+        let target = (x as usize, y as usize);
+
+        if let Some(actors) = occupancy.cells.get(&target) {
+            if let Some((entity, _)) = actors.iter().find(|(entity, _)| *entity != mover) {
+                return GridStep::Attack(*entity);
+            }
+        }
+
+        let tile_type = self.grid[target].tile_type;
+        if tile_type.is_blocking() || tile_type == TileType::Empty {
+            GridStep::Blocked
+        } else {
+            GridStep::Move(target)
+        }
+    }
+
+    /// Circle of an actor or ball overlapping a wall, closed door or empty tile: every cell
+    /// under the bounding box of the circle is tested against the square of its tile
+    fn collide_circle(&self, center: Vec3, radius: f32) -> bool {
+        let (min_x, min_y) = self.world_to_grid(center - Vec3::new(radius, 0.0, radius));
+        let (max_x, max_y) = self.world_to_grid(center + Vec3::new(radius, 0.0, radius));
+        let half_tile = 0.5 * TILE_SIZE;
+
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let tile_type = self.grid[(x, y)].tile_type;
+                if !tile_type.is_blocking() && tile_type != TileType::Empty {
+                    continue;
+                }
+                // point of the tile square closest to the center
+                let tile_center = self.grid_to_world(x, y);
+                let closest_x = center.x.clamp(tile_center.x - half_tile, tile_center.x + half_tile);
+                let closest_z = center.z.clamp(tile_center.z - half_tile, tile_center.z + half_tile);
+                if (center.x - closest_x).powi(2) + (center.z - closest_z).powi(2) < radius * radius {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Closed door touched by an actor at the position
+    fn closed_door_near(&self, position: Vec3, distance: f32) -> Option<(usize, usize)> {
+        [Vec3::new(0.0, 0.0, -distance), Vec3::new(0.0, 0.0, distance),
+         Vec3::new(distance, 0.0, 0.0), Vec3::new(-distance, 0.0, 0.0)].into_iter()
+            .map(|offset| self.world_to_grid(position + offset))
+            .find(|cell| self.grid[*cell].tile_type == TileType::DoorClosed)
+    }
 
+    /// A move into a diagonal neighbour cell squeezing between two walls
+    fn cuts_wall_corner(&self, from: Vec3, to: Vec3) -> bool {
+        let (from_x, from_y) = self.world_to_grid(from);
+        let (to_x, to_y) = self.world_to_grid(to);
+        if from_x == to_x || from_y == to_y {
+            return false;
+        }
+
+        self.grid[(to_x, from_y)].tile_type.is_blocking() && self.grid[(from_x, to_y)].tile_type.is_blocking()
+    }
+
+    /// Mining a mineral vein, a destroyed vein becomes floor closed in by walls
+    fn apply_damage_to_tile(&mut self, cell: (usize, usize), damage: usize) -> TileDamage {
+        let tile = &mut self.grid[cell];
+        let treasure = match tile.tile_type {
+            TileType::MineralVein => false,
+            TileType::MineralVeinWithTreasure => true,
+            _ => return TileDamage::Indestructible
+        };
+
+        tile.damage += damage;
+        if tile.damage < VEIN_HIT_POINTS {
+            return TileDamage::Damaged;
+        }
+
+        tile.tile_type = TileType::Floor;
+        tile.damage = 0;
+
+        for neighbour in self.neighbours(cell) {
+            if self.grid[neighbour].tile_type == TileType::Empty {
+                self.grid[neighbour].tile_type = TileType::Wall;
+            }
+        }
+
+        TileDamage::Destroyed { treasure }
+    }
+
+    fn neighbours(&self, (x, y): (usize, usize)) -> Vec<(usize, usize)> {
+        let (x, y) = (x as i32, y as i32);
+        [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].into_iter()
+            .filter(|(x, y)| *x >= 0 && *y >= 0 && self.grid.is_valid_position(*x as usize, *y as usize))
+            .map(|(x, y)| (x as usize, y as usize))
+            .collect()
+    }
+
+    /// Replaces the meshes of the given cells after the grid changed
+    fn respawn_cells(
+        &self,
+        commands: &mut Commands,
+        cell_meshes: &Query<(Entity, &CellMesh)>,
+        wall_chunks: &Query<(Entity, &WallChunk)>,
+        cells: &[(usize, usize)],
+        floor: usize,
+        wall_rendering: WallRendering,
+        handles: &CellHandles,
+        game_rng: &mut GameRng,
+        meshes: &mut ResMut<Assets<Mesh>>,
+        materials: &mut ResMut<Assets<StandardMaterial>>,
+    ) {
+        for (entity, cell_mesh) in cell_meshes.iter() {
+            if cells.contains(&cell_mesh.0) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+
+        for cell in cells {
+            self.spawn_cell(commands, *cell, floor, wall_rendering, handles, game_rng, meshes, materials);
+        }
+
+        if wall_rendering == WallRendering::Batched {
+            let mut chunks: Vec<(usize, usize)> = cells.iter()
+                .map(|(x, y)| (x / WALL_CHUNK_SIZE, y / WALL_CHUNK_SIZE))
+                .collect();
+            chunks.sort();
+            chunks.dedup();
+            for (entity, wall_chunk) in wall_chunks.iter() {
+                if chunks.contains(&wall_chunk.chunk) {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            for chunk in chunks {
+                self.spawn_wall_chunk(commands, chunk, floor, handles);
+            }
+        }
+    }
+
+    fn generate(
+        &mut self,
+        commands: &mut Commands,
+        current_floor: &mut ResMut<CurrentFloor>,
+        wall_rendering: WallRendering,
+        handles: &CellHandles,
+        game_rng: &mut GameRng,
+        meshes: &mut ResMut<Assets<Mesh>>,
+        materials: &mut ResMut<Assets<StandardMaterial>>,
+    ) {
         for y in 0..self.height {
             for x in 0..self.width {
-                match self.grid[(x,y)].tile_type {
-                    TileType::Wall => {
-                        let position = self.grid_to_world(x,y);
-                        if abstract_mesh {
-                            /*let entity = commands.spawn(PbrBundle {
-                           mesh: meshes.add(Mesh::from(Cuboid::new(TILE_SIZE,TILE_SIZE,TILE_SIZE))),
-                           material: materials.add(Color::Srgba(DARK_GRAY)),
-                           transform: Transform::from_xyz(position.x,TILE_SIZE/2.0,position.z),
-                           ..default()
-                       })
-                           .id();*/
-                        } else {
-                            //right
-                            if x != self.width-1 && self.grid[(x+1,y)].tile_type == TileType::Floor {
-                                commands.spawn((
-                                    SceneRoot( wall_handle.clone()),
-                                    Transform {
-                                        translation:  Vec3::new(position.x+TILE_SIZE*0.5-wall_size*0.5,0.0,position.z),
-                                        rotation: Quat::from_rotation_y(PI/2.0),
-                                        ..default()
-                                    },
-                                    Floor(current_floor.0)
-                                ));
-                            }
-                            //left
-                            if x != 0 && self.grid[(x-1,y)].tile_type == TileType::Floor {
-                                commands.spawn((
-                                    SceneRoot( wall_handle.clone()),
-                                    Transform {
-                                        translation:  Vec3::new(position.x-TILE_SIZE*0.5+wall_size*0.5,0.0,position.z),
-                                        rotation: Quat::from_rotation_y(PI/2.0),
-                                        ..default()
-                                    },
-                                    Floor(current_floor.0)
-                                ));
-                            }
-                            //up
-                            if y != 0 && self.grid[(x,y-1)].tile_type == TileType::Floor {
-                                commands.spawn((
-                                    SceneRoot(wall_handle.clone()),
-                                    Transform {
-                                        translation:  Vec3::new(position.x,0.0,position.z-TILE_SIZE*0.5+wall_size*0.5),
-                                        //rotation: Quat::from_rotation_y(PI/2.0),
-                                        ..default()
-                                    },
-                                    Floor(current_floor.0)
-                                ));
-                            }
-                            //down
-                            if y != self.height-1 && self.grid[(x,y+1)].tile_type == TileType::Floor {
-                                commands.spawn((
-                                    SceneRoot( wall_handle.clone()),
-                                    Transform {
-                                        translation:  Vec3::new(position.x,0.0,position.z+TILE_SIZE*0.5-wall_size*0.5),
-                                        //rotation: Quat::from_rotation_y(PI/2.0),
-                                        ..default()
-                                    },
-                                    Floor(current_floor.0)
-                                ));
-                            }
-                        }
-                    },
-                    TileType::Floor => {
-                        let position = self.grid_to_world(x,y);
-                        if abstract_mesh {
+                self.spawn_cell(commands, (x, y), current_floor.0, wall_rendering, handles, game_rng, meshes, materials);
+            }
+        }
+
+        if wall_rendering == WallRendering::Batched {
+            for chunk_y in 0..self.height.div_ceil(WALL_CHUNK_SIZE) {
+                for chunk_x in 0..self.width.div_ceil(WALL_CHUNK_SIZE) {
+                    self.spawn_wall_chunk(commands, (chunk_x, chunk_y), current_floor.0, handles);
+                }
+            }
+        }
+    }
+
+    /// Transforms of the wall faces of a cell, one for every open neighbour
+    fn wall_faces(&self, (x, y): (usize, usize)) -> Vec<Transform> {
+        let wall_size:f32 = 1.0;
+        let position = self.grid_to_world(x,y);
+        let mut faces = Vec::new();
+
+        if !self.grid[(x,y)].tile_type.is_wall() {
+            return faces;
+        }
+        //right
+        if x != self.width-1 && self.grid[(x+1,y)].tile_type.is_open() {
+            faces.push(Transform {
+                translation:  Vec3::new(position.x+TILE_SIZE*0.5-wall_size*0.5,0.0,position.z),
+                rotation: Quat::from_rotation_y(PI/2.0),
+                ..default()
+            });
+        }
+        //left
+        if x != 0 && self.grid[(x-1,y)].tile_type.is_open() {
+            faces.push(Transform {
+                translation:  Vec3::new(position.x-TILE_SIZE*0.5+wall_size*0.5,0.0,position.z),
+                rotation: Quat::from_rotation_y(PI/2.0),
+                ..default()
+            });
+        }
+        //up
+        if y != 0 && self.grid[(x,y-1)].tile_type.is_open() {
+            faces.push(Transform::from_xyz(position.x,0.0,position.z-TILE_SIZE*0.5+wall_size*0.5));
+        }
+        //down
+        if y != self.height-1 && self.grid[(x,y+1)].tile_type.is_open() {
+            faces.push(Transform::from_xyz(position.x,0.0,position.z+TILE_SIZE*0.5-wall_size*0.5));
+        }
+        faces
+    }
+
+    /// One entity for the wall faces of all cells in the chunk, the mesh is merged
+    /// by build_wall_chunks from the faces of the explored cells
+    fn spawn_wall_chunk(
+        &self,
+        commands: &mut Commands,
+        (chunk_x, chunk_y): (usize, usize),
+        floor: usize,
+        handles: &CellHandles
+    ) {
+        let mut faces = Vec::new();
+        for y in chunk_y * WALL_CHUNK_SIZE..((chunk_y + 1) * WALL_CHUNK_SIZE).min(self.height) {
+            for x in chunk_x * WALL_CHUNK_SIZE..((chunk_x + 1) * WALL_CHUNK_SIZE).min(self.width) {
+                faces.extend(self.wall_faces((x, y)).into_iter().map(|face| ((x, y), face)));
+            }
+        }
+        if faces.is_empty() {
+            return;
+        }
+
+        commands.spawn((
+            WallChunk {
+                chunk: (chunk_x, chunk_y),
+                faces,
+                shown: Vec::new(),
+                wall_mesh: handles.wall_mesh.clone()
+            },
+            MeshMaterial3d(handles.wall_material.clone()),
+            Transform::default(),
+            Visibility::Hidden,
+            Floor(floor)
+        ));
+    }
+
+    fn spawn_cell(
+        &self,
+        commands: &mut Commands,
+        (x, y): (usize, usize),
+        floor: usize,
+        wall_rendering: WallRendering,
+        handles: &CellHandles,
+        game_rng: &mut GameRng,
+        meshes: &mut ResMut<Assets<Mesh>>,
+        materials: &mut ResMut<Assets<StandardMaterial>>,
+    ) {
+        let abstract_mesh = false;
+        let rng = &mut game_rng.0;
+
+        match self.grid[(x,y)].tile_type {
+            TileType::Wall | TileType::MineralVein | TileType::MineralVeinWithTreasure => {
+                if abstract_mesh {
+                    /*let entity = commands.spawn(PbrBundle {
+                   mesh: meshes.add(Mesh::from(Cuboid::new(TILE_SIZE,TILE_SIZE,TILE_SIZE))),
+                   material: materials.add(Color::Srgba(DARK_GRAY)),
+                   transform: Transform::from_xyz(position.x,TILE_SIZE/2.0,position.z),
+                   ..default()
+               })
+                   .id();*/
+                } else if wall_rendering == WallRendering::PerFace {
+                    for face in self.wall_faces((x, y)) {
+                        commands.spawn((
+                            SceneRoot(handles.wall.clone()),
+                            face,
+                            Floor(floor),
+                            CellMesh((x, y))
+                        ));
+                    }
+                }
+            },
+            TileType::Floor => {
+                let position = self.grid_to_world(x,y);
+                if abstract_mesh {
+                    commands.spawn((
+                        Mesh3d( meshes.add(Mesh::from(Cuboid::new(TILE_SIZE,0.1,TILE_SIZE)))),
+                        MeshMaterial3d(materials.add(Color::Srgba(LIGHT_GRAY))),
+                        Transform{
+                            translation: Vec3::new(position.x,-0.05,position.z),
+                            rotation: Quat::from_rotation_y(PI*0.5*rng.gen_range(1..=3)as f32),
+                            ..default()
+                        },
+                        Floor(floor),
+                            CellMesh((x, y))
+                    ));
+                } else {
+                    match self.grid[(x,y)].render_hint {
+                        RenderHint::Corridor => {
                             commands.spawn((
-                                Mesh3d( meshes.add(Mesh::from(Cuboid::new(TILE_SIZE,0.1,TILE_SIZE)))),
-                                MeshMaterial3d(materials.add(Color::Srgba(LIGHT_GRAY))),
-                                Transform{
-                                    translation: Vec3::new(position.x,-0.05,position.z),
-                                    rotation: Quat::from_rotation_y(PI*0.5*rng.gen_range(1..=3)as f32),
-                                    ..default()
-                                },
-                                Floor(current_floor.0)
+                                Mesh3d(handles.floor_corridor_mesh.clone()),
+                                MeshMaterial3d(handles.floor_corridor_material.clone()),
+                                Transform::from_xyz(position.x,-0.05,position.z),
+                                Floor(floor),
+                                CellMesh((x, y))
                             ));
-                        } else {
-                            let new_handle = if self.grid[(x,y)].render_hint == RenderHint::RoomFloor{
-                                floor_room_handle.clone()
+                        },
+                        render_hint => {
+                            let new_handle = if render_hint == RenderHint::RoomFloor{
+                                handles.floor_room.clone()
                             }else{
-                                floor_handle.clone()
+                                handles.floor.clone()
                             };
                             commands.spawn((
                                 SceneRoot(new_handle),
                                 Transform::from_xyz(position.x,-0.05,position.z),
-                                Floor(current_floor.0)
+                                Floor(floor),
+                                    CellMesh((x, y))
                             ));
                         }
-                    },
-                    _ => {}
+                    }
                 }
+            },
+            TileType::StaircaseDown => {
+                let position = self.grid_to_world(x,y);
+                commands.spawn((
+                    SceneRoot(handles.floor_room.clone()),
+                    Transform::from_xyz(position.x,-0.05,position.z),
+                    Floor(floor),
+                    CellMesh((x, y))
+                ));
+                self.spawn_staircase(commands, position, floor, (x, y), meshes, materials);
+            },
+            TileType::StaircaseUp => {
+                let position = self.grid_to_world(x,y);
+                commands.spawn((
+                    SceneRoot(handles.floor_room.clone()),
+                    Transform::from_xyz(position.x,-0.05,position.z),
+                    Floor(floor),
+                    CellMesh((x, y))
+                ));
+                self.spawn_staircase_up(commands, position, floor, (x, y), meshes, materials);
+            },
+            TileType::DoorClosed | TileType::DoorOpen => {
+                let position = self.grid_to_world(x,y);
+                commands.spawn((
+                    SceneRoot(handles.floor_room.clone()),
+                    Transform::from_xyz(position.x,-0.05,position.z),
+                    Floor(floor),
+                    CellMesh((x, y))
+                ));
+                self.spawn_door(commands, position, floor, (x, y), meshes, materials);
+            },
+            _ => {}
+        }
+
+        // ore on the faces of a mineral vein
+        let ore_color = match self.grid[(x,y)].tile_type {
+            TileType::MineralVein => Color::srgb(0.55, 0.35, 0.2),
+            TileType::MineralVeinWithTreasure => Color::srgb(0.9, 0.7, 0.15),
+            _ => return
+        };
+        let position = self.grid_to_world(x,y);
+        for (neighbour_x, neighbour_y) in self.neighbours((x, y)) {
+            if !self.grid[(neighbour_x, neighbour_y)].tile_type.is_open() {
+                continue;
             }
+            let direction = Vec3::new(neighbour_x as f32 - x as f32, 0.0, neighbour_y as f32 - y as f32);
+            commands.spawn((
+                Mesh3d(meshes.add(Mesh::from(Cuboid::new(TILE_SIZE * 0.5, 1.5, TILE_SIZE * 0.5)))),
+                MeshMaterial3d(materials.add(ore_color)),
+                Transform::from_translation(position + direction * TILE_SIZE * 0.5 + Vec3::Y * 1.5)
+                    .with_scale(Vec3::new(1.0 - direction.x.abs() * 0.8, 1.0, 1.0 - direction.z.abs() * 0.8)),
+                Floor(floor),
+                CellMesh((x, y))
+            ));
+        }
+    }
+
+    /// Dark opening with steps leading down
+    fn spawn_staircase(
+        &self,
+        commands: &mut Commands,
+        position: Vec3,
+        floor: usize,
+        cell: (usize, usize),
+        meshes: &mut ResMut<Assets<Mesh>>,
+        materials: &mut ResMut<Assets<StandardMaterial>>,
+    ) {
+        const STEPS: usize = 4;
+        let step_depth = TILE_SIZE * 0.6 / STEPS as f32;
+
+        commands.spawn((
+            Mesh3d(meshes.add(Mesh::from(Cuboid::new(TILE_SIZE * 0.7, 0.02, TILE_SIZE * 0.7)))),
+            MeshMaterial3d(materials.add(Color::srgb(0.02, 0.02, 0.02))),
+            Transform::from_xyz(position.x, 0.0, position.z),
+            Floor(floor),
+            CellMesh(cell)
+        ));
+        for step in 0..STEPS {
+            let brightness = 0.5 - 0.1 * step as f32;
+            commands.spawn((
+                Mesh3d(meshes.add(Mesh::from(Cuboid::new(TILE_SIZE * 0.6, 0.05, step_depth)))),
+                MeshMaterial3d(materials.add(Color::srgb(brightness, brightness, brightness))),
+                Transform::from_xyz(
+                    position.x,
+                    0.02 - 0.01 * step as f32,
+                    position.z - TILE_SIZE * 0.3 + step_depth * (step as f32 + 0.5)
+                ),
+                Floor(floor),
+                CellMesh(cell)
+            ));
+        }
+    }
+
+    /// Wooden door across the passage, an open one swung against the wall
+    fn spawn_door(
+        &self,
+        commands: &mut Commands,
+        position: Vec3,
+        floor: usize,
+        (x, y): (usize, usize),
+        meshes: &mut ResMut<Assets<Mesh>>,
+        materials: &mut ResMut<Assets<StandardMaterial>>,
+    ) {
+        const DOOR_HEIGHT: f32 = 3.0;
+        const DOOR_THICKNESS: f32 = 0.2;
+
+        // walls left and right: the passage runs along z and the door spans x
+        let spans_x = x > 0 && self.grid[(x - 1, y)].tile_type.is_wall();
+        let rotation = if spans_x { Quat::IDENTITY } else { Quat::from_rotation_y(PI / 2.0) };
+        let (offset, swing) = if self.grid[(x, y)].tile_type == TileType::DoorOpen {
+            (Vec3::new(-TILE_SIZE * 0.5 + DOOR_THICKNESS, 0.0, 0.0), Quat::from_rotation_y(PI / 2.0))
+        } else {
+            (Vec3::ZERO, Quat::IDENTITY)
+        };
+
+        commands.spawn((
+            Mesh3d(meshes.add(Mesh::from(Cuboid::new(TILE_SIZE, DOOR_HEIGHT, DOOR_THICKNESS)))),
+            MeshMaterial3d(materials.add(Color::srgb(0.45, 0.28, 0.12))),
+            Transform {
+                translation: position + rotation * offset + Vec3::Y * DOOR_HEIGHT * 0.5,
+                rotation: rotation * swing,
+                ..default()
+            },
+            Floor(floor),
+            CellMesh((x, y))
+        ));
+    }
+
+    /// Stone steps rising towards the floor above
+    fn spawn_staircase_up(
+        &self,
+        commands: &mut Commands,
+        position: Vec3,
+        floor: usize,
+        cell: (usize, usize),
+        meshes: &mut ResMut<Assets<Mesh>>,
+        materials: &mut ResMut<Assets<StandardMaterial>>,
+    ) {
+        const STEPS: usize = 4;
+        const STEP_HEIGHT: f32 = 0.25;
+        let step_depth = TILE_SIZE * 0.6 / STEPS as f32;
+        let material = materials.add(Color::srgb(0.55, 0.55, 0.6));
+
+        for step in 0..STEPS {
+            let height = STEP_HEIGHT * (step + 1) as f32;
+            commands.spawn((
+                Mesh3d(meshes.add(Mesh::from(Cuboid::new(TILE_SIZE * 0.6, height, step_depth)))),
+                MeshMaterial3d(material.clone()),
+                Transform::from_xyz(
+                    position.x,
+                    height * 0.5,
+                    position.z + TILE_SIZE * 0.3 - step_depth * (step as f32 + 0.5)
+                ),
+                Floor(floor),
+                CellMesh(cell)
+            ));
+        }
+    }
+
+    /// Spawns the player with body, arms, sword and throw ball on the start cell,
+    /// for a new game as well as a loaded one
+    fn spawn_player_entity(
+        &self,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        character: Character
+    ) -> Entity {
+        let mut position = self.grid_to_world(self.player_position.0, self.player_position.1);
+        position.y = PLAYER_HEIGHT;
+
+        commands.spawn((
+                Mesh3d(meshes.add(Mesh::from(Capsule3d::new(character.body_radius,
+                                                           character.body_length)))),
+                MeshMaterial3d(materials.add(character.color)),
+                Transform::from_translation(position),
+            Player,
+            HeadUpDisplay::new(),
+            character.actor,
+            Name::new(character.name)
+        )).with_children(|parent| {
+            //front
+            parent.spawn((
+                    Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.25, 0.25, 0.25)))),
+                    MeshMaterial3d(materials.add(character.color)),
+                    Transform::from_xyz(0.0, 0.5, -0.5),
+                Name::new("player-front")
+            ));
+
+            // Left Arm
+            parent.spawn((
+                    Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.3, 0.8, 0.3)))),
+                    MeshMaterial3d(materials.add(character.color)),
+                    Transform::from_xyz(-0.7, 0.2, 0.0)
+                        .with_rotation(Quat::from_rotation_x(0.0)),
+                Name::new("player-left-arm")
+            )).with_children(|arm| {
+                // Wurfkugel an der linken Hand
+                arm.spawn((
+                    Mesh3d( meshes.add(Mesh::from(Sphere::new(0.2)))),
+                    MeshMaterial3d(materials.add(Color::srgb(0.8, 0.3, 0.3))), // Rote Kugel
+                    Transform::from_xyz(0.0, -0.5, 0.2),
+                    ThrowableBall,
+                    Visibility::Hidden,
+                    Name::new("player-throwball")
+                ));
+            });
+
+            // Right Arm with Sword (two components)
+            parent.spawn((
+                    Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.3, 0.8, 0.3)))),
+                    MeshMaterial3d(materials.add(character.color)),
+                    Transform::from_xyz(0.7, 0.2, 0.0)
+                        .with_rotation(Quat::from_rotation_x(0.0)),
+                RightArm,
+                Name::new("player-right-arm")
+            )).with_children(|arm| {
+                // Sword as a long cuboid attached to right arm
+                arm.spawn((
+                      Mesh3d( meshes.add(Mesh::from(Cuboid::new(0.1, 0.8, 0.1)))),
+                        MeshMaterial3d(materials.add(Color::srgb(0.6, 0.6, 0.6))),
+                        Transform::from_xyz(0.0, -0.5, -0.2)
+                            .with_rotation(Quat::from_rotation_x(PI * 0.5)),
+                    Name::new("player-sword")
+                ));
+            });
+        }).id()
+    }
+}
+
+/// Scenes used for the meshes of the grid cells, loaded once at startup
+#[derive(Resource)]
+struct CellHandles {
+    wall: Handle<Scene>,
+    wall_mesh: Handle<Mesh>,
+    wall_material: Handle<StandardMaterial>,
+    floor: Handle<Scene>,
+    floor_room: Handle<Scene>,
+    /// tile floor darkened to carved stone, for the tunnels between rooms
+    floor_corridor_mesh: Handle<Mesh>,
+    floor_corridor_material: Handle<StandardMaterial>
+}
+
+impl CellHandles {
+    fn load(asset_server: &Res<AssetServer>, materials: &mut ResMut<Assets<StandardMaterial>>) -> Self {
+        // By default AssetServer will load assets from inside the "assets" folder.
+        // For example, the next line will load GltfAssetLabel::Primitive{mesh:0,primitive:0}.from_asset("ROOT/assets/models/cube/cube.gltf"),
+        // where "ROOT" is the directory of the Application.
+        //
+        // This can be overridden by setting [`AssetPlugin.file_path`].
+        Self {
+            wall: asset_server.load("models/wall.gltf#Scene0"),
+            wall_mesh: asset_server.load("models/wall.gltf#Mesh0/Primitive0"),
+            wall_material: asset_server.load("models/wall.gltf#Material0"),
+            floor: asset_server.load("models/floor_dirt_large.gltf#Scene0"),
+            floor_room: asset_server.load("models/floor_tile_large.gltf#Scene0"),
+            floor_corridor_mesh: asset_server.load("models/floor_tile_large.gltf#Mesh0/Primitive0"),
+            floor_corridor_material: materials.add(StandardMaterial {
+                base_color: Color::srgb(0.6, 0.55, 0.5),
+                base_color_texture: Some(asset_server.load("models/dungeon_texture.png")),
+                metallic: 0.0,
+                perceptual_roughness: 0.45,
+                ..default()
+            })
+        }
+    }
+}
+
+fn load_cell_handles(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<StandardMaterial>>
+) {
+    commands.insert_resource(CellHandles::load(&asset_server, &mut materials));
+}
+
+/// Mesh belonging to a grid cell, replaced when the cell changes
+#[derive(Component)]
+struct CellMesh((usize, usize));
+
+/// Cells per side of a chunk of batched walls
+const WALL_CHUNK_SIZE: usize = 8;
+
+/// How the wall faces of a floor are spawned
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq)]
+pub enum WallRendering {
+    /// one scene per wall face
+    #[default]
+    PerFace,
+    /// the wall faces of a chunk merged into one mesh
+    Batched
+}
+
+impl WallRendering {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "per-face" => Some(WallRendering::PerFace),
+            "batched" => Some(WallRendering::Batched),
+            _ => None
+        }
+    }
+}
+
+/// Wall faces of a chunk of cells, rendered as a single mesh
+#[derive(Component)]
+struct WallChunk {
+    chunk: (usize, usize),
+    faces: Vec<((usize, usize), Transform)>,
+    /// Explored cells whose faces are in the mesh
+    shown: Vec<(usize, usize)>,
+    wall_mesh: Handle<Mesh>
+}
+
+impl WallChunk {
+    /// Cells of the chunk with wall faces the player has explored
+    fn explored_cells(&self, explored: &HashSet<(usize, usize)>) -> Vec<(usize, usize)> {
+        let mut cells: Vec<(usize, usize)> = self.faces.iter()
+            .map(|(cell, _)| *cell)
+            .filter(|cell| explored.contains(cell))
+            .collect();
+        cells.dedup();
+        cells
+    }
+
+    fn shown_faces(&self) -> Vec<Transform> {
+        self.faces.iter()
+            .filter(|(cell, _)| self.shown.contains(cell))
+            .map(|(_, face)| *face)
+            .collect()
+    }
+}
+
+/// Merges the faces of the explored cells of a wall chunk, again whenever more of it is explored
+fn build_wall_chunks(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    chunk_query: Query<(Entity, &WallChunk, Option<&Mesh3d>), Or<(Without<Mesh3d>, Changed<WallChunk>)>>
+) {
+    for (entity, wall_chunk, old_mesh) in chunk_query.iter() {
+        let faces = wall_chunk.shown_faces();
+        if faces.is_empty() {
+            continue;
+        }
+        let Some(wall_mesh) = meshes.get(&wall_chunk.wall_mesh) else { continue };
+
+        let mut mesh = wall_mesh.clone().transformed_by(faces[0]);
+        for face in &faces[1..] {
+            mesh.merge(&wall_mesh.clone().transformed_by(*face));
+        }
+        if let Some(old_mesh) = old_mesh {
+            meshes.remove(&old_mesh.0);
         }
+        commands.entity(entity).insert(Mesh3d(meshes.add(mesh)));
     }
 }
 
@@ -719,7 +2096,9 @@ struct Player;
 
 enum TransitionStep {
     StairDownStart,
-    StairDownEnd
+    StairDownEnd,
+    StairUpStart,
+    StairUpEnd
 }
 #[derive(Component)]
 struct PlayerTransition{
@@ -727,12 +2106,38 @@ struct PlayerTransition{
     timer: Timer
 }
 
+impl PlayerTransition {
+    /// Darkness of the screen, fades out while leaving and in on the new floor
+    fn fade_alpha(&self) -> f32 {
+        match self.step {
+            TransitionStep::StairDownStart | TransitionStep::StairUpStart => self.timer.fraction(),
+            TransitionStep::StairDownEnd | TransitionStep::StairUpEnd => 1.0 - self.timer.fraction()
+        }
+    }
+}
+
 #[derive(Component)]
 struct RightArm;
 
 #[derive(Component)]
 struct AttackTimer(Timer);
 
+/// The player is drinking a potion and can't act until the timer finishes
+#[derive(Component)]
+struct DrinkTimer(Timer);
+
+/// Potion held up while drinking
+#[derive(Component)]
+struct RaisedPotion;
+
+/// Light flashing up when a potion heals
+#[derive(Component)]
+struct HealFlash(Timer);
+
+/// Scorch mark on the floor where a thrown ball hit, fades out
+#[derive(Component)]
+struct ImpactDecal(Timer);
+
 #[derive(Component,Clone)]
 struct Monster{
     monster_type: MonsterType
@@ -754,19 +2159,86 @@ struct ThrowableBall;
 struct ThrownBall {
     velocity: Vec3,
     lifetime: Timer,
-    item_type: ItemType
+    item_type: ItemType,
+    just_spawned: bool
+}
+
+/// Ball flying cell by cell along a grid axis without gravity
+#[derive(Component)]
+struct GridProjectile {
+    item_type: ItemType,
+    direction: (i32, i32),
+    cell: (usize, usize),
+    steps_left: usize,
+    step_timer: Timer
+}
+
+/// Aiming a throw along one of the four grid directions
+#[derive(Debug, Resource, Default)]
+struct CardinalAim {
+    active: bool,
+    direction: Option<(i32, i32)>
+}
+
+/// Inspecting the cells around the player with a cursor, the player stands still
+#[derive(Debug, Resource, Default)]
+struct LookMode {
+    active: bool,
+    cell: (usize, usize)
 }
 
+#[derive(Component)]
+struct LookCursor;
+
 #[derive(Component)]
 struct MainCamera;
 
+/// Random numbers for gameplay, a seeded run repeats exactly
+#[derive(Resource)]
+struct GameRng(StdRng);
+
+impl GameRng {
+    fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => GameRng(StdRng::seed_from_u64(seed)),
+            None => GameRng(StdRng::from_entropy())
+        }
+    }
+}
+
 fn main() {
-    App::new()
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let launch_options = match LaunchOptions::parse(&args) {
+        Ok(launch_options) => launch_options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if launch_options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let floor_parameters = FloorParameters::new().with_launch_options(&launch_options);
+    let start_floor = floor_parameters.start_floor;
+    if start_floor >= floor_parameters.items.len() {
+        eprintln!("Start floor must be below {}\n\n{}", floor_parameters.items.len(), USAGE);
+        std::process::exit(2);
+    }
+
+    let starting_lives = StartingLives(launch_options.lives.map(LivesMode::Lives).unwrap_or(LivesMode::Permadeath));
+
+    let mut app = App::new();
+    app
         .insert_resource(ClearColor(Color::BLACK))
         .insert_resource(LoadMapAndItems(false))
-        .insert_resource(CurrentFloor(0))
-        .insert_resource(FloorParameters::new())
+        .insert_resource(CurrentFloor(start_floor))
+        .insert_resource(floor_parameters)
         .insert_resource(ItemAndMonsterParameters::new())
+        .insert_resource(launch_options.walls.unwrap_or_default())
+        .insert_resource(launch_options.audio.map(AudioEnabled).unwrap_or_default())
+        .insert_resource(DamageSettings::with_mitigation(launch_options.mitigation.unwrap_or_default()))
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
                 title: "Yet Another Roguelike Tutorial in Rust with Bevy".to_string(),
@@ -777,6 +2249,7 @@ fn main() {
         }))
         .init_state::<GameState>()
         .add_sub_state::<TransitionState>()
+        .add_sub_state::<PauseState>()
         .add_plugins(ThirdPersonCameraPlugin)
         .add_plugins((
             OrbitCameraPlugin,
@@ -785,7 +2258,12 @@ fn main() {
         .add_plugins((
             UiPlugin,
             MonsterAIPlugin,
-            FightingPlugin))
+            FightingPlugin,
+            RenderSettingsPlugin,
+            FieldOfViewPlugin,
+            UserConfigPlugin,
+            KeyBindingsPlugin,
+            SoundEffectsPlugin))
         .add_plugins((
             // Adds frame time diagnostics
             FrameTimeDiagnosticsPlugin,
@@ -798,20 +2276,60 @@ fn main() {
             // Uncomment this to add system info diagnostics:
             // bevy::diagnostic::SystemInformationDiagnosticsPlugin::default()
         ))
+        .add_systems(Startup, load_cell_handles)
         .add_systems(OnEnter(GameState::InGame), (setup_orbitcamera, setup))
+        .add_systems(OnEnter(GameState::GameOver), delete_save_of_dead_player)
+        .add_systems(OnExit(GameState::GameOver), clear_game)
+        .add_systems(OnTransition { exited: GameState::InGame, entered: GameState::MainMenu }, clear_game)
+        .add_systems(OnEnter(PauseState::Paused), pause_time)
+        .add_systems(OnExit(PauseState::Paused), unpause_time)
         .insert_resource(ShowFps(false))
+        .insert_resource(starting_lives.0)
+        .insert_resource(starting_lives)
+        .insert_resource(StartingLoadout::new(Difficulty::Normal))
+        .init_resource::<CardinalAim>()
+        .init_resource::<LookMode>()
+        .init_resource::<Occupancy>()
+        .init_resource::<Pickup>()
+        .init_resource::<GameMode>()
+        .init_resource::<SaveSlot>()
+        .init_resource::<PendingTurns>()
+        .add_event::<OpenDoorEvent>()
+        .add_event::<SaveGameEvent>()
         .insert_resource(ShowPlayerValuesAndInventar(false))
-        //.add_systems(Startup, place_torch_lights)
         .add_systems(Update, do_transition_stairsdown.run_if(in_state(TransitionState::StairsDown)))
-        .add_systems(Update, debug.run_if(in_state(GameState::InGame)))
-        .add_systems(Update,(
-            move_player,
-            player_item_colliding,
-            player_use_item,
-            throw_ball,
-            update_thrown_ball,
-            quit).run_if(in_state(GameState::InGame)))
-        .run();
+        .add_systems(Update, do_transition_stairsup.run_if(in_state(TransitionState::StairsUp)))
+        .add_systems(Update, debug.run_if(in_state(PauseState::Running)))
+        .add_systems(Update, toggle_pause.before(GameplaySet::Input).run_if(in_state(GameState::InGame)))
+        .add_systems(PreUpdate, update_occupancy.run_if(in_state(GameState::InGame)))
+        .add_systems(Update, build_wall_chunks.run_if(in_state(GameState::InGame)))
+        .configure_sets(Update, GameplaySet::chain().run_if(in_state(GameState::InGame)).run_if(in_state(PauseState::Running)))
+        .add_systems(Update, (
+            ((player_use_item,
+              drop_item.run_if(player_not_drinking),
+              preview_ball_trajectory.run_if(player_not_drinking),
+              throw_ball.run_if(player_not_drinking),
+              cardinal_throw.run_if(player_not_drinking)).run_if(player_not_looking),
+             look_around).run_if(inventory_closed).in_set(GameplaySet::Input),
+            (move_player.run_if(player_not_drinking).run_if(player_not_looking).run_if(inventory_closed),
+             update_thrown_ball,
+             update_grid_projectile).in_set(GameplaySet::Movement),
+            (player_item_colliding,
+             player_pick_up.run_if(player_not_drinking).run_if(player_not_looking).run_if(inventory_closed),
+             mine_walls,
+             open_doors).chain().in_set(GameplaySet::Collision),
+            (drink_potion,
+             handle_player_death).in_set(GameplaySet::Combat),
+            (fade_heal_flash,
+             fade_impact_decals).in_set(GameplaySet::Cleanup)
+        ).run_if(camera_intro_finished))
+        .add_systems(Update, quit.run_if(in_state(PauseState::Running)))
+        .add_systems(Update, save_game.run_if(in_state(GameState::InGame)));
+
+    #[cfg(feature = "debug")]
+    app.add_systems(Update, debug_descend.run_if(in_state(TransitionState::Running)));
+
+    app.run();
 }
 
 fn setup(
@@ -822,345 +2340,320 @@ fn setup(
     load_map_and_items: Res<LoadMapAndItems>,
     mut current_floor: ResMut<CurrentFloor>,
     floor_parameters: Res<FloorParameters>,
-    item_and_monster_parameters: Res<ItemAndMonsterParameters>
+    item_and_monster_parameters: Res<ItemAndMonsterParameters>,
+    starting_loadout: Res<StartingLoadout>,
+    wall_rendering: Res<WallRendering>,
+    cell_handles: Res<CellHandles>,
+    save_slot: Res<SaveSlot>,
+    starting_lives: Res<StartingLives>,
+    mut message_log: ResMut<MessageLog>
 ) {
    // let game_map = GameMap::from_string(map_string).expect("Failed to parse level");
+    // a loaded game keeps its difficulty, saves from before the difficulty was saved take the one of the menu
+    let run_difficulty = if load_map_and_items.0 {
+        Difficulty::load(&save_slot).unwrap_or(starting_loadout.difficulty)
+    } else {
+        starting_loadout.difficulty
+    };
+    commands.insert_resource(run_difficulty);
+    let difficulty = run_difficulty.settings();
     let mut game_map = if load_map_and_items.0 {
-        GameMap::load(MAP_TEXT_FILE)
+        GameMap::load(&save_slot)
     } else {
-        GameMap::create_dungeon(MapGeneratorStart::new(80, 45,
-                                                         current_floor.0,
-                                                         MAX_ROOMS,
-                                                         ROOM_MIN_SIZE,
-                                                         ROOM_MAX_SIZE,
-                                                         floor_parameters.items[current_floor.0].max_monsters_per_room,
-                                                         floor_parameters.items[current_floor.0].max_items_per_room,
-                                                         item_and_monster_parameters.parameters[current_floor.0].clone(),
-                                                         None))
-            .expect("Failed to create level")
+        generate_floor_map(current_floor.0, &floor_parameters, &item_and_monster_parameters, &difficulty, None)
     };
 
-    //game_map.print();
+    debug!("Player position: ({}, {})", game_map.player_position.0, game_map.player_position.1);
 
-    println!("Player position: ({}, {})", game_map.player_position.0, game_map.player_position.1);
+    let mut game_rng = GameRng::new(floor_parameters.seed);
 
     if load_map_and_items.0 {
-        commands.insert_resource(Inventory::load(INVENTORY_JSON_FILE));
-        current_floor.0 = CurrentFloor::load(FLOOR_JSON_FILE).0;
+        commands.insert_resource(Inventory::load(&save_slot));
+        current_floor.0 = CurrentFloor::load(&save_slot).0;
+        commands.insert_resource(FieldOfView::load(&save_slot, current_floor.0));
+        // saves from before the lives were saved start with the lives of the menu
+        commands.insert_resource(LivesMode::load(&save_slot).unwrap_or(starting_lives.0));
     } else {
-        commands.insert_resource(Inventory::new());
+        commands.insert_resource(starting_loadout.inventory());
+        commands.insert_resource(starting_lives.0);
+        message_log.clear();
     };
 
-    setup_character(&mut commands, &mut meshes, &mut materials, &mut game_map, &load_map_and_items);
+    setup_character(&mut commands, &mut meshes, &mut materials, &mut game_map, &load_map_and_items, &save_slot, &starting_loadout);
 
     // monster
-    setup_monster(&mut commands, &current_floor, &mut meshes, &mut materials, &mut game_map);
+    setup_monster(&mut commands, &current_floor, &difficulty, &mut meshes, &mut materials, &mut game_map);
     // item
-    setup_item(&mut commands, &asset_server, &current_floor, &mut game_map);
+    setup_item(&mut commands, &asset_server, &current_floor, floor_parameters.seed, &mut game_map);
     // ground
-    game_map.generate(&mut commands, &mut current_floor, &asset_server,  &mut meshes, &mut materials);
+    game_map.generate(&mut commands, &mut current_floor, *wall_rendering, &cell_handles, &mut game_rng, &mut meshes, &mut materials);
 
+    commands.insert_resource(game_rng);
     commands.insert_resource(game_map);
 }
 
+/// Generations with other seeds before a floor falls back to an open hall
+const GENERATION_ATTEMPTS: u64 = 10;
+
+/// Map of a new floor. A failed generation, e.g. with unreachable stairs, is retried
+/// with another seed, the last resort is a single hall that always fits the map.
+fn generate_floor_map(
+    floor: usize,
+    floor_parameters: &FloorParameters,
+    item_and_monster_parameters: &ItemAndMonsterParameters,
+    difficulty: &DifficultySettings,
+    player_start_position: Option<(usize, usize)>
+) -> GameMap {
+    let generator = |kind: GeneratorKind, seed: Option<u64>| MapGeneratorStart::new(80, 45,
+            floor,
+            MAX_ROOMS,
+            ROOM_MIN_SIZE,
+            ROOM_MAX_SIZE,
+            difficulty.max_monsters_per_room(floor_parameters.get(floor).max_monsters_per_room),
+            floor_parameters.get(floor).max_items_per_room,
+            difficulty.item_and_monster_parameters(item_and_monster_parameters.get(floor)),
+            player_start_position,
+            seed)
+        .with_kind(kind)
+        .with_monster_placement(floor_parameters.monster_placement);
+
+    let kind = floor_parameters.get(floor).generator;
+    for attempt in 0..GENERATION_ATTEMPTS {
+        // the first attempt keeps the seed, a seed still gives the same dungeon
+        let seed = floor_parameters.seed.map(|seed| seed.wrapping_add(attempt << 32));
+        match GameMap::create_dungeon(generator(kind, seed)) {
+            Ok(game_map) => return game_map,
+            Err(error) => warn!("Floor {} attempt {} failed: {}", floor, attempt + 1, error)
+        }
+    }
+
+    error!("Floor {} can't be generated as {:?}, it becomes an open hall", floor, kind);
+    GameMap::create_dungeon(generator(GeneratorKind::Open, floor_parameters.seed))
+        .expect("A single hall has reachable stairs on every map")
+}
+
 fn setup_monster(
     commands: &mut Commands,
     current_floor: &ResMut<CurrentFloor>,
+    difficulty: &DifficultySettings,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
     game_map: &mut GameMap
 ) {
     for i in game_map.monsters.iter() {
-
         let position = game_map.grid_to_world(i.position.0, i.position.1);
-        match i.monster_type {
-            MonsterType::Troll => {
-                commands.spawn((
-                    Mesh3d(meshes.add(Mesh::from(Capsule3d::new(0.8, 1.5)))), // Large, bulky troll
-                    MeshMaterial3d( materials.add(StandardMaterial { // Earthy, stone-like color
-                        base_color: Color::srgba(0.5, 0.4, 0.3, 1.0),
-                        alpha_mode: AlphaMode::Blend,
-                        ..default()
-                    })),
-                    Transform::from_xyz(position.x, 0.8, position.z),
-                    Monster{monster_type:MonsterType::Troll}, // Component to identify the Troll
-                    Actor::new(16, 16,1, 4,100),
-                    MonsterAIState::Idle,
-                    Floor(current_floor.0)
-                )).insert(Name::new("troll")).with_children(|parent| {
-                    // Front (rough, rocky appearance)
-                    parent.spawn((
-                                     Mesh3d( meshes.add(Mesh::from(Cuboid::new(0.4, 0.4, 0.4)))),
-                         MeshMaterial3d(materials.add(StandardMaterial { // Earthy, stone-like color
-                                base_color: Color::srgba(0.5, 0.4, 0.3, 1.0),
-                                alpha_mode: AlphaMode::Blend,
-                                ..default()
-                            })),
-                                     Transform::from_xyz(0.0, 0.7, -0.7)
-                    )).insert(Name::new("troll-front"));
+        let stats = i.monster_type.stats();
+        let floor = current_floor.0;
+        let body_material = monster_material(materials, stats.base_color);
+
+        let mut monster = commands.spawn((
+            Mesh3d(meshes.add(Mesh::from(Capsule3d::new(stats.body_radius, stats.body_length)))),
+            MeshMaterial3d(body_material.clone()),
+            Transform::from_xyz(position.x, 0.8, position.z),
+            Monster{monster_type: i.monster_type.clone()},
+            depth_scaled_stats(difficulty.monster_actor(stats.max_hit_points, stats.defense, stats.power, stats.xp_given), floor),
+            MonsterAIState::Idle,
+            StuckDetection::new(position),
+            WanderTimer::new(),
+            MonsterAttackCooldown::new(),
+            PathTo::default(),
+            Floor(floor),
+            Name::new(stats.name)
+        ));
+        if i.monster_type.behaviour().heal_power > 0 {
+            monster.insert(HealCooldown::new());
+        }
+
+        monster.with_children(|parent| {
+            // Front
+            parent.spawn((
+                Mesh3d(meshes.add(Mesh::from(Cuboid::new(stats.front_size, stats.front_size, stats.front_size)))),
+                MeshMaterial3d(body_material.clone()),
+                Transform::from_translation(stats.front_position),
+                Floor(floor),
+                Name::new(format!("{}-front", stats.name))
+            ));
+
+            match stats.held {
+                MonsterHeld::Weapon { arm_size, arm_offset, arm_tilt, weapon_size, weapon_position, weapon_color } => {
+                    let arm_mesh = meshes.add(Mesh::from(Cuboid::new(arm_size.x, arm_size.y, arm_size.z)));
 
-                    // Left Arm (massive, club-like)
                     parent.spawn((
-                        Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.5, 1.2, 0.5)))), // Even larger arm
-                        MeshMaterial3d( materials.add(StandardMaterial { // Earthy, stone-like color
-                            base_color: Color::srgba(0.5, 0.4, 0.3, 1.0),
-                            alpha_mode: AlphaMode::Blend,
-                            ..default()
-                        })),
-                        Transform::from_xyz(-1.0, 0.2, 0.0)
-                            .with_rotation(Quat::from_rotation_x(0.3)),
-                        Floor(current_floor.0)
-                    )).insert(Name::new("troll-left-arm"));
+                        Mesh3d(arm_mesh.clone()),
+                        MeshMaterial3d(body_material.clone()),
+                        Transform::from_xyz(-arm_offset, 0.2, 0.0)
+                            .with_rotation(Quat::from_rotation_x(arm_tilt)),
+                        Floor(floor),
+                        Name::new(format!("{}-left-arm", stats.name))
+                    ));
 
-                    // Right Arm with Giant Club
+                    // Right Arm with the weapon
                     parent.spawn((
-                            Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.5, 1.2, 0.5)))),
-                            MeshMaterial3d( materials.add(StandardMaterial { // Earthy, stone-like color
-                                base_color: Color::srgba(0.5, 0.4, 0.3, 1.0),
-                                alpha_mode: AlphaMode::Blend,
-                                ..default()
-                            })),
-                            Transform::from_xyz(1.0, 0.2, 0.0)
-                                .with_rotation(Quat::from_rotation_x(0.3)),
+                        Mesh3d(arm_mesh),
+                        MeshMaterial3d(body_material.clone()),
+                        Transform::from_xyz(arm_offset, 0.2, 0.0)
+                            .with_rotation(Quat::from_rotation_x(arm_tilt)),
                         RightArm,
-                        Floor(current_floor.0)
-                    )).insert(Name::new("troll-right-arm")).with_children(|arm| {
-                        // Giant Club
-                        arm.spawn(
-                            (
-                                Mesh3d( meshes.add(Mesh::from(Cuboid::new(0.3, 1.5, 0.3)))),
-                                MeshMaterial3d( materials.add(StandardMaterial { // Earthy, stone-like color
-                                    base_color: Color::srgba(0.4, 0.3, 0.2, 1.0),
-                                    alpha_mode: AlphaMode::Blend,
-                                    ..default()
-                                })),
-                                Transform::from_xyz(0.0, -1.0, -0.3)
-                                    .with_rotation(Quat::from_rotation_x(PI * 0.5)),
-                                Floor(current_floor.0)
-                            )).insert(Name::new("troll-sword"));
+                        Floor(floor),
+                        Name::new(format!("{}-right-arm", stats.name))
+                    )).with_children(|arm| {
+                        arm.spawn((
+                            Mesh3d(meshes.add(Mesh::from(Cuboid::new(weapon_size.x, weapon_size.y, weapon_size.z)))),
+                            MeshMaterial3d(monster_material(materials, weapon_color)),
+                            Transform::from_translation(weapon_position)
+                                .with_rotation(Quat::from_rotation_x(PI * 0.5)),
+                            Floor(floor),
+                            Name::new(format!("{}-sword", stats.name))
+                        ));
                     });
-                });
-            }
-            _ => {
-                commands.spawn((
-                        Mesh3d( meshes.add(Mesh::from(Capsule3d::new(0.6, 1.2)))), // Slightly larger and bulkier
-                        MeshMaterial3d( materials.add(StandardMaterial { // Greenish skin tone
-                            base_color: Color::srgba(0.4, 0.6, 0.3, 1.0),
-                            alpha_mode: AlphaMode::Blend,
-                            ..default()
-                        })),
-                        Transform::from_xyz(position.x, 0.8, position.z),
-                    Monster{monster_type: MonsterType::Troll},
-                    Actor::new(10,10, 0, 3,100),
-                    MonsterAIState::Idle,
-                    Floor(current_floor.0)
-                )).insert(Name::new("orc")).with_children(|parent| {
-                    // Front (more brutish look)
-                    parent.spawn((
-                            Mesh3d( meshes.add(Mesh::from(Cuboid::new(0.3, 0.3, 0.3)))), // Slightly larger
-                            MeshMaterial3d( materials.add(StandardMaterial { // Greenish skin tone
-                                base_color: Color::srgba(0.4, 0.6, 0.3, 1.0),
-                                alpha_mode: AlphaMode::Blend,
-                                ..default()
-                            })),
-                            Transform::from_xyz(0.0, 0.6, -0.6), // Adjusted position,
-                        Floor(current_floor.0)
-                    )).insert(Name::new("orc-front"));
-
-                    // Left Arm (more muscular)
-                    parent.spawn((
-                        Mesh3d( meshes.add(Mesh::from(Cuboid::new(0.4, 1.0, 0.4)))), // Thicker arm
-                        MeshMaterial3d( materials.add(StandardMaterial { // Greenish skin tone
-                            base_color: Color::srgba(0.4, 0.6, 0.3, 1.0),
-                            alpha_mode: AlphaMode::Blend,
-                            ..default()
-                        })),
-                        Transform::from_xyz(-0.8, 0.2, 0.0)
-                            .with_rotation(Quat::from_rotation_x(0.2)), // Slight angle
-                        Floor(current_floor.0)
-                    )).insert(Name::new("orc-left-arm"));
-
-                    // Right Arm with Battle Axe
+                }
+                MonsterHeld::Staff => {
+                    // Staff with a glowing tip
                     parent.spawn((
-                            Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.4, 1.0, 0.3)))), // Muscular arm
-                            MeshMaterial3d(materials.add(StandardMaterial { // Greenish skin tone
-                                base_color: Color::srgba(0.4, 0.6, 0.3, 1.0),
+                        Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.1, 2.0, 0.1)))),
+                        MeshMaterial3d(monster_material(materials, Color::srgba(0.4, 0.3, 0.2, 1.0))),
+                        Transform::from_xyz(0.7, 0.3, -0.2),
+                        Floor(floor),
+                        Name::new(format!("{}-staff", stats.name))
+                    )).with_children(|staff| {
+                        staff.spawn((
+                            Mesh3d(meshes.add(Mesh::from(Sphere::new(0.15)))),
+                            MeshMaterial3d(materials.add(StandardMaterial {
+                                base_color: Color::srgba(0.4, 1.0, 0.5, 1.0),
+                                emissive: LinearRgba::rgb(0.4, 2.0, 0.5),
                                 alpha_mode: AlphaMode::Blend,
                                 ..default()
                             })),
-                            Transform::from_xyz(0.8, 0.2, 0.0)
-                                .with_rotation(Quat::from_rotation_x(0.2)), // Slight angle
-                        RightArm,
-                        Floor(current_floor.0)
-                    )).insert(Name::new("orc-right-arm")).with_children(|arm| {
-                        // Battle Axe replacing the sword
-                        arm.spawn((
-                                Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.2, 1.2, 0.3)))), // Larger, brutal weapon
-                                MeshMaterial3d( materials.add(StandardMaterial {
-                                    base_color: Color::srgba(0.5, 0.4, 0.3, 1.0),
-                                    alpha_mode: AlphaMode::Blend,
-                                    ..default()
-                                })),
-                               Transform::from_xyz(0.0, -0.7, -0.3)
-                                    .with_rotation(Quat::from_rotation_x(PI * 0.5)),
-                            Floor(current_floor.0)
-                        )).insert(Name::new("orc-sword"));
+                            Transform::from_xyz(0.0, 1.1, 0.0),
+                            Floor(floor),
+                            Name::new(format!("{}-staff-tip", stats.name))
+                        ));
                     });
-                });
+                }
             }
-        }
+        });
     }
 }
 
+fn monster_material(materials: &mut Assets<StandardMaterial>, base_color: Color) -> Handle<StandardMaterial> {
+    materials.add(StandardMaterial {
+        base_color,
+        alpha_mode: AlphaMode::Blend,
+        ..default()
+    })
+}
+
 struct Character{
     name : String,
     body_radius: f32,
     body_length: f32,
-    position: Vec3,
     color: Color,
-    max_hit_points: usize,
-    hit_points: usize,
-    defense: usize,
-    power: usize,
+    actor: Actor
 }
 
 const PLAYER_BODY_RADIUS: f32 = 0.5;
 const PLAYER_BODY_LENGTH: f32 = 1.0;
+/// Height of the player's body center above the floor
+const PLAYER_HEIGHT: f32 = 0.9;
+
 fn setup_character(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
     game_map: &mut GameMap,
-    load_map_and_items: &Res<LoadMapAndItems>
+    load_map_and_items: &Res<LoadMapAndItems>,
+    save_slot: &SaveSlot,
+    starting_loadout: &StartingLoadout
 ) {
-    let mut player_position = game_map.grid_to_world(game_map.player_position.0,
-                                                     game_map.player_position.1);
-    player_position.y = 0.9;
-
-    let mut max_hit_points = 30;
-    let mut hit_points = 30;
-    let mut defense = 2;
-    let mut power=5;
-
-    if load_map_and_items.0 {
-        let actor = Actor::load();
-        max_hit_points = actor.max_hit_points;
-        hit_points = actor.hit_points;
-        defense = actor.defense;
-        power = actor.power;
-    }
+    // a loaded player keeps the level and the xp too
+    let actor = if load_map_and_items.0 {
+        Actor::load(save_slot)
+    } else {
+        Actor::new(starting_loadout.hit_points, starting_loadout.hit_points, starting_loadout.defense, starting_loadout.power, 0)
+    };
 
     let character = Character{
         name: String::from("player"),
         body_radius : PLAYER_BODY_RADIUS,
         body_length: PLAYER_BODY_LENGTH,
-        position: player_position,
         color: Color::srgb(0.2, 0.4, 0.8),
-        max_hit_points,
-        hit_points,
-        defense,
-        power
+        actor
     };
 
-    commands.spawn((
-            Mesh3d(meshes.add(Mesh::from(Capsule3d::new(character.body_radius,
-                                                       character.body_length)))),
-            MeshMaterial3d(materials.add(character.color)),
-            Transform::from_translation(character.position),
-        Player,
-        HeadUpDisplay::new(),
-        Actor::new (character.max_hit_points, character.hit_points, character.defense, character.power,0),
-        Name::new(character.name)
-    )).with_children(|parent| {
-        //front
-        parent.spawn((
-                Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.25, 0.25, 0.25)))),
-                MeshMaterial3d(materials.add(character.color)),
-                Transform::from_xyz(0.0, 0.5, -0.5),
-            Name::new("player-front")
-        ));
-
-        // Left Arm
-        parent.spawn((
-                Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.3, 0.8, 0.3)))),
-                MeshMaterial3d(materials.add(character.color)),
-                Transform::from_xyz(-0.7, 0.2, 0.0)
-                    .with_rotation(Quat::from_rotation_x(0.0)),
-            Name::new("player-left-arm")
-        )).with_children(|arm| {
-            // Wurfkugel an der linken Hand
-            arm.spawn((
-                Mesh3d( meshes.add(Mesh::from(Sphere::new(0.2)))),
-                MeshMaterial3d(materials.add(Color::srgb(0.8, 0.3, 0.3))), // Rote Kugel
-                Transform::from_xyz(0.0, -0.5, 0.2),
-                ThrowableBall,
-                Visibility::Hidden,
-                Name::new("player-throwball")
-            ));
-        });
+    game_map.spawn_player_entity(commands, meshes, materials, character);
+}
 
-        // Right Arm with Sword (two components)
-        parent.spawn((
-                Mesh3d(meshes.add(Mesh::from(Cuboid::new(0.3, 0.8, 0.3)))),
-                MeshMaterial3d(materials.add(character.color)),
-                Transform::from_xyz(0.7, 0.2, 0.0)
-                    .with_rotation(Quat::from_rotation_x(0.0)),
-            RightArm,
-            Name::new("player-right-arm")
-        )).with_children(|arm| {
-            // Sword as a long cuboid attached to right arm
-            arm.spawn((
-                  Mesh3d( meshes.add(Mesh::from(Cuboid::new(0.1, 0.8, 0.1)))),
-                    MeshMaterial3d(materials.add(Color::srgb(0.6, 0.6, 0.6))),
-                    Transform::from_xyz(0.0, -0.5, -0.2)
-                        .with_rotation(Quat::from_rotation_x(PI * 0.5)),
-                Name::new("player-sword")
-            ));
-        });
-    });
+/// Coins in a gold pile, deeper floors hold bigger piles
+#[derive(Component, Debug, Clone, Copy)]
+struct GoldPile(usize);
+
+/// Items lying on the floor, the player can pick them up
+type ItemPickupQuery<'w, 's> = Query<'w, 's, (Entity, &'static Item, &'static Transform, Option<&'static GoldPile>), (With<Item>, Without<Player>)>;
+/// Items lying on the floor, as they are written to the save
+type ItemSaveQuery<'w, 's> = Query<'w, 's, (&'static Item, &'static Transform, Option<&'static GoldPile>), (With<Item>, Without<Player>)>;
+
+const GOLD_MIN: usize = 5;
+const GOLD_MAX: usize = 20;
+/// Offset of the gold seed from the game seed
+const GOLD_SEED_OFFSET: u64 = 0x6010;
+
+/// Random numbers for the gold piles of a floor, apart from the GameRng so that
+/// the amounts don't change the rest of a seeded run
+fn gold_rng(seed: Option<u64>, floor: usize) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(GOLD_SEED_OFFSET).wrapping_add(floor as u64)),
+        None => StdRng::from_entropy()
+    }
 }
 
+/// Saved gold piles keep their coins, new ones are rolled
 fn setup_item(
     commands: &mut Commands,
     asset_server:  &Res<AssetServer>,
     current_floor: &ResMut<CurrentFloor>,
+    seed: Option<u64>,
     game_map: &mut GameMap
 ) {
-    let heal_portion_handle:Handle<Scene> = asset_server.load("models/bottle_A_brown.gltf#Scene0");
-    let trunk_handle:Handle<Scene> = asset_server.load("models/trunk_small_A.gltf#Scene0");
-
+    let mut gold_rng = gold_rng(seed, current_floor.0);
     for i in game_map.items.iter() {
-
         let position = game_map.grid_to_world(i.position.0, i.position.1);
-        match i.item_type {
-            ItemType::HealPotion => {
-               commands.spawn((
-                   SceneRoot(heal_portion_handle.clone()),
-                   Transform {
-                       translation:  Vec3::new(position.x,0.0,position.z),
-                       //rotation: Quat::from_rotation_y(PI/2.0),
-                       ..default()
-                   },
-                   Item{item_type: ItemType::HealPotion},
-                     Floor(current_floor.0)
-               ));
-            }
-            ItemType::Lightning => {
-                commands.spawn((
-                    SceneRoot(trunk_handle.clone()),
-                    Transform {
-                        translation:  Vec3::new(position.x,0.0,position.z),
-                    //rotation: Quat::from_rotation_y(PI/2.0),
-                    ..default()
-                    },
-                    Item{item_type: ItemType::Lightning},
-                    Floor(current_floor.0)
-                ));
-            }
+        let item = spawn_item(commands, asset_server, current_floor.0, i.item_type, position);
+        if i.item_type == ItemType::Gold {
+            let amount = i.gold.unwrap_or_else(|| gold_rng.gen_range(GOLD_MIN..=GOLD_MAX) * (current_floor.0 + 1));
+            commands.entity(item).insert(GoldPile(amount));
         }
     }
 }
 
+fn spawn_item(
+    commands: &mut Commands,
+    asset_server:  &Res<AssetServer>,
+    floor: usize,
+    item_type: ItemType,
+    position: Vec3
+) -> Entity {
+    let (handle, scale):(Handle<Scene>, f32) = match item_type {
+        ItemType::HealPotion => (asset_server.load("models/bottle_A_brown.gltf#Scene0"), 1.0),
+        ItemType::Lightning => (asset_server.load("models/trunk_small_A.gltf#Scene0"), 1.0),
+        // a scroll comes in a small trunk
+        ItemType::LightningScroll | ItemType::ConfusionScroll => (asset_server.load("models/trunk_small_A.gltf#Scene0"), 0.6),
+        // gold in a tiny trunk
+        ItemType::Gold => (asset_server.load("models/trunk_small_A.gltf#Scene0"), 0.4)
+    };
+
+    commands.spawn((
+        SceneRoot(handle),
+        Transform {
+            translation:  Vec3::new(position.x,0.0,position.z),
+            //rotation: Quat::from_rotation_y(PI/2.0),
+            scale: Vec3::splat(scale),
+            ..default()
+        },
+        Item{item_type},
+        Floor(floor)
+    )).id()
+}
+
 fn setup_orbitcamera(
     mut commands: Commands
 ){
@@ -1182,17 +2675,37 @@ fn debug(
     keyboard_input:Res<ButtonInput<KeyCode>>,
     mut show_fps: ResMut<ShowFps>,
     mut show_player_values_and_inventar: ResMut<ShowPlayerValuesAndInventar>,
-    mut query: Query<&mut Camera>
+    mut render_settings: ResMut<RenderSettings>,
+    mut always_show_healthbars: ResMut<AlwaysShowHealthbars>,
+    mut lighting_settings: ResMut<LightingSettings>,
+    mut minimap: ResMut<Minimap>,
+    mut pickup: ResMut<Pickup>,
+    mut query: Query<&mut Camera>,
+    key_bindings: Res<KeyBindings>
 )
 {
-    if keyboard_input.just_pressed(KeyCode::KeyO) {
+    if keyboard_input.just_pressed(key_bindings.toggle_camera) {
         for mut camera in query.iter_mut() {
             camera.is_active = ! camera.is_active
         }
-    } else if keyboard_input.just_pressed(KeyCode::KeyF) {
+    } else if keyboard_input.just_pressed(key_bindings.toggle_fps) {
         show_fps.0 = !show_fps.0;
-    } else if keyboard_input.just_pressed(KeyCode::KeyI) {
+    } else if keyboard_input.just_pressed(key_bindings.toggle_inventory) {
         show_player_values_and_inventar.0 = !show_player_values_and_inventar.0;
+    } else if keyboard_input.just_pressed(key_bindings.next_msaa) {
+        render_settings.next_msaa();
+    } else if keyboard_input.just_pressed(key_bindings.narrower_fov) {
+        render_settings.narrower_fov();
+    } else if keyboard_input.just_pressed(key_bindings.wider_fov) {
+        render_settings.wider_fov();
+    } else if keyboard_input.just_pressed(key_bindings.toggle_healthbars) {
+        always_show_healthbars.0 = !always_show_healthbars.0;
+    } else if keyboard_input.just_pressed(key_bindings.toggle_fill_light) {
+        lighting_settings.fill_light = !lighting_settings.fill_light;
+    } else if keyboard_input.just_pressed(key_bindings.toggle_minimap) {
+        minimap.show = !minimap.show;
+    } else if keyboard_input.just_pressed(key_bindings.toggle_auto_pickup) {
+        pickup.auto = !pickup.auto;
     }
 }
 
@@ -1214,39 +2727,107 @@ fn move_camera(
     }
 }
 
+fn update_occupancy(
+    mut occupancy: ResMut<Occupancy>,
+    game_map: Res<GameMap>,
+    actor_query: Query<(Entity, &Transform, Option<&MonsterAIState>), Or<(With<Player>, With<Monster>)>>,
+) {
+    occupancy.cells.clear();
+    for (entity, transform, ai_state) in actor_query.iter() {
+        // dying monsters don't block
+        if ai_state == Some(&MonsterAIState::Fading) {
+            continue;
+        }
+        occupancy.insert(game_map.world_to_grid(transform.translation), entity, transform.translation);
+    }
+}
+
 fn move_player(
     mut commands: Commands,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mut player_query: Query<(Entity, &mut Transform), (With<Player>, Without<Monster>)>,
     mut attack_events: EventWriter<AttackEvent>,
     camera_query: Query<&Transform, (With<ThirdPersonCamera>, Without<Player>,Without<Monster>)>,
-    monster_query: Query<&mut Transform, (With<Monster>, Without<Player>)>,
     game_map: Res<GameMap>,
+    mut occupancy: ResMut<Occupancy>,
     time: Res<Time>,
     mut next_state: ResMut<NextState<TransitionState>>,
+    cardinal_aim: Res<CardinalAim>,
+    current_floor: Res<CurrentFloor>,
+    game_mode: Res<GameMode>,
+    mut pending_turns: ResMut<PendingTurns>,
+    key_bindings: Res<KeyBindings>,
 ) {
+        // arrow keys choose the throw direction while aiming
+        if cardinal_aim.active {
+            return;
+        }
+
         for (player_entity, mut player_transform) in player_query.iter_mut() {
-            if keyboard_input.just_pressed(KeyCode::Space) {
+            if keyboard_input.just_pressed(key_bindings.attack) {
                 attack_events.send(AttackEvent {
                     attacker: player_entity,
-                    direction: player_transform.forward().as_vec3()
+                    direction: player_transform.forward().as_vec3(),
+                    target: None
                 });
+                if *game_mode == GameMode::TurnBased {
+                    pending_turns.0 += 1;
+                }
+            } else if *game_mode == GameMode::TurnBased {
+                let Ok(camera_transform) = camera_query.get_single() else { continue };
+                let Some(direction) = turn_step_direction(&keyboard_input, &key_bindings, camera_transform) else { continue };
+                player_transform.look_to(direction, Vec3::Y);
+
+                // stepping into a monster attacks it and takes the turn
+                let from = game_map.world_to_grid(player_transform.translation);
+                let cell = match game_map.grid_step(&occupancy, player_entity, from, (direction.x as i32, direction.z as i32)) {
+                    GridStep::Attack(monster) => {
+                        attack_events.send(AttackEvent {
+                            attacker: player_entity,
+                            direction,
+                            target: Some(monster)
+                        });
+                        pending_turns.0 += 1;
+                        continue;
+                    }
+                    GridStep::Move(cell) => cell,
+                    // walls stop the player, closed doors open below
+                    GridStep::Blocked => game_map.world_to_grid(player_transform.translation + direction * TILE_SIZE)
+                };
+                let target = game_map.grid_to_world(cell.0, cell.1);
+                let old_position = player_transform.translation;
+                let move_vector = Vec3::new(target.x - old_position.x, 0.0, target.z - old_position.z);
+
+                player_transform.translation = player_without_colliding(
+                    &mut commands,
+                    &player_entity,
+                    &game_map,
+                    &current_floor,
+                    &mut next_state,
+                    &occupancy,
+                    old_position,
+                    move_vector
+                );
+                if player_transform.translation != old_position {
+                    occupancy.update(&game_map, player_entity, old_position, player_transform.translation);
+                    pending_turns.0 += 1;
+                }
             } else {
                 for camera_transform in camera_query.iter() {
                     let camera_forward = camera_transform.forward().as_vec3();
                     let camera_right = camera_transform.right().as_vec3();
 
                     let mut move_vector = Vec3::ZERO;
-                    if keyboard_input.pressed(KeyCode::ArrowLeft) {
+                    if keyboard_input.pressed(key_bindings.move_left) {
                         move_vector = Vec3::new(-camera_right.x, 0.0, -camera_right.z);
                     }
-                    if keyboard_input.pressed(KeyCode::ArrowRight) {
+                    if keyboard_input.pressed(key_bindings.move_right) {
                         move_vector = Vec3::new(camera_right.x, 0.0, camera_right.z);
                     }
-                    if keyboard_input.pressed(KeyCode::ArrowUp) {
+                    if keyboard_input.pressed(key_bindings.move_up) {
                         move_vector = Vec3::new(camera_forward.x, 0.0, camera_forward.z);
                     }
-                    if keyboard_input.pressed(KeyCode::ArrowDown) {
+                    if keyboard_input.pressed(key_bindings.move_down) {
                         move_vector = Vec3::new(-camera_forward.x, 0.0, -camera_forward.z);
                     }
 
@@ -1257,15 +2838,18 @@ fn move_player(
                         let original_forward = player_transform.forward().as_vec3();
 
                         // Update player position
+                        let old_position = player_transform.translation;
                         player_transform.translation = player_without_colliding(
                             &mut commands,
                             &player_entity,
                             &game_map,
+                            &current_floor,
                             &mut next_state,
-                            &monster_query,
+                            &occupancy,
                             player_transform.translation,
                             move_vector * time.delta_secs() * SPEED
                         );
+                        occupancy.update(&game_map, player_entity, old_position, player_transform.translation);
 
                         // Only rotate if the move vector is significantly different from current forward
                         let angle = move_vector.angle_between(original_forward);
@@ -1282,14 +2866,87 @@ fn move_player(
     }
 }
 
+/// Grid direction of a pressed movement key, the nearest axis to the camera view
+fn turn_step_direction(keyboard_input: &ButtonInput<KeyCode>, key_bindings: &KeyBindings, camera_transform: &Transform) -> Option<Vec3> {
+    let forward = camera_transform.forward().as_vec3();
+    let right = camera_transform.right().as_vec3();
+
+    let direction = if keyboard_input.just_pressed(key_bindings.move_up) {
+        forward
+    } else if keyboard_input.just_pressed(key_bindings.move_down) {
+        -forward
+    } else if keyboard_input.just_pressed(key_bindings.move_left) {
+        -right
+    } else if keyboard_input.just_pressed(key_bindings.move_right) {
+        right
+    } else {
+        return None;
+    };
+
+    if direction.x.abs() > direction.z.abs() {
+        Some(Vec3::new(direction.x.signum(), 0.0, 0.0))
+    } else {
+        Some(Vec3::new(0.0, 0.0, direction.z.signum()))
+    }
+}
+
 const PLAYER_DISTANCE:f32=0.5;
 
+fn start_stairs_down(
+    commands: &mut Commands,
+    player: Entity,
+    next_state: &mut ResMut<NextState<TransitionState>>
+) {
+    next_state.set(TransitionState::StairsDown);
+    commands.send_event(PlaySoundEvent(SoundEffect::StairsDown));
+    commands.entity(player).insert(PlayerTransition {
+        step: TransitionStep::StairDownStart,
+        timer: Timer::new(Duration::from_secs_f32(1.0), TimerMode::Once)
+    });
+}
+
+/// Nothing is above the first floor, there the stairs up do nothing
+fn start_stairs_up(
+    commands: &mut Commands,
+    player: Entity,
+    current_floor: &CurrentFloor,
+    next_state: &mut ResMut<NextState<TransitionState>>
+) -> bool {
+    if current_floor.0 == 0 {
+        return false;
+    }
+
+    next_state.set(TransitionState::StairsUp);
+    commands.entity(player).insert(PlayerTransition {
+        step: TransitionStep::StairUpStart,
+        timer: Timer::new(Duration::from_secs_f32(1.0), TimerMode::Once)
+    });
+    true
+}
+
+/// Takes the stairs down from anywhere, only with the debug feature
+#[cfg(feature = "debug")]
+fn debug_descend(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    player_query: Query<Entity, (With<Player>, Without<PlayerTransition>)>,
+    mut next_state: ResMut<NextState<TransitionState>>,
+    key_bindings: Res<KeyBindings>,
+) {
+    if keyboard_input.just_pressed(key_bindings.debug_descend) {
+        if let Ok(player_entity) = player_query.get_single() {
+            start_stairs_down(&mut commands, player_entity, &mut next_state);
+        }
+    }
+}
+
 fn player_without_colliding(
     commands: &mut Commands,
     player: &Entity,
     game_map: &GameMap,
+    current_floor: &CurrentFloor,
     next_state: &mut ResMut<NextState<TransitionState>>,
-    monster_query: &Query<&mut Transform, (With<Monster>, Without<Player>)>,
+    occupancy: &Occupancy,
     position:Vec3,
     move_vector:Vec3
 )->Vec3{
@@ -1301,99 +2958,499 @@ fn player_without_colliding(
     let map_stairs_down =  game_map.world_to_grid(stairs_down);
     if game_map.grid[map_stairs_down].tile_type == TileType::StaircaseDown {
         if game_map.grid_to_world(map_stairs_down.0,map_stairs_down.1).distance(stairs_down) <= PLAYER_DISTANCE * 2.0 {
-            next_state.set(TransitionState::StairsDown);
-            commands.entity(*player).insert(PlayerTransition {
-                step: TransitionStep::StairDownStart,
-                timer: Timer::new(Duration::from_secs_f32(1.0), TimerMode::Once)
-            });
+            start_stairs_down(commands, *player, next_state);
             return position;
         } else {
             return new_position;
         }
 
     }
-    //up
-    let up = Vec3::new(0.0,0.0,-PLAYER_DISTANCE) + new_position;
-    let map_up =  game_map.world_to_grid(up);
-    if game_map.grid[map_up].tile_type == TileType::Wall {
-        return position;
-    }
-
-    //down
-    let down = Vec3::new(0.0,0.0,PLAYER_DISTANCE) + new_position;
-    let map_down =  game_map.world_to_grid(down);
-    if game_map.grid[map_down].tile_type == TileType::Wall {
-        return position;
+    //stairs up
+    if game_map.grid[map_stairs_down].tile_type == TileType::StaircaseUp {
+        if game_map.grid_to_world(map_stairs_down.0,map_stairs_down.1).distance(stairs_down) <= PLAYER_DISTANCE * 2.0
+            && start_stairs_up(commands, *player, current_floor, next_state) {
+            return position;
+        } else {
+            return new_position;
+        }
     }
-
-    //left
-    let left = Vec3::new(-PLAYER_DISTANCE,0.0,0.0) + new_position;
-    let map_left =  game_map.world_to_grid(left);
-    if game_map.grid[map_left].tile_type == TileType::Wall {
+    //doors open when the player walks into them
+    if let Some(cell) = game_map.closed_door_near(new_position, ACTOR_DISTANCE) {
+        commands.send_event(OpenDoorEvent { cell });
         return position;
     }
-
-    //right
-    let right = Vec3::new(PLAYER_DISTANCE,0.0,0.0) + new_position;
-    let map_right =  game_map.world_to_grid(right);
-    if game_map.grid[map_right].tile_type == TileType::Wall {
+    //walls, monsters
+    if game_map.is_occupied(occupancy, new_position, *player)
+        || game_map.cuts_wall_corner(position, new_position) {
         return position;
     }
 
-    //monster
-    for i in monster_query.iter() {
-        if new_position.distance(i.translation) <= PLAYER_DISTANCE * 2.0 {
-            return position;
-        }
-    }
-
     new_position
 }
 
 fn player_item_colliding(
     mut commands: Commands,
     mut inventory: ResMut<Inventory>,
+    mut pickup: ResMut<Pickup>,
+    mut message_log: ResMut<MessageLog>,
     player_query: Query<&Transform, (With<Player>, Changed<Transform>)>,
     mut throwball_query: Query<&mut Visibility, (With<ThrowableBall>, Without<ThrownBall>)>,
-    mut item_query: Query<(Entity, &Item, &Transform), (With<Item>, Without<Player>)>
+    mut item_query: ItemPickupQuery
 ) {
     for player_transform in player_query.iter() {
-        for (item_entity, item, item_transform) in item_query.iter_mut() {
+        pickup.item_in_reach = None;
+        for (item_entity, item, item_transform, gold_pile) in item_query.iter_mut() {
             if player_transform.translation.distance(item_transform.translation) <= PLAYER_DISTANCE *2.0 {
-                inventory.add_item(item.item_type.clone());
-                if item.item_type == ItemType::Lightning {
-                    if let Ok(mut ball_visibility) = throwball_query.get_single_mut() {
-                        *ball_visibility = Visibility::Visible;
-                    }
+                if pickup.auto {
+                    pick_up_item(&mut commands, &mut inventory, &mut throwball_query, &mut message_log, item_entity, item.item_type, gold_pile.copied());
+                } else {
+                    pickup.item_in_reach = Some((item_entity, item.item_type));
                 }
-                commands.entity(item_entity).despawn_recursive();
             }
         }
     }
 }
 
+/// Picks up the item in reach with the interact key while auto-pickup is off
+fn player_pick_up(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut inventory: ResMut<Inventory>,
+    mut pickup: ResMut<Pickup>,
+    mut message_log: ResMut<MessageLog>,
+    mut throwball_query: Query<&mut Visibility, (With<ThrowableBall>, Without<ThrownBall>)>,
+    item_query: Query<Option<&GoldPile>, With<Item>>,
+    key_bindings: Res<KeyBindings>
+) {
+    if !keyboard_input.just_pressed(key_bindings.pick_up) {
+        return;
+    }
+    if let Some((item_entity, item_type)) = pickup.item_in_reach.take() {
+        // the item may be gone, e.g. after a floor change
+        if let Ok(gold_pile) = item_query.get(item_entity) {
+            pick_up_item(&mut commands, &mut inventory, &mut throwball_query, &mut message_log, item_entity, item_type, gold_pile.copied());
+        }
+    }
+}
+
+/// Puts the active item down on the player's cell, or a free neighbour cell if an item lies there
+fn drop_item(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut inventory: ResMut<Inventory>,
+    mut message_log: ResMut<MessageLog>,
+    mut throwball_query: Query<&mut Visibility, (With<ThrowableBall>, Without<ThrownBall>)>,
+    player_query: Query<&Transform, With<Player>>,
+    item_query: Query<&Transform, With<Item>>,
+    game_map: Res<GameMap>,
+    current_floor: Res<CurrentFloor>,
+    asset_server: Res<AssetServer>,
+    key_bindings: Res<KeyBindings>
+) {
+    if !keyboard_input.just_pressed(key_bindings.drop) {
+        return;
+    }
+    let Some(item_type) = inventory.activ_item else { return };
+    let Ok(player_transform) = player_query.get_single() else { return };
+
+    let item_cells: HashSet<(usize, usize)> = item_query.iter()
+        .map(|transform| game_map.world_to_grid(transform.translation))
+        .collect();
+    let player_cell = game_map.world_to_grid(player_transform.translation);
+    let Some(cell) = std::iter::once(player_cell)
+        .chain(game_map.neighbours(player_cell))
+        .find(|cell| game_map.grid[*cell].tile_type == TileType::Floor && !item_cells.contains(cell)) else {
+        message_log.push_message(format!("There is no room to drop the {}", item_type.to_string()));
+        return;
+    };
+
+    inventory.remove_item(item_type);
+    spawn_item(&mut commands, &asset_server, current_floor.0, item_type, game_map.grid_to_world(cell.0, cell.1));
+    message_log.push_message(format!("You dropped a {}", item_type.to_string()));
+    if let Ok(mut ball_visibility) = throwball_query.get_single_mut() {
+        *ball_visibility = inventory.held_ball_visibility();
+    }
+}
+
+fn pick_up_item(
+    commands: &mut Commands,
+    inventory: &mut ResMut<Inventory>,
+    throwball_query: &mut Query<&mut Visibility, (With<ThrowableBall>, Without<ThrownBall>)>,
+    message_log: &mut ResMut<MessageLog>,
+    item_entity: Entity,
+    item_type: ItemType,
+    gold_pile: Option<GoldPile>
+) {
+    if let Some(GoldPile(amount)) = gold_pile {
+        inventory.gold += amount;
+        message_log.push_message(format!("You picked up {} gold", amount));
+    } else {
+        inventory.add_item(item_type);
+        message_log.push_message(format!("You picked up a {}", item_type.to_string()));
+    }
+    commands.send_event(PlaySoundEvent(SoundEffect::ItemPickup));
+    if let Ok(mut ball_visibility) = throwball_query.get_single_mut() {
+        *ball_visibility = inventory.held_ball_visibility();
+    }
+    commands.entity(item_entity).despawn_recursive();
+}
+
+const POTION_DRINK_TIME: f32 = 0.8;
+const POTION_HEAL: usize = 20;
+const SCROLL_RADIUS: f32 = 3.0 * TILE_SIZE;
+const SCROLL_DAMAGE: usize = 10;
+const HEAL_FLASH_TIME: f32 = 0.5;
+const HEAL_FLASH_INTENSITY: f32 = 200_000.0;
+
 fn player_use_item(
+    mut commands: Commands,
     keyboard_input:Res<ButtonInput<KeyCode>>,
-    mut query: Query<&mut Actor, With<Player>>,
+    query: Query<(Entity, &Actor, &Transform), (With<Player>, Without<DrinkTimer>)>,
+    monster_query: Query<(Entity, &Transform, &MonsterAIState), With<Monster>>,
     mut inventory: ResMut<Inventory>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut message_log: ResMut<MessageLog>,
+    game_map: Res<GameMap>,
+    asset_server: Res<AssetServer>,
+    key_bindings: Res<KeyBindings>,
 )
 {
+    if keyboard_input.just_pressed(key_bindings.switch_item) {
+        inventory.switch_active_item();
+    }
     //Portion
-    if keyboard_input.just_pressed(KeyCode::KeyP) {
-        if inventory.heal_potion > 0 {
-            for mut actor in query.iter_mut() {
+    if keyboard_input.just_pressed(key_bindings.use_potion) {
+        if inventory.count(ItemType::HealPotion) > 0 {
+            for (entity, actor, _) in query.iter() {
                 if actor.hit_points < actor.max_hit_points {
                     inventory.remove_item(ItemType::HealPotion);
-                    actor.hit_points = actor.max_hit_points.min(actor.hit_points+20);
+                    commands.entity(entity)
+                        .insert(DrinkTimer(Timer::from_seconds(POTION_DRINK_TIME, TimerMode::Once)))
+                        .with_children(|parent| {
+                            parent.spawn((
+                                SceneRoot(asset_server.load("models/bottle_A_brown.gltf#Scene0")),
+                                Transform::from_xyz(0.4, 0.0, -0.4),
+                                RaisedPotion,
+                                Name::new("player-potion")
+                            ));
+                        });
                 }
             }
         }
     };
+    //Scroll
+    if keyboard_input.just_pressed(key_bindings.read_scroll) && inventory.count(ItemType::LightningScroll) > 0 {
+        for (entity, _, transform) in query.iter() {
+            inventory.remove_item(ItemType::LightningScroll);
+            let targets = lightning_scroll_targets(&game_map, transform.translation, &monster_query);
+            message_log.push_message(format!("The lightning strikes {} monsters", targets.len()));
+            for target in targets {
+                damage_events.send(DamageEvent {
+                    attacker: entity,
+                    target,
+                    fixed_damage: SCROLL_DAMAGE
+                });
+            }
+        }
+    }
+}
+
+/// Monsters in the radius of a read lightning scroll the player can see
+fn lightning_scroll_targets(
+    game_map: &GameMap,
+    position: Vec3,
+    monster_query: &Query<(Entity, &Transform, &MonsterAIState), With<Monster>>
+) -> Vec<Entity> {
+    monster_query.iter()
+        .filter(|(_, _, ai_state)| **ai_state != MonsterAIState::Fading)
+        .filter(|(_, transform, _)| transform.translation.distance(position) <= SCROLL_RADIUS)
+        .filter(|(_, transform, _)| has_line_of_sight(game_map, position, transform.translation))
+        .map(|(entity, _, _)| entity)
+        .collect()
+}
+
+/// Raises the potion and heals when it is empty
+fn drink_potion(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut player_query: Query<(Entity, &mut Actor, &mut DrinkTimer), With<Player>>,
+    mut potion_query: Query<(Entity, &mut Transform), With<RaisedPotion>>,
+) {
+    for (player_entity, mut actor, mut drink_timer) in player_query.iter_mut() {
+        drink_timer.0.tick(time.delta());
+
+        for (potion_entity, mut transform) in potion_query.iter_mut() {
+            if drink_timer.0.finished() {
+                commands.entity(potion_entity).despawn_recursive();
+            } else {
+                let fraction = drink_timer.0.fraction();
+                transform.translation.y = fraction * 1.2;
+                transform.rotation = Quat::from_rotation_x(fraction * PI * 0.6);
+            }
+        }
+
+        if drink_timer.0.finished() {
+            actor.hit_points = actor.max_hit_points.min(actor.hit_points + POTION_HEAL);
+            commands.entity(player_entity).remove::<DrinkTimer>();
+            spawn_heal_flash(&mut commands, player_entity);
+        }
+    }
+}
+
+/// Green light flashing up on a healed player or monster
+fn spawn_heal_flash(commands: &mut Commands, healed: Entity) {
+    commands.entity(healed).with_children(|parent| {
+        parent.spawn((
+            PointLight {
+                color: Color::srgb(0.4, 1.0, 0.5),
+                intensity: HEAL_FLASH_INTENSITY,
+                range: 6.0,
+                ..default()
+            },
+            Transform::from_xyz(0.0, 1.0, 0.0),
+            HealFlash(Timer::from_seconds(HEAL_FLASH_TIME, TimerMode::Once)),
+            Name::new("heal-flash")
+        ));
+    });
+}
+
+fn fade_heal_flash(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut flash_query: Query<(Entity, &mut PointLight, &mut HealFlash)>,
+) {
+    for (entity, mut light, mut flash) in flash_query.iter_mut() {
+        flash.0.tick(time.delta());
+        if flash.0.finished() {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            light.intensity = HEAL_FLASH_INTENSITY * flash.0.fraction_remaining();
+        }
+    }
+}
+
+/// Drinking a potion keeps the player from moving, attacking and throwing
+fn player_not_drinking(player_query: Query<(), (With<Player>, With<DrinkTimer>)>) -> bool {
+    player_query.is_empty()
+}
+
+fn player_not_looking(look_mode: Res<LookMode>) -> bool {
+    !look_mode.active
+}
+
+/// The inventory screen takes the arrows and enter
+fn inventory_closed(show_player_values_and_inventar: Res<ShowPlayerValuesAndInventar>) -> bool {
+    !show_player_values_and_inventar.0
+}
+
+/// How far in front of the player a wall can be mined
+const MINING_REACH: f32 = 1.0;
+
+fn mine_walls(
+    mut commands: Commands,
+    mut attack_events: EventReader<AttackEvent>,
+    player_query: Query<(Entity, &Transform, &Actor), With<Player>>,
+    cell_meshes: Query<(Entity, &CellMesh)>,
+    wall_chunks: Query<(Entity, &WallChunk)>,
+    mut game_map: ResMut<GameMap>,
+    mut game_rng: ResMut<GameRng>,
+    mut field_of_view: ResMut<FieldOfView>,
+    current_floor: Res<CurrentFloor>,
+    wall_rendering: Res<WallRendering>,
+    cell_handles: Res<CellHandles>,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let Ok((player_entity, player_transform, actor)) = player_query.get_single() else { return };
+
+    for event in attack_events.read() {
+        if event.attacker != player_entity {
+            continue;
+        }
+
+        let direction = Vec3::new(event.direction.x, 0.0, event.direction.z).normalize_or_zero();
+        let cell = game_map.world_to_grid(player_transform.translation + direction * MINING_REACH);
+
+        if let TileDamage::Destroyed { treasure } = game_map.apply_damage_to_tile(cell, actor.power) {
+            // the player sees through the new gap
+            field_of_view.invalidate();
+            let mut cells = game_map.neighbours(cell);
+            cells.push(cell);
+            game_map.respawn_cells(&mut commands, &cell_meshes, &wall_chunks, &cells, current_floor.0,
+                                   *wall_rendering, &cell_handles, &mut game_rng, &mut meshes, &mut materials);
+
+            if treasure {
+                let item_type = if game_rng.0.gen_bool(0.5) { ItemType::HealPotion } else { ItemType::Lightning };
+                spawn_item(&mut commands, &asset_server, current_floor.0, item_type, game_map.grid_to_world(cell.0, cell.1));
+            }
+        }
+    }
+}
+
+/// A closed door the player walked into
+#[derive(Event)]
+struct OpenDoorEvent {
+    cell: (usize, usize)
+}
+
+fn open_doors(
+    mut commands: Commands,
+    mut open_door_events: EventReader<OpenDoorEvent>,
+    cell_meshes: Query<(Entity, &CellMesh)>,
+    wall_chunks: Query<(Entity, &WallChunk)>,
+    mut game_map: ResMut<GameMap>,
+    mut game_rng: ResMut<GameRng>,
+    mut field_of_view: ResMut<FieldOfView>,
+    current_floor: Res<CurrentFloor>,
+    wall_rendering: Res<WallRendering>,
+    cell_handles: Res<CellHandles>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for event in open_door_events.read() {
+        if game_map.grid[event.cell].tile_type != TileType::DoorClosed {
+            continue;
+        }
+        game_map.grid[event.cell].tile_type = TileType::DoorOpen;
+        field_of_view.invalidate();
+        game_map.respawn_cells(&mut commands, &cell_meshes, &wall_chunks, &[event.cell], current_floor.0,
+                               *wall_rendering, &cell_handles, &mut game_rng, &mut meshes, &mut materials);
+    }
+}
+
+const RESPAWN_HIT_POINTS_FACTOR:f32=0.5;
+
+fn handle_player_death(
+    mut player_died_events: EventReader<PlayerDiedEvent>,
+    mut lives_mode: ResMut<LivesMode>,
+    mut next_state: ResMut<NextState<GameState>>,
+    game_map: Res<GameMap>,
+    occupancy: Res<Occupancy>,
+    mut player_query: Query<(Entity, &mut Transform, &mut Actor), With<Player>>
+) {
+    if player_died_events.read().count() == 0 {
+        return;
+    }
+
+    match *lives_mode {
+        LivesMode::Lives(lives) if lives > 0 => {
+            *lives_mode = LivesMode::Lives(lives - 1);
+            // respawn in the start room of the floor
+            for (player, mut transform, mut actor) in player_query.iter_mut() {
+                let cell = game_map.respawn_cell(&occupancy, player);
+                let position = game_map.grid_to_world(cell.0, cell.1);
+                transform.translation.x = position.x;
+                transform.translation.z = position.z;
+                actor.hit_points = ((actor.max_hit_points as f32 * RESPAWN_HIT_POINTS_FACTOR) as usize).max(1);
+            }
+        },
+        _ => {
+            next_state.set(GameState::GameOver);
+        }
+    }
+}
+
+/// A dead player can't load the run again, however the game over screen is left
+fn delete_save_of_dead_player(mut save_slot: ResMut<SaveSlot>) {
+    save_slot.delete();
+    *save_slot = SaveSlot::next_free();
+}
+
+/// Removes the world of the last game before a new one starts
+fn clear_game(
+    mut commands: Commands,
+    mut current_floor: ResMut<CurrentFloor>,
+    mut load_map_and_items: ResMut<LoadMapAndItems>,
+    mut look_mode: ResMut<LookMode>,
+    floor_parameters: Res<FloorParameters>,
+    root_query: Query<Entity, (With<Transform>, Without<Parent>)>
+) {
+    for entity in root_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    // a new game on the same floor starts unexplored
+    commands.insert_resource(FieldOfView::default());
+    // the look cursor went with the world
+    look_mode.active = false;
+    current_floor.0 = floor_parameters.start_floor;
+    load_map_and_items.0 = false;
 }
 
 const BALL_TEMPO:f32=8.0;
 const BALL_RADIUS:f32=0.2;
+const BALL_SPAWN_OFFSET:f32=PLAYER_BODY_RADIUS + BALL_RADIUS;
+
+/// Time the thrown ball flies before it vanishes
+const BALL_LIFETIME: f32 = 2.0;
+/// Time between the points of the trajectory preview
+const TRAJECTORY_PREVIEW_STEP: f32 = 1.0 / 30.0;
+
+/// Flat throw direction of the player and the start of the ball in front of the hand
+fn ball_throw_start(player_transform: &Transform, hand_position: Vec3, game_map: &GameMap) -> (Vec3, Vec3) {
+    // Determine throw direction based on player orientation
+    let mut throw_direction = player_transform.forward().as_vec3().normalize();
+    throw_direction.y = 0.0;
+
+    // pushed along the throw direction so it starts clear of the player
+    let mut start_position = hand_position + throw_direction * BALL_SPAWN_OFFSET;
+
+    // Pull back towards the hand if the offset ends inside a wall
+    let mut offset = BALL_SPAWN_OFFSET;
+    while offset > 0.0 && game_map.collide_circle(start_position, BALL_RADIUS) {
+        offset -= BALL_RADIUS;
+        start_position = hand_position + throw_direction * offset.max(0.0);
+    }
+
+    // the hand itself is in the wall when the player hugs it, the ball starts in front of the body then
+    if game_map.collide_circle(start_position, BALL_RADIUS) {
+        let body = Vec3::new(player_transform.translation.x, hand_position.y, player_transform.translation.z);
+        start_position = body + throw_direction * BALL_SPAWN_OFFSET;
+    }
 
+    (throw_direction, start_position)
+}
+
+/// One step of the flight, shared by the thrown ball and the preview
+fn ball_flight_step(position: &mut Vec3, velocity: &mut Vec3, delta_time: f32) {
+    velocity.y -= BALL_GRAVITY * delta_time; // Gravity pulls the ball downward
+    *position += *velocity * delta_time;
+}
+
+/// Arc of the ball while x is held, up to the floor or the first wall
+fn preview_ball_trajectory(
+    mut gizmos: Gizmos,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    player_query: Query<&Transform, (With<Player>, Without<ThrownBall>)>,
+    throwball_query: Query<&GlobalTransform, (With<ThrowableBall>, Without<ThrownBall>)>,
+    inventory: Res<Inventory>,
+    game_map: Res<GameMap>,
+    key_bindings: Res<KeyBindings>
+) {
+    if !keyboard_input.pressed(key_bindings.throw) || inventory.activ_item.is_none() {
+        return;
+    }
+    let (Ok(player_transform), Ok(ball_global_transform)) = (player_query.get_single(), throwball_query.get_single()) else {
+        return;
+    };
+
+    let (throw_direction, mut position) = ball_throw_start(player_transform, ball_global_transform.translation(), &game_map);
+    let mut velocity = throw_direction * BALL_TEMPO;
+    let mut points = vec![position];
+    let mut time = 0.0;
+    while time < BALL_LIFETIME {
+        ball_flight_step(&mut position, &mut velocity, TRAJECTORY_PREVIEW_STEP);
+        time += TRAJECTORY_PREVIEW_STEP;
+        points.push(position);
+        if position.y < 0.0 || game_map.collide_circle(position, BALL_RADIUS) {
+            break;
+        }
+    }
+
+    gizmos.linestrip(points, Color::srgb(0.8, 0.3, 0.3));
+}
+
+/// Throws the active item when x is released
 fn throw_ball(
     mut commands: Commands,
     keyboard_input: Res<ButtonInput<KeyCode>>,
@@ -1401,21 +3458,17 @@ fn throw_ball(
     mut throwball_query: Query<(&mut Visibility, &GlobalTransform), (With<ThrowableBall>, Without<ThrownBall>)>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    mut inventory: ResMut<Inventory>
+    mut inventory: ResMut<Inventory>,
+    game_map: Res<GameMap>,
+    key_bindings: Res<KeyBindings>
 ) {
-    if keyboard_input.just_pressed(KeyCode::KeyX) {
+    if keyboard_input.just_released(key_bindings.throw) {
         if let Ok(player_transform) = player_query.get_single() {
             if let Ok((mut ball_visibility, ball_global_transform)) = throwball_query.get_single_mut() {
 
-                // Determine throw direction based on player orientation
-                let mut throw_direction = player_transform.forward().as_vec3().normalize();
-                throw_direction.y = 0.0;
-
                 // Calculate start position using the global transformation of the ball
-                let start_position = ball_global_transform.translation();
-
-                // Remove the ball from the player
-                *ball_visibility = Visibility::Hidden;
+                let (throw_direction, start_position) =
+                    ball_throw_start(player_transform, ball_global_transform.translation(), &game_map);
 
                 // Spawn a new independent ball at the saved global position
                 if let Some(active_item) = inventory.activ_item {
@@ -1425,12 +3478,16 @@ fn throw_ball(
                         Transform::from_translation(start_position),
                         ThrownBall {
                             velocity: throw_direction * BALL_TEMPO,  // Throw velocity
-                            lifetime: Timer::from_seconds(2.0, TimerMode::Once),
-                            item_type: active_item
+                            lifetime: Timer::from_seconds(BALL_LIFETIME, TimerMode::Once),
+                            item_type: active_item,
+                            just_spawned: true
                         }
                     ));
                     inventory.remove_item(active_item);
                 }
+
+                // the next ball is in the hand while items are left
+                *ball_visibility = inventory.held_ball_visibility();
             }
         }
     }
@@ -1446,13 +3503,16 @@ fn update_thrown_ball(
     monster_query: Query<(Entity, &Transform), With<Monster>>,
     mut damage_events: EventWriter<DamageEvent>,
     game_map: Res<GameMap>, // Game world information for collision detection
+    current_floor: Res<CurrentFloor>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
     let delta_time = time.delta_secs();
 
     for (entity, mut transform, mut ball) in ball_query.iter_mut() {
         // Update position: The ball moves in its direction with a given speed
-        ball.velocity.y -= BALL_GRAVITY * delta_time; // Gravity pulls the ball downward
-        transform.translation += ball.velocity * delta_time;
+        let previous_position = transform.translation;
+        ball_flight_step(&mut transform.translation, &mut ball.velocity, delta_time);
 
         // Reduce the lifetime of the ball
         ball.lifetime.tick(time.delta());
@@ -1461,22 +3521,332 @@ fn update_thrown_ball(
             continue;
         }
 
+        // No collision on the very first frame
+        if ball.just_spawned {
+            ball.just_spawned = false;
+            continue;
+        }
+
         //floor or wall
         if transform.translation.y < 0.0  ||
-            game_map.collide_with_wall(transform.translation, BALL_RADIUS) {
+            game_map.collide_circle(transform.translation, BALL_RADIUS) {
+            // a wall is hit from the cell in front of it
+            let cell = game_map.world_to_grid(previous_position);
+            spawn_impact_decal(&mut commands, &game_map, cell, current_floor.0, &mut meshes, &mut materials);
             commands.entity(entity).despawn_recursive();
         } else if let Some(monster) = collide_with_monster(transform.translation,BALL_RADIUS,
                                                            &monster_query) {
-            damage_events.send(DamageEvent {
-                attacker: entity,
-                target: monster,
-                fixed_damage: 10
-            });
+            hit_monster(&mut commands, &mut damage_events, entity, monster, ball.item_type);
+            let cell = game_map.world_to_grid(transform.translation);
+            spawn_impact_decal(&mut commands, &game_map, cell, current_floor.0, &mut meshes, &mut materials);
+            remove_ball(&mut commands, entity);
+        }
+    }
+}
+
+const GRID_PROJECTILE_STEP_TIME:f32=0.05;
+const GRID_PROJECTILE_RANGE:usize=20;
+
+fn cardinal_throw(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut cardinal_aim: ResMut<CardinalAim>,
+    player_query: Query<(Entity, &Transform), With<Player>>,
+    monster_query: Query<&Transform, (With<Monster>, Without<Player>)>,
+    occupancy: Res<Occupancy>,
+    field_of_view: Res<FieldOfView>,
+    mut throwball_query: Query<&mut Visibility, (With<ThrowableBall>, Without<ThrownBall>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut inventory: ResMut<Inventory>,
+    game_map: Res<GameMap>,
+    key_bindings: Res<KeyBindings>
+) {
+    if !cardinal_aim.active {
+        if keyboard_input.just_pressed(key_bindings.aim) && inventory.activ_item.is_some() {
+            cardinal_aim.active = true;
+            // the aim starts towards the nearest monster in sight
+            cardinal_aim.direction = player_query.get_single().ok()
+                .and_then(|(player, player_transform)| {
+                    let (monster, _) = game_map.nearest_monster(&occupancy, player, player_transform.translation)?;
+                    let monster_position = monster_query.get(monster).ok()?.translation;
+                    field_of_view.visible.contains(&game_map.world_to_grid(monster_position))
+                        .then(|| cardinal_direction(player_transform.translation, monster_position))
+                });
+        }
+        return;
+    }
+
+    // choose direction, north is -Z
+    if keyboard_input.just_pressed(key_bindings.move_up) {
+        cardinal_aim.direction = Some((0, -1));
+    } else if keyboard_input.just_pressed(key_bindings.move_down) {
+        cardinal_aim.direction = Some((0, 1));
+    } else if keyboard_input.just_pressed(key_bindings.move_left) {
+        cardinal_aim.direction = Some((-1, 0));
+    } else if keyboard_input.just_pressed(key_bindings.move_right) {
+        cardinal_aim.direction = Some((1, 0));
+    }
+
+    if keyboard_input.just_pressed(key_bindings.cancel) {
+        cardinal_aim.active = false;
+        return;
+    }
+
+    let confirmed = keyboard_input.just_pressed(key_bindings.aim) ||
+        keyboard_input.just_pressed(key_bindings.confirm);
+    let (Some(direction), true) = (cardinal_aim.direction, confirmed) else { return };
+    cardinal_aim.active = false;
+
+    let Ok((_, player_transform)) = player_query.get_single() else { return };
+    let Some(active_item) = inventory.activ_item else { return };
+
+    let cell = game_map.world_to_grid(player_transform.translation);
+    let mut start_position = game_map.grid_to_world(cell.0, cell.1);
+    start_position.y = player_transform.translation.y;
+
+    commands.spawn((
+        Mesh3d(meshes.add(Mesh::from(Sphere::new(BALL_RADIUS)))),
+        MeshMaterial3d(materials.add(Color::srgb(0.8, 0.3, 0.3))), // Rote Kugel
+        Transform::from_translation(start_position),
+        GridProjectile {
+            item_type: active_item,
+            direction,
+            cell,
+            steps_left: GRID_PROJECTILE_RANGE,
+            step_timer: Timer::from_seconds(GRID_PROJECTILE_STEP_TIME, TimerMode::Repeating)
+        }
+    ));
+    inventory.remove_item(active_item);
+    if let Ok(mut ball_visibility) = throwball_query.get_single_mut() {
+        *ball_visibility = inventory.held_ball_visibility();
+    }
+}
+
+/// Grid direction along the longer axis from one position to another, north is -Z
+fn cardinal_direction(from: Vec3, to: Vec3) -> (i32, i32) {
+    let difference = to - from;
+    if difference.x.abs() > difference.z.abs() {
+        (difference.x.signum() as i32, 0)
+    } else {
+        (0, difference.z.signum() as i32)
+    }
+}
+
+fn update_grid_projectile(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut projectile_query: Query<(Entity, &mut Transform, &mut GridProjectile), Without<Monster>>,
+    monster_query: Query<(Entity, &Transform), With<Monster>>,
+    mut damage_events: EventWriter<DamageEvent>,
+    game_map: Res<GameMap>,
+    current_floor: Res<CurrentFloor>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, mut transform, mut projectile) in projectile_query.iter_mut() {
+        projectile.step_timer.tick(time.delta());
+        if !projectile.step_timer.just_finished() {
+            continue;
+        }
+
+        let next_x = projectile.cell.0 as i32 + projectile.direction.0;
+        let next_y = projectile.cell.1 as i32 + projectile.direction.1;
+
+        // first wall ends the flight
+        if projectile.steps_left == 0 || next_x < 0 || next_y < 0 ||
+            !game_map.grid.is_valid_position(next_x as usize, next_y as usize) {
             remove_ball(&mut commands, entity);
+            continue;
+        }
+        let next = (next_x as usize, next_y as usize);
+        let tile_type = game_map.grid[next].tile_type;
+        if tile_type.is_blocking() || tile_type == TileType::Empty {
+            spawn_impact_decal(&mut commands, &game_map, projectile.cell, current_floor.0, &mut meshes, &mut materials);
+            remove_ball(&mut commands, entity);
+            continue;
+        }
+
+        projectile.cell = next;
+        projectile.steps_left -= 1;
+        let position = game_map.grid_to_world(next.0, next.1);
+        transform.translation.x = position.x;
+        transform.translation.z = position.z;
+
+        // first monster in the line gets hit
+        if let Some((monster, _)) = monster_query.iter()
+            .find(|(_, monster_transform)| game_map.world_to_grid(monster_transform.translation) == next) {
+            hit_monster(&mut commands, &mut damage_events, entity, monster, projectile.item_type);
+            spawn_impact_decal(&mut commands, &game_map, next, current_floor.0, &mut meshes, &mut materials);
+            remove_ball(&mut commands, entity);
+        }
+    }
+}
+
+const IMPACT_DECAL_TIME: f32 = 1.5;
+const IMPACT_DECAL_ALPHA: f32 = 0.8;
+
+/// Moves the look cursor cell by cell with the arrows and tells what is on the cell,
+/// the look key or escape give the control back to the player. The cursor starts on the
+/// nearest monster in sight
+fn look_around(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut look_mode: ResMut<LookMode>,
+    cardinal_aim: Res<CardinalAim>,
+    game_map: Res<GameMap>,
+    occupancy: Res<Occupancy>,
+    field_of_view: Res<FieldOfView>,
+    current_floor: Res<CurrentFloor>,
+    mut message_log: ResMut<MessageLog>,
+    player_query: Query<(Entity, &Transform), With<Player>>,
+    mut cursor_query: Query<(Entity, &mut Transform), (With<LookCursor>, Without<Player>)>,
+    monster_query: Query<(&Transform, &Name, &Actor), (With<Monster>, Without<Player>, Without<LookCursor>)>,
+    item_query: Query<(&Transform, &Item), (Without<Player>, Without<LookCursor>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>
+) {
+    if !look_mode.active {
+        if !keyboard_input.just_pressed(key_bindings.look) || cardinal_aim.active {
+            return;
+        }
+        let Ok((player_entity, player_transform)) = player_query.get_single() else { return };
+        look_mode.active = true;
+        look_mode.cell = game_map.world_to_grid(player_transform.translation);
+        if let Some(monster_cell) = game_map.nearest_monster(&occupancy, player_entity, player_transform.translation)
+            .and_then(|(monster, _)| monster_query.get(monster).ok())
+            .map(|(monster_transform, _, _)| game_map.world_to_grid(monster_transform.translation))
+            .filter(|cell| field_of_view.visible.contains(cell)) {
+            look_mode.cell = monster_cell;
+        }
+
+        let position = game_map.grid_to_world(look_mode.cell.0, look_mode.cell.1);
+        commands.spawn((
+            Mesh3d(meshes.add(Mesh::from(Cuboid::new(TILE_SIZE, 0.05, TILE_SIZE)))),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: Color::srgba(1.0, 1.0, 0.2, 0.35),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            })),
+            Transform::from_xyz(position.x, 0.05, position.z),
+            LookCursor,
+            Floor(current_floor.0),
+            Name::new("look-cursor")
+        ));
+    } else if keyboard_input.just_pressed(key_bindings.look) || keyboard_input.just_pressed(key_bindings.cancel) {
+        look_mode.active = false;
+        for (cursor, _) in cursor_query.iter() {
+            commands.entity(cursor).despawn_recursive();
+        }
+        return;
+    } else {
+        // north is -Z like the grid throw
+        let direction = if keyboard_input.just_pressed(key_bindings.move_up) {
+            (0, -1)
+        } else if keyboard_input.just_pressed(key_bindings.move_down) {
+            (0, 1)
+        } else if keyboard_input.just_pressed(key_bindings.move_left) {
+            (-1, 0)
+        } else if keyboard_input.just_pressed(key_bindings.move_right) {
+            (1, 0)
+        } else {
+            return;
+        };
+        let x = (look_mode.cell.0 as i32 + direction.0).clamp(0, game_map.width as i32 - 1) as usize;
+        let y = (look_mode.cell.1 as i32 + direction.1).clamp(0, game_map.height as i32 - 1) as usize;
+        look_mode.cell = (x, y);
+
+        let position = game_map.grid_to_world(x, y);
+        for (_, mut cursor_transform) in cursor_query.iter_mut() {
+            cursor_transform.translation.x = position.x;
+            cursor_transform.translation.z = position.z;
+        }
+    }
+
+    let cell = look_mode.cell;
+    if !field_of_view.explored.contains(&cell) {
+        message_log.push_message("You haven't seen this place");
+        return;
+    }
+
+    let mut seen = vec![game_map.grid[cell].tile_type.description().to_string()];
+    // monsters and items only where the player sees them now
+    if field_of_view.visible.contains(&cell) {
+        let actors = occupancy.cells.get(&cell).into_iter().flatten();
+        for (_, name, actor) in actors.filter_map(|(entity, _)| monster_query.get(*entity).ok()) {
+            seen.push(format!("{} {}/{} HP", name, actor.hit_points, actor.max_hit_points));
+        }
+        for (item_transform, item) in item_query.iter() {
+            if game_map.world_to_grid(item_transform.translation) == cell {
+                seen.push(item.item_type.to_string());
+            }
+        }
+    }
+    message_log.push_message(format!("You see {}", seen.join(", ")));
+}
+
+/// Scorch mark on the floor of the impact cell
+fn spawn_impact_decal(
+    commands: &mut Commands,
+    game_map: &GameMap,
+    cell: (usize, usize),
+    floor: usize,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+) {
+    let position = game_map.grid_to_world(cell.0, cell.1);
+    commands.spawn((
+        Mesh3d(meshes.add(Mesh::from(Circle::new(TILE_SIZE * 0.3)))),
+        MeshMaterial3d(materials.add(StandardMaterial {
+            base_color: Color::srgba(0.05, 0.03, 0.02, IMPACT_DECAL_ALPHA),
+            alpha_mode: AlphaMode::Blend,
+            ..default()
+        })),
+        Transform::from_xyz(position.x, 0.02, position.z)
+            .with_rotation(Quat::from_rotation_x(-PI / 2.0)),
+        ImpactDecal(Timer::from_seconds(IMPACT_DECAL_TIME, TimerMode::Once)),
+        Floor(floor),
+        Name::new("impact-decal")
+    ));
+}
+
+fn fade_impact_decals(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut decal_query: Query<(Entity, &MeshMaterial3d<StandardMaterial>, &mut ImpactDecal)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, material_handle, mut decal) in decal_query.iter_mut() {
+        decal.0.tick(time.delta());
+        if decal.0.finished() {
+            commands.entity(entity).despawn_recursive();
+        } else if let Some(material) = materials.get_mut(material_handle.id()) {
+            material.base_color.set_alpha(IMPACT_DECAL_ALPHA * decal.0.fraction_remaining());
         }
     }
 }
 
+/// A confusion scroll confuses the monster, the other items damage it
+fn hit_monster(
+    commands: &mut Commands,
+    damage_events: &mut EventWriter<DamageEvent>,
+    attacker: Entity,
+    monster: Entity,
+    item_type: ItemType
+) {
+    if item_type == ItemType::ConfusionScroll {
+        commands.entity(monster).insert(StatusEffect::confused());
+    } else {
+        damage_events.send(DamageEvent {
+            attacker,
+            target: monster,
+            fixed_damage: 10
+        });
+    }
+}
+
 fn remove_ball(
     commands: &mut Commands,
     entity: Entity
@@ -1499,52 +3869,125 @@ fn collide_with_monster(
 }
 
 fn quit(
-    keyboard_input:Res<ButtonInput<KeyCode>>,
-    game_map: Res<GameMap>,
-    query_player: Query<(&Transform, &Actor), With<Player>>,
-    query_item: Query<(&Item, &Transform), (With<Item>, Without<Player>)>,
-    query_monster: Query<(&Monster, &Transform), (With<Monster>, Without<Player>)>,
-    inventory: Res<Inventory>,
-    current_floor: Res<CurrentFloor>
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut save_game_events: EventWriter<SaveGameEvent>
+) {
+    if keyboard_input.just_pressed(key_bindings.quit) {
+        save_game_events.send(SaveGameEvent(AfterSave::Exit));
+    }
+}
+
+/// Opens the pause menu, escape first cancels a grid throw being aimed or the look cursor
+fn toggle_pause(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    cardinal_aim: Res<CardinalAim>,
+    look_mode: Res<LookMode>,
+    pause_state: Res<State<PauseState>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>
+) {
+    if !keyboard_input.just_pressed(key_bindings.pause) {
+        return;
+    }
+    match pause_state.get() {
+        PauseState::Running if !cardinal_aim.active && !look_mode.active => next_pause_state.set(PauseState::Paused),
+        PauseState::Paused => next_pause_state.set(PauseState::Running),
+        _ => {}
+    }
+}
+
+/// Timers and movement stand still while paused, so there is no long frame on resume
+fn pause_time(mut time: ResMut<Time<Virtual>>) {
+    time.pause();
+}
+
+fn unpause_time(mut time: ResMut<Time<Virtual>>) {
+    time.unpause();
+}
+
+/// Monsters on the floor, as they are written to the save
+type MonsterSaveQuery<'w, 's> = Query<'w, 's, (&'static Monster, &'static Transform), (With<Monster>, Without<Player>)>;
+
+/// Everything of the running game that goes into a save
+#[derive(SystemParam)]
+struct GameToSave<'w, 's> {
+    game_map: Res<'w, GameMap>,
+    player: Query<'w, 's, (&'static Transform, &'static Actor), With<Player>>,
+    items: ItemSaveQuery<'w, 's>,
+    monsters: MonsterSaveQuery<'w, 's>,
+    inventory: Res<'w, Inventory>,
+    current_floor: Res<'w, CurrentFloor>,
+    field_of_view: Res<'w, FieldOfView>,
+    lives_mode: Res<'w, LivesMode>,
+    difficulty: Res<'w, Difficulty>
+}
+
+fn save_game(
+    mut save_game_events: EventReader<SaveGameEvent>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut message_log: ResMut<MessageLog>,
+    game: GameToSave,
+    save_slot: Res<SaveSlot>,
+    user_config: Res<UserConfig>
 )
 {
-    if keyboard_input.just_pressed(KeyCode::KeyQ) {
-        for (player,player_actor) in query_player.iter() {
-            let mut items: Vec<(Vec3,ItemType)> = Vec::new();
-            for (item, item_transform) in query_item.iter() {
-                items.push((item_transform.translation.clone(),item.item_type.clone()));
-            }
-            let mut monsters:Vec<(Vec3,MonsterType)> = Vec::new();
-            for (monster, monster_transform) in query_monster.iter() {
-                monsters.push((monster_transform.translation.clone(),monster.monster_type.clone()))
-            }
-            game_map.save(
-                player.translation.clone(),
-                items,
-                monsters
-            );
+    for SaveGameEvent(after_save) in save_game_events.read() {
+        let Ok((player, player_actor)) = game.player.get_single() else { continue };
+        save_slot.create_dir();
+
+        let mut items: Vec<(Vec3, ItemType, Option<usize>)> = Vec::new();
+        for (item, item_transform, gold_pile) in game.items.iter() {
+            items.push((item_transform.translation, item.item_type, gold_pile.map(|gold_pile| gold_pile.0)));
+        }
+        let mut monsters:Vec<(Vec3,MonsterType)> = Vec::new();
+        for (monster, monster_transform) in game.monsters.iter() {
+            monsters.push((monster_transform.translation,monster.monster_type.clone()))
+        }
+        game.game_map.save(
+            &save_slot,
+            player.translation,
+            items,
+            monsters
+        );
+
+        game.inventory.save(&save_slot);
+
+        player_actor.save(&save_slot);
+
+        game.current_floor.save(&save_slot);
 
-            inventory.save();
+        game.field_of_view.save(&save_slot);
 
-            player_actor.save();
+        game.lives_mode.save(&save_slot);
 
-            current_floor.save();
+        game.difficulty.save(&save_slot);
 
-            std::process::exit(0);
+        user_config.save(USER_CONFIG_FILE);
+
+        match after_save {
+            AfterSave::Continue => message_log.push_message(String::from("Game saved")),
+            AfterSave::MainMenu => next_state.set(GameState::MainMenu),
+            AfterSave::Exit => std::process::exit(0)
         }
-    };
+    }
 }
 
 const TRANSITION_SPEED:f32=2.0;
 
 fn do_transition_stairsdown(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
+    // one parameter for both, the system is at the parameter limit
+    (asset_server, cell_handles): (Res<AssetServer>, Res<CellHandles>),
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut current_floor: ResMut<CurrentFloor>,
     floor_parameters: Res<FloorParameters>,
     item_and_monster_parameters: Res<ItemAndMonsterParameters>,
+    wall_rendering: Res<WallRendering>,
+    lighting_settings: Res<LightingSettings>,
+    difficulty: Res<Difficulty>,
+    mut game_rng: ResMut<GameRng>,
     mut game_map: ResMut<GameMap>,
     time: Res<Time>,
     mut next_state: ResMut<NextState<TransitionState>>,
@@ -1558,6 +4001,7 @@ fn do_transition_stairsdown(
                 if player_transition.timer.finished() {
                     player_transition.step = TransitionStep::StairDownEnd;
                     player_transition.timer.set_duration(Duration::from_secs_f32(2.0));
+                    player_transition.timer.reset();
                     //respawn current floor
                     despawn_current_floor(
                         &mut commands,
@@ -1572,9 +4016,16 @@ fn do_transition_stairsdown(
                         &mut current_floor,
                         &floor_parameters,
                         &item_and_monster_parameters,
+                        *wall_rendering,
+                        &cell_handles,
+                        &lighting_settings,
+                        &difficulty.settings(),
+                        &mut game_rng,
                         &mut game_map,
                         &mut player_transform);
                     player_transform.translation.y = 4.0 * PLAYER_DISTANCE;
+                    // autosave, a crash later on keeps the new floor
+                    commands.send_event(SaveGameEvent(AfterSave::Continue));
                 } else {
                     player_transition.timer.tick(time.delta());
                     let mut new_position = player_transform.translation.clone();
@@ -1596,43 +4047,122 @@ fn do_transition_stairsdown(
                         player_transform.translation.y = 0.0;
                     }
                 }
-            }
+            },
+            _ => {}
+        }
+    }
+}
+
+fn do_transition_stairsup(
+    mut commands: Commands,
+    // one parameter for both, the system is at the parameter limit
+    (asset_server, cell_handles): (Res<AssetServer>, Res<CellHandles>),
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut current_floor: ResMut<CurrentFloor>,
+    floor_parameters: Res<FloorParameters>,
+    item_and_monster_parameters: Res<ItemAndMonsterParameters>,
+    wall_rendering: Res<WallRendering>,
+    lighting_settings: Res<LightingSettings>,
+    difficulty: Res<Difficulty>,
+    mut game_rng: ResMut<GameRng>,
+    mut game_map: ResMut<GameMap>,
+    time: Res<Time>,
+    mut next_state: ResMut<NextState<TransitionState>>,
+    mut player_query: Query<(Entity, &mut Transform, &mut PlayerTransition), With<Player>>,
+    despawn_query: Query<Entity, (With<Floor>, Without<Player>)>,
+){
+
+    for(player_entity, mut player_transform, mut player_transition) in player_query.iter_mut() {
+        match player_transition.step {
+            TransitionStep::StairUpStart => {
+                if player_transition.timer.finished() {
+                    player_transition.step = TransitionStep::StairUpEnd;
+                    player_transition.timer.set_duration(Duration::from_secs_f32(2.0));
+                    player_transition.timer.reset();
+                    //respawn current floor
+                    despawn_current_floor(
+                        &mut commands,
+                         &despawn_query);
+                    //generate previous floor
+                    current_floor.0 -= 1;
+                    setup_next_floor(
+                        &mut commands,
+                        &asset_server,
+                        &mut meshes,
+                        &mut materials,
+                        &mut current_floor,
+                        &floor_parameters,
+                        &item_and_monster_parameters,
+                        *wall_rendering,
+                        &cell_handles,
+                        &lighting_settings,
+                        &difficulty.settings(),
+                        &mut game_rng,
+                        &mut game_map,
+                        &mut player_transform);
+                    player_transform.translation.y = -4.0 * PLAYER_DISTANCE;
+                    // autosave, a crash later on keeps the new floor
+                    commands.send_event(SaveGameEvent(AfterSave::Continue));
+                } else {
+                    player_transition.timer.tick(time.delta());
+                    let mut new_position = player_transform.translation;
+                    new_position.y += time.delta_secs()*TRANSITION_SPEED;
+                    player_transform.translation = new_position;
+                }
+            },
+            TransitionStep::StairUpEnd => {
+                if player_transition.timer.finished() {
+                    //despawn player_transition
+                    commands.entity(player_entity).remove::<PlayerTransition>();
+                    next_state.set(TransitionState::Running);
+                } else {
+                    player_transition.timer.tick(time.delta());
+                    let mut new_position = player_transform.translation;
+                    new_position.y += time.delta_secs()*TRANSITION_SPEED;
+                    player_transform.translation = new_position;
+                    if player_transform.translation.y > 0.0 {
+                        player_transform.translation.y = 0.0;
+                    }
+                }
+            },
+            _ => {}
         }
     }
 }
 
 fn setup_next_floor(
-    mut commands: &mut Commands,
+    commands: &mut Commands,
     asset_server: &Res<AssetServer>,
-    mut meshes: &mut ResMut<Assets<Mesh>>,
-    mut materials: &mut ResMut<Assets<StandardMaterial>>,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
     current_floor: &mut ResMut<CurrentFloor>,
     floor_parameters: &Res<FloorParameters>,
     item_and_monster_parameters: &Res<ItemAndMonsterParameters>,
-    mut game_map: &mut ResMut<GameMap>,
+    wall_rendering: WallRendering,
+    cell_handles: &CellHandles,
+    lighting_settings: &LightingSettings,
+    difficulty: &DifficultySettings,
+    game_rng: &mut GameRng,
+    game_map: &mut ResMut<GameMap>,
     player: &mut Transform
 ) {
 
     let player_position = game_map.world_to_grid(player.translation.clone());
-    **game_map = GameMap::create_dungeon(MapGeneratorStart::new(80, 45,
-                                                                current_floor.0,
-                                                       MAX_ROOMS,
-                                                       ROOM_MIN_SIZE,
-                                                       ROOM_MAX_SIZE,
-                                                         floor_parameters.items[current_floor.0].max_monsters_per_room,
-                                                            floor_parameters.items[current_floor.0].max_items_per_room,
-                                                                item_and_monster_parameters.parameters[current_floor.0].clone(),
-                                                                Some(player_position)
-    ))
-            .expect("Failed to create level");
+    **game_map = generate_floor_map(current_floor.0, floor_parameters, item_and_monster_parameters, difficulty, Some(player_position));
+
+    // don't land on a monster or inside a wall of the new floor
+    game_map.move_player_to_free_cell(player);
 
     // monster
-    setup_monster(&mut commands, &current_floor, &mut meshes, &mut materials, &mut game_map);
+    setup_monster(commands, current_floor, difficulty, meshes, materials, game_map);
     // item
-    setup_item(&mut commands, &asset_server, &current_floor, &mut game_map);
+    setup_item(commands, asset_server, current_floor, floor_parameters.seed, game_map);
 
     // ground
-    game_map.generate(&mut commands, current_floor, asset_server,  meshes, materials);
+    game_map.generate(commands, current_floor, wall_rendering, cell_handles, game_rng, meshes, materials);
+    // torches
+    spawn_torch_lights(commands, game_map, current_floor.0, lighting_settings, &mut game_rng.0);
 }
 
 fn despawn_current_floor(
@@ -1642,4 +4172,1003 @@ fn despawn_current_floor(
     for entity in query.iter() {
         commands.entity(entity).despawn_recursive();
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use crate::save_slot::test_slot;
+
+    #[test]
+    fn explored_cells_are_read_back_from_the_file() {
+        let path = std::env::temp_dir().join("rust-bevy-rogue-test-explored.json");
+        let explored: HashSet<(usize, usize)> = [(1, 1), (2, 1), (30, 12)].into_iter().collect();
+        save_explored_cells(&path, &explored);
+
+        assert_eq!(load_explored_cells(&path), explored);
+        fs::remove_file(&path).unwrap();
+        // a save without the file starts unexplored
+        assert!(load_explored_cells(&path).is_empty());
+    }
+
+    #[test]
+    fn floor_file_holds_the_floor_after_a_descent() {
+        let save_slot = test_slot("descent");
+        let mut current_floor = CurrentFloor(0);
+        current_floor.next();
+        current_floor.save(&save_slot);
+
+        assert_eq!(fs::read_to_string(save_slot.path(FLOOR_JSON_FILE)).unwrap(), "1");
+        assert_eq!(CurrentFloor::load(&save_slot).0, 1);
+        save_slot.delete();
+    }
+
+    #[test]
+    fn lives_left_survive_save_and_load() {
+        let save_slot = test_slot("lives");
+        LivesMode::Lives(1).save(&save_slot);
+
+        let loaded = LivesMode::load(&save_slot);
+        save_slot.delete();
+        assert_eq!(loaded, Some(LivesMode::Lives(1)));
+        assert_eq!(LivesMode::load(&save_slot), None);
+    }
+
+    #[test]
+    fn loaded_game_keeps_its_difficulty() {
+        let load_game = |saved_difficulty: Option<Difficulty>| {
+            let save_slot = test_slot("difficulty");
+            let game_map = GameMap::from_string("#######\n#@...>#\n#######").unwrap();
+            game_map.save(&save_slot, game_map.grid_to_world(1, 1), Vec::new(), vec![(game_map.grid_to_world(3, 1), MonsterType::Orc)]);
+            Actor::new(30, 30, 2, 5, 0).save(&save_slot);
+            Inventory::new().save(&save_slot);
+            CurrentFloor(1).save(&save_slot);
+            if let Some(difficulty) = saved_difficulty {
+                difficulty.save(&save_slot);
+            }
+
+            let mut app = app_with_cell_handles();
+            let world = app.world_mut();
+            world.insert_resource(LoadMapAndItems(true));
+            world.insert_resource(CurrentFloor(0));
+            world.insert_resource(FloorParameters::new());
+            world.insert_resource(ItemAndMonsterParameters::new());
+            world.insert_resource(StartingLoadout::new(Difficulty::Easy));
+            world.init_resource::<WallRendering>();
+            world.insert_resource(save_slot.clone());
+            world.insert_resource(StartingLives(LivesMode::Permadeath));
+            world.init_resource::<MessageLog>();
+            world.run_system_once(setup).unwrap();
+            save_slot.delete();
+            let orc_hit_points = world.query_filtered::<&Actor, With<Monster>>().single(world).max_hit_points;
+            (*world.resource::<Difficulty>(), orc_hit_points)
+        };
+
+        let (difficulty, hard_orc_hit_points) = load_game(Some(Difficulty::Hard));
+        assert_eq!(difficulty, Difficulty::Hard);
+        // saves without a difficulty take the one of the menu
+        let (difficulty, easy_orc_hit_points) = load_game(None);
+        assert_eq!(difficulty, Difficulty::Easy);
+        assert!(hard_orc_hit_points > easy_orc_hit_points);
+    }
+
+    #[test]
+    fn wall_chunk_only_shows_the_faces_of_explored_cells() {
+        let game_map = GameMap::from_string("#####\n#@.>#\n#####").unwrap();
+        let mut faces = Vec::new();
+        for y in 0..game_map.height {
+            for x in 0..game_map.width {
+                faces.extend(game_map.wall_faces((x, y)).into_iter().map(|face| ((x, y), face)));
+            }
+        }
+        let mut wall_chunk = WallChunk { chunk: (0, 0), faces, shown: Vec::new(), wall_mesh: Handle::default() };
+        assert!(wall_chunk.shown_faces().is_empty());
+
+        // the walls behind the explored cell stay hidden
+        let explored = HashSet::from([(1, 0), (1, 1)]);
+        wall_chunk.shown = wall_chunk.explored_cells(&explored);
+
+        assert_eq!(wall_chunk.shown, vec![(1, 0)]);
+        assert_eq!(wall_chunk.shown_faces().len(), 1);
+    }
+
+    #[test]
+    fn same_seed_draws_the_same_numbers_in_two_apps() {
+        let draws = |seed: u64| {
+            let mut app = App::new();
+            app.insert_resource(GameRng::new(Some(seed)));
+            let mut game_rng = app.world_mut().resource_mut::<GameRng>();
+            (0..16).map(|_| game_rng.0.gen_range(0..1000)).collect::<Vec<u32>>()
+        };
+
+        assert_eq!(draws(42), draws(42));
+        assert_ne!(draws(42), draws(43));
+    }
+
+    #[test]
+    fn opened_door_stays_open_after_save_and_load() {
+        let save_slot = test_slot("doors");
+        let mut game_map = GameMap::from_string("#######\n#@+.+>#\n#######").unwrap();
+        game_map.grid[(2, 1)].tile_type = TileType::DoorOpen;
+        game_map.save(&save_slot, game_map.grid_to_world(1, 1), vec![], vec![]);
+
+        let loaded = GameMap::load(&save_slot);
+        save_slot.delete();
+
+        assert_eq!(loaded.grid[(2, 1)].tile_type, TileType::DoorOpen);
+        assert_eq!(loaded.grid[(4, 1)].tile_type, TileType::DoorClosed);
+    }
+
+    /// Stats and the sorted part names of the player set up for a new or a loaded game
+    fn set_up_player(load: bool, save_slot: SaveSlot) -> (Actor, Vec3, Vec<String>) {
+        let mut world = World::new();
+        world.insert_resource(LoadMapAndItems(load));
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        let mut game_map = GameMap::from_string("#####\n#.@>#\n#####").unwrap();
+        world.run_system_once(move |mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>,
+                                    mut materials: ResMut<Assets<StandardMaterial>>, load_map_and_items: Res<LoadMapAndItems>| {
+            setup_character(&mut commands, &mut meshes, &mut materials, &mut game_map, &load_map_and_items,
+                            &save_slot, &StartingLoadout::new(Difficulty::Normal));
+        }).unwrap();
+
+        let (actor, transform) = world.query_filtered::<(&Actor, &Transform), With<Player>>().single(&world);
+        let (actor, position) = (actor.clone(), transform.translation);
+        let mut parts: Vec<String> = world.query_filtered::<&Name, Without<Player>>().iter(&world)
+            .map(|name| name.to_string())
+            .collect();
+        parts.sort();
+        (actor, position, parts)
+    }
+
+    #[test]
+    fn new_and_loaded_player_are_spawned_alike() {
+        let (actor, position, parts) = set_up_player(false, SaveSlot::default());
+        assert_eq!((actor.max_hit_points, actor.defense, actor.power), (30, 2, 5));
+        assert_eq!(parts, ["player-front", "player-left-arm", "player-right-arm", "player-sword", "player-throwball"]);
+
+        let save_slot = test_slot("player");
+        let mut saved_actor = Actor::new(50, 42, 4, 9, 0);
+        saved_actor.current_level = 2;
+        saved_actor.current_xp = 80;
+        saved_actor.save(&save_slot);
+        let (loaded_actor, loaded_position, loaded_parts) = set_up_player(true, save_slot.clone());
+        save_slot.delete();
+
+        assert_eq!((loaded_actor.max_hit_points, loaded_actor.hit_points, loaded_actor.defense, loaded_actor.power), (50, 42, 4, 9));
+        assert_eq!((loaded_actor.current_level, loaded_actor.current_xp), (2, 80));
+        assert_eq!(loaded_position, position);
+        assert_eq!(loaded_parts, parts);
+    }
+
+    #[test]
+    fn item_under_a_monster_survives_save_and_load() {
+        let save_slot = test_slot("items");
+        let game_map = GameMap::from_string("#######\n#@...>#\n#######").unwrap();
+        let items = vec![
+            (game_map.grid_to_world(3, 1), ItemType::HealPotion, None),
+            (game_map.grid_to_world(2, 1), ItemType::Gold, Some(42))
+        ];
+        let monsters = vec![(game_map.grid_to_world(3, 1), MonsterType::Orc)];
+        game_map.save(&save_slot, game_map.grid_to_world(1, 1), items, monsters);
+
+        let loaded = GameMap::load(&save_slot);
+        save_slot.delete();
+
+        assert_eq!(loaded.monsters.len(), 1);
+        assert_eq!((loaded.monsters[0].monster_type.clone(), loaded.monsters[0].position), (MonsterType::Orc, (3, 1)));
+        assert_eq!(loaded.items, vec![
+            ItemInMap { position: (3, 1), item_type: ItemType::HealPotion, gold: None },
+            ItemInMap { position: (2, 1), item_type: ItemType::Gold, gold: Some(42) }
+        ]);
+        assert_eq!(loaded.grid[(3, 1)].tile_type, TileType::Floor);
+    }
+
+    #[test]
+    fn floors_past_the_table_use_its_last_entry() {
+        let floor_parameters = FloorParameters::new();
+        assert!(std::ptr::eq(floor_parameters.get(100), floor_parameters.items.last().unwrap()));
+        assert!(std::ptr::eq(floor_parameters.get(0), &floor_parameters.items[0]));
+    }
+
+    #[test]
+    fn three_heal_potions_are_counted_and_shown() {
+        let mut inventory = Inventory::new();
+        for _ in 0..3 {
+            inventory.add_item(ItemType::HealPotion);
+        }
+
+        assert_eq!(inventory.count(ItemType::HealPotion), 3);
+        assert_eq!(inventory.potion_label(), "<P>ortion 3");
+    }
+
+    #[test]
+    fn removing_lightning_leaves_the_potions() {
+        let mut inventory = Inventory::new();
+        inventory.add_item(ItemType::HealPotion);
+        inventory.add_item(ItemType::HealPotion);
+        inventory.add_item(ItemType::Lightning);
+        inventory.add_item(ItemType::Lightning);
+
+        inventory.remove_item(ItemType::Lightning);
+        assert_eq!(inventory.count(ItemType::HealPotion), 2);
+        assert_eq!(inventory.count(ItemType::Lightning), 1);
+        assert_eq!(inventory.activ_item, Some(ItemType::Lightning));
+    }
+
+    #[test]
+    fn removing_the_last_lightning_clears_the_active_item() {
+        let mut inventory = Inventory::new();
+        inventory.add_item(ItemType::HealPotion);
+        inventory.add_item(ItemType::Lightning);
+        assert_eq!(inventory.activ_item, Some(ItemType::Lightning));
+
+        inventory.remove_item(ItemType::Lightning);
+        assert_eq!(inventory.count(ItemType::Lightning), 0);
+        assert_eq!(inventory.count(ItemType::HealPotion), 1);
+        assert_eq!(inventory.activ_item, None);
+    }
+
+    #[test]
+    fn minimap_texture_has_a_pixel_per_cell_and_walls_differ_from_floor() {
+        let game_map = GameMap::from_string("######\n#@..>#\n######").unwrap();
+        let pixel = |image: &egui::ColorImage, (x, y): (usize, usize)| image.pixels[y * image.size[0] + x];
+
+        let image = game_map.to_minimap_texture((1, 1), None);
+        assert_eq!(image.size, [6, 3]);
+        assert_ne!(pixel(&image, (0, 0)), pixel(&image, (2, 1)));
+        assert_ne!(pixel(&image, (1, 1)), pixel(&image, (2, 1)), "the player is marked");
+
+        let explored = HashSet::from([(1, 1), (2, 1)]);
+        let image = game_map.to_minimap_texture((1, 1), Some(&explored));
+        assert_eq!(pixel(&image, (3, 1)), egui::Color32::TRANSPARENT);
+        assert_ne!(pixel(&image, (2, 1)), egui::Color32::TRANSPARENT);
+    }
+
+    #[test]
+    fn positions_outside_the_map_give_a_border_cell() {
+        let game_map = GameMap::from_string("#####\n#@.>#\n#####").unwrap();
+
+        assert_eq!(game_map.world_to_grid(Vec3::new(-1000.0, 0.0, -1000.0)), (0, 0));
+        assert_eq!(game_map.world_to_grid(Vec3::new(1000.0, 0.0, 1000.0)), (4, 2));
+        assert_eq!(game_map.world_to_grid(game_map.grid_to_world(3, 1)), (3, 1));
+    }
+
+    #[test]
+    fn center_follows_a_resized_grid() {
+        let mut game_map = GameMap::from_string("#####\n#@.>#\n#####").unwrap();
+        assert_eq!(game_map.center(), (2, 1));
+
+        game_map.grid = Grid::new(9, 7, TileType::Floor);
+
+        assert_eq!(game_map.center(), (4, 3));
+        assert_eq!(game_map.grid_to_world(4, 3), Vec3::ZERO);
+        assert_eq!(game_map.world_to_grid(game_map.grid_to_world(8, 6)), (8, 6));
+    }
+
+    #[test]
+    fn player_in_a_wall_fails_validation() {
+        let mut game_map = GameMap::from_string("#####\n#@.>#\n#####").unwrap();
+        assert_eq!(game_map.validate(), Ok(()));
+
+        game_map.player_position = (0, 1);
+        assert_eq!(game_map.validate(), Err(vec![String::from("player at (0, 1) is on Wall")]));
+    }
+
+    #[test]
+    fn troll_is_stronger_than_orc() {
+        let (orc, troll) = (MonsterType::Orc.stats(), MonsterType::Troll.stats());
+
+        assert!(troll.max_hit_points > orc.max_hit_points);
+        assert!(troll.xp_given > orc.xp_given);
+    }
+
+    #[test]
+    fn monsters_on_hard_have_more_hit_points_than_on_easy() {
+        let spawned_hit_points = |difficulty: Difficulty| {
+            let mut world = World::new();
+            world.insert_resource(CurrentFloor(1));
+            world.init_resource::<Assets<Mesh>>();
+            world.init_resource::<Assets<StandardMaterial>>();
+            let mut game_map = GameMap::from_string("#####\n#@.>#\n#####").unwrap();
+            game_map.monsters = vec![MonsterInMap { position: (2, 1), monster_type: MonsterType::Troll }];
+            world.run_system_once(move |mut commands: Commands, current_floor: ResMut<CurrentFloor>,
+                                        mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<StandardMaterial>>| {
+                setup_monster(&mut commands, &current_floor, &difficulty.settings(), &mut meshes, &mut materials, &mut game_map);
+            }).unwrap();
+            world.query_filtered::<&Actor, With<Monster>>().single(&world).max_hit_points
+        };
+
+        assert!(spawned_hit_points(Difficulty::Hard) > spawned_hit_points(Difficulty::Easy));
+    }
+
+    #[test]
+    fn deeper_orcs_are_stronger_and_worth_more() {
+        let stats = MonsterType::Orc.stats();
+        let orc = Actor::new(stats.max_hit_points, stats.max_hit_points, stats.defense, stats.power, stats.xp_given);
+        let (first_floor, fifth_floor) = (depth_scaled_stats(orc.clone(), 1), depth_scaled_stats(orc, 5));
+
+        assert!(fifth_floor.max_hit_points > first_floor.max_hit_points);
+        assert_eq!(fifth_floor.hit_points, fifth_floor.max_hit_points);
+        assert!(fifth_floor.xp_given > first_floor.xp_given);
+    }
+
+    #[test]
+    fn ball_stays_in_the_hand_while_lightning_is_left() {
+        let mut world = World::new();
+        let game_map = GameMap::from_string("#######\n#@...>#\n#######").unwrap();
+        let player_position = game_map.grid_to_world(1, 1);
+        world.insert_resource(game_map);
+        world.insert_resource(Inventory::new());
+        world.insert_resource(Pickup::default());
+        world.insert_resource(KeyBindings::default());
+        world.init_resource::<MessageLog>();
+        world.init_resource::<Events<PlaySoundEvent>>();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        world.spawn((Transform::from_translation(player_position), Player));
+        let ball = world.spawn((ThrowableBall, Visibility::Hidden, GlobalTransform::from_translation(player_position))).id();
+        for _ in 0..2 {
+            world.spawn((Item { item_type: ItemType::Lightning }, Transform::from_translation(player_position)));
+        }
+
+        world.run_system_once(player_item_colliding).unwrap();
+        assert_eq!(world.resource::<Inventory>().count(ItemType::Lightning), 2);
+        assert_eq!(world.get::<Visibility>(ball), Some(&Visibility::Visible));
+
+        let mut keyboard_input = ButtonInput::<KeyCode>::default();
+        keyboard_input.press(KeyCode::KeyX);
+        keyboard_input.release(KeyCode::KeyX);
+        world.insert_resource(keyboard_input);
+        world.run_system_once(throw_ball).unwrap();
+
+        assert_eq!(world.resource::<Inventory>().count(ItemType::Lightning), 1);
+        assert_eq!(world.resource::<Inventory>().get_active_item_name(), "<X> Lightning 1");
+        assert_eq!(world.get::<Visibility>(ball), Some(&Visibility::Visible));
+    }
+
+    #[test]
+    fn legacy_heal_potions_are_loaded_into_the_items() {
+        let inventory: Inventory = serde_json::from_str(r#"{"heal_potion":2,"items":{"Lightning":1},"activ_item":"Lightning"}"#).unwrap();
+
+        assert_eq!(inventory.count(ItemType::HealPotion), 2);
+        assert_eq!(inventory.count(ItemType::Lightning), 1);
+        assert_eq!(inventory.activ_item, Some(ItemType::Lightning));
+        assert_eq!(inventory.gold, 0);
+
+        let saved: Inventory = serde_json::from_str(&serde_json::to_string(&inventory).unwrap()).unwrap();
+        assert_eq!(saved.count(ItemType::HealPotion), 2);
+    }
+
+    #[test]
+    fn two_gold_piles_add_up() {
+        let mut world = World::new();
+        world.insert_resource(Inventory::new());
+        world.insert_resource(Pickup::default());
+        world.init_resource::<MessageLog>();
+        world.init_resource::<Events<PlaySoundEvent>>();
+        world.spawn((Transform::default(), Player));
+        for (amount, x) in [(12, 0.5), (30, -0.5)] {
+            world.spawn((Item { item_type: ItemType::Gold }, Transform::from_xyz(x, 0.0, 0.0), GoldPile(amount)));
+        }
+
+        world.run_system_once(player_item_colliding).unwrap();
+
+        assert_eq!(world.resource::<Inventory>().gold, 42);
+        assert_eq!(world.query::<&GoldPile>().iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn gold_amounts_repeat_for_a_seed_and_floor() {
+        let amounts = |floor| -> Vec<usize> {
+            let mut rng = gold_rng(Some(7), floor);
+            (0..10).map(|_| rng.gen_range(GOLD_MIN..=GOLD_MAX)).collect()
+        };
+
+        assert_eq!(amounts(0), amounts(0));
+        assert_ne!(amounts(0), amounts(1));
+    }
+
+    #[test]
+    fn nearest_monster_is_found_among_several() {
+        let mut world = World::new();
+        let player = world.spawn_empty().id();
+        let near = world.spawn_empty().id();
+        let far = world.spawn_empty().id();
+        let farthest = world.spawn_empty().id();
+        let game_map = GameMap::from_string("#########\n#@......#\n#.......#\n#......>#\n#########").unwrap();
+        let mut occupancy = Occupancy::default();
+        for (entity, cell) in [(player, (1, 1)), (farthest, (7, 3)), (far, (5, 1)), (near, (2, 3))] {
+            occupancy.insert(cell, entity, game_map.grid_to_world(cell.0, cell.1));
+        }
+
+        let (nearest, distance) = game_map.nearest_monster(&occupancy, player, game_map.grid_to_world(1, 1)).unwrap();
+        assert_eq!(nearest, near);
+        assert!((distance - 5.0_f32.sqrt() * TILE_SIZE).abs() < 1e-4);
+        assert_eq!(cardinal_direction(game_map.grid_to_world(1, 1), game_map.grid_to_world(2, 3)), (0, 1));
+    }
+
+    #[test]
+    fn cell_with_a_monster_is_occupied_for_the_player_and_other_monsters() {
+        let mut world = World::new();
+        let player = world.spawn_empty().id();
+        let monster = world.spawn_empty().id();
+        let other_monster = world.spawn_empty().id();
+        let game_map = GameMap::from_string("#######\n#@...>#\n#######").unwrap();
+        let mut occupancy = Occupancy::default();
+        occupancy.insert((1, 1), player, game_map.grid_to_world(1, 1));
+        occupancy.insert((3, 1), monster, game_map.grid_to_world(3, 1));
+        occupancy.insert((5, 1), other_monster, game_map.grid_to_world(5, 1));
+        let monster_cell = game_map.grid_to_world(3, 1);
+
+        assert!(game_map.is_occupied(&occupancy, monster_cell, player));
+        assert!(game_map.is_occupied(&occupancy, monster_cell, other_monster));
+        // a monster doesn't block itself
+        assert!(!game_map.is_occupied(&occupancy, monster_cell, monster));
+        assert!(!game_map.is_occupied(&occupancy, game_map.grid_to_world(2, 1), player));
+    }
+
+    #[test]
+    fn grid_step_into_a_monster_attacks_it() {
+        let mut world = World::new();
+        let player = world.spawn_empty().id();
+        let monster = world.spawn_empty().id();
+        let game_map = GameMap::from_string("#####\n#@.>#\n#####").unwrap();
+        let mut occupancy = Occupancy::default();
+        occupancy.insert((1, 1), player, game_map.grid_to_world(1, 1));
+        occupancy.insert((2, 1), monster, game_map.grid_to_world(2, 1));
+
+        assert_eq!(game_map.grid_step(&occupancy, player, (1, 1), (1, 0)), GridStep::Attack(monster));
+        assert_eq!(game_map.grid_step(&occupancy, player, (1, 1), (0, 1)), GridStep::Blocked);
+        assert_eq!(game_map.grid_step(&occupancy, monster, (2, 1), (1, 0)), GridStep::Move((3, 1)));
+    }
+
+    /// Turn-based player at the start of the map and the camera looking north
+    fn spawn_turn_based_player(world: &mut World, map: &str) -> Entity {
+        let game_map = GameMap::from_string(map).unwrap();
+        let start = game_map.grid_to_world(game_map.player_position.0, game_map.player_position.1);
+        let player = world.spawn((Player, Transform::from_translation(start)))
+            .with_children(|parent| { parent.spawn(RightArm); })
+            .id();
+        world.spawn((ThirdPersonCamera::default(), Transform::from_xyz(start.x, 10.0, start.z + 10.0).looking_at(start, Vec3::Y)));
+        let mut occupancy = Occupancy::default();
+        occupancy.insert(game_map.player_position, player, start);
+        world.insert_resource(occupancy);
+        world.insert_resource(game_map);
+        world.insert_resource(GameMode::TurnBased);
+        world.insert_resource(CurrentFloor(0));
+        world.init_resource::<ButtonInput<KeyCode>>();
+        world.init_resource::<Time>();
+        world.init_resource::<NextState<TransitionState>>();
+        world.init_resource::<CardinalAim>();
+        world.init_resource::<PendingTurns>();
+        world.init_resource::<KeyBindings>();
+        world.init_resource::<Events<AttackEvent>>();
+        world.init_resource::<Events<DamageEvent>>();
+        world.init_resource::<Events<PlaySoundEvent>>();
+        player
+    }
+
+    /// App loading the cell meshes, for systems that respawn cells
+    fn app_with_cell_handles() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Mesh>()
+            .init_asset::<StandardMaterial>()
+            .init_asset::<Scene>()
+            .init_asset::<Image>()
+            .add_systems(Startup, load_cell_handles);
+        app.finish();
+        app.update();
+        app
+    }
+
+    #[test]
+    fn easy_preset_starts_with_a_heal_potion() {
+        let start_game = |difficulty: Difficulty| {
+            let mut app = app_with_cell_handles();
+            let world = app.world_mut();
+            world.insert_resource(LoadMapAndItems(false));
+            world.insert_resource(CurrentFloor(0));
+            world.insert_resource(FloorParameters::new());
+            world.insert_resource(ItemAndMonsterParameters::new());
+            world.insert_resource(StartingLoadout::new(difficulty));
+            world.init_resource::<WallRendering>();
+            world.init_resource::<SaveSlot>();
+            world.insert_resource(StartingLives(LivesMode::Permadeath));
+            world.init_resource::<MessageLog>();
+            world.run_system_once(setup).unwrap();
+            let max_hit_points = world.query_filtered::<&Actor, With<Player>>().single(world).max_hit_points;
+            (world.resource::<Inventory>().count(ItemType::HealPotion), max_hit_points)
+        };
+
+        assert_eq!(start_game(Difficulty::Easy), (1, 40));
+        assert_eq!(start_game(Difficulty::Normal), (0, 30));
+    }
+
+    #[test]
+    fn fade_darkens_while_leaving_and_clears_on_the_new_floor() {
+        let fade_alpha = |step: TransitionStep, elapsed: f32| {
+            let mut timer = Timer::from_seconds(2.0, TimerMode::Once);
+            timer.tick(Duration::from_secs_f32(elapsed));
+            PlayerTransition { step, timer }.fade_alpha()
+        };
+
+        assert_eq!(fade_alpha(TransitionStep::StairDownStart, 0.0), 0.0);
+        assert_eq!(fade_alpha(TransitionStep::StairDownStart, 0.5), 0.25);
+        assert_eq!(fade_alpha(TransitionStep::StairDownStart, 2.0), 1.0);
+        assert_eq!(fade_alpha(TransitionStep::StairDownEnd, 0.0), 1.0);
+        assert_eq!(fade_alpha(TransitionStep::StairDownEnd, 1.5), 0.25);
+        assert_eq!(fade_alpha(TransitionStep::StairDownEnd, 2.0), 0.0);
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn debug_key_takes_the_stairs_down_from_anywhere() {
+        let mut world = World::new();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        world.init_resource::<NextState<TransitionState>>();
+        world.init_resource::<Events<PlaySoundEvent>>();
+        world.init_resource::<KeyBindings>();
+        let player = world.spawn((Player, Transform::default())).id();
+
+        world.resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::KeyN);
+        world.run_system_once(debug_descend).unwrap();
+
+        assert!(matches!(world.resource::<NextState<TransitionState>>(), NextState::Pending(TransitionState::StairsDown)));
+        assert!(world.get::<PlayerTransition>(player).is_some());
+    }
+
+    #[test]
+    fn depth_shown_increments_after_a_stairs_down_transition() {
+        let mut app = app_with_cell_handles();
+        let world = app.world_mut();
+        world.insert_resource(CurrentFloor(0));
+        world.insert_resource(FloorParameters::new());
+        world.insert_resource(ItemAndMonsterParameters::new());
+        world.init_resource::<WallRendering>();
+        world.init_resource::<LightingSettings>();
+        world.insert_resource(Difficulty::Normal);
+        world.insert_resource(GameRng::new(Some(7)));
+        world.insert_resource(GameMap::from_string("#####\n#@.>#\n#####").unwrap());
+        world.init_resource::<NextState<TransitionState>>();
+        world.init_resource::<Events<SaveGameEvent>>();
+        let mut timer = Timer::from_seconds(0.1, TimerMode::Once);
+        timer.tick(Duration::from_secs(1));
+        world.spawn((Player, Transform::default(), PlayerTransition { step: TransitionStep::StairDownStart, timer }));
+        assert_eq!(world.resource::<CurrentFloor>().depth_label(), "Depth: 0");
+
+        world.run_system_once(do_transition_stairsdown).unwrap();
+
+        assert_eq!(world.resource::<CurrentFloor>().depth_label(), "Depth: 1");
+    }
+
+    #[test]
+    fn player_arriving_on_a_monster_is_moved_to_a_free_neighbour() {
+        let mut game_map = GameMap::from_string("#####\n#.@>#\n#####").unwrap();
+        game_map.monsters = vec![MonsterInMap { position: (2, 1), monster_type: MonsterType::Orc }];
+        let mut player = Transform::from_translation(game_map.grid_to_world(2, 1) + Vec3::Y * 4.0);
+
+        game_map.move_player_to_free_cell(&mut player);
+
+        assert_eq!(game_map.player_position, (1, 1));
+        assert_eq!(game_map.world_to_grid(player.translation), (1, 1));
+        assert_eq!(player.translation.y, game_map.grid_to_world(2, 1).y + 4.0);
+    }
+
+    #[test]
+    fn staircase_cells_get_a_staircase_model() {
+        let mut app = app_with_cell_handles();
+        let world = app.world_mut();
+        world.insert_resource(CurrentFloor(0));
+        let mut game_map = GameMap::from_string("######\n#<@.>#\n######").unwrap();
+        world.run_system_once(move |mut commands: Commands, mut current_floor: ResMut<CurrentFloor>, cell_handles: Res<CellHandles>,
+                                    mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<StandardMaterial>>| {
+            game_map.generate(&mut commands, &mut current_floor, WallRendering::PerFace, &cell_handles,
+                              &mut GameRng::new(Some(1)), &mut meshes, &mut materials);
+        }).unwrap();
+
+        let mut models = world.query_filtered::<&CellMesh, (With<Mesh3d>, With<Floor>)>();
+        let mut model_parts = |cell: (usize, usize)| models.iter(world).filter(|cell_mesh| cell_mesh.0 == cell).count();
+        assert!(model_parts((4, 1)) > 0, "stairs down");
+        assert!(model_parts((1, 1)) > 0, "stairs up");
+        assert_eq!(model_parts((3, 1)), 0, "plain floor");
+    }
+
+    #[test]
+    fn batched_walls_need_far_fewer_entities_than_one_per_face() {
+        let floor_entities = |wall_rendering: WallRendering| {
+            let mut app = app_with_cell_handles();
+            let world = app.world_mut();
+            world.insert_resource(CurrentFloor(0));
+            let mut game_map = GameMap::from_string(
+                "############\n#@.........#\n#..........#\n#..........#\n#..........#\n#.........>#\n############").unwrap();
+            world.run_system_once(move |mut commands: Commands, mut current_floor: ResMut<CurrentFloor>, cell_handles: Res<CellHandles>,
+                                        mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<StandardMaterial>>| {
+                game_map.generate(&mut commands, &mut current_floor, wall_rendering, &cell_handles,
+                                  &mut GameRng::new(Some(1)), &mut meshes, &mut materials);
+            }).unwrap();
+            world.query_filtered::<(), With<Floor>>().iter(world).count()
+        };
+
+        let per_face = floor_entities(WallRendering::PerFace);
+        let batched = floor_entities(WallRendering::Batched);
+        // a scene for each of the 30 faces around the 10 x 5 floor, batched one mesh per chunk,
+        // the 12 cells wide map has two chunks
+        assert_eq!(per_face - batched, 30 - 2);
+    }
+
+    #[test]
+    fn one_press_of_move_up_steps_one_cell_north() {
+        let mut world = World::new();
+        let player = spawn_turn_based_player(&mut world, "#####\n#...#\n#.@>#\n#####");
+        let key_bindings = world.resource::<KeyBindings>().clone();
+        let start = world.resource::<GameMap>().world_to_grid(world.get::<Transform>(player).unwrap().translation);
+
+        world.resource_mut::<ButtonInput<KeyCode>>().press(key_bindings.move_up);
+        world.run_system_once(move_player).unwrap();
+
+        let end = world.resource::<GameMap>().world_to_grid(world.get::<Transform>(player).unwrap().translation);
+        assert_eq!((end.0 as i32 - start.0 as i32, end.1 as i32 - start.1 as i32), (0, -1));
+    }
+
+    #[test]
+    fn bumping_into_a_monster_damages_it_without_moving() {
+        let mut world = World::new();
+        let player = spawn_turn_based_player(&mut world, "#####\n#@.>#\n#####");
+        let monster_position = world.resource::<GameMap>().grid_to_world(2, 1);
+        let monster = world.spawn((Monster { monster_type: MonsterType::Orc }, Transform::from_translation(monster_position))).id();
+        world.resource_mut::<Occupancy>().insert((2, 1), monster, monster_position);
+        let start = world.get::<Transform>(player).unwrap().translation;
+
+        world.resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::ArrowRight);
+        world.run_system_once(move_player).unwrap();
+        world.run_system_once(crate::fighting::handle_attacks).unwrap();
+
+        assert_eq!(world.get::<Transform>(player).unwrap().translation, start);
+        assert_eq!(world.resource::<PendingTurns>().0, 1);
+        let damage_events = world.resource::<Events<DamageEvent>>();
+        let targets: Vec<Entity> = damage_events.get_cursor().read(damage_events).map(|event| event.target).collect();
+        assert_eq!(targets, vec![monster]);
+    }
+
+    #[test]
+    fn item_collision_uses_the_position_the_player_moved_to_in_the_same_frame() {
+        let mut world = World::new();
+        spawn_turn_based_player(&mut world, "#####\n#@.>#\n#####");
+        world.insert_resource(Inventory::new());
+        world.init_resource::<Pickup>();
+        world.init_resource::<MessageLog>();
+        let item_position = world.resource::<GameMap>().grid_to_world(2, 1);
+        world.spawn((Item { item_type: ItemType::HealPotion }, Transform::from_translation(item_position)));
+        let mut schedule = Schedule::default();
+        schedule.configure_sets(GameplaySet::chain())
+            .add_systems((player_item_colliding.in_set(GameplaySet::Collision), move_player.in_set(GameplaySet::Movement)));
+
+        world.resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::ArrowRight);
+        schedule.run(&mut world);
+
+        assert_eq!(world.resource::<Inventory>().count(ItemType::HealPotion), 1);
+    }
+
+    #[test]
+    fn diagonal_through_a_wall_corner_is_blocked_but_not_along_the_wall() {
+        let game_map = GameMap::from_string("#####\n#@#>#\n##..#\n#####").unwrap();
+        let (start, beyond_corner) = (game_map.grid_to_world(1, 1), game_map.grid_to_world(2, 2));
+        assert!(game_map.cuts_wall_corner(start, beyond_corner));
+
+        // close to the wall below, sliding east along it
+        let along_wall = game_map.grid_to_world(2, 2) + Vec3::new(0.0, 0.0, 1.4);
+        let next = along_wall + Vec3::new(1.0, 0.0, 0.0);
+        assert!(!game_map.cuts_wall_corner(along_wall, next));
+        assert!(!game_map.collide_circle(next, ACTOR_DISTANCE));
+        assert!(game_map.collide_circle(along_wall + Vec3::new(0.0, 0.0, 0.3), ACTOR_DISTANCE));
+    }
+
+    #[test]
+    fn bumping_a_closed_door_opens_it_without_moving() {
+        let mut app = app_with_cell_handles();
+        let world = app.world_mut();
+        let player = spawn_turn_based_player(world, "######\n#@+.>#\n######");
+        world.insert_resource(GameRng::new(Some(1)));
+        world.insert_resource(WallRendering::PerFace);
+        world.init_resource::<FieldOfView>();
+        world.init_resource::<Events<OpenDoorEvent>>();
+        let start = world.get::<Transform>(player).unwrap().translation;
+
+        world.resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::ArrowRight);
+        world.run_system_once(move_player).unwrap();
+        world.run_system_once(open_doors).unwrap();
+
+        assert_eq!(world.get::<Transform>(player).unwrap().translation, start);
+        assert_eq!(world.resource::<GameMap>().grid[(2, 1)].tile_type, TileType::DoorOpen);
+    }
+
+    #[test]
+    fn potion_heals_when_drunk_not_when_raised() {
+        let mut app = app_with_cell_handles();
+        let world = app.world_mut();
+        let mut inventory = Inventory::new();
+        inventory.add_item(ItemType::HealPotion);
+        world.insert_resource(inventory);
+        world.insert_resource(GameMap::from_string("#####\n#@.>#\n#####").unwrap());
+        world.init_resource::<MessageLog>();
+        world.init_resource::<KeyBindings>();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        world.init_resource::<Events<DamageEvent>>();
+        let player = world.spawn((Player, Actor::new(30, 10, 0, 1, 0), Transform::default())).id();
+        let hit_points = |world: &World| world.get::<Actor>(player).unwrap().hit_points;
+
+        let use_potion = world.resource::<KeyBindings>().use_potion;
+        world.resource_mut::<ButtonInput<KeyCode>>().press(use_potion);
+        world.run_system_once(player_use_item).unwrap();
+        assert!(world.get::<DrinkTimer>(player).is_some());
+        assert_eq!(world.resource::<Inventory>().count(ItemType::HealPotion), 0);
+        assert_eq!(hit_points(world), 10);
+
+        let drink_potion = world.register_system(drink_potion);
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(POTION_DRINK_TIME / 2.0));
+        world.run_system(drink_potion).unwrap();
+        assert_eq!(hit_points(world), 10);
+
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(POTION_DRINK_TIME / 2.0));
+        world.run_system(drink_potion).unwrap();
+        assert_eq!(hit_points(world), 10 + POTION_HEAL);
+        assert!(world.get::<DrinkTimer>(player).is_none());
+    }
+
+    #[test]
+    fn without_auto_pickup_an_item_waits_for_the_pick_up_key() {
+        let mut world = World::new();
+        world.insert_resource(Inventory::new());
+        world.insert_resource(Pickup { auto: false, item_in_reach: None });
+        world.init_resource::<MessageLog>();
+        world.init_resource::<KeyBindings>();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        world.init_resource::<Events<PlaySoundEvent>>();
+        world.spawn((Player, Transform::default()));
+        let item = world.spawn((Item { item_type: ItemType::HealPotion }, Transform::from_xyz(0.5, 0.0, 0.0))).id();
+        let player_pick_up = world.register_system(player_pick_up);
+
+        world.run_system_once(player_item_colliding).unwrap();
+        world.run_system(player_pick_up).unwrap();
+        assert_eq!(world.resource::<Inventory>().count(ItemType::HealPotion), 0);
+        assert!(world.get_entity(item).is_ok());
+
+        let pick_up = world.resource::<KeyBindings>().pick_up;
+        world.resource_mut::<ButtonInput<KeyCode>>().press(pick_up);
+        world.run_system(player_pick_up).unwrap();
+        assert_eq!(world.resource::<Inventory>().count(ItemType::HealPotion), 1);
+        assert!(world.get_entity(item).is_err());
+    }
+
+    #[test]
+    fn lightning_scroll_only_hits_monsters_in_sight() {
+        let mut app = app_with_cell_handles();
+        let world = app.world_mut();
+        let game_map = GameMap::from_string("#######\n#..@..#\n###.###\n#....>#\n#######").unwrap();
+        let monster = |world: &mut World, cell: (usize, usize)| {
+            let position = world.resource::<GameMap>().grid_to_world(cell.0, cell.1);
+            world.spawn((Monster { monster_type: MonsterType::Orc }, Transform::from_translation(position), MonsterAIState::Idle)).id()
+        };
+        let player_position = game_map.grid_to_world(3, 1);
+        world.insert_resource(game_map);
+        let mut inventory = Inventory::new();
+        inventory.add_item(ItemType::LightningScroll);
+        world.insert_resource(inventory);
+        world.init_resource::<MessageLog>();
+        world.init_resource::<KeyBindings>();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        world.init_resource::<Events<DamageEvent>>();
+        world.spawn((Player, Actor::new(10, 10, 0, 1, 0), Transform::from_translation(player_position)));
+        let in_sight = vec![monster(world, (1, 1)), monster(world, (5, 1))];
+        // within the radius, but the wall is in between
+        monster(world, (1, 3));
+
+        let read_scroll = world.resource::<KeyBindings>().read_scroll;
+        world.resource_mut::<ButtonInput<KeyCode>>().press(read_scroll);
+        world.run_system_once(player_use_item).unwrap();
+
+        let damage_events = world.resource::<Events<DamageEvent>>();
+        let mut targets: Vec<Entity> = damage_events.get_cursor().read(damage_events).map(|event| event.target).collect();
+        targets.sort();
+        assert_eq!(targets, in_sight);
+        assert_eq!(world.resource::<Inventory>().count(ItemType::LightningScroll), 0);
+    }
+
+    #[test]
+    fn north_grid_throw_travels_along_minus_z_and_hits_the_first_monster() {
+        let mut world = World::new();
+        let player = spawn_turn_based_player(&mut world, "#####\n#.o.#\n#...#\n#.o.#\n#...#\n#.@>#\n#####");
+        let mut inventory = Inventory::new();
+        inventory.add_item(ItemType::Lightning);
+        world.insert_resource(inventory);
+        world.init_resource::<FieldOfView>();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        let monster = |world: &mut World, cell: (usize, usize)| {
+            let position = world.resource::<GameMap>().grid_to_world(cell.0, cell.1);
+            world.spawn((Monster { monster_type: MonsterType::Orc }, Transform::from_translation(position))).id()
+        };
+        let first = monster(&mut world, (2, 3));
+        monster(&mut world, (2, 1));
+        let start = world.get::<Transform>(player).unwrap().translation;
+
+        let key_bindings = world.resource::<KeyBindings>().clone();
+        for key in [key_bindings.aim, key_bindings.move_up, key_bindings.confirm] {
+            let mut keyboard_input = world.resource_mut::<ButtonInput<KeyCode>>();
+            keyboard_input.reset_all();
+            keyboard_input.press(key);
+            world.run_system_once(cardinal_throw).unwrap();
+        }
+
+        let update_grid_projectile = world.register_system(update_grid_projectile);
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(GRID_PROJECTILE_STEP_TIME));
+        world.run_system(update_grid_projectile).unwrap();
+        let position = world.query_filtered::<&Transform, With<GridProjectile>>().single(&world).translation;
+        assert_eq!(position.x, start.x);
+        assert!(position.z < start.z);
+
+        for _ in 0..3 {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(GRID_PROJECTILE_STEP_TIME));
+            world.run_system(update_grid_projectile).unwrap();
+        }
+        let damage_events = world.resource::<Events<DamageEvent>>();
+        let targets: Vec<Entity> = damage_events.get_cursor().read(damage_events).map(|event| event.target).collect();
+        assert_eq!(targets, vec![first]);
+        assert!(world.query::<&GridProjectile>().iter(&world).next().is_none());
+    }
+
+    #[test]
+    fn ball_thrown_along_a_wall_flies_on() {
+        let mut world = World::new();
+        let game_map = GameMap::from_string("#######\n#@....#\n#....>#\n#######").unwrap();
+        // hugging the north wall and facing east, the left hand is in the wall
+        let player_position = game_map.grid_to_world(1, 1) + Vec3::new(0.0, 0.9, -1.5);
+        let hand_position = player_position + Vec3::new(0.0, -0.3, -0.7);
+        world.insert_resource(game_map);
+        let mut inventory = Inventory::new();
+        inventory.add_item(ItemType::Lightning);
+        world.insert_resource(inventory);
+        world.insert_resource(CurrentFloor(0));
+        world.init_resource::<KeyBindings>();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        world.init_resource::<Time>();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        world.init_resource::<Events<DamageEvent>>();
+        world.spawn((Player, Transform::from_translation(player_position).looking_to(Vec3::X, Vec3::Y)));
+        world.spawn((ThrowableBall, Visibility::Inherited, GlobalTransform::from_translation(hand_position)));
+
+        let throw = world.resource::<KeyBindings>().throw;
+        world.resource_mut::<ButtonInput<KeyCode>>().press(throw);
+        world.resource_mut::<ButtonInput<KeyCode>>().release(throw);
+        world.run_system_once(throw_ball).unwrap();
+        let start = world.query_filtered::<&Transform, With<ThrownBall>>().single(&world).translation;
+
+        let update_thrown_ball = world.register_system(update_thrown_ball);
+        for _ in 0..3 {
+            world.resource_mut::<Time>().advance_by(Duration::from_millis(50));
+            world.run_system(update_thrown_ball).unwrap();
+        }
+
+        let position = world.query_filtered::<&Transform, With<ThrownBall>>().get_single(&world)
+            .expect("the ball is still flying").translation;
+        assert!(position.x > start.x);
+    }
+
+    #[test]
+    fn ball_hitting_a_wall_leaves_one_decal_for_a_while() {
+        let mut world = World::new();
+        let game_map = GameMap::from_string("######\n#@..>#\n######").unwrap();
+        let impact_cell = game_map.grid_to_world(4, 1);
+        world.insert_resource(game_map);
+        world.insert_resource(CurrentFloor(0));
+        world.init_resource::<Time>();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<StandardMaterial>>();
+        world.init_resource::<Events<DamageEvent>>();
+        world.spawn((
+            Transform::from_translation(impact_cell + Vec3::Y),
+            ThrownBall {
+                velocity: Vec3::X * BALL_TEMPO,
+                lifetime: Timer::from_seconds(BALL_LIFETIME, TimerMode::Once),
+                item_type: ItemType::Lightning,
+                just_spawned: true
+            }
+        ));
+
+        let update_thrown_ball = world.register_system(update_thrown_ball);
+        for _ in 0..20 {
+            world.resource_mut::<Time>().advance_by(Duration::from_millis(20));
+            world.run_system(update_thrown_ball).unwrap();
+        }
+        assert!(world.query::<&ThrownBall>().iter(&world).next().is_none());
+        let decals: Vec<Vec3> = world.query_filtered::<&Transform, With<ImpactDecal>>().iter(&world)
+            .map(|transform| transform.translation)
+            .collect();
+        assert_eq!(decals.len(), 1);
+        assert_eq!((decals[0].x, decals[0].z), (impact_cell.x, impact_cell.z));
+
+        let fade_impact_decals = world.register_system(fade_impact_decals);
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(IMPACT_DECAL_TIME / 2.0));
+        world.run_system(fade_impact_decals).unwrap();
+        assert_eq!(world.query::<&ImpactDecal>().iter(&world).count(), 1);
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(IMPACT_DECAL_TIME / 2.0));
+        world.run_system(fade_impact_decals).unwrap();
+        assert_eq!(world.query::<&ImpactDecal>().iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn respawn_avoids_a_monster_at_the_start() {
+        let mut world = World::new();
+        let player = world.spawn_empty().id();
+        let monster = world.spawn_empty().id();
+        let mut game_map = GameMap::from_string("######\n#@..>#\n#....#\n######").unwrap();
+        let mut occupancy = Occupancy::default();
+        occupancy.insert((3, 2), player, game_map.grid_to_world(3, 2));
+        assert_eq!(game_map.respawn_cell(&occupancy, player), (1, 1));
+
+        occupancy.insert((1, 1), monster, game_map.grid_to_world(1, 1));
+        assert_eq!(game_map.respawn_cell(&occupancy, player), (1, 1), "without a start room");
+
+        game_map.player_start_room = Some(Room::new(String::from("start"), 0, 0, 5, 3));
+        let cell = game_map.respawn_cell(&occupancy, player);
+        assert_eq!(cell.0.abs_diff(1) + cell.1.abs_diff(1), 1);
+        assert_eq!(game_map.grid[cell].tile_type, TileType::Floor);
+    }
+
+    /// World with a player on a small map who has just died
+    fn world_with_dead_player(lives_mode: LivesMode) -> World {
+        let mut world = World::new();
+        world.insert_resource(lives_mode);
+        world.init_resource::<NextState<GameState>>();
+        world.init_resource::<Events<PlayerDiedEvent>>();
+        world.insert_resource(GameMap::from_string("#######\n#@...>#\n#######").unwrap());
+        world.init_resource::<Occupancy>();
+        let mut actor = Actor::new(20, 20, 1, 1, 0);
+        actor.hit_points = 0;
+        let position = world.resource::<GameMap>().grid_to_world(4, 1);
+        world.spawn((Transform::from_translation(position), actor, Player));
+        world.send_event(PlayerDiedEvent);
+        world
+    }
+
+    #[test]
+    fn death_with_lives_left_respawns_the_player() {
+        let mut world = world_with_dead_player(LivesMode::Lives(2));
+        world.run_system_once(handle_player_death).unwrap();
+
+        assert_eq!(*world.resource::<LivesMode>(), LivesMode::Lives(1));
+        assert!(matches!(*world.resource::<NextState<GameState>>(), NextState::Unchanged));
+        let (transform, actor) = world.query::<(&Transform, &Actor)>().single(&world);
+        let start = world.resource::<GameMap>().grid_to_world(1, 1);
+        assert_eq!((transform.translation.x, transform.translation.z), (start.x, start.z));
+        assert_eq!(actor.hit_points, 10);
+    }
+
+    #[test]
+    fn death_without_lives_ends_the_game() {
+        for lives_mode in [LivesMode::Lives(0), LivesMode::Permadeath] {
+            let mut world = world_with_dead_player(lives_mode);
+            world.run_system_once(handle_player_death).unwrap();
+
+            assert!(matches!(*world.resource::<NextState<GameState>>(), NextState::Pending(GameState::GameOver)));
+        }
+    }
+
+    #[test]
+    fn game_over_deletes_the_save() {
+        let save_slot = test_slot("game_over");
+        fs::write(save_slot.path(MAP_TEXT_FILE), "#").unwrap();
+        CurrentFloor(3).save(&save_slot);
+
+        let mut world = World::new();
+        world.insert_resource(save_slot.clone());
+        world.run_system_once(delete_save_of_dead_player).unwrap();
+
+        assert!(!save_slot.exists());
+        assert!(!save_slot.path(FLOOR_JSON_FILE).exists());
+        assert_ne!(*world.resource::<SaveSlot>(), save_slot);
+    }
+}
@@ -0,0 +1,137 @@
+use std::f32::consts::FRAC_PI_4;
+use bevy::prelude::*;
+
+/// Vertical field of view limits in degrees
+const MIN_FOV_DEGREES: f32 = 40.0;
+const MAX_FOV_DEGREES: f32 = 100.0;
+const FOV_STEP_DEGREES: f32 = 5.0;
+
+/// Render quality shared by all cameras
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct RenderSettings {
+    pub msaa: Msaa,
+    /// vertical field of view of the perspective cameras in radians
+    pub fov: f32
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            msaa: Msaa::Sample4,
+            fov: FRAC_PI_4
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Samples per pixel, 1 without anti-aliasing
+    pub fn samples(&self) -> u32 {
+        self.msaa.samples()
+    }
+
+    /// Closest supported anti-aliasing for the samples per pixel
+    pub fn with_samples(samples: u32) -> Self {
+        let msaa = match samples {
+            0 | 1 => Msaa::Off,
+            2 => Msaa::Sample2,
+            _ => Msaa::Sample4
+        };
+        Self { msaa, ..default() }
+    }
+
+    /// Field of view in degrees, limited to a range without strong distortion
+    pub fn with_fov_degrees(mut self, degrees: f32) -> Self {
+        self.fov = degrees.clamp(MIN_FOV_DEGREES, MAX_FOV_DEGREES).to_radians();
+        self
+    }
+
+    pub fn fov_degrees(&self) -> f32 {
+        self.fov.to_degrees()
+    }
+
+    pub fn wider_fov(&mut self) {
+        *self = self.with_fov_degrees(self.fov_degrees() + FOV_STEP_DEGREES);
+    }
+
+    pub fn narrower_fov(&mut self) {
+        *self = self.with_fov_degrees(self.fov_degrees() - FOV_STEP_DEGREES);
+    }
+
+    /// Off -> 2x -> 4x -> Off
+    pub fn next_msaa(&mut self) {
+        self.msaa = match self.msaa {
+            Msaa::Off => Msaa::Sample2,
+            Msaa::Sample2 => Msaa::Sample4,
+            _ => Msaa::Off
+        };
+    }
+}
+
+pub struct RenderSettingsPlugin;
+
+impl Plugin for RenderSettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RenderSettings>()
+            .add_systems(PostUpdate, apply_render_settings);
+    }
+}
+
+/// Applies the settings to new cameras and to all cameras when they change
+fn apply_render_settings(
+    render_settings: Res<RenderSettings>,
+    mut query: Query<(&mut Msaa, Option<&mut Projection>, Ref<Camera>)>
+) {
+    for (mut msaa, projection, camera) in query.iter_mut() {
+        if !render_settings.is_changed() && !camera.is_added() {
+            continue;
+        }
+        if *msaa != render_settings.msaa {
+            *msaa = render_settings.msaa;
+        }
+        if let Some(mut projection) = projection {
+            if let Projection::Perspective(ref mut perspective) = *projection {
+                perspective.fov = render_settings.fov;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changing_the_fov_updates_the_camera_projection() {
+        let mut world = World::new();
+        world.init_resource::<RenderSettings>();
+        let camera = world.spawn((Camera::default(), Msaa::default(), Projection::Perspective(PerspectiveProjection::default()))).id();
+        let apply = world.register_system(apply_render_settings);
+        world.run_system(apply).unwrap();
+
+        world.resource_mut::<RenderSettings>().wider_fov();
+        world.run_system(apply).unwrap();
+
+        let Some(Projection::Perspective(perspective)) = world.get::<Projection>(camera) else { panic!("no perspective camera") };
+        assert_eq!(perspective.fov, world.resource::<RenderSettings>().fov);
+        assert!(perspective.fov > FRAC_PI_4);
+    }
+
+    #[test]
+    fn msaa_off_updates_all_cameras() {
+        let mut world = World::new();
+        world.init_resource::<RenderSettings>();
+        let cameras = [
+            world.spawn((Camera::default(), Msaa::Sample4)).id(),
+            world.spawn((Camera::default(), Msaa::Sample2)).id()
+        ];
+        let apply = world.register_system(apply_render_settings);
+        world.run_system(apply).unwrap();
+
+        world.resource_mut::<RenderSettings>().msaa = Msaa::Off;
+        world.run_system(apply).unwrap();
+
+        for camera in cameras {
+            assert_eq!(world.get::<Msaa>(camera), Some(&Msaa::Off));
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs;
+use std::path::PathBuf;
+use bevy::prelude::*;
+
+use crate::{ACTOR_JSON_FILE, DIFFICULTY_JSON_FILE, EXPLORED_JSON_FILE, FLOOR_JSON_FILE, INVENTORY_JSON_FILE, ITEMS_JSON_FILE, LIVES_JSON_FILE, MAP_TEXT_FILE};
+use crate::fighting::Actor;
+
+/// Directory with one sub directory per save slot
+pub const SAVES_DIR: &str = "saves";
+/// Files of the time before slots, saved next to the game
+pub const LEGACY_SLOT: &str = "legacy";
+
+/// Base directory of the slots, tests keep theirs out of the saves of the game
+fn saves_dir() -> PathBuf {
+    if cfg!(test) {
+        std::env::temp_dir().join("rust-bevy-rogue-test-saves")
+    } else {
+        PathBuf::from(SAVES_DIR)
+    }
+}
+
+/// Name of the save the game is loaded from and saved to
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct SaveSlot(pub String);
+
+impl Default for SaveSlot {
+    fn default() -> Self {
+        SaveSlot(String::from("slot1"))
+    }
+}
+
+impl SaveSlot {
+    fn dir(&self) -> PathBuf {
+        if self.0 == LEGACY_SLOT {
+            PathBuf::from(".")
+        } else {
+            saves_dir().join(&self.0)
+        }
+    }
+
+    /// Path of a save file of this slot
+    pub fn path(&self, file_name: &str) -> PathBuf {
+        self.dir().join(file_name)
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path(MAP_TEXT_FILE).exists()
+    }
+
+    /// Floor reached and level of the player, unreadable files give none
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        let floor = fs::read_to_string(self.path(FLOOR_JSON_FILE)).ok()?.trim().parse().ok()?;
+        let actor: Actor = serde_json::from_str(&fs::read_to_string(self.path(ACTOR_JSON_FILE)).ok()?).ok()?;
+        Some((floor, actor.current_level))
+    }
+
+    /// Name and progress for the slot list
+    pub fn description(&self) -> String {
+        match self.progress() {
+            Some((floor, level)) => format!("{} floor {} level {}", self.0, floor, level),
+            None => self.0.clone()
+        }
+    }
+
+    pub fn create_dir(&self) {
+        if let Err(error) = fs::create_dir_all(self.dir()) {
+            warn!("Unable to create {:?}: {}", self.dir(), error);
+        }
+    }
+
+    /// A dead player can't load the game, missing files are fine
+    pub fn delete(&self) {
+        for file in [MAP_TEXT_FILE, INVENTORY_JSON_FILE, ACTOR_JSON_FILE, FLOOR_JSON_FILE, ITEMS_JSON_FILE, EXPLORED_JSON_FILE, LIVES_JSON_FILE, DIFFICULTY_JSON_FILE] {
+            let _ = fs::remove_file(self.path(file));
+        }
+        if self.0 != LEGACY_SLOT {
+            let _ = fs::remove_dir(self.dir());
+        }
+    }
+
+    /// Slots with a saved game, the legacy one first
+    pub fn list() -> Vec<SaveSlot> {
+        let mut slots: Vec<SaveSlot> = fs::read_dir(saves_dir())
+            .map(|entries| entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .map(SaveSlot)
+                .filter(|slot| slot.0 != LEGACY_SLOT && slot.exists())
+                .collect())
+            .unwrap_or_default();
+        slots.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let legacy = SaveSlot(String::from(LEGACY_SLOT));
+        if legacy.exists() {
+            slots.insert(0, legacy);
+        }
+        slots
+    }
+
+    /// First slot without a saved game for a new game
+    pub fn next_free() -> Self {
+        (1..)
+            .map(|number| SaveSlot(format!("slot{}", number)))
+            .find(|slot| !slot.exists())
+            .unwrap()
+    }
+}
+
+/// Empty slot only used by one test
+#[cfg(test)]
+pub fn test_slot(name: &str) -> SaveSlot {
+    let save_slot = SaveSlot(format!("test_{}", name));
+    save_slot.delete();
+    save_slot.create_dir();
+    save_slot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CurrentFloor, GameMap};
+
+    #[test]
+    fn two_slots_save_and_load_independently() {
+        let (first, second) = (test_slot("first"), test_slot("second"));
+        let short_map = GameMap::from_string("#####\n#@.>#\n#####").unwrap();
+        let long_map = GameMap::from_string("#######\n#@...>#\n#######").unwrap();
+        short_map.save(&first, short_map.grid_to_world(1, 1), vec![], vec![]);
+        CurrentFloor(1).save(&first);
+        long_map.save(&second, long_map.grid_to_world(1, 1), vec![], vec![]);
+        CurrentFloor(4).save(&second);
+
+        let (first_map, first_floor) = (GameMap::load(&first), CurrentFloor::load(&first));
+        let (second_map, second_floor) = (GameMap::load(&second), CurrentFloor::load(&second));
+        first.delete();
+        second.delete();
+
+        assert_eq!((first_map.width, first_floor.0), (5, 1));
+        assert_eq!((second_map.width, second_floor.0), (7, 4));
+    }
+
+    #[test]
+    fn saved_slots_are_listed_with_floor_and_level() {
+        let (listed, untouched) = (test_slot("listed"), test_slot("untouched"));
+        let game_map = GameMap::from_string("#####\n#@.>#\n#####").unwrap();
+        game_map.save(&listed, game_map.grid_to_world(1, 1), vec![], vec![]);
+        CurrentFloor(3).save(&listed);
+        let mut actor = Actor::new(10, 10, 0, 1, 0);
+        actor.current_level = 2;
+        actor.save(&listed);
+
+        let slots = SaveSlot::list();
+        let description = listed.description();
+        let untouched_progress = untouched.progress();
+        listed.delete();
+        untouched.delete();
+
+        assert!(slots.contains(&listed));
+        assert!(!slots.contains(&untouched), "a slot without a saved game is not listed");
+        assert_eq!(description, "test_listed floor 3 level 2");
+        assert_eq!(untouched_progress, None);
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::*;
+
+/// Sounds are played, off by default because the sound files are not shipped with the game
+#[derive(Resource, Debug, Clone, PartialEq, Default)]
+pub struct AudioEnabled(pub bool);
+
+impl fmt::Display for AudioEnabled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.0 { "On" } else { "Off" })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoundEffect {
+    SwordSwing,
+    ItemPickup,
+    MonsterDeath,
+    StairsDown
+}
+
+/// Sent by the systems where something audible happens
+#[derive(Event, Debug)]
+pub struct PlaySoundEvent(pub SoundEffect);
+
+/// Sounds loaded at startup
+#[derive(Resource)]
+struct AudioAssets {
+    sword_swing: Handle<AudioSource>,
+    item_pickup: Handle<AudioSource>,
+    monster_death: Handle<AudioSource>,
+    stairs_down: Handle<AudioSource>
+}
+
+const SOUND_FILES: [&str; 4] = [
+    "sounds/sword_swing.ogg",
+    "sounds/item_pickup.ogg",
+    "sounds/monster_death.ogg",
+    "sounds/stairs_down.ogg"
+];
+
+impl AudioAssets {
+    /// None when a sound file is missing in the assets directory
+    fn load(asset_server: &AssetServer) -> Option<Self> {
+        let assets_dir = FileAssetReader::get_base_path().join("assets");
+        if let Some(missing) = SOUND_FILES.iter().find(|file| !assets_dir.join(file).exists()) {
+            warn!("Sound file {} is missing, sound effects are switched off", missing);
+            return None;
+        }
+        let [sword_swing, item_pickup, monster_death, stairs_down] = SOUND_FILES;
+        Some(Self {
+            sword_swing: asset_server.load(sword_swing),
+            item_pickup: asset_server.load(item_pickup),
+            monster_death: asset_server.load(monster_death),
+            stairs_down: asset_server.load(stairs_down)
+        })
+    }
+
+    fn get(&self, sound_effect: SoundEffect) -> Handle<AudioSource> {
+        match sound_effect {
+            SoundEffect::SwordSwing => self.sword_swing.clone(),
+            SoundEffect::ItemPickup => self.item_pickup.clone(),
+            SoundEffect::MonsterDeath => self.monster_death.clone(),
+            SoundEffect::StairsDown => self.stairs_down.clone()
+        }
+    }
+}
+
+pub struct SoundEffectsPlugin;
+
+impl Plugin for SoundEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlaySoundEvent>()
+            .init_resource::<AudioEnabled>()
+            .add_systems(Startup, load_audio_assets)
+            .add_systems(Update, play_sound_effects);
+    }
+}
+
+/// Nothing is loaded while the audio is off, missing sound files switch it off
+fn load_audio_assets(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut audio_enabled: ResMut<AudioEnabled>
+) {
+    if audio_enabled.0 {
+        match AudioAssets::load(&asset_server) {
+            Some(audio_assets) => commands.insert_resource(audio_assets),
+            None => audio_enabled.0 = false
+        }
+    }
+}
+
+/// One short lived player per sound, despawned when it has finished
+fn play_sound_effects(
+    mut commands: Commands,
+    mut sound_events: EventReader<PlaySoundEvent>,
+    mut audio_enabled: ResMut<AudioEnabled>,
+    audio_assets: Option<Res<AudioAssets>>,
+    asset_server: Res<AssetServer>
+) {
+    if !audio_enabled.0 {
+        sound_events.clear();
+        return;
+    }
+    // audio switched on in the menu after the start
+    let Some(audio_assets) = audio_assets else {
+        match AudioAssets::load(&asset_server) {
+            Some(audio_assets) => commands.insert_resource(audio_assets),
+            None => {
+                audio_enabled.0 = false;
+                sound_events.clear();
+            }
+        }
+        return;
+    };
+    for PlaySoundEvent(sound_effect) in sound_events.read() {
+        commands.spawn((
+            AudioPlayer::new(audio_assets.get(*sound_effect)),
+            PlaybackSettings::DESPAWN
+        ));
+    }
+}
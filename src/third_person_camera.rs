@@ -1,8 +1,11 @@
 use bevy::prelude::*;
 use bevy::render::view::RenderLayers;
+use serde::{Serialize, Deserialize};
 use crate::{GameMap, GameState, Player, setup, PLAYER_BODY_LENGTH, PLAYER_BODY_RADIUS};
+use crate::user_config::UserConfig;
+use crate::key_bindings::KeyBindings;
 
-#[derive(Component, Default, Clone)]
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum CameraMode {
     #[default]
     YawPitch,
@@ -17,6 +20,9 @@ pub struct ThirdPersonCamera {
     target_distance: f32,
     settings: CameraSettings,
     mode: CameraMode,
+    /// current lead of the look target ahead of the player
+    look_ahead: Vec3,
+    last_player_position: Option<Vec3>,
 }
 
 #[derive(Clone)]
@@ -25,13 +31,17 @@ pub struct CameraSettings {
     max_zoom: f32,
     zoom_speed: f32,
     smoothing_factor: f32,
+    /// how far the look target leads a moving player
+    lead_distance: f32,
+    lead_smoothing: f32,
     height_offset: f32,
     min_pitch: f32,
     max_pitch: f32,
     rotate_left_key: KeyCode,
     rotate_right_key: KeyCode,
     look_up_key: KeyCode,
-    look_down_key: KeyCode
+    look_down_key: KeyCode,
+    toggle_mode_key: KeyCode
 }
 
 impl Default for CameraSettings {
@@ -41,13 +51,16 @@ impl Default for CameraSettings {
             max_zoom: 10.0,
             zoom_speed: 0.5,
             smoothing_factor: 0.5,
+            lead_distance: 2.0,
+            lead_smoothing: 3.0,
             height_offset: 10.0,
             min_pitch: -1.5,
             max_pitch: 1.5,
             rotate_left_key: KeyCode::KeyA,
             rotate_right_key: KeyCode::KeyD,
             look_up_key: KeyCode::KeyW,
-            look_down_key: KeyCode::KeyS
+            look_down_key: KeyCode::KeyS,
+            toggle_mode_key: KeyCode::KeyC
         }
     }
 }
@@ -57,11 +70,13 @@ impl ThirdPersonCamera {
         let settings = CameraSettings::default();
         Self {
             yaw: 0.0,
-            pitch: 0.3,
+            pitch: DEFAULT_PITCH,
             distance: initial_distance,
             target_distance: initial_distance,
             settings,
             mode: CameraMode::Orbit,
+            look_ahead: Vec3::ZERO,
+            last_player_position: None,
         }
     }
 
@@ -69,6 +84,80 @@ impl ThirdPersonCamera {
         self.settings = settings;
         self
     }
+
+    /// Mode, zoom and height saved in the last session
+    pub fn with_user_config(mut self, user_config: &UserConfig) -> Self {
+        self.mode = user_config.camera_mode;
+        self.distance = user_config.camera_distance;
+        self.target_distance = user_config.camera_distance;
+        self.settings.height_offset = user_config.camera_height_offset;
+        self
+    }
+
+    /// Rotation and mode keys of the key bindings
+    pub fn with_key_bindings(mut self, key_bindings: &KeyBindings) -> Self {
+        self.settings.rotate_left_key = key_bindings.camera_left;
+        self.settings.rotate_right_key = key_bindings.camera_right;
+        self.settings.look_up_key = key_bindings.camera_up;
+        self.settings.look_down_key = key_bindings.camera_down;
+        self.settings.toggle_mode_key = key_bindings.camera_mode;
+        self
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    /// Switches between yaw pitch and orbit, yaw pitch starts with the default pitch
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            CameraMode::YawPitch => CameraMode::Orbit,
+            CameraMode::Orbit => {
+                self.pitch = DEFAULT_PITCH;
+                CameraMode::YawPitch
+            }
+        };
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.target_distance
+    }
+
+    pub fn height_offset(&self) -> f32 {
+        self.settings.height_offset
+    }
+}
+
+impl ThirdPersonCamera {
+    /// Leans the look target towards the movement of the player,
+    /// back to the player when standing still
+    fn update_look_ahead(&mut self, player_position: Vec3, delta_secs: f32) -> Vec3 {
+        let movement = match self.last_player_position {
+            Some(last) => Vec3::new(player_position.x - last.x, 0.0, player_position.z - last.z),
+            None => Vec3::ZERO
+        };
+        self.last_player_position = Some(player_position);
+
+        let lead = movement.normalize_or_zero() * self.settings.lead_distance;
+        self.look_ahead = self.look_ahead.lerp(lead, (self.settings.lead_smoothing * delta_secs).min(1.0));
+        self.look_ahead
+    }
+
+    /// Camera position relative to the player
+    fn follow_offset(&self) -> Vec3 {
+        match self.mode {
+            CameraMode::YawPitch => Vec3::new(
+                self.distance * self.yaw.cos() * self.pitch.cos(),
+                self.distance * self.pitch.sin() + self.settings.height_offset,
+                self.distance * self.yaw.sin() * self.pitch.cos()
+            ),
+            CameraMode::Orbit => Vec3::new(
+                self.distance * self.yaw.cos(),
+                self.settings.height_offset,
+                self.distance * self.yaw.sin()
+            ),
+        }
+    }
 }
 
 impl Default for ThirdPersonCamera {
@@ -77,6 +166,19 @@ impl Default for ThirdPersonCamera {
     }
 }
 
+/// Establishing move from above to the follow position when the game starts
+#[derive(Component)]
+pub struct CameraIntro {
+    timer: Timer,
+    start: Vec3,
+}
+
+const DEFAULT_PITCH: f32 = 0.3;
+const CAMERA_INTRO_TIME: f32 = 1.0;
+const CAMERA_INTRO_HEIGHT: f32 = 30.0;
+/// Closest the camera is pulled towards the player by a wall
+pub const CAMERA_MIN_STANDOFF: f32 = 2.0;
+
 #[derive(Component)]
 struct GhostCamera{}
 
@@ -91,33 +193,37 @@ impl Plugin for ThirdPersonCameraPlugin {
                         (setup_camera,
                          setup_player_ghost).after(setup))
             .add_systems(Update, (
-                update_camera_rotation_keyboard,
-                update_camera_position,
+                play_camera_intro,
+                (update_camera_rotation_keyboard,
+                 update_camera_position).run_if(camera_intro_finished),
                 show_player_ghost
-            ).run_if(in_state(GameState::InGame)));
+            ).chain().run_if(in_state(GameState::InGame)));
     }
 }
 
 fn setup_camera(
     mut commands: Commands,
-    player_query: Query<&Transform, With<crate::Player>>
+    player_query: Query<&Transform, With<crate::Player>>,
+    user_config: Res<UserConfig>,
+    key_bindings: Res<KeyBindings>
 ) {
     let player_transform = player_query.single();
+    let intro_start = player_transform.translation + Vec3::new(0.0, CAMERA_INTRO_HEIGHT, 0.1);
 
     commands.spawn((
             Camera3d::default(),
-        Msaa::Sample4,
             Camera{
                 order: 0,
                 ..Default::default()
             },
-             Transform::from_xyz(
-                player_transform.translation.x,
-                player_transform.translation.y + 1.0,
-                player_transform.translation.z + 10.0
-            ).looking_at(player_transform.translation, Vec3::Y),
+            Transform::from_translation(intro_start)
+                .looking_at(player_transform.translation, Vec3::Y),
         RenderLayers::layer(0),
-        ThirdPersonCamera::new(10.0),
+        ThirdPersonCamera::new(10.0).with_user_config(&user_config).with_key_bindings(&key_bindings),
+        CameraIntro {
+            timer: Timer::from_seconds(CAMERA_INTRO_TIME, TimerMode::Once),
+            start: intro_start,
+        },
     )).with_children(|parent| {
         parent.spawn((
             Camera3d::default(),
@@ -154,6 +260,36 @@ fn setup_player_ghost(
     ));
 }
 
+/// Player and camera control wait until the intro has finished
+pub fn camera_intro_finished(intro_query: Query<(), With<CameraIntro>>) -> bool {
+    intro_query.is_empty()
+}
+
+fn play_camera_intro(
+    mut commands: Commands,
+    mut query: Query<(Entity, &ThirdPersonCamera, &mut CameraIntro, &mut Transform)>,
+    player_query: Query<&Transform, (With<crate::Player>, Without<ThirdPersonCamera>)>,
+    time: Res<Time>,
+) {
+    let Ok(player_transform) = player_query.get_single() else { return };
+
+    for (entity, camera, mut intro, mut camera_transform) in query.iter_mut() {
+        intro.timer.tick(time.delta());
+
+        // ease in and out
+        let t = intro.timer.fraction();
+        let t = t * t * (3.0 - 2.0 * t);
+
+        let target_pos = player_transform.translation;
+        camera_transform.translation = intro.start.lerp(target_pos + camera.follow_offset(), t);
+        camera_transform.look_at(target_pos, Vec3::Y);
+
+        if intro.timer.finished() {
+            commands.entity(entity).remove::<CameraIntro>();
+        }
+    }
+}
+
 fn update_camera_rotation_keyboard(
     mut query: Query<&mut ThirdPersonCamera>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
@@ -162,6 +298,10 @@ fn update_camera_rotation_keyboard(
     let mut camera = query.single_mut();
     let rotation_speed = 2.0;
 
+    if keyboard_input.just_pressed(camera.settings.toggle_mode_key) {
+        camera.toggle_mode();
+    }
+
     match camera.mode {
         CameraMode::YawPitch => {
             if keyboard_input.pressed(camera.settings.rotate_left_key) {
@@ -203,8 +343,9 @@ fn update_camera_rotation_keyboard(
 }
 
 fn update_camera_position(
-    mut query: Query<(&ThirdPersonCamera, &mut Transform)>,
+    mut query: Query<(&mut ThirdPersonCamera, &mut Transform)>,
     player_query: Query<&Transform, (With<crate::Player>, Without<ThirdPersonCamera>)>,
+    game_map: Res<GameMap>,
     time: Res<Time>,
 ) {
     let player_transform = match player_query.get_single() {
@@ -212,21 +353,11 @@ fn update_camera_position(
         Err(_) => return,
     };
 
-    let (camera, mut camera_transform) = query.single_mut();
+    let (mut camera, mut camera_transform) = query.single_mut();
     let target_pos = player_transform.translation;
+    let look_ahead = camera.update_look_ahead(target_pos, time.delta_secs());
 
-    let offset = match camera.mode {
-        CameraMode::YawPitch => Vec3::new(
-            camera.distance * camera.yaw.cos() * camera.pitch.cos(),
-            camera.distance * camera.pitch.sin() + camera.settings.height_offset,
-            camera.distance * camera.yaw.sin() * camera.pitch.cos()
-        ),
-        CameraMode::Orbit => Vec3::new(
-            camera.distance * camera.yaw.cos(),
-            camera.settings.height_offset,
-            camera.distance * camera.yaw.sin()
-        ),
-    };
+    let offset = unblocked_offset(&game_map, target_pos, camera.follow_offset());
 
     let current_pos = camera_transform.translation;
     let new_pos = current_pos.lerp(
@@ -235,7 +366,32 @@ fn update_camera_position(
     );
 
     camera_transform.translation = new_pos;
-    camera_transform.look_at(target_pos, Vec3::Y);
+    camera_transform.look_at(target_pos + look_ahead, Vec3::Y);
+}
+
+/// Shortens the offset from the player to the camera to the last point before a wall,
+/// but not below the standoff so a tight corridor doesn't put the camera into the player
+fn unblocked_offset(game_map: &GameMap, target: Vec3, offset: Vec3) -> Vec3 {
+    const STEP_SIZE: f32 = 0.5;
+    let total_distance = offset.length();
+    if total_distance <= CAMERA_MIN_STANDOFF {
+        return offset;
+    }
+    let direction = offset / total_distance;
+
+    let mut free_distance = 0.0;
+    while free_distance + STEP_SIZE <= total_distance {
+        let grid_pos = game_map.world_to_grid(target + direction * (free_distance + STEP_SIZE));
+        if game_map.grid[grid_pos].tile_type.is_blocking() {
+            break;
+        }
+        free_distance += STEP_SIZE;
+    }
+    if free_distance + STEP_SIZE > total_distance {
+        free_distance = total_distance;
+    }
+
+    direction * free_distance.max(CAMERA_MIN_STANDOFF)
 }
 
 fn show_player_ghost(
@@ -258,4 +414,83 @@ fn show_player_ghost(
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn look_target_leads_a_moving_player_and_recenters_when_standing_still() {
+        let mut camera = ThirdPersonCamera::default();
+        let mut player_position = Vec3::ZERO;
+        camera.update_look_ahead(player_position, 0.1);
+
+        let mut look_ahead = Vec3::ZERO;
+        for _ in 0..20 {
+            player_position.z -= 0.5;
+            look_ahead = camera.update_look_ahead(player_position, 0.1);
+        }
+        assert!(look_ahead.z < -0.9 * camera.settings.lead_distance, "{:?}", look_ahead);
+        assert!(look_ahead.x.abs() < 1e-4);
+
+        for _ in 0..40 {
+            look_ahead = camera.update_look_ahead(player_position, 0.1);
+        }
+        assert!(look_ahead.length() < 0.01, "{:?}", look_ahead);
+    }
+
+    #[test]
+    fn camera_intro_plays_once_and_holds_the_controls_until_then() {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.spawn((Player, Transform::default()));
+        let intro_start = Vec3::new(0.0, CAMERA_INTRO_HEIGHT, 0.1);
+        let camera = world.spawn((
+            ThirdPersonCamera::default(),
+            Transform::from_translation(intro_start),
+            CameraIntro {
+                timer: Timer::from_seconds(CAMERA_INTRO_TIME, TimerMode::Once),
+                start: intro_start,
+            }
+        )).id();
+        let play_camera_intro = world.register_system(play_camera_intro);
+        let camera_intro_finished = world.register_system(camera_intro_finished);
+
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(CAMERA_INTRO_TIME / 2.0));
+        world.run_system(play_camera_intro).unwrap();
+        assert_eq!(world.get::<CameraIntro>(camera).unwrap().timer.fraction(), 0.5);
+        assert!(world.get::<Transform>(camera).unwrap().translation.y < CAMERA_INTRO_HEIGHT);
+        assert!(!world.run_system(camera_intro_finished).unwrap());
+
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(CAMERA_INTRO_TIME));
+        world.run_system(play_camera_intro).unwrap();
+        assert!(world.get::<CameraIntro>(camera).is_none());
+        assert!(world.run_system(camera_intro_finished).unwrap());
+    }
+
+    #[test]
+    fn mode_key_toggles_the_camera_mode() {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        let camera = world.spawn(ThirdPersonCamera { pitch: 1.2, ..default() }).id();
+        let update_camera_rotation_keyboard = world.register_system(update_camera_rotation_keyboard);
+        assert_eq!(world.get::<ThirdPersonCamera>(camera).unwrap().mode(), CameraMode::Orbit);
+
+        world.resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::KeyC);
+        world.run_system(update_camera_rotation_keyboard).unwrap();
+        let third_person_camera = world.get::<ThirdPersonCamera>(camera).unwrap();
+        assert_eq!(third_person_camera.mode(), CameraMode::YawPitch);
+        assert_eq!(third_person_camera.pitch, DEFAULT_PITCH);
+
+        world.resource_mut::<ButtonInput<KeyCode>>().clear();
+        world.run_system(update_camera_rotation_keyboard).unwrap();
+        assert_eq!(world.get::<ThirdPersonCamera>(camera).unwrap().mode(), CameraMode::YawPitch);
+
+        world.resource_mut::<ButtonInput<KeyCode>>().reset_all();
+        world.resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::KeyC);
+        world.run_system(update_camera_rotation_keyboard).unwrap();
+        assert_eq!(world.get::<ThirdPersonCamera>(camera).unwrap().mode(), CameraMode::Orbit);
+    }
+}
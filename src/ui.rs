@@ -3,20 +3,88 @@ use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts};
 use crate::ui::egui::Color32;
 use crate::ui::egui::pos2;
-use crate::fighting::Actor;
-use crate::{GameMap, Inventory, Player, ShowFps, GameState, MAP_TEXT_FILE, LoadMapAndItems, ShowPlayerValuesAndInventar, CurrentFloor};
-use std::path::Path;
+use crate::fighting::{Actor, LevelUpEvent};
+use crate::{GameMap, GameMode, Inventory, ItemType, Player, ShowFps, GameState, LoadMapAndItems, ShowPlayerValuesAndInventar, CurrentFloor, LivesMode, StartingLives, Pickup, StartingLoadout,
+            PlayerTransition, TransitionState, PauseState, SaveGameEvent, AfterSave};
+use crate::user_config::{UserConfig, USER_CONFIG_FILE};
+use crate::save_slot::SaveSlot;
+use crate::third_person_camera::ThirdPersonCamera;
+use crate::field_of_view::FieldOfView;
+use crate::sound_effects::AudioEnabled;
+use std::collections::VecDeque;
 
 // Komponente für das ausgewählte Menü-Item
 #[derive(Resource, Default)]
 struct SelectedMenuItem(usize);
 
 #[derive(Resource)]
-struct BackgroundTextureId(egui::TextureId);
+struct BackgroundTextureId(egui::TextureId, Handle<Image>);
+
+/// Slots with a saved game and their descriptions, read once when the main menu opens
+#[derive(Resource, Default)]
+struct SavedGames(Vec<(SaveSlot, String)>);
+
+impl SavedGames {
+    fn read() -> Self {
+        Self(SaveSlot::list().into_iter()
+            .map(|slot| {
+                let description = slot.description();
+                (slot, description)
+            })
+            .collect())
+    }
+}
 
 #[derive(Component)]
 struct MainMenuCamera;
 
+/// Image based minimap of the explored cells, rebuilt when the map or the player cell changes
+#[derive(Resource, Default)]
+pub struct Minimap {
+    pub show: bool,
+    texture: Option<egui::TextureHandle>,
+    player_position: Option<(usize, usize)>
+}
+
+const MINIMAP_PIXELS_PER_CELL: f32 = 3.0;
+
+/// Messages kept in the log, older ones are dropped
+const MESSAGE_LOG_CAPACITY: usize = 20;
+/// Messages shown in the HUD
+const MESSAGE_LOG_LINES: usize = 5;
+
+/// What happened in the game, newest message last
+#[derive(Resource, Default)]
+pub struct MessageLog {
+    messages: VecDeque<String>
+}
+
+impl MessageLog {
+    pub fn push_message(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        info!("{}", message);
+        if self.messages.len() == MESSAGE_LOG_CAPACITY {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+
+    pub fn last_messages(&self, count: usize) -> impl Iterator<Item = &String> {
+        self.messages.iter().skip(self.messages.len().saturating_sub(count))
+    }
+}
+
+/// Seconds the level up notification stays on screen
+const LEVEL_UP_FLASH_TIME: f32 = 2.5;
+
+/// Level the player just reached, shown while the timer runs
+#[derive(Resource, Default)]
+struct LevelUpFlash(Option<(usize, Timer)>);
+
 #[derive(Component)]
 pub struct HeadUpDisplay{
     width:usize,
@@ -43,10 +111,19 @@ pub struct UiPlugin;
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<SelectedMenuItem>()
-            .add_systems(Startup, setup_main_menu.run_if(in_state(GameState::MainMenu)))
+            .init_resource::<Minimap>()
+            .init_resource::<MessageLog>()
+            .init_resource::<LevelUpFlash>()
+            .add_systems(OnEnter(GameState::MainMenu), setup_main_menu)
             .add_systems(OnExit(GameState::MainMenu), despawn_main_menu)
             .add_systems(Update, main_menu.run_if(in_state(GameState::MainMenu)))
-            .add_systems(Update, (update_headupdisplay, render_ui).run_if(in_state(GameState::InGame)));
+            .add_systems(Update, (update_headupdisplay, update_minimap, update_level_up_flash, render_ui).run_if(in_state(GameState::InGame)))
+            .add_systems(Update, inventory_screen.after(render_ui).run_if(in_state(PauseState::Running)))
+            .add_systems(Update, game_over.run_if(in_state(GameState::GameOver)))
+            .add_systems(OnEnter(PauseState::Paused), reset_selected_menu_item)
+            .add_systems(Update, pause_menu.after(render_ui).run_if(in_state(PauseState::Paused)))
+            .add_systems(Update, floor_transition_fade.after(render_ui)
+                .run_if(in_state(TransitionState::StairsDown).or(in_state(TransitionState::StairsUp))));
     }
 }
 
@@ -61,18 +138,27 @@ fn setup_main_menu(
     ));
 
     let image_handle = asset_server.load("images/background.png");
-    let texture_id = egui_contexts.add_image(image_handle);
+    let texture_id = egui_contexts.add_image(image_handle.clone());
 
-    commands.insert_resource(BackgroundTextureId(texture_id));
+    commands.insert_resource(BackgroundTextureId(texture_id, image_handle));
+    commands.insert_resource(SavedGames::read());
 }
 
 fn despawn_main_menu(
     mut commands: Commands,
+    mut egui_contexts: EguiContexts,
+    background_texture: Option<Res<BackgroundTextureId>>,
     query: Query<Entity, With<MainMenuCamera>>,
 ) {
     for entity in query.iter() {
         commands.entity(entity).despawn_recursive();
     }
+
+    // release the background image, it is loaded again on the next visit
+    if let Some(background_texture) = background_texture {
+        egui_contexts.remove_image(&background_texture.1);
+        commands.remove_resource::<BackgroundTextureId>();
+    }
 }
 
 fn main_menu(
@@ -81,19 +167,29 @@ fn main_menu(
     mut next_state: ResMut<NextState<GameState>>,
     keyboard: Res<ButtonInput<KeyCode>>,
     background_texture: Res<BackgroundTextureId>,
-    mut load_map_and_items: ResMut<LoadMapAndItems>
+    mut load_map_and_items: ResMut<LoadMapAndItems>,
+    mut starting_loadout: ResMut<StartingLoadout>,
+    mut game_mode: ResMut<GameMode>,
+    mut save_slot: ResMut<SaveSlot>,
+    saved_games: Res<SavedGames>,
+    mut audio_enabled: ResMut<AudioEnabled>,
+    mut starting_lives: ResMut<StartingLives>
 ) {
-    let neon_green = Color32::from_rgb(57, 255, 20);
-    let light_gray = Color32::from_rgb(128, 128, 128);
-
-    // file exists
-    let save_exists = Path::new(MAP_TEXT_FILE).exists();
-
-    // menu items
-    let mut menu_items = vec!["<S>tart Game", "<Q>uit Game"];
-    if save_exists {
-        menu_items.insert(0, "<L>oad Game");
-    }
+    // menu items, one per saved game first
+    let slot_count = saved_games.0.len();
+    let mut menu_items: Vec<String> = saved_games.0.iter().enumerate()
+        .map(|(index, (_, description))| if index == 0 {
+            format!("<L>oad {}", description)
+        } else {
+            format!("Load {}", description)
+        })
+        .collect();
+    menu_items.push(String::from("<S>tart Game"));
+    menu_items.push(format!("<D>ifficulty: {}", starting_loadout.difficulty));
+    menu_items.push(format!("<T>urns: {}", *game_mode));
+    menu_items.push(format!("<A>udio: {}", *audio_enabled));
+    menu_items.push(format!("Li<V>es: {}", starting_lives.0));
+    menu_items.push(String::from("<Q>uit Game"));
 
     // keyboard input
     if keyboard.just_pressed(KeyCode::ArrowUp) {
@@ -105,12 +201,26 @@ fn main_menu(
 
     // keyboard shortcuts
     if keyboard.just_pressed(KeyCode::KeyS) {
+        *save_slot = SaveSlot::next_free();
         next_state.set(GameState::InGame);
     }
+    if keyboard.just_pressed(KeyCode::KeyD) {
+        *starting_loadout = StartingLoadout::new(starting_loadout.difficulty.next());
+    }
+    if keyboard.just_pressed(KeyCode::KeyT) {
+        *game_mode = game_mode.next();
+    }
+    if keyboard.just_pressed(KeyCode::KeyA) {
+        audio_enabled.0 = !audio_enabled.0;
+    }
+    if keyboard.just_pressed(KeyCode::KeyV) {
+        starting_lives.0 = starting_lives.0.next();
+    }
     if keyboard.just_pressed(KeyCode::KeyQ) {
         std::process::exit(0);
     }
-    if save_exists && keyboard.just_pressed(KeyCode::KeyL) {
+    if let (Some((slot, _)), true) = (saved_games.0.first(), keyboard.just_pressed(KeyCode::KeyL)) {
+        *save_slot = slot.clone();
         load_map_and_items.0 = true;
         next_state.set(GameState::InGame);
     }
@@ -139,46 +249,224 @@ fn main_menu(
                 ui.horizontal(|ui| {
                     ui.add_space(ui.available_width() / 3.0);
 
-                    egui::Frame {
-                        fill: Color32::TRANSPARENT,
-                        stroke: egui::Stroke {
-                            width: if index == selected.0 { 2.0 } else { 1.0 },
-                            color: if index == selected.0 {neon_green} else {light_gray}
-                        },
-                        inner_margin: egui::Margin::same(10.0),
-                        ..Default::default()
-                    }.show(ui, |ui| {
-                        let response = ui.add_sized(
-                            [200.0, 40.0],
-                            egui::Button::new(
-                                egui::RichText::new(*item)
-                                    .size(20.0)
-                                    .color(neon_green)
-                                    .monospace()
-                            ).frame(false)
-                        );
-
-                        if response.clicked() || (index == selected.0 && keyboard.just_pressed(KeyCode::Enter)) {
-                            match (save_exists, index) {
-                                (true, 0) => {
-                                    next_state.set(GameState::InGame);
-                                }
-                                (true, 1) | (false, 0) => {
-                                    next_state.set(GameState::InGame);
-                                }
-                                (true, 2) | (false, 1) => {
-                                    std::process::exit(0);
-                                }
-                                _ => {}
+                    let response = menu_button(ui, item, index == selected.0);
+                    if response.clicked() || (index == selected.0 && keyboard.just_pressed(KeyCode::Enter)) {
+                        if index < slot_count {
+                            *save_slot = saved_games.0[index].0.clone();
+                            load_map_and_items.0 = true;
+                            next_state.set(GameState::InGame);
+                        }
+                        match index.checked_sub(slot_count) {
+                            Some(0) => {
+                                *save_slot = SaveSlot::next_free();
+                                next_state.set(GameState::InGame);
+                            }
+                            Some(1) => {
+                                *starting_loadout = StartingLoadout::new(starting_loadout.difficulty.next());
+                            }
+                            Some(2) => {
+                                *game_mode = game_mode.next();
+                            }
+                            Some(3) => {
+                                audio_enabled.0 = !audio_enabled.0;
+                            }
+                            Some(4) => {
+                                starting_lives.0 = starting_lives.0.next();
                             }
+                            Some(5) => {
+                                std::process::exit(0);
+                            }
+                            _ => {}
                         }
-                    });
+                    }
                 });
                 ui.add_space(10.0);
             }
         });
 }
 
+/// Framed menu item, the selected one is highlighted
+fn menu_button(ui: &mut egui::Ui, item: &str, selected: bool) -> egui::Response {
+    let neon_green = Color32::from_rgb(57, 255, 20);
+    let light_gray = Color32::from_rgb(128, 128, 128);
+
+    egui::Frame {
+        fill: Color32::TRANSPARENT,
+        stroke: egui::Stroke {
+            width: if selected { 2.0 } else { 1.0 },
+            color: if selected {neon_green} else {light_gray}
+        },
+        inner_margin: egui::Margin::same(10.0),
+        ..Default::default()
+    }.show(ui, |ui| {
+        ui.add_sized(
+            [360.0, 40.0],
+            egui::Button::new(
+                egui::RichText::new(item)
+                    .size(20.0)
+                    .color(neon_green)
+                    .monospace()
+            ).frame(false)
+        )
+    }).inner
+}
+
+fn reset_selected_menu_item(mut selected: ResMut<SelectedMenuItem>) {
+    selected.0 = 0;
+}
+
+fn pause_menu(
+    mut egui_context: EguiContexts,
+    mut selected: ResMut<SelectedMenuItem>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+    mut save_game_events: EventWriter<SaveGameEvent>
+) {
+    let neon_green = Color32::from_rgb(57, 255, 20);
+    let menu_items = ["<R>esume", "<S>ave", "<Q>uit to Main Menu"];
+
+    // keyboard input
+    if keyboard.just_pressed(KeyCode::ArrowUp) {
+        selected.0 = (selected.0 + menu_items.len() - 1) % menu_items.len();
+    }
+    if keyboard.just_pressed(KeyCode::ArrowDown) {
+        selected.0 = (selected.0 + 1) % menu_items.len();
+    }
+
+    // keyboard shortcuts pick the item like a click
+    let mut chosen = [KeyCode::KeyR, KeyCode::KeyS, KeyCode::KeyQ].iter()
+        .position(|key| keyboard.just_pressed(*key));
+    if keyboard.just_pressed(KeyCode::Enter) {
+        chosen = Some(selected.0);
+    }
+
+    egui::CentralPanel::default()
+        .frame(egui::Frame {
+            fill: Color32::from_black_alpha(160),
+            ..Default::default()
+        })
+        .show(egui_context.ctx_mut(), |ui| {
+            let window_height = ui.available_height();
+            ui.add_space(window_height / 4.0);
+
+            ui.vertical_centered(|ui| {
+                ui.label(
+                    egui::RichText::new("Paused")
+                        .size(40.0)
+                        .color(neon_green)
+                        .monospace()
+                );
+            });
+            ui.add_space(20.0);
+
+            for (index, item) in menu_items.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.add_space(ui.available_width() / 3.0);
+                    if menu_button(ui, item, index == selected.0).clicked() {
+                        chosen = Some(index);
+                    }
+                });
+                ui.add_space(10.0);
+            }
+        });
+
+    match chosen {
+        Some(0) => next_pause_state.set(PauseState::Running),
+        Some(1) => {
+            save_game_events.send(SaveGameEvent(AfterSave::Continue));
+        }
+        Some(2) => {
+            save_game_events.send(SaveGameEvent(AfterSave::MainMenu));
+        }
+        _ => {}
+    }
+}
+
+/// Every item of the inventory with its count, up and down select one and enter makes
+/// a throwable item the active one
+fn inventory_screen(
+    mut egui_context: EguiContexts,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    show_player_values_inventory: Res<ShowPlayerValuesAndInventar>,
+    mut inventory: ResMut<Inventory>,
+    mut message_log: ResMut<MessageLog>,
+    mut selected: Local<usize>
+) {
+    if !show_player_values_inventory.0 {
+        return;
+    }
+    let Some(ctx) = egui_context.try_ctx_mut() else { return };
+    let neon_green = Color32::from_rgb(57, 255, 20);
+
+    let mut entries: Vec<(ItemType, usize)> = inventory.items.iter()
+        .map(|(item_type, count)| (*item_type, *count))
+        .collect();
+    entries.sort_by_key(|(item_type, _)| item_type.to_string());
+
+    if !entries.is_empty() {
+        // items may have been used up since the last frame
+        *selected = (*selected).min(entries.len() - 1);
+        if keyboard.just_pressed(KeyCode::ArrowUp) {
+            *selected = (*selected + entries.len() - 1) % entries.len();
+        }
+        if keyboard.just_pressed(KeyCode::ArrowDown) {
+            *selected = (*selected + 1) % entries.len();
+        }
+        if keyboard.just_pressed(KeyCode::Enter) {
+            let item_type = entries[*selected].0;
+            if item_type.is_throwable() {
+                inventory.activ_item = Some(item_type);
+            } else {
+                message_log.push_message(format!("A {} can't be thrown, it has its own key", item_type.to_string()));
+            }
+        }
+    }
+
+    egui::Area::new(egui::Id::new("inventory_screen"))
+        .fixed_pos(pos2(10.0, 240.0))
+        .show(ctx, |ui| {
+            egui::Frame {
+                fill: egui::Color32::TRANSPARENT,
+                stroke: egui::Stroke {
+                    width: 2.0,
+                    color: neon_green,
+                },
+                inner_margin: egui::Margin::same(10.0),
+                ..Default::default()
+            }
+                .show(ui, |ui| {
+                    ui.set_min_width(270.0);
+                    ui.heading(
+                        egui::RichText::new("Inventory")
+                            .color(neon_green)
+                            .monospace(),
+                    );
+                    ui.label(
+                        egui::RichText::new(format!("Gold: {}", inventory.gold))
+                            .color(neon_green)
+                            .monospace(),
+                    );
+                    ui.add_space(5.0);
+                    if entries.is_empty() {
+                        ui.label(
+                            egui::RichText::new("Empty")
+                                .color(neon_green)
+                                .monospace(),
+                        );
+                    }
+                    for (index, (item_type, count)) in entries.iter().enumerate() {
+                        let marker = if index == *selected { ">" } else { " " };
+                        let active = if inventory.activ_item == Some(*item_type) { " (active)" } else { "" };
+                        ui.label(
+                            egui::RichText::new(format!("{} {} {}{}", marker, item_type.to_string(), count, active))
+                                .color(neon_green)
+                                .monospace(),
+                        );
+                    }
+                });
+        });
+}
+
 fn update_headupdisplay(
     game_map: Res<GameMap>,
     mut query_player:Query<(&mut HeadUpDisplay, &Transform),Changed<Transform>>
@@ -199,6 +487,32 @@ fn update_headupdisplay(
     }
 }
 
+fn update_minimap(
+    mut egui_context: EguiContexts,
+    mut minimap: ResMut<Minimap>,
+    game_map: Res<GameMap>,
+    field_of_view: Option<Res<FieldOfView>>,
+    query_player: Query<&Transform, With<Player>>
+) {
+    let Ok(player_transform) = query_player.get_single() else { return };
+    let player_position = game_map.world_to_grid(player_transform.translation);
+
+    let explored_changed = field_of_view.as_ref().is_some_and(|field_of_view| field_of_view.is_changed());
+    if minimap.texture.is_some() && !game_map.is_changed() && !explored_changed
+        && minimap.player_position == Some(player_position) {
+        return;
+    }
+
+    let image = game_map.to_minimap_texture(player_position,
+                                            field_of_view.as_ref().map(|field_of_view| &field_of_view.explored));
+    match minimap.texture.as_mut() {
+        Some(texture) => texture.set(image, egui::TextureOptions::NEAREST),
+        None => {
+            minimap.texture = Some(egui_context.ctx_mut().load_texture("minimap", image, egui::TextureOptions::NEAREST));
+        }
+    }
+    minimap.player_position = Some(player_position);
+}
 
 fn render_ui(
     mut egui_context: EguiContexts,
@@ -208,8 +522,30 @@ fn render_ui(
     show_player_values_inventory: Res<ShowPlayerValuesAndInventar>,
     diagnostics: Res<DiagnosticsStore>,
     inventory: Res<Inventory>,
-    current_floor: Res<CurrentFloor>
+    current_floor: Res<CurrentFloor>,
+    lives_mode: Res<LivesMode>,
+    minimap: Res<Minimap>,
+    pickup: Res<Pickup>,
+    message_log: Res<MessageLog>,
+    level_up_flash: Res<LevelUpFlash>,
+    camera_query: Query<&ThirdPersonCamera>
 ) {
+    // no window to draw into, e.g. during state transitions
+    let Some(ctx) = egui_context.try_ctx_mut() else { return };
+
+    // fades out at the end
+    if let Some((level, timer)) = &level_up_flash.0 {
+        let alpha = ((timer.remaining_secs() / 0.5).min(1.0) * 255.0) as u8;
+        ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("level_up_flash")))
+            .text(
+                ctx.screen_rect().center_top() + egui::vec2(0.0, ctx.screen_rect().height() / 4.0),
+                egui::Align2::CENTER_CENTER,
+                format!("Level {}!", level),
+                egui::FontId::monospace(40.0),
+                Color32::from_rgba_unmultiplied(57, 255, 20, alpha)
+            );
+    }
+
     if let Ok(actor) = query.get_single() {
         let neon_green = Color32::from_rgb(57, 255, 20);
 
@@ -218,20 +554,20 @@ fn render_ui(
                 fill: egui::Color32::TRANSPARENT,
                 ..Default::default()
             })
-            .show(egui_context.ctx_mut(), |ui| {
+            .show(ctx, |ui| {
                 let panel_rect = ui.available_rect_before_wrap();
 
 
                     // Character Information Panel
                     let char_info_width = 300.0;
-                    let char_info_height = 150.0;
+                    let char_info_height = 170.0;
                     let char_info_rect = egui::Rect {
                         min: panel_rect.left_top() + egui::vec2(10.0, 10.0),
                         max: panel_rect.left_top() + egui::vec2(char_info_width, char_info_height),
                     };
 
                 if show_player_values_inventory.0 {
-                    ui.allocate_ui_at_rect(char_info_rect, |ui| {
+                    ui.allocate_new_ui(egui::UiBuilder::new().max_rect(char_info_rect), |ui| {
                         egui::Frame {
                             fill: egui::Color32::TRANSPARENT,
                             stroke: egui::Stroke {
@@ -279,6 +615,13 @@ fn render_ui(
                                         .color(neon_green)
                                         .monospace(),
                                 );
+                                if let Ok(camera) = camera_query.get_single() {
+                                    ui.label(
+                                        egui::RichText::new(format!("Camera: {:?}", camera.mode()))
+                                            .color(neon_green)
+                                            .monospace(),
+                                    );
+                                }
                             });
                     });
                 };
@@ -294,7 +637,7 @@ fn render_ui(
                             max: char_info_rect.left_bottom() + egui::vec2(120.0, 50.0),
                         };
 
-                        ui.allocate_ui_at_rect(fps_rect, |ui| {
+                        ui.allocate_new_ui(egui::UiBuilder::new().max_rect(fps_rect), |ui| {
                             egui::Frame {
                                 fill: egui::Color32::TRANSPARENT,
                                 stroke: egui::Stroke {
@@ -326,7 +669,7 @@ fn render_ui(
                         max: panel_rect.right_top() + egui::vec2(0.0, text_height + 10.0),
                     };
 
-                    ui.allocate_ui_at_rect(text_rect, |ui| {
+                    ui.allocate_new_ui(egui::UiBuilder::new().max_rect(text_rect), |ui| {
                         egui::Frame {
                             fill: egui::Color32::TRANSPARENT,
                             stroke: egui::Stroke {
@@ -343,10 +686,33 @@ fn render_ui(
                                 );
                             });
                     });
+
+                    // Minimap (below the text display)
+                    if let (true, Some(texture)) = (minimap.show, minimap.texture.as_ref()) {
+                        let minimap_size = texture.size_vec2() * MINIMAP_PIXELS_PER_CELL;
+                        let minimap_rect = egui::Rect {
+                            min: panel_rect.right_top() + egui::vec2(-minimap_size.x - 10.0, text_height + 30.0),
+                            max: panel_rect.right_top() + egui::vec2(-10.0, text_height + 30.0 + minimap_size.y),
+                        };
+
+                        ui.allocate_new_ui(egui::UiBuilder::new().max_rect(minimap_rect), |ui| {
+                            egui::Frame {
+                                fill: egui::Color32::TRANSPARENT,
+                                stroke: egui::Stroke {
+                                    width: 2.0,
+                                    color: neon_green,
+                                },
+                                ..Default::default()
+                            }
+                                .show(ui, |ui| {
+                                    ui.image((texture.id(), minimap_size));
+                                });
+                        });
+                    }
                 }
 
                 // Progress bar section
-                let progress_width = 600.0;
+                let progress_width = 700.0;
                 let progress_height = 80.0;
 
                 let progress_rect = egui::Rect {
@@ -354,8 +720,61 @@ fn render_ui(
                     max: panel_rect.center_bottom() + egui::vec2(progress_width / 2.0, progress_height / 2.0),
                 };
 
-                ui.allocate_ui_at_rect(progress_rect, |ui| {
+                // Message log (bottom left)
+                let log_height = ui.text_style_height(&egui::TextStyle::Monospace) * MESSAGE_LOG_LINES as f32;
+                let log_rect = egui::Rect {
+                    min: panel_rect.left_bottom() + egui::vec2(10.0, -log_height - 30.0),
+                    max: panel_rect.left_bottom() + egui::vec2(progress_rect.left() - panel_rect.left() - 20.0, -10.0),
+                };
+
+                ui.allocate_new_ui(egui::UiBuilder::new().max_rect(log_rect), |ui| {
+                    ui.vertical(|ui| {
+                        for message in message_log.last_messages(MESSAGE_LOG_LINES) {
+                            ui.label(
+                                egui::RichText::new(message)
+                                    .color(neon_green)
+                                    .monospace()
+                            );
+                        }
+                    });
+                });
+
+                // Pickup prompt (above the progress bar)
+                if let Some((_, item_type)) = pickup.item_in_reach {
+                    let prompt_rect = egui::Rect {
+                        min: progress_rect.left_top() - egui::vec2(0.0, 40.0),
+                        max: progress_rect.right_top(),
+                    };
+
+                    ui.allocate_new_ui(egui::UiBuilder::new().max_rect(prompt_rect), |ui| {
+                        ui.vertical_centered(|ui| {
+                            ui.label(
+                                egui::RichText::new(format!("<E> pick up {}", item_type.to_string()))
+                                    .color(neon_green)
+                                    .monospace()
+                            );
+                        });
+                    });
+                }
+
+                ui.allocate_new_ui(egui::UiBuilder::new().max_rect(progress_rect), |ui| {
                     ui.horizontal(|ui| {
+                        egui::Frame {
+                            fill: egui::Color32::TRANSPARENT,
+                            stroke: egui::Stroke {
+                                width: 1.0,
+                                color: neon_green,
+                            },
+                            inner_margin: egui::Margin::same(5.0),
+                            ..Default::default()
+                        }.show(ui, |ui| {
+                            ui.label(
+                                egui::RichText::new(current_floor.depth_label())
+                                    .color(neon_green)
+                                    .monospace()
+                            );
+                        });
+
                         egui::Frame {
                             fill: egui::Color32::TRANSPARENT,
                             stroke: egui::Stroke {
@@ -413,12 +832,62 @@ fn render_ui(
                             ..Default::default()
                         }.show(ui, |ui| {
                             ui.label(
-                                egui::RichText::new(format!("<P>ortion {}", inventory.heal_potion))
+                                egui::RichText::new(format!("Gold {}", inventory.gold))
+                                    .color(neon_green)
+                                    .monospace()
+                            );
+                        });
+
+                        egui::Frame {
+                            fill: egui::Color32::TRANSPARENT,
+                            stroke: egui::Stroke {
+                                width: 1.0,
+                                color: neon_green,
+                            },
+                            inner_margin: egui::Margin::same(5.0),
+                            ..Default::default()
+                        }.show(ui, |ui| {
+                            ui.label(
+                                egui::RichText::new(inventory.potion_label())
+                                    .color(neon_green)
+                                    .monospace()
+                            );
+                        });
+
+                        egui::Frame {
+                            fill: egui::Color32::TRANSPARENT,
+                            stroke: egui::Stroke {
+                                width: 1.0,
+                                color: neon_green,
+                            },
+                            inner_margin: egui::Margin::same(5.0),
+                            ..Default::default()
+                        }.show(ui, |ui| {
+                            ui.label(
+                                egui::RichText::new(format!("<R>ead scroll {}", inventory.count(ItemType::LightningScroll)))
                                     .color(neon_green)
                                     .monospace()
                             );
                         });
 
+                        if let LivesMode::Lives(lives) = *lives_mode {
+                            egui::Frame {
+                                fill: egui::Color32::TRANSPARENT,
+                                stroke: egui::Stroke {
+                                    width: 1.0,
+                                    color: neon_green,
+                                },
+                                inner_margin: egui::Margin::same(5.0),
+                                ..Default::default()
+                            }.show(ui, |ui| {
+                                ui.label(
+                                    egui::RichText::new(format!("Lives {}", lives))
+                                        .color(neon_green)
+                                        .monospace()
+                                );
+                            });
+                        }
+
                         egui::Frame {
                             fill: egui::Color32::TRANSPARENT,
                             stroke: egui::Stroke {
@@ -438,4 +907,121 @@ fn render_ui(
                 });
             });
     }
-}
\ No newline at end of file
+}
+
+/// Black overlay hiding the pop-in of the next floor
+/// Starts the level up notification, the newest level wins
+fn update_level_up_flash(
+    time: Res<Time>,
+    mut level_up_events: EventReader<LevelUpEvent>,
+    mut level_up_flash: ResMut<LevelUpFlash>,
+    mut message_log: ResMut<MessageLog>
+) {
+    for event in level_up_events.read() {
+        message_log.push_message(format!("You reached level {}", event.level));
+        level_up_flash.0 = Some((event.level, Timer::from_seconds(LEVEL_UP_FLASH_TIME, TimerMode::Once)));
+    }
+
+    if let Some((_, timer)) = level_up_flash.0.as_mut() {
+        timer.tick(time.delta());
+        if timer.finished() {
+            level_up_flash.0 = None;
+        }
+    }
+}
+
+fn floor_transition_fade(
+    mut egui_context: EguiContexts,
+    query: Query<&PlayerTransition, With<Player>>
+) {
+    let Ok(transition) = query.get_single() else { return };
+    let Some(ctx) = egui_context.try_ctx_mut() else { return };
+
+    let alpha = (transition.fade_alpha().clamp(0.0, 1.0) * 255.0) as u8;
+    ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("floor_transition_fade")))
+        .rect_filled(ctx.screen_rect(), 0.0, Color32::from_black_alpha(alpha));
+}
+
+fn game_over(
+    mut egui_context: EguiContexts,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    user_config: Res<UserConfig>,
+    current_floor: Res<CurrentFloor>,
+    mut next_state: ResMut<NextState<GameState>>,
+    query: Query<&Actor, With<Player>>
+) {
+    let neon_green = Color32::from_rgb(57, 255, 20);
+
+    if keyboard.just_pressed(KeyCode::KeyR) {
+        next_state.set(GameState::InGame);
+    }
+    if keyboard.just_pressed(KeyCode::KeyQ) {
+        user_config.save(USER_CONFIG_FILE);
+        std::process::exit(0);
+    }
+
+    let level = query.get_single().map(|actor| actor.current_level).unwrap_or(1);
+
+    egui::CentralPanel::default()
+        .frame(egui::Frame {
+            fill: Color32::TRANSPARENT,
+            ..Default::default()
+        })
+        .show(egui_context.ctx_mut(), |ui| {
+            let window_height = ui.available_height();
+            ui.add_space(window_height / 3.0);
+
+            ui.vertical_centered(|ui| {
+                ui.label(
+                    egui::RichText::new(format!("You died on floor {}", current_floor.0))
+                        .size(40.0)
+                        .color(neon_green)
+                        .monospace()
+                );
+                ui.add_space(10.0);
+                ui.label(
+                    egui::RichText::new(format!("Level {}", level))
+                        .size(20.0)
+                        .color(neon_green)
+                        .monospace()
+                );
+                ui.add_space(20.0);
+                ui.label(
+                    egui::RichText::new("<R>estart Game")
+                        .size(20.0)
+                        .color(neon_green)
+                        .monospace()
+                );
+                ui.add_space(10.0);
+                ui.label(
+                    egui::RichText::new("<Q>uit Game")
+                        .size(20.0)
+                        .color(neon_green)
+                        .monospace()
+                );
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy_egui::EguiUserTextures;
+
+    #[test]
+    fn leaving_the_main_menu_releases_the_background() {
+        let mut world = World::new();
+        world.init_resource::<EguiUserTextures>();
+        let image_handle = Handle::<Image>::weak_from_u128(42);
+        let texture_id = world.resource_mut::<EguiUserTextures>().add_image(image_handle.clone());
+        world.insert_resource(BackgroundTextureId(texture_id, image_handle.clone()));
+        let camera = world.spawn((Camera3d::default(), MainMenuCamera)).id();
+
+        world.run_system_once(despawn_main_menu).unwrap();
+
+        assert!(!world.contains_resource::<BackgroundTextureId>());
+        assert_eq!(world.resource::<EguiUserTextures>().image_id(&image_handle), None);
+        assert!(world.get_entity(camera).is_err());
+    }
+}
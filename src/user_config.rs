@@ -0,0 +1,128 @@
+use std::fs;
+use bevy::prelude::*;
+use bevy::app::AppExit;
+use serde::{Serialize, Deserialize};
+
+use crate::render_settings::RenderSettings;
+use crate::third_person_camera::{CameraMode, ThirdPersonCamera};
+
+pub const USER_CONFIG_FILE: &str = "config.json";
+
+/// Camera and video settings kept between two sessions, missing values are defaults
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserConfig {
+    pub camera_mode: CameraMode,
+    pub camera_distance: f32,
+    pub camera_height_offset: f32,
+    pub msaa_samples: u32,
+    pub fov_degrees: f32
+}
+
+impl Default for UserConfig {
+    fn default() -> Self {
+        let camera = ThirdPersonCamera::new(10.0);
+        Self {
+            camera_mode: camera.mode(),
+            camera_distance: camera.zoom(),
+            camera_height_offset: camera.height_offset(),
+            msaa_samples: RenderSettings::default().samples(),
+            fov_degrees: RenderSettings::default().fov_degrees()
+        }
+    }
+}
+
+impl UserConfig {
+    /// Defaults when there is no config yet or it can't be read
+    pub fn load(file_name: &str) -> Self {
+        let Ok(input) = fs::read_to_string(file_name) else {
+            return UserConfig::default();
+        };
+        serde_json::from_str(&input).unwrap_or_else(|error| {
+            warn!("Ignoring {}: {}", file_name, error);
+            UserConfig::default()
+        })
+    }
+
+    pub fn save(&self, file_name: &str) {
+        let config = serde_json::to_string(self).expect("Unable to serialize config");
+        if let Err(error) = fs::write(file_name, config) {
+            warn!("Unable to write {}: {}", file_name, error);
+        }
+    }
+}
+
+pub struct UserConfigPlugin;
+
+impl Plugin for UserConfigPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(UserConfig::load(USER_CONFIG_FILE))
+            .add_systems(Startup, apply_user_config)
+            .add_systems(Update, record_user_config)
+            .add_systems(Last, save_user_config_on_exit);
+    }
+}
+
+fn apply_user_config(
+    user_config: Res<UserConfig>,
+    mut render_settings: ResMut<RenderSettings>
+) {
+    *render_settings = RenderSettings::with_samples(user_config.msaa_samples)
+        .with_fov_degrees(user_config.fov_degrees);
+}
+
+/// Keeps the config in step with the camera and the render settings
+fn record_user_config(
+    mut user_config: ResMut<UserConfig>,
+    render_settings: Res<RenderSettings>,
+    camera_query: Query<&ThirdPersonCamera>
+) {
+    let mut config = user_config.clone();
+    config.msaa_samples = render_settings.samples();
+    config.fov_degrees = render_settings.fov_degrees();
+    if let Ok(camera) = camera_query.get_single() {
+        config.camera_mode = camera.mode();
+        config.camera_distance = camera.zoom();
+        config.camera_height_offset = camera.height_offset();
+    }
+
+    user_config.set_if_neq(config);
+}
+
+fn save_user_config_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    user_config: Res<UserConfig>
+) {
+    if exit_events.read().next().is_some() {
+        user_config.save(USER_CONFIG_FILE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn changed_camera_mode_is_restored_after_save_and_load() {
+        let file_name = std::env::temp_dir().join("rust-bevy-rogue-test-config.json");
+        let file_name = file_name.to_str().unwrap();
+        let _ = fs::remove_file(file_name);
+        assert_eq!(UserConfig::load(file_name), UserConfig::default());
+
+        let mut world = World::new();
+        world.init_resource::<UserConfig>();
+        world.init_resource::<RenderSettings>();
+        let mut camera = ThirdPersonCamera::new(10.0);
+        camera.toggle_mode();
+        let mode = camera.mode();
+        assert_ne!(mode, UserConfig::default().camera_mode);
+        world.spawn(camera);
+        world.run_system_once(record_user_config).unwrap();
+        world.resource::<UserConfig>().save(file_name);
+
+        let loaded = UserConfig::load(file_name);
+        fs::remove_file(file_name).unwrap();
+        assert_eq!(ThirdPersonCamera::new(10.0).with_user_config(&loaded).mode(), mode);
+    }
+}